@@ -0,0 +1,244 @@
+//! Timings of the stages of the variant pipeline on synthetic graphs,
+//! run with `cargo bench`. Each stage is repeated a few times and the
+//! fastest and median times are printed, so changes can be compared
+//! by running the benchmark before and after them.
+//!
+//! The graph size can be changed with the `BENCH_BUBBLES` and
+//! `BENCH_PATHS` environment variables.
+//!
+//! Loading saved ultrabubbles, in the text and binary formats, is
+//! timed on a separate, larger, set, whose size is set with
+//! `BENCH_SAVED_BUBBLES`.
+//!
+//! Parsing a GFA file is timed on a larger synthetic graph, written
+//! to a temporary file, with `BENCH_PARSE_BUBBLES` bubbles. Builds
+//! with the `mmap` feature also time reading it through a memory map,
+//! e.g. with `cargo bench --features mmap`.
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    time::{Duration, Instant},
+};
+
+use fnv::FnvHashSet;
+
+use gfautil::{
+    commands::saboten,
+    synthetic::SyntheticGraph,
+    variants::{self, PathOffsetIndex, VariantConfig},
+};
+
+const RUNS: usize = 5;
+
+fn env_or(var: &str, default: usize) -> usize {
+    std::env::var(var)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Run `f` `RUNS` times, printing the fastest and median times, and
+/// return the result of the last run.
+fn bench<T, F: FnMut() -> T>(name: &str, mut f: F) -> T {
+    let mut times: Vec<Duration> = Vec::with_capacity(RUNS);
+    let mut result = None;
+    for _ in 0..RUNS {
+        let t = Instant::now();
+        result = Some(f());
+        times.push(t.elapsed());
+    }
+    times.sort_unstable();
+    println!(
+        "{:<32} {:>10.3} ms {:>10.3} ms",
+        name,
+        times[0].as_secs_f64() * 1000.0,
+        times[RUNS / 2].as_secs_f64() * 1000.0
+    );
+    result.unwrap()
+}
+
+fn main() {
+    // `cargo bench` passes `--bench`; other arguments are ignored
+    let params = SyntheticGraph {
+        bubbles: env_or("BENCH_BUBBLES", 5000),
+        paths: env_or("BENCH_PATHS", 32),
+        ..SyntheticGraph::default()
+    };
+    println!(
+        "{} bubbles, {} segments, {} paths",
+        params.bubbles,
+        params.segment_count(),
+        params.paths
+    );
+    println!("{:<32} {:>13} {:>13}", "stage", "fastest", "median");
+
+    let gfa = params.gfa();
+    let ultrabubbles = params.ultrabubbles();
+
+    bench("borrowed_segment_map", || {
+        variants::borrowed_segment_map(&gfa, Default::default())
+            .unwrap()
+            .len()
+    });
+
+    // Extracting the paths consumes the GFA, so this includes a clone
+    let path_data = bench("gfa_path_data", || {
+        variants::gfa_path_data(gfa.clone(), false, Default::default()).unwrap()
+    });
+
+    let nodes: FnvHashSet<u64> = ultrabubbles
+        .iter()
+        .flat_map(|&(from, to)| vec![from, to])
+        .collect();
+    let path_indices = bench("bubble_path_indices", || {
+        variants::bubble_path_indices(&path_data.paths, &nodes)
+    });
+
+    // Filtering the bubbles to 100 regions along the first path, by
+    // the span of each bubble, and by the steps the offset index finds
+    // for the region
+    let ref_length = path_data.path_length(0);
+    let regions: Vec<(usize, usize)> = (0..100)
+        .map(|ix| {
+            let start = ix * ref_length / 100 + 1;
+            (start, start + ref_length / 1000)
+        })
+        .collect();
+    let by_span = bench("region filter (bubble spans)", || {
+        regions
+            .iter()
+            .map(|&(start, end)| {
+                ultrabubbles
+                    .iter()
+                    .filter(|&&(from, to)| {
+                        matches!(
+                            variants::bubble_path_range(
+                                &path_data,
+                                &path_indices,
+                                0,
+                                from,
+                                to
+                            ),
+                            Some((s, e)) if s <= end && e >= start
+                        )
+                    })
+                    .count()
+            })
+            .sum::<usize>()
+    });
+    let by_index = bench("region filter (PathOffsetIndex)", || {
+        let index = PathOffsetIndex::new(&path_data, 0);
+        let step_ix = |node: u64| path_indices.get(&node)?.get(&0).copied();
+        regions
+            .iter()
+            .filter_map(|&(start, end)| index.steps_in_range(start, end))
+            .map(|(first, last)| {
+                ultrabubbles
+                    .iter()
+                    .filter(|&&(from, to)| {
+                        matches!(
+                            (step_ix(from), step_ix(to)),
+                            (Some(f), Some(t)) if f.min(t) <= last
+                                && f.max(t) >= first
+                        )
+                    })
+                    .count()
+            })
+            .sum::<usize>()
+    });
+    assert_eq!(by_span, by_index);
+
+    let ref_paths: FnvHashSet<_> =
+        std::iter::once(path_data.path_names[0].clone()).collect();
+    let config = VariantConfig::default();
+    bench("detect_variants_in_sub_paths", || {
+        ultrabubbles
+            .iter()
+            .filter_map(|&(from, to)| {
+                variants::detect_variants_in_sub_paths(
+                    &config,
+                    &path_data,
+                    Some(&ref_paths),
+                    &path_indices,
+                    from,
+                    to,
+                )
+            })
+            .count()
+    });
+
+    bench_saved_ultrabubbles(env_or("BENCH_SAVED_BUBBLES", 1_000_000));
+    bench_gfa_parse(env_or("BENCH_PARSE_BUBBLES", 200_000));
+}
+
+/// Time loading `count` ultrabubbles from a text and a binary file.
+fn bench_saved_ultrabubbles(count: usize) {
+    let ultrabubbles: Vec<(u64, u64)> = (0..count as u64)
+        .map(|ix| (2 * ix + 1, 2 * ix + 3))
+        .collect();
+
+    let dir = std::env::temp_dir();
+    let pid = std::process::id();
+    let text = dir.join(format!("gfautil_bench_{}.ultrabubbles", pid));
+    let binary = dir.join(format!("gfautil_bench_{}.ubz", pid));
+    saboten::save_ultrabubbles(&text, &ultrabubbles).unwrap();
+    saboten::save_ultrabubbles(&binary, &ultrabubbles).unwrap();
+
+    println!();
+    println!("{} saved ultrabubbles", count);
+    bench("load_ultrabubbles (text)", || {
+        saboten::load_ultrabubbles(&text).unwrap().len()
+    });
+    bench("load_ultrabubbles (binary)", || {
+        saboten::load_ultrabubbles(&binary).unwrap().len()
+    });
+
+    std::fs::remove_file(&text).unwrap();
+    std::fs::remove_file(&binary).unwrap();
+}
+
+/// Time loading the path data of a synthetic GFA file with `bubbles`
+/// bubbles, with buffered reading, and, with the `mmap` feature,
+/// through a memory map.
+fn bench_gfa_parse(bubbles: usize) {
+    let params = SyntheticGraph {
+        bubbles,
+        paths: 4,
+        ..SyntheticGraph::default()
+    };
+
+    let path = std::env::temp_dir()
+        .join(format!("gfautil_bench_{}.gfa", std::process::id()));
+    {
+        let mut writer = BufWriter::new(File::create(&path).unwrap());
+        for line in params.lines() {
+            writeln!(writer, "{}", line).unwrap();
+        }
+    }
+    let size = std::fs::metadata(&path).unwrap().len();
+
+    println!();
+    println!(
+        "{} bubbles, {} segments, {:.1} MB GFA",
+        params.bubbles,
+        params.segment_count(),
+        size as f64 / 1e6
+    );
+    let load = || {
+        variants::load_path_data(&path, false, Default::default())
+            .unwrap()
+            .paths
+            .len()
+    };
+    bench("load_path_data (buffered)", load);
+
+    #[cfg(feature = "mmap")]
+    {
+        gfautil::commands::set_use_mmap(true);
+        bench("load_path_data (mmap)", load);
+        gfautil::commands::set_use_mmap(false);
+    }
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,350 @@
+//! A minimal writer for the BGZF format used by `bgzip` and `tabix`.
+//!
+//! BGZF is a series of concatenated gzip members, each holding at
+//! most 64KiB of uncompressed data and recording its own compressed
+//! size in a `BC` extra field, followed by an empty end-of-file
+//! member. Each block is compressed using DEFLATE with the fixed
+//! Huffman codes and a simple hash chain LZ77 matcher, falling back
+//! to a stored block if that doesn't make the block smaller.
+
+use std::io::{self, Write};
+
+use crate::gzip::{crc32, DIST_BASE, DIST_EXTRA, LENGTH_BASE, LENGTH_EXTRA};
+
+/// The maximum number of uncompressed bytes in a single block, the
+/// same as used by htslib.
+pub const MAX_BLOCK_DATA: usize = 0xff00;
+
+const HEADER_LEN: usize = 18;
+const FOOTER_LEN: usize = 8;
+
+/// The empty block that marks the end of a BGZF file.
+pub const EOF_BLOCK: [u8; 28] = [
+    0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00,
+    0x42, 0x43, 0x02, 0x00, 0x1b, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+    0x00, 0x00, 0x00, 0x00,
+];
+
+/// Wraps a writer, compressing everything written to it into BGZF
+/// blocks. The end-of-file block is written by `finish`, or when the
+/// writer is dropped.
+pub struct BgzfWriter<W: Write> {
+    inner: Option<W>,
+    buf: Vec<u8>,
+    block: Vec<u8>,
+    position: u64,
+}
+
+impl<W: Write> BgzfWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner: Some(inner),
+            buf: Vec::with_capacity(MAX_BLOCK_DATA),
+            block: Vec::with_capacity(MAX_BLOCK_DATA + HEADER_LEN + FOOTER_LEN),
+            position: 0,
+        }
+    }
+
+    /// The number of compressed bytes written so far, i.e. the file
+    /// offset of the block that currently buffered data will end up
+    /// in.
+    pub fn block_offset(&self) -> u64 {
+        self.position
+    }
+
+    /// The offset of the next byte within the current, not yet
+    /// written, block.
+    pub fn block_data_offset(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// The BGZF virtual offset of the next byte to be written.
+    pub fn virtual_position(&self) -> u64 {
+        (self.position << 16) | self.buf.len() as u64
+    }
+
+    fn write_block(&mut self) -> io::Result<()> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+
+        let inner = self.inner.as_mut().unwrap();
+
+        self.block.clear();
+        compress_block(&self.buf, &mut self.block);
+        inner.write_all(&self.block)?;
+
+        self.position += self.block.len() as u64;
+        self.buf.clear();
+        Ok(())
+    }
+
+    /// Write any buffered data and the end-of-file block, returning
+    /// the wrapped writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        if self.inner.is_none() {
+            return Ok(());
+        }
+        self.write_block()?;
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&EOF_BLOCK)?;
+        inner.flush()?;
+        self.position += EOF_BLOCK.len() as u64;
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for BgzfWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        let len = data.len().min(MAX_BLOCK_DATA - self.buf.len());
+        self.buf.extend_from_slice(&data[..len]);
+        if self.buf.len() >= MAX_BLOCK_DATA {
+            self.write_block()?;
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for BgzfWriter<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// Compress `data` into a single BGZF block, appended to `out`.
+pub fn compress_block(data: &[u8], out: &mut Vec<u8>) {
+    assert!(data.len() <= MAX_BLOCK_DATA);
+
+    let start = out.len();
+    out.extend_from_slice(&[
+        0x1f, 0x8b, 0x08, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0x06, 0x00,
+        b'B', b'C', 0x02, 0x00, 0x00, 0x00,
+    ]);
+
+    let mut compressed = deflate_fixed(data);
+    if compressed.len() >= data.len() + 5 {
+        compressed = deflate_stored(data);
+    }
+    out.extend_from_slice(&compressed);
+
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+
+    let bsize = (out.len() - start - 1) as u16;
+    out[start + 16..start + 18].copy_from_slice(&bsize.to_le_bytes());
+}
+
+fn deflate_stored(data: &[u8]) -> Vec<u8> {
+    let len = data.len() as u16;
+    let mut out = Vec::with_capacity(data.len() + 5);
+    out.push(0x01);
+    out.extend_from_slice(&len.to_le_bytes());
+    out.extend_from_slice(&(!len).to_le_bytes());
+    out.extend_from_slice(data);
+    out
+}
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW_SIZE: usize = 32768;
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: usize = 15;
+
+struct BitWriter {
+    out: Vec<u8>,
+    bits: u32,
+    len: u32,
+}
+
+impl BitWriter {
+    fn new(capacity: usize) -> Self {
+        Self {
+            out: Vec::with_capacity(capacity),
+            bits: 0,
+            len: 0,
+        }
+    }
+
+    /// Write the `len` lowest bits of `value`, least significant bit
+    /// first.
+    fn write_bits(&mut self, value: u32, len: u32) {
+        self.bits |= value << self.len;
+        self.len += len;
+        while self.len >= 8 {
+            self.out.push(self.bits as u8);
+            self.bits >>= 8;
+            self.len -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is stored most significant bit
+    /// first.
+    fn write_code(&mut self, code: u32, len: u32) {
+        let reversed = code.reverse_bits() >> (32 - len);
+        self.write_bits(reversed, len);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.out.push(self.bits as u8);
+        }
+        self.out
+    }
+}
+
+fn write_literal(writer: &mut BitWriter, symbol: u16) {
+    let symbol = symbol as u32;
+    match symbol {
+        0..=143 => writer.write_code(0x30 + symbol, 8),
+        144..=255 => writer.write_code(0x190 + symbol - 144, 9),
+        256..=279 => writer.write_code(symbol - 256, 7),
+        _ => writer.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(writer: &mut BitWriter, length: usize, distance: usize) {
+    let len_ix = LENGTH_BASE.partition_point(|&b| b as usize <= length) - 1;
+    write_literal(writer, 257 + len_ix as u16);
+    writer.write_bits(
+        (length - LENGTH_BASE[len_ix] as usize) as u32,
+        LENGTH_EXTRA[len_ix] as u32,
+    );
+
+    let dist_ix = DIST_BASE.partition_point(|&b| b as usize <= distance) - 1;
+    writer.write_code(dist_ix as u32, 5);
+    writer.write_bits(
+        (distance - DIST_BASE[dist_ix] as usize) as u32,
+        DIST_EXTRA[dist_ix] as u32,
+    );
+}
+
+fn hash3(data: &[u8]) -> usize {
+    let v = (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32;
+    (v.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn insert_hash(
+    data: &[u8],
+    pos: usize,
+    head: &mut [usize],
+    prev: &mut [usize],
+) {
+    if pos + MIN_MATCH <= data.len() {
+        let h = hash3(&data[pos..]);
+        prev[pos] = head[h];
+        head[h] = pos;
+    }
+}
+
+/// Compress `data` as a single final DEFLATE block using the fixed
+/// Huffman codes.
+fn deflate_fixed(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new(data.len() / 2);
+    // BFINAL = 1, BTYPE = 01
+    writer.write_bits(0b011, 3);
+
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+
+    let mut pos = 0;
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if pos + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut candidate = head[hash3(&data[pos..])];
+            let mut chain = 0;
+            while candidate != usize::MAX
+                && pos - candidate <= WINDOW_SIZE
+                && chain < MAX_CHAIN
+            {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            write_match(&mut writer, best_len, best_dist);
+            for p in pos..pos + best_len {
+                insert_hash(data, p, &mut head, &mut prev);
+            }
+            pos += best_len;
+        } else {
+            write_literal(&mut writer, data[pos] as u16);
+            insert_hash(data, pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    write_literal(&mut writer, 256);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_writer_is_eof_block() {
+        let writer = BgzfWriter::new(Vec::new());
+        let bytes = writer.finish().unwrap();
+        assert_eq!(&EOF_BLOCK[..], bytes.as_slice());
+    }
+
+    #[test]
+    fn block_size_field_matches_block() {
+        let data = b"chr1\t100\t.\tA\tC\t.\tPASS\t.\n".repeat(4000);
+
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        let bytes = writer.finish().unwrap();
+
+        let mut offset = 0;
+        let mut total = 0;
+        while offset < bytes.len() {
+            let block = &bytes[offset..];
+            assert_eq!(&[0x1f, 0x8b, 0x08, 0x04], &block[..4]);
+            let bsize = u16::from_le_bytes([block[16], block[17]]) as usize;
+            let block_len = bsize + 1;
+            let isize = u32::from_le_bytes([
+                block[block_len - 4],
+                block[block_len - 3],
+                block[block_len - 2],
+                block[block_len - 1],
+            ]) as usize;
+            assert!(isize <= MAX_BLOCK_DATA);
+            total += isize;
+            offset += block_len;
+        }
+
+        assert_eq!(bytes.len(), offset);
+        assert_eq!(data.len(), total);
+        assert!(bytes.ends_with(&EOF_BLOCK));
+        assert!(bytes.len() < data.len() / 4);
+    }
+}
@@ -0,0 +1,369 @@
+use fnv::{FnvHashMap, FnvHashSet};
+use gfa::gfa::{Orientation, GFA};
+use saboten::{
+    biedgedgraph::BiedgedGraph,
+    cactusgraph,
+    cactusgraph::{BridgeForest, CactusGraph, CactusTree},
+};
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+/// A way of finding the bubbles in a graph.
+pub trait BubbleFinder {
+    /// Find the bubbles in the graph, as pairs of the segment IDs at
+    /// their ends, sorted by start, then end, and without duplicates.
+    fn find(&self, gfa: &GFA<usize, ()>) -> Vec<(u64, u64)>;
+}
+
+/// The available bubble finders, as chosen with `--bubble-algo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BubbleAlgorithm {
+    Saboten,
+    Bcc,
+}
+
+impl std::str::FromStr for BubbleAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "saboten" => Ok(BubbleAlgorithm::Saboten),
+            "bcc" => Ok(BubbleAlgorithm::Bcc),
+            _ => Err(format!("Unknown bubble algorithm: {}", s)),
+        }
+    }
+}
+
+impl BubbleAlgorithm {
+    pub fn finder(self) -> Box<dyn BubbleFinder> {
+        match self {
+            BubbleAlgorithm::Saboten => Box::new(Saboten),
+            BubbleAlgorithm::Bcc => Box::new(Bcc),
+        }
+    }
+}
+
+fn sorted_bubbles(mut bubbles: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    bubbles.sort_unstable();
+    bubbles.dedup();
+    bubbles
+}
+
+/// Find the ultrabubbles using saboten's cactus graph decomposition.
+pub struct Saboten;
+
+impl BubbleFinder for Saboten {
+    fn find(&self, gfa: &GFA<usize, ()>) -> Vec<(u64, u64)> {
+        debug!("Building biedged graph");
+        let t = std::time::Instant::now();
+        let be_graph = BiedgedGraph::from_gfa(gfa);
+        debug!(
+            "  biedged graph took {:.3} ms",
+            t.elapsed().as_secs_f64() * 1000.0
+        );
+        debug!("");
+
+        debug!("Building cactus graph");
+        let t = std::time::Instant::now();
+        let cactus_graph = CactusGraph::from_biedged_graph(&be_graph);
+        debug!(
+            "  cactus graph took {:.3} ms",
+            t.elapsed().as_secs_f64() * 1000.0
+        );
+        debug!("");
+
+        debug!("Building cactus tree");
+        let t = std::time::Instant::now();
+        let cactus_tree = CactusTree::from_cactus_graph(&cactus_graph);
+        debug!(
+            "  cactus tree took {:.3} ms",
+            t.elapsed().as_secs_f64() * 1000.0
+        );
+        debug!("");
+
+        debug!("Building bridge forest");
+        let t = std::time::Instant::now();
+        let bridge_forest = BridgeForest::from_cactus_graph(&cactus_graph);
+        debug!(
+            "  bridge forest took {:.3} ms",
+            t.elapsed().as_secs_f64() * 1000.0
+        );
+        debug!("");
+
+        debug!("Finding ultrabubbles");
+        let t = std::time::Instant::now();
+        let ultrabubbles =
+            cactusgraph::find_ultrabubbles(&cactus_tree, &bridge_forest);
+        debug!(
+            "  ultrabubbles took {:.3} ms",
+            t.elapsed().as_secs_f64() * 1000.0
+        );
+        debug!("");
+
+        let t = std::time::Instant::now();
+        let ultrabubbles = cactusgraph::inverse_map_ultrabubbles(ultrabubbles);
+        debug!(
+            "  inverting ultrabubbles took {:.3} ms",
+            t.elapsed().as_secs_f64() * 1000.0
+        );
+
+        sorted_bubbles(ultrabubbles.into_keys().collect())
+    }
+}
+
+/// Find bubbles using the biconnected components of the graph, with
+/// the segment orientations ignored. Each biconnected component that
+/// contains a cycle, and is attached to the rest of the graph through
+/// exactly two cut vertices, is reported as a bubble between those
+/// two segments, with the smaller ID first. Segments with no links on
+/// one side count as attached to the outside of the graph, so bubbles
+/// at the ends of a chain are found too.
+///
+/// This is simpler and coarser than the cactus graph decomposition:
+/// bubbles nested inside another bubble aren't reported, and neither
+/// are components bounded by more than two cut vertices.
+pub struct Bcc;
+
+impl BubbleFinder for Bcc {
+    fn find(&self, gfa: &GFA<usize, ()>) -> Vec<(u64, u64)> {
+        let ids: Vec<usize> = gfa.segments.iter().map(|s| s.name).collect();
+        let index: FnvHashMap<usize, usize> =
+            ids.iter().enumerate().map(|(ix, &id)| (id, ix)).collect();
+
+        let mut adjacency: Vec<Vec<usize>> = vec![Vec::new(); ids.len()];
+        let mut edges: FnvHashSet<(usize, usize)> = FnvHashSet::default();
+        // Whether each segment has links on its left and right sides
+        let mut linked_sides: Vec<[bool; 2]> = vec![[false; 2]; ids.len()];
+
+        for link in gfa.links.iter() {
+            let from = index.get(&link.from_segment);
+            let to = index.get(&link.to_segment);
+            let (from, to) = match (from, to) {
+                (Some(&from), Some(&to)) => (from, to),
+                _ => continue,
+            };
+
+            let from_side = (link.from_orient == Orientation::Forward) as usize;
+            let to_side = (link.to_orient == Orientation::Backward) as usize;
+            linked_sides[from][from_side] = true;
+            linked_sides[to][to_side] = true;
+
+            let edge = (from.min(to), from.max(to));
+            if from != to && edges.insert(edge) {
+                adjacency[from].push(to);
+                adjacency[to].push(from);
+            }
+        }
+
+        // Give each tip its own pendant vertex, standing in for the
+        // outside of the graph, which makes the tip a cut vertex
+        for (ix, sides) in linked_sides.iter().enumerate() {
+            if sides.contains(&false) && !adjacency[ix].is_empty() {
+                let pendant = adjacency.len();
+                adjacency.push(vec![ix]);
+                adjacency[ix].push(pendant);
+            }
+        }
+
+        let components = biconnected_components(&adjacency);
+
+        let mut memberships = vec![0usize; adjacency.len()];
+        let component_vertices: Vec<Vec<usize>> = components
+            .iter()
+            .map(|component| {
+                let mut vertices: Vec<usize> =
+                    component.iter().flat_map(|&(a, b)| vec![a, b]).collect();
+                vertices.sort_unstable();
+                vertices.dedup();
+                for &v in vertices.iter() {
+                    memberships[v] += 1;
+                }
+                vertices
+            })
+            .collect();
+
+        let bubbles = components
+            .iter()
+            .zip(component_vertices)
+            .filter(|(component, _)| component.len() > 1)
+            .filter_map(|(_, vertices)| {
+                let cut: Vec<usize> = vertices
+                    .into_iter()
+                    .filter(|&v| memberships[v] > 1)
+                    .collect();
+                match cut[..] {
+                    [a, b] if a < ids.len() && b < ids.len() => {
+                        let (a, b) = (ids[a] as u64, ids[b] as u64);
+                        Some((a.min(b), a.max(b)))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+
+        sorted_bubbles(bubbles)
+    }
+}
+
+/// Find the biconnected components of a simple undirected graph, as
+/// lists of edges, using an iterative version of Tarjan's algorithm
+/// so deep graphs don't overflow the stack. Every edge ends up in
+/// exactly one component; bridges are components with one edge.
+fn biconnected_components(
+    adjacency: &[Vec<usize>],
+) -> Vec<Vec<(usize, usize)>> {
+    let mut discovered = vec![0usize; adjacency.len()];
+    let mut low = vec![0usize; adjacency.len()];
+    let mut time = 0;
+
+    let mut components = Vec::new();
+    let mut edge_stack: Vec<(usize, usize)> = Vec::new();
+    // Each frame holds a vertex, its parent, and the index of the
+    // next neighbor to visit
+    let mut stack: Vec<(usize, usize, usize)> = Vec::new();
+
+    for root in 0..adjacency.len() {
+        if discovered[root] != 0 {
+            continue;
+        }
+        time += 1;
+        discovered[root] = time;
+        low[root] = time;
+        stack.push((root, usize::MAX, 0));
+
+        while let Some(frame) = stack.last_mut() {
+            let (v, parent, next) = *frame;
+            if let Some(&w) = adjacency[v].get(next) {
+                frame.2 += 1;
+                if discovered[w] == 0 {
+                    edge_stack.push((v, w));
+                    time += 1;
+                    discovered[w] = time;
+                    low[w] = time;
+                    stack.push((w, v, 0));
+                } else if w != parent && discovered[w] < discovered[v] {
+                    edge_stack.push((v, w));
+                    low[v] = low[v].min(discovered[w]);
+                }
+                continue;
+            }
+
+            stack.pop();
+            if let Some(&(u, _, _)) = stack.last() {
+                low[u] = low[u].min(low[v]);
+                if low[v] >= discovered[u] {
+                    let mut component = Vec::new();
+                    while let Some(edge) = edge_stack.pop() {
+                        component.push(edge);
+                        if edge == (u, v) {
+                            break;
+                        }
+                    }
+                    components.push(component);
+                }
+            }
+        }
+    }
+
+    components
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gfa::parser::GFAParser;
+
+    fn gfa(lines: &[&str]) -> GFA<usize, ()> {
+        let parser = GFAParser::new();
+        parser
+            .parse_lines(lines.iter().map(|l| l.as_bytes()))
+            .unwrap()
+    }
+
+    #[test]
+    fn bcc_bubbles_in_chain() {
+        // 1 -> {2, 3} -> 4 -> {5, 6} -> 7 -> 8, with a deletion 4 -> 7
+        let gfa = gfa(&[
+            "S\t1\tA",
+            "S\t2\tC",
+            "S\t3\tG",
+            "S\t4\tT",
+            "S\t5\tA",
+            "S\t6\tC",
+            "S\t7\tG",
+            "S\t8\tT",
+            "L\t1\t+\t2\t+\t0M",
+            "L\t1\t+\t3\t+\t0M",
+            "L\t2\t+\t4\t+\t0M",
+            "L\t3\t+\t4\t+\t0M",
+            "L\t4\t+\t5\t+\t0M",
+            "L\t4\t+\t6\t+\t0M",
+            "L\t4\t+\t7\t+\t0M",
+            "L\t5\t+\t7\t+\t0M",
+            "L\t7\t-\t6\t-\t0M",
+            "L\t7\t+\t8\t+\t0M",
+        ]);
+
+        assert_eq!(vec![(1, 4), (4, 7)], Bcc.find(&gfa));
+    }
+
+    #[test]
+    fn bcc_skips_components_with_more_ends() {
+        // The cycle 1, 2, 3 has a branch leading off from each segment
+        let gfa = gfa(&[
+            "S\t1\tA",
+            "S\t2\tC",
+            "S\t3\tG",
+            "S\t4\tT",
+            "S\t5\tA",
+            "S\t6\tC",
+            "L\t1\t+\t2\t+\t0M",
+            "L\t2\t+\t3\t+\t0M",
+            "L\t3\t+\t1\t+\t0M",
+            "L\t1\t-\t4\t+\t0M",
+            "L\t2\t-\t5\t+\t0M",
+            "L\t3\t-\t6\t+\t0M",
+        ]);
+
+        assert!(Bcc.find(&gfa).is_empty());
+    }
+
+    #[test]
+    fn biconnected_components_of_graph() {
+        // A triangle 0, 1, 2 joined to a square 2, 3, 4, 5 at 2, and a
+        // bridge from 5 to 6
+        let adjacency = vec![
+            vec![1, 2],
+            vec![0, 2],
+            vec![0, 1, 3, 5],
+            vec![2, 4],
+            vec![3, 5],
+            vec![4, 2, 6],
+            vec![5],
+        ];
+
+        let mut components: Vec<Vec<(usize, usize)>> =
+            biconnected_components(&adjacency)
+                .into_iter()
+                .map(|mut component| {
+                    for edge in component.iter_mut() {
+                        *edge = (edge.0.min(edge.1), edge.0.max(edge.1));
+                    }
+                    component.sort_unstable();
+                    component
+                })
+                .collect();
+        components.sort_unstable();
+
+        assert_eq!(
+            vec![
+                vec![(0, 1), (0, 2), (1, 2)],
+                vec![(2, 3), (2, 5), (3, 4), (4, 5)],
+                vec![(5, 6)],
+            ],
+            components
+        );
+    }
+}
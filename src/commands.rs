@@ -1,18 +1,35 @@
 pub mod convert_names;
 pub mod gaf2paf;
 pub mod gfa2vcf;
+pub mod merge_vcf;
+pub mod path_diff;
+pub mod paths2fasta;
 pub mod saboten;
 pub mod snps;
 pub mod stats;
 pub mod subgraph;
+pub mod validate;
 
-use std::io::{BufReader, Read};
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+#[cfg(feature = "mmap")]
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use bstr::io::*;
+use bstr::{io::*, ByteSlice};
 use gfa::{
-    gfa::{SegmentId, GFA},
+    gfa::{Line, SegmentId, GFA},
     optfields::OptFields,
-    parser::GFAParser,
+    parser::{error::ParserTolerance, GFAParser, ParseError, ParseFieldError},
+};
+
+use crate::{
+    error::GfaUtilsError,
+    gfa2::{self, GFAVersion},
+    gzip,
 };
 
 #[allow(unused_imports)]
@@ -20,13 +37,280 @@ use log::{debug, info, warn};
 
 pub type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+/// Iterate the lines of `reader`, without their line endings. Like
+/// the other line readers in the crate, `\r\n` line endings are
+/// removed too, including a `\r` at the end of a file without a final
+/// newline, so files written on Windows can be read.
 pub fn byte_lines_iter<'a, R: Read + 'a>(
     reader: R,
 ) -> Box<dyn Iterator<Item = Vec<u8>> + 'a> {
-    Box::new(BufReader::new(reader).byte_lines().map(|l| l.unwrap()))
+    Box::new(BufReader::new(reader).byte_lines().map(|l| {
+        let mut line = l.unwrap();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        line
+    }))
+}
+
+/// Open a GFA file for reading, decompressing it if it's gzipped.
+fn open_gfa_reader(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    if gzip::is_gzip_file(path)? {
+        debug!("Decompressing gzipped GFA");
+        Ok(Box::new(BufReader::new(gzip::GzDecoder::new(file))))
+    } else {
+        Ok(Box::new(BufReader::new(file)))
+    }
+}
+
+#[cfg(feature = "mmap")]
+static USE_MMAP: AtomicBool = AtomicBool::new(false);
+
+/// Read uncompressed GFA1 files through a memory map, instead of a
+/// buffered reader, which avoids copying every line of large files.
+#[cfg(feature = "mmap")]
+pub fn set_use_mmap(use_mmap: bool) {
+    USE_MMAP.store(use_mmap, Ordering::Relaxed);
+}
+
+/// Map the GFA file into memory, if enabled with `set_use_mmap`.
+/// Returns `None` if the file is gzipped or can't be mapped, e.g.
+/// because it's a pipe, in which case it should be read normally.
+#[cfg(feature = "mmap")]
+fn map_gfa_file(path: &Path) -> Option<memmap2::Mmap> {
+    if !USE_MMAP.load(Ordering::Relaxed) || gzip::is_gzip_file(path).ok()? {
+        return None;
+    }
+    let file = File::open(path).ok()?;
+    // The file must not be changed while it's mapped
+    match unsafe { memmap2::Mmap::map(&file) } {
+        Ok(mmap) => {
+            debug!("Reading memory mapped GFA");
+            Some(mmap)
+        }
+        Err(err) => {
+            warn!(
+                "Could not memory map {}, reading it normally: {}",
+                path.display(),
+                err
+            );
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "mmap"))]
+fn map_gfa_file(_path: &Path) -> Option<Vec<u8>> {
+    None
+}
+
+/// Find the first segment name in a GFA line that isn't an unsigned
+/// integer.
+fn non_integer_segment(line: &str) -> Option<&str> {
+    let is_id = |name: &str| name.parse::<u64>().is_ok();
+    let fields: Vec<&str> = line.trim().split('\t').collect();
+    match *fields.first()? {
+        "S" => fields.get(1).copied().filter(|name| !is_id(name)),
+        "L" | "C" => [fields.get(1)?, fields.get(3)?]
+            .iter()
+            .copied()
+            .find(|name| !is_id(name))
+            .copied(),
+        "P" => fields.get(2)?.split(',').find_map(|step| {
+            let name = step.get(..step.len().checked_sub(1)?)?;
+            if is_id(name) {
+                None
+            } else {
+                Some(name)
+            }
+        }),
+        _ => None,
+    }
+}
+
+/// Replace the parser's error for non-integer segment names with one
+/// that explains what's wrong and names the segment.
+fn parse_error(err: ParseError) -> GfaUtilsError {
+    match err {
+        ParseError::InvalidLine(ParseFieldError::UintIdError, line) => {
+            let segment = match non_integer_segment(&line) {
+                Some(name) => format!("segment name `{}`", name),
+                None => format!("line `{}`", line.trim()),
+            };
+            GfaUtilsError::GfaParse(format!(
+                "Only integer segment IDs are currently supported, but the \
+                 GFA contains the {}. The `id-convert` command can be used \
+                 to convert the segment names to integers.",
+                segment
+            ))
+        }
+        err => err.into(),
+    }
+}
+
+/// Translate a GFA 1.1 walk line into a path line, so walks can be
+/// used everywhere paths are. The path is named
+/// `sample#haplotype#contig`, following the PanSN convention, with
+/// `:start-end` appended if the walk doesn't start at the beginning
+/// of the contig, so the walks of a fragmented assembly get distinct
+/// names. Any optional fields are kept.
+fn walk_to_path_line(line: &[u8]) -> crate::error::Result<Vec<u8>> {
+    let invalid = || {
+        GfaUtilsError::GfaParse(format!(
+            "Invalid walk line: {}",
+            line.as_bstr()
+        ))
+    };
+
+    let fields: Vec<&[u8]> = line.split_str("\t").collect();
+    if fields.len() < 7 {
+        return Err(invalid());
+    }
+    let (start, end) = (fields[4], fields[5]);
+
+    let mut name: Vec<u8> = bstr::join("#", &fields[1..4]);
+    if start != b"*" && start != b"0" {
+        name.extend_from_slice(
+            format!(":{}-{}", start.as_bstr(), end.as_bstr()).as_bytes(),
+        );
+    }
+
+    // Each step is an orientation, `>` or `<`, followed by a segment
+    let mut steps: Vec<(u8, Vec<u8>)> = Vec::new();
+    for &c in fields[6].iter() {
+        match c {
+            b'>' => steps.push((b'+', Vec::new())),
+            b'<' => steps.push((b'-', Vec::new())),
+            _ => match steps.last_mut() {
+                Some((_, segment)) => segment.push(c),
+                None => return Err(invalid()),
+            },
+        }
+    }
+    if steps.is_empty() || steps.iter().any(|(_, seg)| seg.is_empty()) {
+        return Err(invalid());
+    }
+
+    let segments: Vec<Vec<u8>> = steps
+        .into_iter()
+        .map(|(orient, mut segment)| {
+            segment.push(orient);
+            segment
+        })
+        .collect();
+
+    let mut path_line = b"P\t".to_vec();
+    path_line.extend_from_slice(&name);
+    path_line.push(b'\t');
+    path_line.extend(bstr::join(",", &segments));
+    path_line.extend_from_slice(b"\t*");
+    for field in fields[7..].iter() {
+        path_line.push(b'\t');
+        path_line.extend_from_slice(field);
+    }
+    Ok(path_line)
 }
 
-pub fn load_gfa<N, T, P>(path: P) -> Result<GFA<N, T>>
+/// Parse a GFA file using the given parser, transparently
+/// decompressing it if it's gzipped, and translating it to GFA1 if
+/// it's a GFA2 file. Walks (W lines) are loaded as paths; see
+/// `walk_to_path_line`.
+pub fn parse_gfa_file<N, T, P>(
+    parser: &GFAParser<N, T>,
+    path: P,
+) -> crate::error::Result<GFA<N, T>>
+where
+    N: SegmentId,
+    T: OptFields,
+    P: AsRef<Path>,
+{
+    let mut gfa = GFA::new();
+    for_each_gfa_line(parser, path, |line| gfa.insert_line(line))?;
+    Ok(gfa)
+}
+
+/// Parse a GFA file line by line, passing each line of a type the
+/// parser includes to `f`, without building a `GFA`. Like
+/// `parse_gfa_file`, gzipped and GFA2 files are supported, though
+/// GFA2 files are read into memory in full before being translated.
+pub fn for_each_gfa_line<N, T, P, F>(
+    parser: &GFAParser<N, T>,
+    path: P,
+    mut f: F,
+) -> crate::error::Result<()>
+where
+    N: SegmentId,
+    T: OptFields,
+    P: AsRef<Path>,
+    F: FnMut(Line<N, T>),
+{
+    let path = path.as_ref();
+
+    let version = {
+        let lines = open_gfa_reader(path)?.byte_lines();
+        gfa2::detect_version(lines.map_while(|l| l.ok()))
+    };
+
+    let mut lines = open_gfa_reader(path)?.byte_lines().map(|line| {
+        line.map_err(|err| {
+            let msg = format!("Error reading {}: {}", path.display(), err);
+            io::Error::new(err.kind(), msg)
+        })
+    });
+
+    let mut parse_line = |line: &[u8]| -> crate::error::Result<()> {
+        // The line iterators remove `\r\n` line endings, but not a
+        // `\r` at the end of a file without a final newline
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let walk_path;
+        let line = if line.starts_with(b"W\t") {
+            walk_path = walk_to_path_line(line)?;
+            &walk_path
+        } else {
+            line
+        };
+        match parser.parse_gfa_line_filtered(line) {
+            Ok(Some(parsed)) => f(parsed),
+            Ok(None) => (),
+            Err(err) if err.can_safely_continue(&ParserTolerance::Safe) => (),
+            Err(err) => return Err(parse_error(err)),
+        }
+        Ok(())
+    };
+
+    match version {
+        GFAVersion::GFA1 => {
+            if let Some(mmap) = map_gfa_file(path) {
+                for line in ByteSlice::lines(&mmap[..]) {
+                    parse_line(line)?;
+                }
+                return Ok(());
+            }
+            for line in lines {
+                parse_line(&line?)?;
+            }
+        }
+        GFAVersion::GFA2 => {
+            info!("Translating GFA2 to GFA1");
+            let lines = lines.try_fold(Vec::new(), |mut lines, line| {
+                let mut line = line?;
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                lines.push(line);
+                Ok::<_, io::Error>(lines)
+            })?;
+            for line in gfa2::gfa2_to_gfa1_lines(&lines) {
+                parse_line(&line)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn load_gfa<N, T, P>(path: P) -> crate::error::Result<GFA<N, T>>
 where
     N: SegmentId,
     T: OptFields,
@@ -34,6 +318,45 @@ where
 {
     let parser = GFAParser::new();
     info!("Parsing GFA from {}", path.as_ref().display());
-    let gfa = parser.parse_file(path.as_ref())?;
+    let gfa = parse_gfa_file(&parser, path)?;
     Ok(gfa)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_non_integer_segments() {
+        assert_eq!(Some("s1"), non_integer_segment("S\ts1\tACGT"));
+        assert_eq!(None, non_integer_segment("S\t1\tACGT"));
+        assert_eq!(Some("b"), non_integer_segment("L\t1\t+\tb\t-\t0M"));
+        assert_eq!(
+            Some("chr1_42"),
+            non_integer_segment("P\tp\t1+,chr1_42-,3+\t*")
+        );
+        assert_eq!(None, non_integer_segment("P\tp\t1+,2-\t*"));
+    }
+
+    #[test]
+    fn walks_as_paths() {
+        let path = |line: &str| {
+            let path = walk_to_path_line(line.as_bytes()).unwrap();
+            String::from_utf8(path).unwrap()
+        };
+
+        assert_eq!(
+            "P\tHG002#1#chr1\t1+,2-,13+\t*",
+            path("W\tHG002\t1\tchr1\t0\t100\t>1<2>13")
+        );
+        assert_eq!(
+            "P\tHG002#2#chr1:500-900\t4-\t*\tRC:i:2",
+            path("W\tHG002\t2\tchr1\t500\t900\t<4\tRC:i:2")
+        );
+        assert_eq!("P\tref#0#chr2\t5+\t*", path("W\tref\t0\tchr2\t*\t*\t>5"));
+
+        assert!(walk_to_path_line(b"W\tHG002\t1\tchr1\t0\t100").is_err());
+        assert!(walk_to_path_line(b"W\tHG002\t1\tchr1\t0\t100\t1>2").is_err());
+        assert!(walk_to_path_line(b"W\tHG002\t1\tchr1\t0\t100\t>1<").is_err());
+    }
+}
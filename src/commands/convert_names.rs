@@ -1,6 +1,10 @@
 use structopt::StructOpt;
 
-use std::{fs::File, io::Write, path::PathBuf};
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
 
 use gfa::{
     gfa::{name_conversion::NameMap, GFA},
@@ -32,24 +36,24 @@ pub struct GfaIdConvertArgs {
     check_hash: bool,
 }
 
-fn gfa_to_name_map_path(path: &PathBuf) -> PathBuf {
-    let mut new_path: PathBuf = path.clone();
+fn gfa_to_name_map_path(path: &Path) -> PathBuf {
+    let mut new_path: PathBuf = path.to_owned();
     let old_name = new_path.file_stem().and_then(|p| p.to_str()).unwrap();
     let new_name = format!("{}.name_map.json", old_name);
     new_path.set_file_name(&new_name);
     new_path
 }
 
-fn converted_gfa_path(path: &PathBuf) -> PathBuf {
-    let mut new_path: PathBuf = path.clone();
+fn converted_gfa_path(path: &Path) -> PathBuf {
+    let mut new_path: PathBuf = path.to_owned();
     let old_name = new_path.file_stem().and_then(|p| p.to_str()).unwrap();
     let new_name = format!("{}.uint_ids.gfa", old_name);
     new_path.set_file_name(&new_name);
     new_path
 }
 
-fn restored_gfa_path(path: &PathBuf) -> PathBuf {
-    let mut new_path: PathBuf = path.clone();
+fn restored_gfa_path(path: &Path) -> PathBuf {
+    let mut new_path: PathBuf = path.to_owned();
     let old_name = new_path.file_stem().and_then(|p| p.to_str()).unwrap();
     let new_name = format!("{}.str_ids.gfa", old_name);
     new_path.set_file_name(&new_name);
@@ -57,20 +61,20 @@ fn restored_gfa_path(path: &PathBuf) -> PathBuf {
 }
 
 fn segment_id_to_usize(
-    gfa_path: &PathBuf,
+    gfa_path: &Path,
     gfa: &GFA<Vec<u8>, OptionalFields>,
     args: &GfaIdConvertArgs,
 ) -> Result<()> {
     let name_map = if let Some(ref path) = &args.name_map_path {
-        NameMap::load_json(&path)?
+        NameMap::load_json(path)?
     } else {
         NameMap::build_from_gfa(gfa)
     };
 
     if let Some(new_gfa) =
-        name_map.gfa_bytestring_to_usize(&gfa, args.check_hash)
+        name_map.gfa_bytestring_to_usize(gfa, args.check_hash)
     {
-        let new_gfa_path = converted_gfa_path(&gfa_path);
+        let new_gfa_path = converted_gfa_path(gfa_path);
         let mut new_gfa_file = File::create(new_gfa_path.clone())?;
         let mut gfa_str = String::new();
         write_gfa(&new_gfa, &mut gfa_str);
@@ -78,7 +82,7 @@ fn segment_id_to_usize(
         println!("Saved converted GFA to {}", new_gfa_path.display());
 
         if args.name_map_path.is_none() {
-            let name_map_path = gfa_to_name_map_path(&gfa_path);
+            let name_map_path = gfa_to_name_map_path(gfa_path);
             name_map.save_json(&name_map_path)?;
             println!("Saved new name map to {}", name_map_path.display());
         }
@@ -90,7 +94,7 @@ fn segment_id_to_usize(
 }
 
 fn segment_id_to_bstring(
-    gfa_path: &PathBuf,
+    gfa_path: &Path,
     gfa: &GFA<usize, OptionalFields>,
     args: &GfaIdConvertArgs,
 ) -> Result<()> {
@@ -98,10 +102,10 @@ fn segment_id_to_bstring(
         .name_map_path
         .as_ref()
         .expect("Need name map to convert back");
-    let name_map = NameMap::load_json(&name_map_path)?;
+    let name_map = NameMap::load_json(name_map_path)?;
 
     let new_gfa: GFA<Vec<u8>, OptionalFields> = name_map
-        .gfa_usize_to_bytestring(&gfa)
+        .gfa_usize_to_bytestring(gfa)
         .expect("Error during conversion -- is it the right name map?");
 
     let new_gfa_path = restored_gfa_path(gfa_path);
@@ -123,12 +127,12 @@ pub fn convert_segment_ids(
     }
 
     if args.to_usize {
-        let gfa: GFA<Vec<u8>, OptionalFields> = load_gfa(&gfa_path)?;
-        segment_id_to_usize(&gfa_path, &gfa, args)
+        let gfa: GFA<Vec<u8>, OptionalFields> = load_gfa(gfa_path)?;
+        segment_id_to_usize(gfa_path, &gfa, args)
     } else {
         // Converting from integer to string names
-        let gfa: GFA<usize, OptionalFields> = load_gfa(&gfa_path)?;
-        segment_id_to_bstring(&gfa_path, &gfa, args)
+        let gfa: GFA<usize, OptionalFields> = load_gfa(gfa_path)?;
+        segment_id_to_bstring(gfa_path, &gfa, args)
     }
 }
 
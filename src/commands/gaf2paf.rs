@@ -25,7 +25,7 @@ pub fn gaf2paf(gfa_path: &PathBuf, args: &GAF2PAFArgs) -> Result<()> {
 
     if let Some(out_path) = &args.out {
         let mut out_file =
-            File::create(&out_path).expect("Error creating PAF output file");
+            File::create(out_path).expect("Error creating PAF output file");
 
         paf_lines.iter().for_each(|p| {
             writeln!(out_file, "{}", p).unwrap();
@@ -1,34 +1,61 @@
 use bstr::{io::*, BString, ByteSlice};
 use fnv::{FnvHashMap, FnvHashSet};
-use indicatif::{
-    ParallelProgressIterator, ProgressBar, ProgressIterator, ProgressStyle,
-};
+use gfa::gfa::Orientation;
+use indicatif::ParallelProgressIterator;
 use rayon::prelude::*;
-use std::{fs::File, io::BufReader, path::PathBuf};
+use regex::bytes::Regex;
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 use structopt::StructOpt;
 
-#[allow(unused_imports)]
 use log::{debug, info, log_enabled, warn};
 
-use gfa::gfa::GFA;
-
-use crate::{util::progress_bar, variants, variants::PathStep};
+use crate::{
+    bgzf::BgzfWriter,
+    bubbles::BubbleAlgorithm,
+    error::{GfaUtilsError, Result},
+    util::progress_bar,
+    variants::{self, vcf::VCFRecord},
+};
 
-use super::{load_gfa, Result};
+use super::saboten::{BedInterval, UltrabubblesFile};
 
 /// Output a VCF for the given GFA, using the graph's ultrabubbles to
 /// identify areas of variation.
-#[derive(StructOpt, Debug)]
+#[derive(StructOpt, Debug, Clone)]
 pub struct GFA2VCFArgs {
-    /// Load ultrabubbles from a file instead of calculating them.
+    /// Load ultrabubbles from a file instead of calculating them. If
+    /// the file is a BED file of intervals on reference paths, the
+    /// ultrabubbles are calculated, and only those that lie within
+    /// one of the intervals are used.
     #[structopt(
         name = "ultrabubbles file",
         long = "ultrabubbles",
         short = "ub"
     )]
     ultrabubbles_file: Option<PathBuf>,
-    /// Don't compare two paths if their start and end orientations
-    /// don't match each other
+    /// The algorithm used to find the ultrabubbles, when they aren't
+    /// loaded from a file: `saboten`, or the simpler `bcc`, which
+    /// doesn't find nested bubbles. See the `ultrabubbles` command.
+    #[structopt(
+        name = "bubble algorithm",
+        long = "bubble-algo",
+        default_value = "saboten",
+        possible_values = &["saboten", "bcc"]
+    )]
+    bubble_algo: BubbleAlgorithm,
+    /// Don't compare a path against the reference if the orientation
+    /// of either end segment of the bubble differs between the two
+    /// paths. This also turns off the INV INFO flag, which otherwise
+    /// marks records with an ALT allele that's the reverse complement
+    /// of the REF.
     #[structopt(name = "ignore inverted paths", long = "no-inv")]
     ignore_inverted_paths: bool,
     #[structopt(
@@ -36,29 +63,1131 @@ pub struct GFA2VCFArgs {
         long = "paths-file"
     )]
     ref_paths_file: Option<PathBuf>,
+    /// Use these paths as references. The names can be given as
+    /// separate values, as a comma-separated list, or both.
     #[structopt(name = "list of paths to use as references", long = "refs")]
     ref_paths_vec: Option<Vec<String>>,
+    /// Leave these paths out of variant calling entirely, as if they
+    /// weren't in the graph. Paths that are also given as references
+    /// are excluded. Like with --refs, the names can be separated by
+    /// commas.
+    #[structopt(name = "list of paths to exclude", long = "exclude-paths")]
+    exclude_paths_vec: Option<Vec<String>>,
+    /// Leave the paths listed in this file, one per line, out of
+    /// variant calling, like --exclude-paths.
+    #[structopt(
+        name = "file containing paths to exclude",
+        long = "exclude-paths-file"
+    )]
+    exclude_paths_file: Option<PathBuf>,
+    /// Use every path whose name starts with this prefix as a
+    /// reference. Can be given more than once, and combined with the
+    /// other reference path options.
+    #[structopt(
+        name = "reference path prefix",
+        long = "ref-prefix",
+        number_of_values = 1
+    )]
+    ref_prefixes: Vec<String>,
+    /// Use every path whose name matches this regular expression as a
+    /// reference. Can be given more than once, and combined with the
+    /// other reference path options.
+    #[structopt(
+        name = "reference path regex",
+        long = "ref-regex",
+        number_of_values = 1,
+        parse(try_from_str = Regex::new)
+    )]
+    ref_regexes: Vec<Regex>,
+    /// When no reference paths are given, use a single path chosen by
+    /// this rule as the reference, instead of every path: `first`, the
+    /// first path in the GFA, `longest`, the path with the longest
+    /// sequence, or `most-bubbles`, the path that traverses the most
+    /// ultrabubbles. Ties go to the path that comes first in the GFA.
+    #[structopt(
+        name = "auto reference rule",
+        long = "auto-ref",
+        possible_values = &["first", "longest", "most-bubbles"],
+        conflicts_with_all = &[
+            "list of paths to use as references",
+            "file containing paths to use as references",
+            "reference path prefix",
+            "reference path regex",
+            "consensus reference",
+        ]
+    )]
+    auto_ref: Option<AutoRef>,
+    /// Call variants against a synthetic consensus reference instead
+    /// of paths in the graph. The consensus follows the path given
+    /// with --consensus-backbone, except in ultrabubbles, where it
+    /// takes the most common allele. Its coordinates are positions on
+    /// the consensus sequence, not on any path.
+    #[structopt(
+        name = "consensus reference",
+        long = "consensus-ref",
+        conflicts_with_all = &[
+            "list of paths to use as references",
+            "file containing paths to use as references",
+            "reference path prefix",
+            "reference path regex",
+        ]
+    )]
+    consensus_ref: bool,
+    /// The path the consensus reference follows outside of the
+    /// ultrabubbles. Defaults to the first path in the GFA.
+    #[structopt(
+        name = "consensus backbone",
+        long = "consensus-backbone",
+        requires = "consensus reference"
+    )]
+    consensus_backbone: Option<String>,
+    /// The name of the consensus reference, used as the CHROM of its
+    /// records.
+    #[structopt(
+        name = "consensus name",
+        long = "consensus-name",
+        default_value = "consensus"
+    )]
+    consensus_name: String,
+    /// Write the VCF to the given file instead of stdout.
+    #[structopt(
+        name = "VCF output file",
+        long = "output",
+        short = "o",
+        parse(from_os_str)
+    )]
+    output: Option<PathBuf>,
+    /// Compress the output file with bgzip. This is the default if
+    /// the output file name ends with ".vcf.gz".
+    #[structopt(
+        name = "bgzip output",
+        long = "bgzip",
+        requires = "VCF output file"
+    )]
+    bgzip: bool,
+    /// Write a tabix index of the output file next to it, with a
+    /// ".tbi" extension. The output must be bgzipped.
+    #[structopt(
+        name = "tabix index",
+        long = "tabix",
+        requires = "VCF output file"
+    )]
+    tabix: bool,
+    /// Write the records of each reference path to its own file, named
+    /// after the output file with the reference's name before the
+    /// extension, e.g. "out.chr1.vcf" for "-o out.vcf". Each file only
+    /// has the `##contig` line of its reference.
+    #[structopt(
+        name = "split by reference",
+        long = "split-by-ref",
+        requires = "VCF output file"
+    )]
+    split_by_ref: bool,
+    /// Only call variants in ultrabubbles that overlap the given
+    /// region, as 1-based inclusive START:END coordinates on the
+    /// reference path chosen with --region-ref.
+    #[structopt(
+        name = "region",
+        long = "region",
+        parse(try_from_str = parse_region)
+    )]
+    region: Option<(usize, usize)>,
+    /// The reference path that --region refers to. Can be omitted if
+    /// exactly one reference path is given.
+    #[structopt(
+        name = "region reference path",
+        long = "region-ref",
+        requires = "region"
+    )]
+    region_ref: Option<String>,
+    /// Only output the records whose POS lies in one of the intervals
+    /// of this BED file, on the reference path named by the
+    /// interval's chrom. Unlike --region, this filters the records
+    /// rather than the ultrabubbles, so records in a bubble that
+    /// spans several targets are kept only if they're in one.
+    #[structopt(
+        name = "targets BED file",
+        long = "targets",
+        parse(from_os_str)
+    )]
+    targets: Option<PathBuf>,
+    /// Save the ultrabubbles used to a file, which can be loaded with
+    /// --ultrabubbles in later runs. If the name ends with ".ubz", the
+    /// compact binary format is used.
+    #[structopt(
+        name = "save ultrabubbles file",
+        long = "save-ultrabubbles",
+        parse(from_os_str)
+    )]
+    save_ultrabubbles_file: Option<PathBuf>,
+    /// Records with more alternate alleles than this get the COMPLEX
+    /// filter instead of PASS.
+    #[structopt(
+        name = "max alleles",
+        long = "max-alleles",
+        default_value = "1"
+    )]
+    max_alleles: usize,
+    /// Write the name and length of each reference path, separated by
+    /// a tab, to this file, like the first two columns of a samtools
+    /// faidx index.
+    #[structopt(
+        name = "reference lengths file",
+        long = "write-fai",
+        parse(from_os_str)
+    )]
+    write_fai: Option<PathBuf>,
+    /// Write the ultrabubbles assigned to each representative path,
+    /// i.e. the first path in the GFA that contains both ends of the
+    /// bubble, to this file, as tab-separated path name, from, and to
+    /// columns. Bubbles that aren't on any path are left out.
+    #[structopt(
+        name = "assignments file",
+        long = "write-assignments",
+        parse(from_os_str)
+    )]
+    write_assignments: Option<PathBuf>,
+    /// Also write the alleles of each ultrabubble in graph space to
+    /// this file, as tab-separated from, to, REF or ALT, the oriented
+    /// segments of the allele, e.g. `1+,3+,4+`, and the paths that
+    /// take it. Reference alleles come first in each bubble.
+    #[structopt(
+        name = "graph coordinates file",
+        long = "graph-coords",
+        parse(from_os_str)
+    )]
+    graph_coords: Option<PathBuf>,
+    /// Only warn about reference paths that don't exist in the graph,
+    /// instead of exiting with an error.
+    #[structopt(name = "lenient", long = "lenient")]
+    lenient: bool,
+    /// Extract the paths and assign the ultrabubbles to them, then
+    /// print a summary of the work to stderr and exit without
+    /// detecting variants.
+    #[structopt(name = "dry run", long = "dry-run")]
+    dry_run: bool,
+    /// Only process the first N ultrabubbles, in sorted order, after
+    /// any filtering with --ultrabubbles intervals or --region, e.g.
+    /// to try out options on a large graph.
+    #[structopt(name = "limit", long = "limit")]
+    limit: Option<usize>,
+    /// Skip ultrabubbles that span more than N bases on a reference
+    /// path, such as artifacts that cover large parts of the graph and
+    /// take long to process. Bubbles that aren't on a reference path
+    /// are kept.
+    #[structopt(name = "max bubble span", long = "max-bubble-span")]
+    max_bubble_span: Option<usize>,
+    /// Only output the VCF records, without the header, e.g. when
+    /// concatenating the output of several runs.
+    #[structopt(name = "no header", long = "no-header")]
+    no_header: bool,
+    /// Normalize the variants, by trimming bases shared by all alleles
+    /// and left-aligning indels on the reference path.
+    #[structopt(name = "normalize", long = "normalize")]
+    normalize: bool,
+    /// Split records whose REF and ALT alleles have the same length,
+    /// such as MNPs, into one SNV record for each base that differs,
+    /// at that base's position. Indels are kept as they are.
+    #[structopt(name = "atomize", long = "atomize")]
+    atomize: bool,
+    /// Add the number of paths that take each allele, the REF first,
+    /// as an `AD` INFO field, and each sample's count of each allele
+    /// as an `AD` FORMAT field.
+    #[structopt(name = "allele depth", long = "allele-depth")]
+    allele_depth: bool,
+    /// Fill the ID column with an ID made from the segment IDs of the
+    /// ends of each record's ultrabubble, like `ub_12_15`. If a bubble
+    /// has several records, they get a suffix with their CHROM and POS,
+    /// and their ALT if those are shared too, like `ub_12_15_chr1_105`.
+    #[structopt(name = "id from bubble", long = "id-from-bubble")]
+    id_from_bubble: bool,
+    /// Only output biallelic sites, i.e. records with a single ALT
+    /// allele, dropping the others. By default, multiallelic records
+    /// are kept.
+    #[structopt(name = "biallelic only", long = "biallelic-only")]
+    biallelic_only: bool,
+    /// With --biallelic-only, split multiallelic records into one
+    /// record per ALT allele instead of dropping them. Genotypes of
+    /// the other ALT alleles become `.` in each record.
+    #[structopt(
+        name = "split multiallelic",
+        long = "split-multiallelic",
+        requires = "biallelic only"
+    )]
+    split_multiallelic: bool,
+    /// Report one ALT allele for each distinct sequence in a bubble,
+    /// even if the paths spelling it visit different segments.
+    #[structopt(
+        name = "merge identical alleles",
+        long = "merge-identical-alleles"
+    )]
+    merge_identical_alleles: bool,
+    /// Only output SNVs. Paths whose allele through a bubble has a
+    /// different length than the reference's are skipped, and MNPs
+    /// are dropped, unless --atomize is also given, in which case
+    /// they're split into SNVs.
+    #[structopt(name = "only snps", long = "only-snps")]
+    only_snps: bool,
+    /// Use the sample names in this file for the genotype columns,
+    /// instead of the path names. Each line holds a path name and a
+    /// sample name, separated by a tab.
+    #[structopt(
+        name = "sample names file",
+        long = "sample-names",
+        parse(from_os_str)
+    )]
+    sample_names_file: Option<PathBuf>,
+    /// Group haplotype paths into samples, with one genotype column
+    /// per sample holding the phased genotypes of its haplotypes,
+    /// like `0|1`. Each line of the file holds a path name and a
+    /// sample name, separated by a tab. The haplotypes of a sample
+    /// are in the order of the paths in the GFA, and paths that
+    /// aren't listed keep their own column.
+    #[structopt(
+        name = "sample map file",
+        long = "sample-map",
+        parse(from_os_str),
+        conflicts_with = "sample names file"
+    )]
+    sample_map_file: Option<PathBuf>,
+    /// The expected number of haplotypes of each sample in the
+    /// --sample-map file. Samples with more haplotypes are warned
+    /// about, but keep all of them.
+    #[structopt(name = "ploidy", long = "ploidy", default_value = "2")]
+    ploidy: usize,
+    /// Write the genotypes of the haplotypes grouped by --sample-map
+    /// as unphased, like `0/1`, instead of phased, like `0|1`, and
+    /// note this in the header.
+    #[structopt(name = "unphased", long = "unphased")]
+    unphased: bool,
+    /// Leave out the paths that visit any segment more than once, as
+    /// the coordinates of the variants on them may be wrong.
+    #[structopt(name = "skip cyclic paths", long = "skip-cyclic-paths")]
+    skip_cyclic_paths: bool,
+    /// The output format: `vcf`, or `json` for one JSON object per
+    /// line for each variant record.
+    #[structopt(
+        name = "output format",
+        long = "format",
+        default_value = "vcf",
+        possible_values = &["vcf", "json"]
+    )]
+    format: OutputFormat,
+    /// Drop alleles whose REF or ALT is shorter than this. The paths
+    /// carrying a dropped allele get a missing (`.`) genotype.
+    #[structopt(name = "min allele length", long = "min-allele-length")]
+    min_allele_length: Option<usize>,
+    /// Drop alleles whose REF or ALT is longer than this. The paths
+    /// carrying a dropped allele get a missing (`.`) genotype.
+    #[structopt(name = "max allele length", long = "max-allele-length")]
+    max_allele_length: Option<usize>,
+    /// Drop ALT alleles whose frequency across the paths, i.e. AC
+    /// divided by AN, is below this, and records with no ALT alleles
+    /// left. The genotypes of the dropped alleles become `.`, so the
+    /// AC, AN, AF, and NS fields of the output count the remaining
+    /// alleles.
+    #[structopt(name = "min allele frequency", long = "min-af")]
+    min_af: Option<f64>,
+    /// Skip the ultrabubbles where an allele has a run of at least
+    /// this many N bases, such as an assembly gap, as the variants
+    /// found there are meaningless. Runs in the segments at the ends
+    /// of a bubble are shared by all its alleles, and aren't counted.
+    /// Can also be given as --skip-n-runs.
+    #[structopt(
+        name = "N run length",
+        long = "trim-n-runs",
+        alias = "skip-n-runs",
+        parse(try_from_str = parse_n_run_length)
+    )]
+    trim_n_runs: Option<usize>,
+    /// Write deletions and insertions longer than
+    /// --max-allele-length as <DEL> and <INS> symbolic alleles instead
+    /// of dropping them, and, if N is given, also those that change
+    /// the length by more than N bases. Records with symbolic alleles
+    /// get SVTYPE, SVLEN, and END INFO fields, and only the first base
+    /// of the REF if no other alleles remain. Can also be given as
+    /// --symbolic-sv-threshold N.
+    #[structopt(
+        name = "symbolic alleles",
+        long = "symbolic-alleles",
+        alias = "symbolic-sv-threshold",
+        value_name = "N"
+    )]
+    symbolic_alleles: Option<Option<usize>>,
+    /// Also write a record for each ultrabubble where every path
+    /// agrees with the reference, with `.` as the ALT allele and the
+    /// bubble's reference sequence as the REF.
+    #[structopt(
+        name = "include reference allele records",
+        long = "include-ref-allele-record"
+    )]
+    include_ref_allele_record: bool,
+    /// Shorten the REF of the records added by
+    /// --include-ref-allele-record to the first base, and give the
+    /// end of the bubble in an END INFO field, like gVCF reference
+    /// blocks.
+    #[structopt(
+        name = "reference record end",
+        long = "ref-block-end",
+        requires = "include reference allele records"
+    )]
+    ref_block_end: bool,
+    /// Time the variant detection in each ultrabubble, and log the
+    /// slowest ones at the end, to find the bubbles that dominate the
+    /// runtime.
+    #[structopt(name = "profile", long = "profile")]
+    profile: bool,
+    /// The number of the slowest ultrabubbles to log with --profile.
+    #[structopt(
+        name = "profile top",
+        long = "profile-top",
+        default_value = "10"
+    )]
+    profile_top: usize,
+    /// If interrupted with Ctrl-C, still write the records found in
+    /// the ultrabubbles processed so far, instead of exiting without
+    /// writing any records.
+    #[structopt(name = "partial on interrupt", long = "partial-on-interrupt")]
+    partial_on_interrupt: bool,
+    /// Save the ultrabubbles processed so far, and their records, to a
+    /// checkpoint in this directory. If the directory already holds a
+    /// checkpoint, e.g. from a run that crashed or was interrupted, the
+    /// ultrabubbles in it are skipped and their saved records used
+    /// instead. The rerun must use the same GFA and options.
+    #[structopt(
+        name = "checkpoint directory",
+        long = "checkpoint",
+        parse(from_os_str)
+    )]
+    checkpoint: Option<PathBuf>,
+    /// Write a JSON manifest describing the run to this file once the
+    /// output is written: the input GFA and its size, the number of
+    /// segments, links, paths, and ultrabubbles, the reference paths,
+    /// the command line, the wall-clock time, and the output file.
+    #[structopt(name = "manifest file", long = "manifest", parse(from_os_str))]
+    manifest: Option<PathBuf>,
+    /// Read options from this TOML file, one `key = value` pair per
+    /// option, with the long option name as the key, like
+    /// `refs = ["chm13"]` or `no-header = true`. Options given on the
+    /// command line take precedence over those in the file.
+    #[structopt(name = "config file", long = "config", parse(from_os_str))]
+    config: Option<PathBuf>,
+    /// Leave this option out of the --config file, so that its default
+    /// is used, e.g. `--unset normalize` to turn off a flag set in the
+    /// file. Can be given more than once.
+    #[structopt(
+        name = "unset config option",
+        long = "unset",
+        number_of_values = 1,
+        requires = "config file"
+    )]
+    unset: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    VCF,
+    JSON,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "vcf" => Ok(OutputFormat::VCF),
+            "json" => Ok(OutputFormat::JSON),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+/// How `--auto-ref` picks the reference path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoRef {
+    First,
+    Longest,
+    MostBubbles,
+}
+
+impl std::str::FromStr for AutoRef {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(AutoRef::First),
+            "longest" => Ok(AutoRef::Longest),
+            "most-bubbles" => Ok(AutoRef::MostBubbles),
+            _ => Err(format!("Unknown reference rule: {}", s)),
+        }
+    }
+}
+
+/// The index of the path chosen as the reference by `rule`. The
+/// bubble counts are the number of ultrabubbles in `path_indices`
+/// whose ends are both on the path, rather than the representative
+/// path assignment, which gives each bubble to the first path that
+/// contains it and so favors the paths that come first.
+fn auto_ref_path(
+    rule: AutoRef,
+    path_data: &variants::PathData,
+    path_indices: &variants::PathIndices,
+    ultrabubbles: &[(u64, u64)],
+) -> usize {
+    let path_count = path_data.paths.len();
+    let scores: Vec<usize> = match rule {
+        AutoRef::First => return 0,
+        AutoRef::Longest => (0..path_count)
+            .map(|path_ix| path_data.path_length(path_ix))
+            .collect(),
+        AutoRef::MostBubbles => {
+            let mut counts = vec![0; path_count];
+            for (from, to) in ultrabubbles.iter() {
+                let (from_ixs, to_ixs) =
+                    match (path_indices.get(from), path_indices.get(to)) {
+                        (Some(from_ixs), Some(to_ixs)) => (from_ixs, to_ixs),
+                        _ => continue,
+                    };
+                for path_ix in from_ixs.keys() {
+                    if to_ixs.contains_key(path_ix) {
+                        counts[*path_ix] += 1;
+                    }
+                }
+            }
+            counts
+        }
+    };
+
+    // The first of the paths with the highest score
+    scores
+        .iter()
+        .enumerate()
+        .max_by(|(ix0, s0), (ix1, s1)| s0.cmp(s1).then(ix1.cmp(ix0)))
+        .map_or(0, |(ix, _)| ix)
+}
+
+fn parse_region(region: &str) -> std::result::Result<(usize, usize), String> {
+    let mut fields = region.split(':');
+    let start = fields.next().and_then(|s| s.trim().parse::<usize>().ok());
+    let end = fields.next().and_then(|s| s.trim().parse::<usize>().ok());
+
+    match (start, end, fields.next()) {
+        (Some(start), Some(end), None) if start <= end => Ok((start, end)),
+        _ => Err(format!(
+            "Region must be in the format START:END, with START <= END, \
+             but was {}",
+            region
+        )),
+    }
 }
 
-fn load_paths_file(file_path: PathBuf) -> Result<Vec<BString>> {
+fn parse_n_run_length(length: &str) -> std::result::Result<usize, String> {
+    match length.trim().parse::<usize>() {
+        Ok(length) if length > 0 => Ok(length),
+        _ => Err(format!(
+            "The N run length must be a positive integer, but was {}",
+            length
+        )),
+    }
+}
+
+pub(crate) fn load_paths_file(file_path: PathBuf) -> Result<Vec<BString>> {
     let file = File::open(file_path)?;
-    let reader = BufReader::new(file);
-    let lines = reader.byte_lines();
+    read_path_names(BufReader::new(file))
+}
 
+/// Read one path name per line, skipping blank lines and lines
+/// starting with `#`.
+fn read_path_names<R: std::io::BufRead>(reader: R) -> Result<Vec<BString>> {
     let mut paths = Vec::new();
-    for line in lines {
+    for line in reader.byte_lines() {
         let line = line?;
+        let line = line.trim_end_with(|c| c == '\r');
+        if line.trim().is_empty() || line.starts_with(b"#") {
+            continue;
+        }
         paths.push(line.into());
     }
 
     Ok(paths)
 }
 
+/// Load a mapping from path names to sample names, from a file with
+/// one tab-separated pair per line.
+fn load_sample_names(file_path: &Path) -> Result<FnvHashMap<BString, BString>> {
+    let file = File::open(file_path).map_err(|err| {
+        format!(
+            "Error opening sample names file {}: {}",
+            file_path.display(),
+            err
+        )
+    })?;
+    let lines = BufReader::new(file).byte_lines();
+
+    let mut sample_names = FnvHashMap::default();
+    for (line_num, line) in lines.enumerate() {
+        let line = line?;
+        let line = line.trim_end_with(|c| c == '\r');
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.splitn_str(2, "\t");
+        match (fields.next(), fields.next()) {
+            (Some(path_name), Some(sample_name)) => {
+                sample_names.insert(path_name.into(), sample_name.into());
+            }
+            _ => {
+                return Err(format!(
+                    "Line {} of sample names file {} must contain a path \
+                     name and a sample name, separated by a tab",
+                    line_num + 1,
+                    file_path.display()
+                )
+                .into())
+            }
+        }
+    }
+
+    Ok(sample_names)
+}
+
+/// Replace the whitespace in a sample name, which would break the
+/// VCF header line, with underscores.
+fn sanitize_sample_name(name: &[u8]) -> BString {
+    name.trim()
+        .iter()
+        .map(|&c| if c.is_ascii_whitespace() { b'_' } else { c })
+        .collect()
+}
+
+/// The sample name of each path, in path order, using the mapped
+/// names from `--sample-names` where given, and the path names
+/// otherwise. Returns an error if two paths get the same sample name,
+/// as VCF sample names must be unique.
+fn sample_names(
+    path_names: &[BString],
+    mapping: Option<&FnvHashMap<BString, BString>>,
+) -> Result<Vec<BString>> {
+    let names: Vec<BString> = path_names
+        .iter()
+        .map(|path_name| {
+            let name = mapping
+                .and_then(|mapping| mapping.get(path_name))
+                .unwrap_or(path_name);
+            sanitize_sample_name(name)
+        })
+        .collect();
+
+    let mut seen = FnvHashSet::default();
+    for name in names.iter() {
+        if !seen.insert(name) {
+            return Err(format!(
+                "Sample name {} is used for more than one path",
+                name
+            )
+            .into());
+        }
+    }
+
+    Ok(names)
+}
+
+/// Group the paths into samples by their names in `mapping`, as for
+/// `--sample-map`. The samples are in the order of their first path,
+/// and paths that aren't in the mapping are samples of their own.
+/// Samples with more than `ploidy` haplotypes are warned about.
+fn sample_groups(
+    path_names: &[BString],
+    mapping: &FnvHashMap<BString, BString>,
+    ploidy: usize,
+) -> Result<variants::vcf::SampleGroups> {
+    let mut names: Vec<BString> = Vec::new();
+    let mut haplotypes: Vec<Vec<usize>> = Vec::new();
+    let mut mapped: Vec<bool> = Vec::new();
+    let mut sample_ixs: FnvHashMap<BString, usize> = FnvHashMap::default();
+
+    for (path_ix, path_name) in path_names.iter().enumerate() {
+        let (name, is_mapped) = match mapping.get(path_name) {
+            Some(sample) => (sanitize_sample_name(sample), true),
+            None => (sanitize_sample_name(path_name), false),
+        };
+        match sample_ixs.get(&name) {
+            Some(&sample_ix) if is_mapped && mapped[sample_ix] => {
+                haplotypes[sample_ix].push(path_ix);
+            }
+            Some(_) => {
+                return Err(format!(
+                    "Sample name {} is used for more than one path",
+                    name
+                )
+                .into())
+            }
+            None => {
+                sample_ixs.insert(name.clone(), names.len());
+                names.push(name);
+                haplotypes.push(vec![path_ix]);
+                mapped.push(is_mapped);
+            }
+        }
+    }
+
+    for (name, paths) in names.iter().zip(haplotypes.iter()) {
+        if paths.len() > ploidy {
+            warn!(
+                "Sample {} has {} haplotypes, more than the ploidy of {}",
+                name,
+                paths.len(),
+                ploidy
+            );
+        }
+    }
+
+    Ok(variants::vcf::SampleGroups::new(names, haplotypes))
+}
+
+/// The names of the paths that start with any of the `prefixes` or
+/// match any of the `regexes`, in path order.
+fn matching_path_names(
+    path_names: &[BString],
+    prefixes: &[String],
+    regexes: &[Regex],
+) -> Vec<BString> {
+    path_names
+        .iter()
+        .filter(|name| {
+            prefixes
+                .iter()
+                .any(|prefix| name.starts_with(prefix.as_bytes()))
+                || regexes.iter().any(|regex| regex.is_match(name))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Split the path names given on the command line, where each value
+/// can be a comma-separated list of names, trimming the whitespace
+/// around each name.
 fn paths_list(paths: Vec<String>) -> Vec<BString> {
-    paths.into_iter().map(BString::from).collect()
+    paths
+        .iter()
+        .flat_map(|names| names.split(','))
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(BString::from)
+        .collect()
+}
+
+/// Whether the VCF written to `output` is compressed with bgzip.
+fn is_bgzip_output(output: &Path, bgzip: bool) -> bool {
+    bgzip || output.to_string_lossy().ends_with(".vcf.gz")
+}
+
+/// A writer for VCF output, which must be finished with `finish`, so
+/// that errors writing the end of the output are reported.
+pub(crate) enum VcfWriter {
+    Plain(Box<dyn Write>),
+    Bgzip(BgzfWriter<BufWriter<File>>),
+}
+
+impl VcfWriter {
+    /// Flush the output, and write the final block and end-of-file
+    /// marker of bgzipped output.
+    pub(crate) fn finish(self) -> Result<()> {
+        match self {
+            VcfWriter::Plain(mut out) => out.flush()?,
+            VcfWriter::Bgzip(out) => {
+                out.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for VcfWriter {
+    fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+        match self {
+            VcfWriter::Plain(out) => out.write(data),
+            VcfWriter::Bgzip(out) => out.write(data),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            VcfWriter::Plain(out) => out.flush(),
+            VcfWriter::Bgzip(out) => out.flush(),
+        }
+    }
+}
+
+pub(crate) fn vcf_writer(
+    output: Option<&Path>,
+    bgzip: bool,
+) -> Result<VcfWriter> {
+    if let Some(path) = output {
+        let file = File::create(path).map_err(|err| {
+            format!(
+                "Error creating VCF output file {}: {}",
+                path.display(),
+                err
+            )
+        })?;
+        if is_bgzip_output(path, bgzip) {
+            info!("Writing bgzipped VCF to {}", path.display());
+            Ok(VcfWriter::Bgzip(BgzfWriter::new(BufWriter::new(file))))
+        } else {
+            info!("Writing VCF to {}", path.display());
+            Ok(VcfWriter::Plain(Box::new(BufWriter::new(file))))
+        }
+    } else {
+        Ok(VcfWriter::Plain(Box::new(
+            BufWriter::new(std::io::stdout()),
+        )))
+    }
+}
+
+/// The options of `GFA2VCFArgs` that can be given in a `--config`
+/// file, with the same keys as the long option names. Options that
+/// take several values are arrays, and options with values that the
+/// command line parses are strings, parsed the same way.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct GFA2VCFConfig {
+    #[serde(rename = "ultrabubbles")]
+    ultrabubbles_file: Option<PathBuf>,
+    bubble_algo: Option<String>,
+    #[serde(rename = "no-inv")]
+    ignore_inverted_paths: Option<bool>,
+    #[serde(rename = "paths-file")]
+    ref_paths_file: Option<PathBuf>,
+    #[serde(rename = "refs")]
+    ref_paths_vec: Option<Vec<String>>,
+    #[serde(rename = "exclude-paths")]
+    exclude_paths_vec: Option<Vec<String>>,
+    exclude_paths_file: Option<PathBuf>,
+    #[serde(rename = "ref-prefix")]
+    ref_prefixes: Option<Vec<String>>,
+    #[serde(rename = "ref-regex")]
+    ref_regexes: Option<Vec<String>>,
+    auto_ref: Option<String>,
+    consensus_ref: Option<bool>,
+    consensus_backbone: Option<String>,
+    consensus_name: Option<String>,
+    output: Option<PathBuf>,
+    bgzip: Option<bool>,
+    tabix: Option<bool>,
+    split_by_ref: Option<bool>,
+    region: Option<String>,
+    region_ref: Option<String>,
+    targets: Option<PathBuf>,
+    #[serde(rename = "save-ultrabubbles")]
+    save_ultrabubbles_file: Option<PathBuf>,
+    max_alleles: Option<usize>,
+    write_fai: Option<PathBuf>,
+    write_assignments: Option<PathBuf>,
+    graph_coords: Option<PathBuf>,
+    lenient: Option<bool>,
+    dry_run: Option<bool>,
+    limit: Option<usize>,
+    max_bubble_span: Option<usize>,
+    no_header: Option<bool>,
+    normalize: Option<bool>,
+    atomize: Option<bool>,
+    allele_depth: Option<bool>,
+    id_from_bubble: Option<bool>,
+    biallelic_only: Option<bool>,
+    split_multiallelic: Option<bool>,
+    merge_identical_alleles: Option<bool>,
+    only_snps: Option<bool>,
+    #[serde(rename = "sample-names")]
+    sample_names_file: Option<PathBuf>,
+    #[serde(rename = "sample-map")]
+    sample_map_file: Option<PathBuf>,
+    ploidy: Option<usize>,
+    unphased: Option<bool>,
+    skip_cyclic_paths: Option<bool>,
+    format: Option<String>,
+    min_allele_length: Option<usize>,
+    max_allele_length: Option<usize>,
+    min_af: Option<f64>,
+    #[serde(alias = "skip-n-runs")]
+    trim_n_runs: Option<usize>,
+    symbolic_alleles: Option<SymbolicAllelesConfig>,
+    include_ref_allele_record: Option<bool>,
+    ref_block_end: Option<bool>,
+    profile: Option<bool>,
+    profile_top: Option<usize>,
+    partial_on_interrupt: Option<bool>,
+    checkpoint: Option<PathBuf>,
+    manifest: Option<PathBuf>,
+}
+
+/// The `symbolic-alleles` config value, which is either `true` or
+/// `false`, or the threshold N of `--symbolic-alleles N`.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SymbolicAllelesConfig {
+    Enabled(bool),
+    Threshold(usize),
+}
+
+/// Check the relations between the options that clap checks on the
+/// command line, for the options merged with a config file.
+fn check_merged_options(args: &GFA2VCFArgs) -> std::result::Result<(), String> {
+    let has_output = args.output.is_some();
+    let requirements = [
+        (args.bgzip, "--bgzip", has_output, "--output"),
+        (args.tabix, "--tabix", has_output, "--output"),
+        (args.split_by_ref, "--split-by-ref", has_output, "--output"),
+        (
+            args.region_ref.is_some(),
+            "--region-ref",
+            args.region.is_some(),
+            "--region",
+        ),
+        (
+            args.consensus_backbone.is_some(),
+            "--consensus-backbone",
+            args.consensus_ref,
+            "--consensus-ref",
+        ),
+        (
+            args.split_multiallelic,
+            "--split-multiallelic",
+            args.biallelic_only,
+            "--biallelic-only",
+        ),
+        (
+            args.ref_block_end,
+            "--ref-block-end",
+            args.include_ref_allele_record,
+            "--include-ref-allele-record",
+        ),
+    ];
+    for &(given, name, required, requirement) in requirements.iter() {
+        if given && !required {
+            return Err(format!("{} requires {}", name, requirement));
+        }
+    }
+
+    let explicit_refs = args.ref_paths_vec.is_some()
+        || args.ref_paths_file.is_some()
+        || !args.ref_prefixes.is_empty()
+        || !args.ref_regexes.is_empty();
+    if args.auto_ref.is_some() && (explicit_refs || args.consensus_ref) {
+        return Err("--auto-ref cannot be used with the other reference \
+                    options"
+            .into());
+    }
+    if args.consensus_ref && explicit_refs {
+        return Err("--consensus-ref cannot be used with --refs, \
+                    --paths-file, --ref-prefix, or --ref-regex"
+            .into());
+    }
+    if args.sample_map_file.is_some() && args.sample_names_file.is_some() {
+        return Err("--sample-map cannot be used with --sample-names".into());
+    }
+    Ok(())
+}
+
+/// Merge the options in the file given with `--config` into `args`,
+/// which were parsed from the command line arguments `cli_args`,
+/// starting with the name of the command. Options given on the
+/// command line take precedence over those in the file, which take
+/// precedence over the defaults, and the options given with `--unset`
+/// are left out of the file.
+pub fn apply_config(
+    mut args: GFA2VCFArgs,
+    cli_args: &[OsString],
+) -> Result<GFA2VCFArgs> {
+    let path = match &args.config {
+        Some(path) => path.clone(),
+        None => return Ok(args),
+    };
+    let config: GFA2VCFConfig = crate::config::load(&path, &args.unset)?;
+    let config_error = |err: String| crate::config::config_error(&path, err);
+
+    let matches = GFA2VCFArgs::clap()
+        .get_matches_from_safe(cli_args)
+        .map_err(|err| GfaUtilsError::Other(err.message))?;
+    let given = |name: &str| matches.occurrences_of(name) > 0;
+
+    // Each option is set from the file if it wasn't given on the
+    // command line, with its value as is, wrapped in `Some`, or
+    // parsed like on the command line
+    macro_rules! merge {
+        ($field:ident, $name:expr) => {
+            merge!($field, $name, |value| Ok(value))
+        };
+        (Some $field:ident, $name:expr) => {
+            merge!($field, $name, |value| Ok(Some(value)))
+        };
+        ($field:ident, $name:expr, $parse:expr) => {
+            if let Some(value) = config.$field {
+                if !given($name) {
+                    let parse: &dyn Fn(_) -> std::result::Result<_, String> =
+                        &$parse;
+                    args.$field = parse(value).map_err(|err| {
+                        config_error(format!(
+                            "Invalid value for {}: {}",
+                            $name, err
+                        ))
+                    })?;
+                }
+            }
+        };
+    }
+
+    merge!(Some ultrabubbles_file, "ultrabubbles file");
+    merge!(bubble_algo, "bubble algorithm", |value: String| value
+        .parse());
+    merge!(ignore_inverted_paths, "ignore inverted paths");
+    merge!(Some ref_paths_file, "file containing paths to use as references");
+    merge!(Some ref_paths_vec, "list of paths to use as references");
+    merge!(Some exclude_paths_vec, "list of paths to exclude");
+    merge!(Some exclude_paths_file, "file containing paths to exclude");
+    merge!(ref_prefixes, "reference path prefix");
+    merge!(ref_regexes, "reference path regex", |values: Vec<
+        String,
+    >| {
+        values
+            .iter()
+            .map(|value| Regex::new(value).map_err(|err| err.to_string()))
+            .collect()
+    });
+    merge!(auto_ref, "auto reference rule", |value: String| value
+        .parse()
+        .map(Some));
+    merge!(consensus_ref, "consensus reference");
+    merge!(Some consensus_backbone, "consensus backbone");
+    merge!(consensus_name, "consensus name");
+    merge!(Some output, "VCF output file");
+    merge!(bgzip, "bgzip output");
+    merge!(tabix, "tabix index");
+    merge!(split_by_ref, "split by reference");
+    merge!(region, "region", |value: String| parse_region(&value)
+        .map(Some));
+    merge!(Some region_ref, "region reference path");
+    merge!(Some targets, "targets BED file");
+    merge!(Some save_ultrabubbles_file, "save ultrabubbles file");
+    merge!(max_alleles, "max alleles");
+    merge!(Some write_fai, "reference lengths file");
+    merge!(Some write_assignments, "assignments file");
+    merge!(Some graph_coords, "graph coordinates file");
+    merge!(lenient, "lenient");
+    merge!(dry_run, "dry run");
+    merge!(Some limit, "limit");
+    merge!(Some max_bubble_span, "max bubble span");
+    merge!(no_header, "no header");
+    merge!(normalize, "normalize");
+    merge!(atomize, "atomize");
+    merge!(allele_depth, "allele depth");
+    merge!(id_from_bubble, "id from bubble");
+    merge!(biallelic_only, "biallelic only");
+    merge!(split_multiallelic, "split multiallelic");
+    merge!(merge_identical_alleles, "merge identical alleles");
+    merge!(only_snps, "only snps");
+    merge!(Some sample_names_file, "sample names file");
+    merge!(Some sample_map_file, "sample map file");
+    merge!(ploidy, "ploidy");
+    merge!(unphased, "unphased");
+    merge!(skip_cyclic_paths, "skip cyclic paths");
+    merge!(format, "output format", |value: String| value.parse());
+    merge!(Some min_allele_length, "min allele length");
+    merge!(Some max_allele_length, "max allele length");
+    merge!(Some min_af, "min allele frequency");
+    merge!(trim_n_runs, "N run length", |value: usize| {
+        parse_n_run_length(&value.to_string()).map(Some)
+    });
+    merge!(symbolic_alleles, "symbolic alleles", |value| Ok(
+        match value {
+            SymbolicAllelesConfig::Enabled(true) => Some(None),
+            SymbolicAllelesConfig::Enabled(false) => None,
+            SymbolicAllelesConfig::Threshold(n) => Some(Some(n)),
+        }
+    ));
+    merge!(
+        include_ref_allele_record,
+        "include reference allele records"
+    );
+    merge!(ref_block_end, "reference record end");
+    merge!(profile, "profile");
+    merge!(profile_top, "profile top");
+    merge!(partial_on_interrupt, "partial on interrupt");
+    merge!(Some checkpoint, "checkpoint directory");
+    merge!(Some manifest, "manifest file");
+
+    check_merged_options(&args).map_err(config_error)?;
+    debug!("Options with config file options: {:?}", args);
+    Ok(args)
 }
 
-pub fn gfa2vcf(gfa_path: &PathBuf, args: GFA2VCFArgs) -> Result<()> {
+pub fn gfa2vcf(
+    gfa_path: &PathBuf,
+    args: GFA2VCFArgs,
+    path_options: variants::PathDataOptions,
+) -> Result<()> {
+    let start_time = Instant::now();
+
+    // The options a checkpoint must have been made with to be resumed,
+    // leaving out those that only change where and how the output is
+    // written
+    let checkpoint_options = format!(
+        "{:?} {:?}",
+        GFA2VCFArgs {
+            output: None,
+            bgzip: false,
+            tabix: false,
+            profile: false,
+            profile_top: 0,
+            partial_on_interrupt: false,
+            checkpoint: None,
+            manifest: None,
+            config: None,
+            unset: Vec::new(),
+            ..args.clone()
+        },
+        path_options
+    );
+
+    // Tabix can only index bgzipped VCFs
+    if args.tabix {
+        let bgzipped = args
+            .output
+            .as_deref()
+            .is_some_and(|path| is_bgzip_output(path, args.bgzip));
+        if !bgzipped {
+            return Err("--tabix requires bgzipped output, use --bgzip or \
+                        an output file ending with .vcf.gz"
+                .into());
+        }
+        if args.format != OutputFormat::VCF {
+            return Err("--tabix can only be used with VCF output".into());
+        }
+    }
+
+    // Opened before doing any work, so that an unwritable output file
+    // is reported right away. The files of --split-by-ref can only be
+    // named once the references are known.
+    let out = if args.dry_run {
+        None
+    } else if args.split_by_ref {
+        Some(None)
+    } else {
+        Some(Some(vcf_writer(args.output.as_deref(), args.bgzip)?))
+    };
+
+    let sample_name_map = args
+        .sample_names_file
+        .as_deref()
+        .map(load_sample_names)
+        .transpose()?;
+
+    let sample_map = args
+        .sample_map_file
+        .as_deref()
+        .map(load_sample_names)
+        .transpose()?;
+
     let ref_paths_list = args.ref_paths_vec.map(paths_list).unwrap_or_default();
 
     let ref_paths_file = args
@@ -67,11 +1196,22 @@ pub fn gfa2vcf(gfa_path: &PathBuf, args: GFA2VCFArgs) -> Result<()> {
         .transpose()?
         .unwrap_or_default();
 
-    let ref_path_names: Option<FnvHashSet<BString>> = {
-        let ref_paths: FnvHashSet<BString> = ref_paths_list
-            .into_iter()
-            .chain(ref_paths_file.into_iter())
-            .collect();
+    let exclude_paths: FnvHashSet<BString> = args
+        .exclude_paths_vec
+        .map(paths_list)
+        .unwrap_or_default()
+        .into_iter()
+        .chain(
+            args.exclude_paths_file
+                .map(load_paths_file)
+                .transpose()?
+                .unwrap_or_default(),
+        )
+        .collect();
+
+    let mut ref_path_names: Option<FnvHashSet<BString>> = {
+        let ref_paths: FnvHashSet<BString> =
+            ref_paths_list.into_iter().chain(ref_paths_file).collect();
         if ref_paths.is_empty() {
             None
         } else {
@@ -85,45 +1225,145 @@ pub fn gfa2vcf(gfa_path: &PathBuf, args: GFA2VCFArgs) -> Result<()> {
         }
     };
 
-    let path_data = {
-        let gfa: GFA<usize, ()> = load_gfa(&gfa_path)?;
+    let (ultrabubbles_file, intervals) = match &args.ultrabubbles_file {
+        Some(path) => {
+            match super::saboten::load_ultrabubbles_or_intervals(path)? {
+                UltrabubblesFile::Ultrabubbles(ultrabubbles) => {
+                    (Some(ultrabubbles), None)
+                }
+                UltrabubblesFile::Intervals(intervals) => {
+                    (None, Some(intervals))
+                }
+            }
+        }
+        None => (None, None),
+    };
+
+    // Only the segment sequences and paths are needed for the
+    // variants, so the full GFA is never loaded; see
+    // `variants::load_path_data`. If the ultrabubbles aren't loaded
+    // from a file, the links are read in the same pass, into a graph
+    // without sequences that's dropped once its bubbles are found, so
+    // the file is only read once
+    let (mut path_data, mut ultrabubbles, link_count) = match ultrabubbles_file
+    {
+        Some(ultrabubbles) => (
+            variants::load_path_data(gfa_path, true, path_options)?,
+            ultrabubbles,
+            None,
+        ),
+        None => {
+            let (path_data, graph) = variants::load_path_data_with_graph(
+                gfa_path,
+                true,
+                path_options,
+            )?;
+            let ultrabubbles = super::saboten::find_ultrabubbles_in_gfa(
+                &graph,
+                args.bubble_algo,
+            );
+            let link_count = graph.links.len();
+            drop(graph);
+            (path_data, ultrabubbles, Some(link_count))
+        }
+    };
+    let graph_counts = (
+        path_data.segment_map.len(),
+        link_count,
+        path_data.paths.len(),
+    );
 
-        if gfa.paths.len() < 2 {
-            panic!("GFA must contain at least two paths");
+    if args.skip_cyclic_paths {
+        let removed = path_data.remove_cyclic_paths();
+        if removed > 0 {
+            info!("Skipping {} cyclic paths", removed);
         }
+    }
 
-        if let Some(ref_paths) = ref_path_names.as_ref() {
-            let gfa_paths = gfa
-                .paths
-                .iter()
-                .map(|path| path.path_name.as_bstr())
-                .collect::<FnvHashSet<_>>();
-
-            for path in ref_paths.iter() {
-                if !gfa_paths.contains(path.as_bstr()) {
-                    eprintln!(
-                        "Reference path does not exist in graph: {}",
-                        path.as_bstr()
-                    );
-                    std::process::exit(1);
-                }
+    if !exclude_paths.is_empty() {
+        exclude_named_paths(
+            &mut path_data,
+            &exclude_paths,
+            ref_path_names.as_mut(),
+        )?;
+    }
+
+    if path_data.paths.len() < 2 {
+        return Err(GfaUtilsError::TooFewPaths {
+            gfa: gfa_path.clone(),
+            count: path_data.paths.len(),
+        });
+    }
+
+    if !args.ref_prefixes.is_empty() || !args.ref_regexes.is_empty() {
+        let matched = matching_path_names(
+            &path_data.path_names,
+            &args.ref_prefixes,
+            &args.ref_regexes,
+        );
+        if matched.is_empty() {
+            return Err("No paths match the reference path prefixes or \
+                        regular expressions"
+                .into());
+        }
+        if log_enabled!(log::Level::Debug) {
+            debug!("Reference path patterns matched {} paths:", matched.len());
+            for p in matched.iter() {
+                debug!("\t{}", p);
             }
         }
+        ref_path_names
+            .get_or_insert_with(FnvHashSet::default)
+            .extend(matched);
+    }
 
-        info!("GFA has {} paths", gfa.paths.len());
+    if let Some(ref_paths) = ref_path_names.as_mut() {
+        let gfa_paths = path_data
+            .path_names
+            .iter()
+            .map(|name| name.as_bstr())
+            .collect::<FnvHashSet<_>>();
 
-        variants::gfa_path_data(gfa)
-    };
+        let mut missing: Vec<&BString> = ref_paths
+            .iter()
+            .filter(|path| !gfa_paths.contains(path.as_bstr()))
+            .collect();
+        missing.sort();
 
-    let mut ultrabubbles = if let Some(path) = &args.ultrabubbles_file {
-        super::saboten::load_ultrabubbles(path)
-    } else {
-        super::saboten::find_ultrabubbles(gfa_path)
-    }?;
+        if !missing.is_empty() {
+            let missing: Vec<BString> = missing.into_iter().cloned().collect();
+            if !args.lenient {
+                return Err(GfaUtilsError::MissingReferencePaths(missing));
+            }
+            warn!("{}", GfaUtilsError::MissingReferencePaths(missing.clone()));
+            ref_paths.retain(|path| !missing.contains(path));
+            if ref_paths.is_empty() {
+                return Err(GfaUtilsError::NoReferencePaths);
+            }
+        }
+    }
+
+    info!("GFA has {} paths", path_data.paths.len());
+
+    let ploidy = args.ploidy;
+    let unphased = args.unphased;
+    let sample_groups = sample_map
+        .map(|mapping| sample_groups(&path_data.path_names, &mapping, ploidy))
+        .transpose()?
+        .map(|groups| if unphased { groups.unphased() } else { groups });
+
+    let sample_names = match &sample_groups {
+        Some(groups) => groups.sample_names().to_vec(),
+        None => sample_names(&path_data.path_names, sample_name_map.as_ref())?,
+    };
 
     info!("Using {} ultrabubbles", ultrabubbles.len());
 
-    ultrabubbles.sort();
+    super::saboten::sort_ultrabubbles(&mut ultrabubbles);
+
+    if let Some(path) = &args.save_ultrabubbles_file {
+        super::saboten::save_ultrabubbles(path, &ultrabubbles)?;
+    }
 
     let ultrabubble_nodes = ultrabubbles
         .iter()
@@ -133,155 +1373,1612 @@ pub fn gfa2vcf(gfa_path: &PathBuf, args: GFA2VCFArgs) -> Result<()> {
         })
         .collect::<FnvHashSet<_>>();
 
-    let path_indices =
+    let mut path_indices =
         variants::bubble_path_indices(&path_data.paths, &ultrabubble_nodes);
 
-    let mut all_vcf_records = Vec::new();
+    if let Some(rule) = args.auto_ref {
+        if ref_path_names.is_none() {
+            let ref_ix =
+                auto_ref_path(rule, &path_data, &path_indices, &ultrabubbles);
+            let name = path_data.path_names[ref_ix].clone();
+            info!("Using {} as the reference path", name);
+            ref_path_names = Some(std::iter::once(name).collect());
+        }
+    }
 
-    let var_config = variants::VariantConfig {
-        ignore_inverted_paths: args.ignore_inverted_paths,
+    // Only the paths in the graph get genotype columns, so the
+    // consensus reference is added as the last path
+    let sample_count = path_data.paths.len();
+    let consensus_ix = if args.consensus_ref {
+        let consensus_ix = add_consensus_path(
+            &mut path_data,
+            &mut path_indices,
+            &ultrabubbles,
+            args.consensus_backbone.as_deref(),
+            &args.consensus_name,
+        )?;
+        ref_path_names = Some(
+            std::iter::once(args.consensus_name.as_str().into()).collect(),
+        );
+        Some(consensus_ix)
+    } else {
+        None
     };
 
-    info!(
-        "Identifying variants in {} ultrabubbles",
-        ultrabubbles.len()
-    );
+    if let Some(intervals) = intervals {
+        retain_bubbles_in_intervals(
+            &mut ultrabubbles,
+            &intervals,
+            &path_data,
+            &path_indices,
+        );
+        info!(
+            "{} ultrabubbles are within the {} BED intervals",
+            ultrabubbles.len(),
+            intervals.len()
+        );
+    }
 
-    let p_bar = progress_bar(ultrabubbles.len(), false);
-
-    all_vcf_records.par_extend(
-        ultrabubbles
-            .par_iter()
-            .progress_with(p_bar)
-            .filter_map(|&(from, to)| {
-                let vars = variants::detect_variants_in_sub_paths(
-                    &var_config,
-                    &path_data,
-                    ref_path_names.as_ref(),
-                    &path_indices,
-                    from,
-                    to,
-                )?;
+    if let Some((start, end)) = args.region {
+        let region_ref: BString = match (&args.region_ref, &ref_path_names) {
+            (Some(name), _) => name.as_str().into(),
+            (None, Some(ref_paths)) if ref_paths.len() == 1 => {
+                ref_paths.iter().next().unwrap().clone()
+            }
+            _ => {
+                return Err("--region-ref must be given when not using \
+                            exactly one reference path"
+                    .into())
+            }
+        };
 
-                let vcf_records = variants::variant_vcf_record(&vars);
-                Some(vcf_records)
-            })
-            .flatten(),
-    );
-    info!("Variant identification complete");
+        let ref_ix = path_data
+            .path_names
+            .iter()
+            .position(|name| name == &region_ref)
+            .ok_or_else(|| {
+                format!("Region reference path not in graph: {}", region_ref)
+            })?;
 
-    all_vcf_records.sort_by(|v0, v1| v0.vcf_cmp(v1));
-    all_vcf_records.dedup();
+        // Find the reference steps in the region once, so each bubble
+        // only needs its end steps compared against a range of step
+        // indices
+        let offset_index = variants::PathOffsetIndex::new(&path_data, ref_ix);
+        match offset_index.steps_in_range(start, end) {
+            Some((first_step, last_step)) => {
+                ultrabubbles.retain(|&(from, to)| {
+                    let step_ix = |node: u64| {
+                        path_indices.get(&node)?.get(&ref_ix).copied()
+                    };
+                    match (step_ix(from), step_ix(to)) {
+                        (Some(from_ix), Some(to_ix)) => {
+                            from_ix.min(to_ix) <= last_step
+                                && from_ix.max(to_ix) >= first_step
+                        }
+                        _ => false,
+                    }
+                });
+            }
+            None => ultrabubbles.clear(),
+        }
 
-    info!("Writing {} unique VCF records", all_vcf_records.len());
+        info!(
+            "{} ultrabubbles overlap the region {}:{}-{}",
+            ultrabubbles.len(),
+            region_ref,
+            start,
+            end
+        );
+    }
+
+    if let Some(max_span) = args.max_bubble_span {
+        let skipped = retain_bubbles_within_span(
+            &mut ultrabubbles,
+            max_span,
+            &path_data,
+            &path_indices,
+            ref_path_names.as_ref(),
+        );
+        info!(
+            "Skipped {} ultrabubbles spanning more than {} reference bases",
+            skipped, max_span
+        );
+    }
 
-    let vcf_header = variants::vcf::VCFHeader::new(gfa_path);
+    if let Some(limit) = args.limit {
+        if ultrabubbles.len() > limit {
+            ultrabubbles.truncate(limit);
+            info!("Limiting to the first {} ultrabubbles", limit);
+        }
+    }
 
-    println!("{}", vcf_header);
+    let representative_paths =
+        if out.is_none() || args.write_assignments.is_some() {
+            find_representative_paths(&ultrabubbles, &path_data)
+        } else {
+            Vec::new()
+        };
 
-    for vcf in all_vcf_records {
-        println!("{}", vcf);
+    if let Some(path) = &args.write_assignments {
+        write_assignments(path, &representative_paths)?;
     }
 
-    Ok(())
+    let out = match out {
+        Some(out) => out,
+        None => {
+            print_dry_run_summary(
+                &path_data,
+                &ultrabubbles,
+                &representative_paths,
+            );
+            return Ok(());
+        }
+    };
+
+    if let Some(path) = &args.graph_coords {
+        write_graph_coords(
+            path,
+            &path_data,
+            &path_indices,
+            ref_path_names.as_ref(),
+            &ultrabubbles,
+        )?;
+    }
+
+    let mut all_vcf_records = Vec::new();
+
+    let max_alleles = args.max_alleles;
+    let include_ref_blocks = args.include_ref_allele_record;
+    let annotate_inversions = !args.ignore_inverted_paths;
+    let atomize = args.atomize;
+    let only_snps = args.only_snps;
+    let biallelic_only = args.biallelic_only;
+    let split_multiallelic = args.split_multiallelic;
+    let ref_block_end = args.ref_block_end;
+
+    let allele_lengths = variants::AlleleLengthFilter {
+        min_length: args.min_allele_length,
+        max_length: args.max_allele_length,
+        symbolic: args.symbolic_alleles,
+    };
+
+    let var_config = variants::VariantConfig {
+        ignore_inverted_paths: args.ignore_inverted_paths,
+        merge_identical_alleles: args.merge_identical_alleles,
+        only_snps: args.only_snps,
+    };
+
+    // The bubbles are processed against one reference at a time, so
+    // that each reference's records are found in a pass of their own.
+    // When every path is a reference, there's a single pass against
+    // them all, as each path would otherwise be extracted once for
+    // each of the references.
+    let references: Vec<Option<(usize, BString)>> = match &ref_path_names {
+        Some(refs) => path_data
+            .path_names
+            .iter()
+            .enumerate()
+            .filter(|(_, name)| refs.contains(*name))
+            .map(|(path_ix, name)| Some((path_ix, name.clone())))
+            .collect(),
+        None => vec![None],
+    };
+
+    let bubbles_with_variants: Mutex<FnvHashSet<(u64, u64)>> =
+        Mutex::new(FnvHashSet::default());
+    let n_run_bubbles: Mutex<FnvHashSet<(u64, u64)>> =
+        Mutex::new(FnvHashSet::default());
+    let trim_n_runs = args.trim_n_runs;
+
+    let (checkpoint, mut completed) = match &args.checkpoint {
+        Some(dir) => {
+            let (checkpoint, completed) =
+                variants::checkpoint::Checkpoint::open(
+                    dir,
+                    gfa_path,
+                    ultrabubbles.len(),
+                    &checkpoint_options,
+                )?;
+            (Some(checkpoint), completed)
+        }
+        None => (None, Default::default()),
+    };
 
-    /*
-    for (path_name, bubbles) in representative_paths.into_iter().progress_with(p_bar) {
-        let ref_path_name: FnvHashSet<BString> =
-            [path_name].iter().cloned().collect::<FnvHashSet<_>>();
-        let before_len = all_vcf_records.len();
+    // The bubbles of each reference that aren't in the checkpoint,
+    // skipping those the reference doesn't go through
+    let mut remaining_bubbles = Vec::with_capacity(references.len());
+    for reference in references.iter() {
+        let on_reference = |segment: u64| match reference {
+            Some((path_ix, _)) => path_indices
+                .get(&segment)
+                .is_some_and(|steps| steps.contains_key(path_ix)),
+            None => true,
+        };
+        let mut remaining = Vec::new();
+        for &(from, to) in ultrabubbles.iter() {
+            if !on_reference(from) || !on_reference(to) {
+                continue;
+            }
+            let key =
+                (reference.as_ref().map(|(_, name)| name.clone()), (from, to));
+            match completed.remove(&key) {
+                Some(records) => {
+                    if !records.is_empty() {
+                        bubbles_with_variants
+                            .lock()
+                            .unwrap()
+                            .insert((from, to));
+                    }
+                    all_vcf_records.extend(records);
+                }
+                None => remaining.push((from, to)),
+            }
+        }
+        remaining_bubbles.push(remaining);
+    }
+    let remaining_count: usize = remaining_bubbles.iter().map(Vec::len).sum();
+    if references.len() > 1 {
+        info!(
+            "Identifying variants in {} ultrabubbles on {} references",
+            remaining_count,
+            references.len()
+        );
+    } else {
+        info!("Identifying variants in {} ultrabubbles", remaining_count);
+    }
+
+    let p_bar = progress_bar(remaining_count, false);
+
+    let bubble_timings: Option<Mutex<Vec<BubbleTiming>>> = if args.profile {
+        Some(Mutex::new(Vec::with_capacity(remaining_count)))
+    } else {
+        None
+    };
+
+    // Stop taking on new ultrabubbles once interrupted
+    let interrupt_guard = crate::util::catch_interrupts();
+
+    for (reference, bubbles) in references.iter().zip(remaining_bubbles) {
+        if crate::util::interrupted() {
+            break;
+        }
+        let ref_name = reference.as_ref().map(|(_, name)| name.as_bstr());
+        let refs: Option<FnvHashSet<BString>> =
+            ref_name.map(|name| std::iter::once(name.to_owned()).collect());
+
+        // Runs on the global thread pool, which is bounded by the
+        // `--threads` option
         all_vcf_records.par_extend(
             bubbles
                 .par_iter()
-                .filter_map(|&(from, to)| {
-                    let vars = variants::detect_variants_in_sub_paths(
-                        &var_config,
-                        &segment_map,
-                        Some(&ref_path_name),
-                        &all_paths,
-                        &path_indices,
-                        from,
-                        to,
-                    )?;
-
-                    let vcf_records = variants::variant_vcf_record(&vars);
+                .progress_with(p_bar.clone())
+                .map(|&(from, to)| {
+                    if crate::util::interrupted() {
+                        return None;
+                    }
+
+                    let t = Instant::now();
+                    let gap = trim_n_runs.is_some_and(|min_run| {
+                        variants::longest_n_run(
+                            &path_data,
+                            &path_indices,
+                            from,
+                            to,
+                        ) >= min_run
+                    });
+                    let vars = if gap {
+                        n_run_bubbles.lock().unwrap().insert((from, to));
+                        None
+                    } else {
+                        variants::detect_variants_in_sub_paths(
+                            &var_config,
+                            &path_data,
+                            refs.as_ref(),
+                            &path_indices,
+                            from,
+                            to,
+                        )
+                    };
+                    if let Some(timings) = &bubble_timings {
+                        let elapsed = t.elapsed();
+                        timings.lock().unwrap().push(((from, to), elapsed));
+                    }
+
+                    let vcf_records = vars
+                        .map(|mut vars| {
+                            if let Some(consensus_ix) = consensus_ix {
+                                for bubble_vars in vars.values_mut() {
+                                    bubble_vars.remove_path(consensus_ix);
+                                }
+                            }
+                            let mut vcf_records = variants::variant_vcf_record(
+                                &vars,
+                                sample_count,
+                                max_alleles,
+                                &allele_lengths,
+                            );
+                            if annotate_inversions {
+                                for record in vcf_records.iter_mut() {
+                                    record.annotate_inversion();
+                                }
+                            }
+                            if !vcf_records.is_empty() {
+                                bubbles_with_variants
+                                    .lock()
+                                    .unwrap()
+                                    .insert((from, to));
+                            } else if include_ref_blocks {
+                                vcf_records = variants::ref_block_vcf_records(
+                                    &vars,
+                                    &path_data,
+                                    &path_indices,
+                                    sample_count,
+                                    ref_block_end,
+                                );
+                            }
+                            if atomize {
+                                vcf_records = vcf_records
+                                    .into_iter()
+                                    .flat_map(VCFRecord::atomize)
+                                    .collect();
+                            }
+                            // MNPs that weren't atomized are dropped, but
+                            // reference blocks have no ALT and are kept
+                            if only_snps {
+                                vcf_records.retain(|record| {
+                                    record.reference.len() == 1
+                                        || record.alternate.is_none()
+                                });
+                            }
+                            if split_multiallelic {
+                                vcf_records = vcf_records
+                                    .into_iter()
+                                    .flat_map(VCFRecord::split_multiallelic)
+                                    .collect();
+                            } else if biallelic_only {
+                                vcf_records
+                                    .retain(|record| record.alt_count() < 2);
+                            }
+                            if let Some(groups) = &sample_groups {
+                                for record in vcf_records.iter_mut() {
+                                    groups.group_genotypes(record);
+                                }
+                            }
+                            vcf_records
+                        })
+                        .unwrap_or_default();
+                    if let Some(checkpoint) = &checkpoint {
+                        if let Err(err) =
+                            checkpoint.add(ref_name, (from, to), &vcf_records)
+                        {
+                            warn!("Error writing to checkpoint: {}", err);
+                        }
+                    }
                     Some(vcf_records)
                 })
+                .while_some()
                 .flatten(),
         );
-        let after_len = all_vcf_records.len();
-        // info!("\tfound {} variants", after_len - before_len);
     }
-    */
+
+    // Interrupts while the records are sorted and written exit as usual
+    drop(interrupt_guard);
+
+    let interrupted = crate::util::interrupted();
+    if let Some(checkpoint) = &checkpoint {
+        checkpoint.flush()?;
+        if interrupted {
+            info!(
+                "The processed ultrabubbles are saved to {}, rerun with the \
+                 same --checkpoint to resume",
+                checkpoint.path().display()
+            );
+        }
+    }
+    if interrupted {
+        if !args.partial_on_interrupt {
+            // Don't leave the empty output file behind
+            drop(out);
+            if let (Some(path), false) = (&args.output, args.split_by_ref) {
+                if !args.dry_run {
+                    std::fs::remove_file(path)?;
+                }
+            }
+            return Err("Interrupted, no records were written. Use \
+                        --partial-on-interrupt to write the records found \
+                        before the interrupt"
+                .into());
+        }
+        warn!(
+            "Interrupted, writing the {} records found so far",
+            all_vcf_records.len()
+        );
+    } else {
+        info!("Variant identification complete");
+    }
+
+    if let Some(min_run) = trim_n_runs {
+        debug!(
+            "Skipped {} ultrabubbles with runs of at least {} Ns",
+            n_run_bubbles.into_inner().unwrap().len(),
+            min_run
+        );
+    }
+
+    if let Some(timings) = bubble_timings {
+        let timings = timings.into_inner().unwrap();
+        let total: Duration = timings.iter().map(|(_, t)| *t).sum();
+        let slowest = slowest_bubbles(timings, args.profile_top);
+        info!(
+            "Variant detection took {:.3} s in total; the {} slowest \
+             ultrabubbles were:",
+            total.as_secs_f64(),
+            slowest.len()
+        );
+        for ((from, to), elapsed) in slowest {
+            info!(
+                "  {:>12} {:>12} {:>12.3} ms",
+                from,
+                to,
+                elapsed.as_secs_f64() * 1000.0
+            );
+        }
+    }
+
+    if args.normalize {
+        info!("Normalizing variants");
+        variants::normalize::normalize_records(
+            &path_data,
+            &mut all_vcf_records,
+        );
+    }
+
+    if let Some(path) = &args.targets {
+        let targets =
+            TargetIntervals::new(super::saboten::load_bed_intervals(path)?);
+        for chrom in targets.chroms() {
+            if !path_data.path_names.contains(chrom) {
+                warn!(
+                    "Target intervals are on {}, which is not a path in \
+                     the graph",
+                    chrom
+                );
+            }
+        }
+        let count = all_vcf_records.len();
+        all_vcf_records.retain(|record| {
+            targets.contains(&record.chromosome, record.position as usize)
+        });
+        info!(
+            "{} of {} records are in the target intervals",
+            all_vcf_records.len(),
+            count
+        );
+    }
+
+    let contigs: Vec<(BString, usize)> = path_data
+        .path_names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| match &ref_path_names {
+            Some(refs) => refs.contains(*name),
+            None => true,
+        })
+        .map(|(path_ix, name)| (name.clone(), path_data.path_length(path_ix)))
+        .collect();
+
+    if let Some(path) = &args.write_fai {
+        write_contig_lengths(path, &contigs)?;
+    }
+
+    variants::vcf::merge_duplicate_records(&mut all_vcf_records, &contigs);
+
+    // Only the merged genotypes give the frequencies across all paths
+    if let Some(min_af) = args.min_af {
+        let count = all_vcf_records.len();
+        all_vcf_records
+            .retain_mut(|record| record.drop_rare_alleles(min_af, max_alleles));
+        info!(
+            "Dropped {} of {} records with no ALT alleles of frequency at \
+             least {}",
+            count - all_vcf_records.len(),
+            count,
+            min_af
+        );
+    }
+
+    // Added after the records are merged, as the merged genotypes
+    // must be counted
+    if args.allele_depth {
+        for record in all_vcf_records.iter_mut() {
+            record.add_allele_depths();
+        }
+    }
+
+    // The indices of the records from the same bubble follow the
+    // final order, so they're assigned last
+    if args.id_from_bubble {
+        variants::vcf::assign_bubble_ids(&mut all_vcf_records);
+    }
+
+    info!("Writing {} unique VCF records", all_vcf_records.len());
+
+    let command =
+        std::env::args_os().map(|arg| arg.to_string_lossy().into_owned());
+    let mut vcf_header = variants::vcf::VCFHeader::new(
+        gfa_path,
+        sample_names,
+        contigs,
+        max_alleles,
+    )
+    .with_command(command);
+    if unphased {
+        vcf_header = vcf_header.unphased();
+    }
+    if args.allele_depth {
+        vcf_header = vcf_header.with_allele_depth();
+    }
+
+    // The files written, for the manifest
+    let mut output_files: Vec<PathBuf> = Vec::new();
+    match (out, args.output.as_deref()) {
+        (Some(mut out), output) => {
+            write_records(
+                &mut out,
+                args.format,
+                &vcf_header,
+                args.no_header,
+                all_vcf_records.iter(),
+            )?;
+            // The bgzipped file must be finished before it's read back
+            // to index it
+            out.finish()?;
+            if let Some(path) = output {
+                if args.tabix {
+                    index_output(path)?;
+                }
+                output_files.push(path.to_owned());
+            }
+        }
+        (None, Some(output)) => {
+            let mut by_ref: FnvHashMap<&[u8], Vec<&VCFRecord>> =
+                FnvHashMap::default();
+            for record in all_vcf_records.iter() {
+                by_ref
+                    .entry(record.chromosome.as_slice())
+                    .or_default()
+                    .push(record);
+            }
+            for contig in vcf_header.contigs() {
+                let path = split_output_path(output, &contig.0);
+                let records = by_ref.remove(contig.0.as_slice());
+                let mut out = vcf_writer(Some(&path), args.bgzip)?;
+                write_records(
+                    &mut out,
+                    args.format,
+                    &vcf_header.clone().with_contigs(vec![contig.clone()]),
+                    args.no_header,
+                    records.into_iter().flatten(),
+                )?;
+                out.finish()?;
+                if args.tabix {
+                    index_output(&path)?;
+                }
+                output_files.push(path);
+            }
+        }
+        (None, None) => unreachable!("--split-by-ref requires --output"),
+    }
+
+    let mut counts = variants::vcf::RecordCounts::default();
+    for vcf in all_vcf_records.iter() {
+        counts.add(vcf);
+    }
+
+    info!(
+        "Wrote {} records ({} SNVs, {} insertions, {} deletions, {} \
+         complex, {} reference); {} of {} ultrabubbles had no variants",
+        counts.total(),
+        counts.snv,
+        counts.insertion,
+        counts.deletion,
+        counts.complex,
+        counts.reference,
+        ultrabubbles.len() - bubbles_with_variants.into_inner().unwrap().len(),
+        ultrabubbles.len()
+    );
+
+    if interrupted {
+        return Err("Interrupted, the output only contains the records \
+                    found before the interrupt"
+            .into());
+    }
+
+    if let Some(path) = &args.manifest {
+        let (segments, links, paths) = graph_counts;
+        let manifest = RunManifest {
+            version: env!("CARGO_PKG_VERSION"),
+            gfa: gfa_path.display().to_string(),
+            gfa_size: std::fs::metadata(gfa_path).ok().map(|m| m.len()),
+            segments,
+            links,
+            paths,
+            ultrabubbles: ultrabubbles.len(),
+            reference_paths: vcf_header
+                .contigs()
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            records: counts.total(),
+            command: std::env::args_os()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect(),
+            wall_clock_seconds: start_time.elapsed().as_secs_f64(),
+            outputs: output_files
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+        };
+        write_manifest(path, &manifest)?;
+    }
+
+    Ok(())
 }
 
-#[allow(dead_code)]
-fn find_representative_paths(
+/// A description of a successful `gfa2vcf` run, written as JSON with
+/// `--manifest` for pipelines to pick up.
+#[derive(Debug, Clone, Serialize)]
+struct RunManifest {
+    version: &'static str,
+    gfa: String,
+    /// The size of the GFA file in bytes.
+    gfa_size: Option<u64>,
+    segments: usize,
+    /// Links are only parsed when the ultrabubbles are found in the
+    /// graph, rather than loaded from a file.
+    links: Option<usize>,
+    paths: usize,
+    ultrabubbles: usize,
+    reference_paths: Vec<String>,
+    records: usize,
+    command: Vec<String>,
+    wall_clock_seconds: f64,
+    /// The output files: the `-o` file, or one file per reference
+    /// with `--split-by-ref`, or none for stdout.
+    outputs: Vec<String>,
+}
+
+fn write_manifest(path: &Path, manifest: &RunManifest) -> Result<()> {
+    let file = File::create(path).map_err(|err| {
+        format!("Error creating file {}: {}", path.display(), err)
+    })?;
+    let mut out = BufWriter::new(file);
+    serde_json::to_writer_pretty(&mut out, manifest)?;
+    writeln!(out)?;
+    out.flush()?;
+    info!("Wrote run manifest to {}", path.display());
+    Ok(())
+}
+
+/// Build the consensus reference along the path named `backbone`,
+/// or the first path if it's `None`, and add it to `path_data` and
+/// `path_indices` as the last path, returning its index.
+fn add_consensus_path(
+    path_data: &mut variants::PathData,
+    path_indices: &mut variants::PathIndices,
     ultrabubbles: &[(u64, u64)],
-    all_paths: &FnvHashMap<BString, Vec<PathStep>>,
-) -> Vec<(BString, Vec<(u64, u64)>)> {
-    let mut representative_paths = Vec::new();
+    backbone: Option<&str>,
+    name: &str,
+) -> Result<usize> {
+    if path_data.path_names.iter().any(|path| path == name) {
+        return Err(format!(
+            "The graph already has a path named {}, use --consensus-name \
+             to give the consensus reference another name",
+            name
+        )
+        .into());
+    }
 
-    let mut remaining_ultrabubbles: FnvHashMap<u64, u64> =
-        ultrabubbles.iter().copied().collect();
+    let backbone_ix = match backbone {
+        Some(backbone) => path_data
+            .path_names
+            .iter()
+            .position(|path| path == backbone)
+            .ok_or_else(|| {
+                format!("Consensus backbone path not in graph: {}", backbone)
+            })?,
+        None => 0,
+    };
 
-    info!("Building set of reference paths");
-    let p_bar = ProgressBar::new(all_paths.len() as u64);
-    p_bar.set_style(
-        ProgressStyle::default_bar()
-            .template("[{elapsed_precise}] {bar:80} {pos:>7}/{len:7}")
-            .progress_chars("##-"),
+    let (steps, changed) = variants::consensus::consensus_path(
+        path_data,
+        path_indices,
+        ultrabubbles,
+        backbone_ix,
     );
 
-    for (path, steps) in all_paths.iter().progress_with(p_bar) {
-        if remaining_ultrabubbles.is_empty() {
-            break;
+    let consensus_ix = path_data.paths.len();
+    variants::consensus::add_path_indices(path_indices, consensus_ix, &steps);
+    path_data.path_names.push(name.into());
+    path_data.paths.push(steps);
+
+    info!(
+        "Built consensus reference {} along {}, of length {}, differing \
+         from it in {} ultrabubbles",
+        name,
+        path_data.path_names[backbone_ix],
+        path_data.path_length(consensus_ix),
+        changed
+    );
+
+    Ok(consensus_ix)
+}
+
+/// Write the reference paths and their lengths, one tab-separated
+/// pair per line, in the same order as the `##contig` lines.
+fn write_contig_lengths(
+    path: &Path,
+    contigs: &[(BString, usize)],
+) -> Result<()> {
+    let file = File::create(path).map_err(|err| {
+        format!("Error creating file {}: {}", path.display(), err)
+    })?;
+    let mut out = BufWriter::new(file);
+    for (name, length) in contigs.iter() {
+        writeln!(out, "{}\t{}", name, length)?;
+    }
+    out.flush()?;
+    info!(
+        "Wrote {} reference path lengths to {}",
+        contigs.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Remove the `exclude` paths from the path data, and from the
+/// reference paths if given. Excluding a reference path takes
+/// precedence, with a warning, but excluding all of them is an
+/// error.
+fn exclude_named_paths(
+    path_data: &mut variants::PathData,
+    exclude: &FnvHashSet<BString>,
+    ref_path_names: Option<&mut FnvHashSet<BString>>,
+) -> Result<()> {
+    if let Some(ref_paths) = ref_path_names {
+        let mut excluded_refs: Vec<&BString> =
+            ref_paths.intersection(exclude).collect();
+        excluded_refs.sort();
+        if !excluded_refs.is_empty() {
+            warn!(
+                "Excluding paths that were also given as references: {}",
+                bstr::join(", ", &excluded_refs).as_bstr()
+            );
+        }
+        ref_paths.retain(|path| !exclude.contains(path));
+        if ref_paths.is_empty() {
+            return Err("All of the reference paths are excluded".into());
         }
-        let path_name = path.clone().to_owned();
+    }
+
+    let removed = path_data.remove_named_paths(exclude);
+    if removed < exclude.len() {
+        warn!(
+            "{} of the excluded paths do not exist in the graph",
+            exclude.len() - removed
+        );
+    }
+    info!("Excluding {} paths", removed);
+
+    Ok(())
+}
 
-        let maybe_contained: Vec<(u64, u64)> = steps
+/// Remove the ultrabubbles that span more than `max_span` bases on
+/// any of the reference paths, or on any path if no references are
+/// given, and return the number removed.
+fn retain_bubbles_within_span(
+    ultrabubbles: &mut Vec<(u64, u64)>,
+    max_span: usize,
+    path_data: &variants::PathData,
+    path_indices: &variants::PathIndices,
+    ref_path_names: Option<&FnvHashSet<BString>>,
+) -> usize {
+    let ref_ixs: Vec<usize> = path_data
+        .path_names
+        .iter()
+        .enumerate()
+        .filter(|(_, name)| {
+            ref_path_names.is_none_or(|refs| refs.contains(*name))
+        })
+        .map(|(ix, _)| ix)
+        .collect();
+
+    let count = ultrabubbles.len();
+    ultrabubbles.retain(|&(from, to)| {
+        let span = ref_ixs
             .iter()
-            .filter_map(|&(step, _, _)| {
-                let x = step as u64;
-                let y = remaining_ultrabubbles.get(&x)?;
-                Some((*y, x))
+            .filter_map(|&path_ix| {
+                variants::bubble_path_range(
+                    path_data,
+                    path_indices,
+                    path_ix,
+                    from,
+                    to,
+                )
             })
-            .collect();
+            .map(|(start, end)| end + 1 - start)
+            .max();
+        match span {
+            Some(span) if span > max_span => {
+                debug!(
+                    "Skipping ultrabubble {}-{} spanning {} reference bases",
+                    from, to, span
+                );
+                false
+            }
+            _ => true,
+        }
+    });
+    count - ultrabubbles.len()
+}
 
-        if maybe_contained.is_empty() {
-            continue;
+/// Keep only the ultrabubbles that lie entirely within one of the
+/// BED intervals, on the path named by the interval's chrom.
+fn retain_bubbles_in_intervals(
+    ultrabubbles: &mut Vec<(u64, u64)>,
+    intervals: &[BedInterval],
+    path_data: &variants::PathData,
+    path_indices: &variants::PathIndices,
+) {
+    let path_ixs: FnvHashMap<&BString, usize> = path_data
+        .path_names
+        .iter()
+        .enumerate()
+        .map(|(ix, name)| (name, ix))
+        .collect();
+
+    // The intervals on each path, as 1-based inclusive ranges
+    let mut path_intervals: FnvHashMap<usize, Vec<(usize, usize)>> =
+        FnvHashMap::default();
+    for interval in intervals {
+        match path_ixs.get(&interval.chrom) {
+            Some(&path_ix) => path_intervals
+                .entry(path_ix)
+                .or_default()
+                .push((interval.start + 1, interval.end)),
+            None => warn!(
+                "BED interval {}:{}-{} is on a path that's not in the graph",
+                interval.chrom, interval.start, interval.end
+            ),
         }
+    }
 
-        let contained = steps
-            .iter()
-            .flat_map(|&(step, _, _)| {
-                let y = step as u64;
-                maybe_contained.iter().filter_map(move |&(a, b)| {
-                    if a == y {
-                        Some((b, a))
-                    } else {
-                        None
+    ultrabubbles.retain(|&(from, to)| {
+        path_intervals.iter().any(|(&path_ix, ranges)| {
+            let range = variants::bubble_path_range(
+                path_data,
+                path_indices,
+                path_ix,
+                from,
+                to,
+            );
+            match range {
+                Some((bubble_start, bubble_end)) => {
+                    ranges.iter().any(|&(start, end)| {
+                        start <= bubble_start && bubble_end <= end
+                    })
+                }
+                None => false,
+            }
+        })
+    });
+}
+
+/// BED intervals for filtering records by position, as sorted and
+/// merged 1-based inclusive ranges for each chrom, so that a position
+/// can be looked up with a binary search.
+struct TargetIntervals {
+    ranges: FnvHashMap<BString, Vec<(usize, usize)>>,
+}
+
+impl TargetIntervals {
+    fn new(intervals: Vec<BedInterval>) -> Self {
+        let mut ranges: FnvHashMap<BString, Vec<(usize, usize)>> =
+            FnvHashMap::default();
+        for interval in intervals {
+            // Empty intervals contain no positions
+            if interval.start < interval.end {
+                ranges
+                    .entry(interval.chrom)
+                    .or_default()
+                    .push((interval.start + 1, interval.end));
+            }
+        }
+
+        // Overlapping and adjacent ranges are merged, so the range
+        // that starts last at or before a position is the only one
+        // that can contain it
+        for chrom_ranges in ranges.values_mut() {
+            chrom_ranges.sort_unstable();
+            let mut merged: Vec<(usize, usize)> =
+                Vec::with_capacity(chrom_ranges.len());
+            for &(start, end) in chrom_ranges.iter() {
+                match merged.last_mut() {
+                    Some(last) if start <= last.1 + 1 => {
+                        last.1 = last.1.max(end);
                     }
-                })
-            })
-            .collect::<Vec<_>>();
+                    _ => merged.push((start, end)),
+                }
+            }
+            *chrom_ranges = merged;
+        }
 
-        for &(x, _y) in contained.iter() {
-            remaining_ultrabubbles.remove(&x);
+        Self { ranges }
+    }
+
+    fn chroms(&self) -> impl Iterator<Item = &BString> {
+        self.ranges.keys()
+    }
+
+    /// Whether the 1-based position `pos` on `chrom` is in any of the
+    /// intervals.
+    fn contains(&self, chrom: &BString, pos: usize) -> bool {
+        let ranges = match self.ranges.get(chrom) {
+            Some(ranges) => ranges,
+            None => return false,
+        };
+        let ix = ranges.partition_point(|&(start, _)| start <= pos);
+        ix > 0 && pos <= ranges[ix - 1].1
+    }
+}
+
+/// Write `records` to `out` in the given format, with the header
+/// first for VCF output unless `no_header`.
+fn write_records<'a, W, I>(
+    out: &mut W,
+    format: OutputFormat,
+    header: &variants::vcf::VCFHeader,
+    no_header: bool,
+    records: I,
+) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a VCFRecord>,
+{
+    match format {
+        OutputFormat::VCF => {
+            if !no_header {
+                writeln!(out, "{}", header)?;
+            }
+            for vcf in records {
+                writeln!(out, "{}", vcf)?;
+            }
+        }
+        OutputFormat::JSON => {
+            for vcf in records {
+                let record = variants::json::JsonRecord::from_vcf_record(
+                    vcf,
+                    header.sample_names(),
+                );
+                serde_json::to_writer(&mut *out, &record)?;
+                writeln!(out)?;
+            }
         }
+    }
+    Ok(())
+}
+
+/// Write a tabix index of the bgzipped VCF at `path`.
+fn index_output(path: &Path) -> Result<()> {
+    let index_path = crate::tabix::write_vcf_index(path)
+        .map_err(|err| format!("Error indexing {}: {}", path.display(), err))?;
+    info!("Wrote tabix index to {}", index_path.display());
+    Ok(())
+}
+
+/// The file the records of `reference` are written to with
+/// `--split-by-ref`: `output` with the name of the reference before
+/// its extension, which is ".vcf.gz" for bgzipped VCFs. Slashes and
+/// control characters in the name are replaced with underscores.
+fn split_output_path(output: &Path, reference: &[u8]) -> PathBuf {
+    let file_name = output
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let (stem, extension) = match file_name.strip_suffix(".vcf.gz") {
+        Some(stem) => (stem, ".vcf.gz"),
+        None => match file_name.rfind('.') {
+            Some(ix) if ix > 0 => file_name.split_at(ix),
+            _ => (file_name.as_str(), ""),
+        },
+    };
+    let reference: String = reference
+        .to_str_lossy()
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    output.with_file_name(format!("{}.{}{}", stem, reference, extension))
+}
 
-        let bubbles = contained.into_iter().collect::<Vec<_>>();
+/// The ends of an ultrabubble, and how long detecting its variants
+/// took.
+type BubbleTiming = ((u64, u64), Duration);
 
-        if !bubbles.is_empty() {
-            representative_paths.push((path_name, bubbles));
+/// The `n` ultrabubbles whose variant detection took the longest,
+/// slowest first, with the times of a bubble that was processed
+/// against several references added up. Ties are broken by the
+/// bubble ends, so the result doesn't depend on the order the threads
+/// finished in.
+fn slowest_bubbles(timings: Vec<BubbleTiming>, n: usize) -> Vec<BubbleTiming> {
+    let mut totals: FnvHashMap<(u64, u64), Duration> = FnvHashMap::default();
+    for (ends, elapsed) in timings {
+        *totals.entry(ends).or_default() += elapsed;
+    }
+    let mut timings: Vec<BubbleTiming> = totals.into_iter().collect();
+    timings.sort_by(|(a_ends, a_t), (b_ends, b_t)| {
+        b_t.cmp(a_t).then(a_ends.cmp(b_ends))
+    });
+    timings.truncate(n);
+    timings
+}
+
+/// Write the alleles of each ultrabubble as the segments they
+/// traverse, one tab-separated line per allele; see `--graph-coords`.
+fn write_graph_coords(
+    path: &Path,
+    path_data: &variants::PathData,
+    path_indices: &variants::PathIndices,
+    ref_path_names: Option<&FnvHashSet<BString>>,
+    ultrabubbles: &[(u64, u64)],
+) -> Result<()> {
+    let file = File::create(path).map_err(|err| {
+        format!("Error creating file {}: {}", path.display(), err)
+    })?;
+    let mut out = BufWriter::new(file);
+
+    let bubble_alleles: Vec<_> = ultrabubbles
+        .par_iter()
+        .map(|&(from, to)| {
+            let alleles = variants::bubble_alleles(
+                path_data,
+                path_indices,
+                ref_path_names,
+                from,
+                to,
+            );
+            ((from, to), alleles)
+        })
+        .collect();
+
+    let mut count = 0;
+    for ((from, to), alleles) in bubble_alleles {
+        for allele in alleles {
+            let steps: Vec<String> = allele
+                .steps
+                .iter()
+                .map(|&(node, orient)| {
+                    let orient = match orient {
+                        Orientation::Forward => '+',
+                        Orientation::Backward => '-',
+                    };
+                    format!("{}{}", node, orient)
+                })
+                .collect();
+            let paths = bstr::join(
+                ",",
+                allele.paths.iter().map(|&ix| &path_data.path_names[ix]),
+            );
+            writeln!(
+                out,
+                "{}\t{}\t{}\t{}\t{}",
+                from,
+                to,
+                if allele.is_ref { "REF" } else { "ALT" },
+                steps.join(","),
+                paths.as_bstr()
+            )?;
+            count += 1;
         }
     }
+    out.flush()?;
+    info!(
+        "Wrote {} alleles of {} ultrabubbles to {}",
+        count,
+        ultrabubbles.len(),
+        path.display()
+    );
+    Ok(())
+}
 
-    info!("Chose {} reference paths", representative_paths.len());
+/// A representative path and the ultrabubbles assigned to it.
+type PathBubbles = (BString, Vec<(u64, u64)>);
+
+fn print_dry_run_summary(
+    path_data: &variants::PathData,
+    ultrabubbles: &[(u64, u64)],
+    representative_paths: &[PathBubbles],
+) {
+    let assigned: usize =
+        representative_paths.iter().map(|(_, b)| b.len()).sum();
+
+    eprintln!("{:<32}{:>12}", "paths", path_data.paths.len());
+    eprintln!("{:<32}{:>12}", "ultrabubbles", ultrabubbles.len());
+    eprintln!(
+        "{:<32}{:>12}",
+        "representative paths",
+        representative_paths.len()
+    );
+    eprintln!(
+        "{:<32}{:>12}",
+        "ultrabubbles not on any path",
+        ultrabubbles.len() - assigned
+    );
+}
+
+/// Write the ultrabubbles assigned to each representative path, one
+/// tab-separated line of path name, from, and to per bubble.
+fn write_assignments(
+    path: &Path,
+    representative_paths: &[PathBubbles],
+) -> Result<()> {
+    let file = File::create(path).map_err(|err| {
+        format!("Error creating file {}: {}", path.display(), err)
+    })?;
+    let mut out = BufWriter::new(file);
+    let mut count = 0;
+    for (name, bubbles) in representative_paths.iter() {
+        for (from, to) in bubbles.iter() {
+            writeln!(out, "{}\t{}\t{}", name, from, to)?;
+            count += 1;
+        }
+    }
+    out.flush()?;
     info!(
-        "{} ultrabubbles did not exist in any paths",
-        remaining_ultrabubbles.len()
+        "Wrote {} ultrabubble assignments to {}",
+        count,
+        path.display()
     );
+    Ok(())
+}
+
+/// Assign each ultrabubble to the first path, in the order the paths
+/// appear in the GFA, that contains both of its ends. The result is
+/// the same regardless of how many threads are used.
+fn find_representative_paths(
+    ultrabubbles: &[(u64, u64)],
+    path_data: &variants::PathData,
+) -> Vec<PathBubbles> {
+    // Bubbles in a chain share their ends, so there are about as many
+    // ends as bubbles; sizing the map for them up front saves the
+    // rehashing as it grows
+    let mut bubble_ends: FnvHashMap<u64, Vec<usize>> =
+        FnvHashMap::with_capacity_and_hasher(
+            ultrabubbles.len(),
+            Default::default(),
+        );
+    for (ix, &(from, to)) in ultrabubbles.iter().enumerate() {
+        bubble_ends.entry(from).or_default().push(ix);
+        if to != from {
+            bubble_ends.entry(to).or_default().push(ix);
+        }
+    }
+
+    info!("Building set of reference paths");
+    let p_bar = progress_bar(path_data.paths.len(), false);
+
+    let claims: Vec<usize> = path_data
+        .paths
+        .par_iter()
+        .enumerate()
+        .progress_with(p_bar)
+        .fold(
+            || vec![usize::MAX; ultrabubbles.len()],
+            |mut claims, (path_ix, steps)| {
+                let mut seen_from: FnvHashSet<usize> = FnvHashSet::default();
+                let mut seen_to: FnvHashSet<usize> = FnvHashSet::default();
+
+                for &(step, _, _) in steps.iter() {
+                    let node = step as u64;
+                    let bubbles = bubble_ends.get(&node).into_iter().flatten();
+                    for &ix in bubbles {
+                        let (from, to) = ultrabubbles[ix];
+                        if from == node {
+                            seen_from.insert(ix);
+                        }
+                        if to == node {
+                            seen_to.insert(ix);
+                        }
+                    }
+                }
+
+                for &ix in seen_from.intersection(&seen_to) {
+                    claims[ix] = claims[ix].min(path_ix);
+                }
+                claims
+            },
+        )
+        .reduce(
+            || vec![usize::MAX; ultrabubbles.len()],
+            |mut claims, other| {
+                claims.iter_mut().zip(other).for_each(|(c, o)| {
+                    *c = (*c).min(o);
+                });
+                claims
+            },
+        );
+
+    let mut path_bubbles: Vec<Vec<(u64, u64)>> =
+        vec![Vec::new(); path_data.paths.len()];
+    let mut unclaimed = 0;
+    for (ix, &path_ix) in claims.iter().enumerate() {
+        if path_ix == usize::MAX {
+            unclaimed += 1;
+        } else {
+            path_bubbles[path_ix].push(ultrabubbles[ix]);
+        }
+    }
+
+    let representative_paths: Vec<_> = path_data
+        .path_names
+        .iter()
+        .cloned()
+        .zip(path_bubbles)
+        .filter(|(_, bubbles)| !bubbles.is_empty())
+        .collect();
+
+    info!("Chose {} reference paths", representative_paths.len());
+    info!("{} ultrabubbles did not exist in any paths", unclaimed);
 
     representative_paths
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gfa::{gfa::GFA, parser::GFAParser};
+
+    fn path_data() -> variants::PathData {
+        let parser = GFAParser::new();
+        let gfa: GFA<usize, ()> = parser
+            .parse_lines(
+                [
+                    "S\t1\tA",
+                    "S\t2\tC",
+                    "S\t3\tG",
+                    "S\t4\tT",
+                    "S\t5\tA",
+                    "P\tp1\t1+,2+,4+\t*",
+                    "P\tp2\t1+,3+,4+,5+\t*",
+                    "P\tp3\t4+,5+\t*",
+                ]
+                .iter()
+                .map(|l| l.as_bytes()),
+            )
+            .unwrap();
+        variants::gfa_path_data(gfa, false, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn representative_paths_are_deterministic() {
+        let path_data = path_data();
+        let ultrabubbles = [(1, 4), (4, 5), (2, 5), (6, 7)];
+
+        let expected: Vec<(BString, Vec<(u64, u64)>)> =
+            vec![("p1".into(), vec![(1, 4)]), ("p2".into(), vec![(4, 5)])];
+
+        for &threads in &[1, 2, 4] {
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            let result = pool.install(|| {
+                find_representative_paths(&ultrabubbles, &path_data)
+            });
+            assert_eq!(expected, result);
+        }
+    }
+
+    #[test]
+    fn paths_file_skips_comments_and_blank_lines() {
+        let file: &[u8] =
+            b"# reference paths\r\nref\r\n\nalt1\r\n  \n#alt2\nalt3\n\n";
+        let paths = read_path_names(file).unwrap();
+        assert_eq!(vec!["ref", "alt1", "alt3"], paths);
+    }
+
+    #[test]
+    fn mapped_and_sanitized_sample_names() {
+        let path_names: Vec<BString> =
+            vec!["p1".into(), "p2 hap1".into(), "p3".into()];
+        let mapping: FnvHashMap<BString, BString> =
+            std::iter::once(("p3".into(), "sample 3".into())).collect();
+
+        let names = sample_names(&path_names, Some(&mapping)).unwrap();
+        assert_eq!(vec!["p1", "p2_hap1", "sample_3"], names);
+
+        let names = sample_names(&path_names, None).unwrap();
+        assert_eq!(vec!["p1", "p2_hap1", "p3"], names);
+
+        let mapping: FnvHashMap<BString, BString> =
+            std::iter::once(("p3".into(), "p1".into())).collect();
+        assert!(sample_names(&path_names, Some(&mapping)).is_err());
+    }
+
+    #[test]
+    fn haplotypes_grouped_into_samples() {
+        let path_names: Vec<BString> = vec![
+            "HG002#1".into(),
+            "ref".into(),
+            "HG002#2".into(),
+            "HG005#1".into(),
+        ];
+        let mapping: FnvHashMap<BString, BString> = vec![
+            ("HG002#1".into(), "HG002".into()),
+            ("HG002#2".into(), "HG002".into()),
+            ("HG005#1".into(), "HG005".into()),
+        ]
+        .into_iter()
+        .collect();
+
+        let groups = sample_groups(&path_names, &mapping, 2).unwrap();
+        assert_eq!(vec!["HG002", "ref", "HG005"], groups.sample_names());
+        assert_eq!(&[vec![0, 2], vec![1], vec![3]], groups.haplotypes());
+
+        // An unlisted path can't share a name with a sample
+        let mapping: FnvHashMap<BString, BString> =
+            std::iter::once(("HG002#1".into(), "ref".into())).collect();
+        assert!(sample_groups(&path_names, &mapping, 2).is_err());
+    }
+
+    #[test]
+    fn reference_path_patterns() {
+        let path_names: Vec<BString> = vec![
+            "GRCh38#chr1".into(),
+            "HG002#1#chr1".into(),
+            "GRCh38#chr2".into(),
+            "CHM13#chr1".into(),
+        ];
+        let prefixes = vec!["GRCh38#".to_string()];
+        let regexes = vec![Regex::new("^CHM13#").unwrap()];
+
+        assert_eq!(
+            vec!["GRCh38#chr1", "GRCh38#chr2"],
+            matching_path_names(&path_names, &prefixes, &[])
+        );
+        assert_eq!(
+            vec!["GRCh38#chr1", "GRCh38#chr2", "CHM13#chr1"],
+            matching_path_names(&path_names, &prefixes, &regexes)
+        );
+        assert_eq!(
+            vec!["GRCh38#chr1", "HG002#1#chr1", "CHM13#chr1"],
+            matching_path_names(
+                &path_names,
+                &[],
+                &[Regex::new("#chr1$").unwrap()]
+            )
+        );
+        assert!(
+            matching_path_names(&path_names, &["chm13".into()], &[]).is_empty()
+        );
+    }
+
+    #[test]
+    fn bubbles_in_bed_intervals() {
+        let path_data = path_data();
+        let ultrabubbles = vec![(1, 4), (4, 5), (2, 5)];
+        let nodes = [1, 2, 4, 5].iter().copied().collect();
+        let path_indices =
+            variants::bubble_path_indices(&path_data.paths, &nodes);
+
+        let interval = |chrom: &str, start, end| BedInterval {
+            chrom: chrom.into(),
+            start,
+            end,
+        };
+
+        let retained = |intervals: &[BedInterval]| {
+            let mut ultrabubbles = ultrabubbles.clone();
+            retain_bubbles_in_intervals(
+                &mut ultrabubbles,
+                intervals,
+                &path_data,
+                &path_indices,
+            );
+            ultrabubbles
+        };
+
+        assert_eq!(vec![(1, 4)], retained(&[interval("p1", 0, 3)]));
+        assert!(retained(&[interval("p1", 1, 3)]).is_empty());
+        assert_eq!(
+            vec![(4, 5)],
+            retained(&[interval("p2", 2, 4), interval("missing", 0, 10)])
+        );
+        assert_eq!(vec![(1, 4), (4, 5)], retained(&[interval("p2", 0, 4)]));
+    }
+
+    #[test]
+    fn bubbles_within_reference_span() {
+        let path_data = path_data();
+        let nodes = [1, 2, 4, 5].iter().copied().collect();
+        let path_indices =
+            variants::bubble_path_indices(&path_data.paths, &nodes);
+
+        let retained = |max_span, refs: Option<&[&str]>| {
+            let refs: Option<FnvHashSet<BString>> =
+                refs.map(|refs| refs.iter().map(|&r| r.into()).collect());
+            let mut ultrabubbles = vec![(1, 4), (4, 5), (2, 5)];
+            let skipped = retain_bubbles_within_span(
+                &mut ultrabubbles,
+                max_span,
+                &path_data,
+                &path_indices,
+                refs.as_ref(),
+            );
+            assert_eq!(3 - ultrabubbles.len(), skipped);
+            ultrabubbles
+        };
+
+        // 1-4 spans 3 bases on both p1 and p2, 4-5 spans 2 on p2 and
+        // p3, and no path traverses all of 2-5
+        assert_eq!(vec![(1, 4), (4, 5), (2, 5)], retained(3, None));
+        assert_eq!(vec![(4, 5), (2, 5)], retained(2, None));
+        assert_eq!(vec![(4, 5), (2, 5)], retained(2, Some(&["p1"])));
+        assert_eq!(vec![(2, 5)], retained(1, Some(&["p2"])));
+        assert_eq!(
+            vec![(1, 4), (4, 5), (2, 5)],
+            retained(0, Some(&["missing"]))
+        );
+    }
+
+    #[test]
+    fn comma_separated_refs() {
+        let ref_paths = |args: &[&str]| {
+            let args = std::iter::once("gfa2vcf").chain(args.iter().copied());
+            let args = GFA2VCFArgs::from_iter_safe(args).unwrap();
+            paths_list(args.ref_paths_vec.unwrap())
+                .into_iter()
+                .collect::<FnvHashSet<_>>()
+        };
+
+        let repeated = ref_paths(&["--refs", "a", "--refs", "b", "c"]);
+        assert_eq!(3, repeated.len());
+        assert_eq!(repeated, ref_paths(&["--refs", "a,b,c"]));
+        assert_eq!(repeated, ref_paths(&["--refs", " a , b,", "--refs", "c"]));
+    }
+
+    #[test]
+    fn positions_in_target_intervals() {
+        let interval = |chrom: &str, start, end| BedInterval {
+            chrom: chrom.into(),
+            start,
+            end,
+        };
+        // On p1, 5-10 and 8-12 overlap, 12-15 is adjacent to them, and
+        // 20-20 is empty
+        let targets = TargetIntervals::new(vec![
+            interval("p1", 7, 12),
+            interval("p1", 4, 10),
+            interval("p1", 12, 15),
+            interval("p1", 30, 40),
+            interval("p1", 20, 20),
+            interval("p2", 0, 1),
+        ]);
+
+        assert_eq!(
+            vec![(5, 15), (31, 40)],
+            targets.ranges[&BString::from("p1")]
+        );
+
+        let p1 = BString::from("p1");
+        let inside: Vec<usize> =
+            (0..45).filter(|&pos| targets.contains(&p1, pos)).collect();
+        let expected: Vec<usize> = (5..=15).chain(31..=40).collect();
+        assert_eq!(expected, inside);
+
+        let p2 = BString::from("p2");
+        assert!(targets.contains(&p2, 1));
+        assert!(!targets.contains(&p2, 2));
+        assert!(!targets.contains(&BString::from("p3"), 1));
+    }
+
+    #[test]
+    fn auto_ref_rules() {
+        let data = variants::gfa_path_data(
+            GFAParser::new()
+                .parse_lines(
+                    [
+                        "S\t1\tACGT",
+                        "S\t2\tA",
+                        "S\t3\tGGTTAC",
+                        "S\t4\tC",
+                        "S\t5\tT",
+                        "P\tshort\t1+,2+,4+\t*",
+                        "P\tlong\t1+,3+,4+\t*",
+                        "P\tbubbly\t1+,2+,4+,5+\t*",
+                        "P\tlong_too\t3+,1+,2+\t*",
+                    ]
+                    .iter()
+                    .map(|l| l.as_bytes()),
+                )
+                .unwrap(),
+            false,
+            Default::default(),
+        )
+        .unwrap();
+        let ultrabubbles = vec![(1, 4), (4, 5)];
+        let nodes = [1, 4, 5].iter().copied().collect();
+        let path_indices = variants::bubble_path_indices(&data.paths, &nodes);
+
+        let choose =
+            |rule| auto_ref_path(rule, &data, &path_indices, &ultrabubbles);
+        assert_eq!(0, choose(AutoRef::First));
+        assert_eq!(1, choose(AutoRef::Longest));
+        assert_eq!(2, choose(AutoRef::MostBubbles));
+    }
+
+    #[test]
+    fn split_output_named_after_reference() {
+        let path = |output: &str, reference: &str| {
+            split_output_path(Path::new(output), reference.as_bytes())
+        };
+        assert_eq!(PathBuf::from("out.chr1.vcf"), path("out.vcf", "chr1"));
+        assert_eq!(
+            PathBuf::from("dir/out.chr1.vcf.gz"),
+            path("dir/out.vcf.gz", "chr1")
+        );
+        assert_eq!(PathBuf::from("out.ref.json"), path("out.json", "ref"));
+        assert_eq!(PathBuf::from("out.ref"), path("out", "ref"));
+        assert_eq!(
+            PathBuf::from("out.HG002#1#chr_1.vcf"),
+            path("out.vcf", "HG002#1#chr/1")
+        );
+    }
+
+    #[test]
+    fn slowest_bubbles_first() {
+        let ms = Duration::from_millis;
+        let timings = vec![
+            ((1, 4), ms(3)),
+            ((4, 9), ms(12)),
+            ((9, 10), ms(1)),
+            ((12, 15), ms(3)),
+            ((10, 12), ms(7)),
+            ((12, 15), ms(6)),
+        ];
+
+        assert_eq!(
+            vec![((4, 9), ms(12)), ((12, 15), ms(9)), ((10, 12), ms(7))],
+            slowest_bubbles(timings.clone(), 3)
+        );
+        assert_eq!(5, slowest_bubbles(timings, 10).len());
+    }
+
+    #[test]
+    fn excluded_paths_take_precedence() {
+        let exclude: FnvHashSet<BString> =
+            vec!["p2".into(), "missing".into()].into_iter().collect();
+
+        let mut data = path_data();
+        let mut refs: FnvHashSet<BString> =
+            vec!["p1".into(), "p2".into()].into_iter().collect();
+        exclude_named_paths(&mut data, &exclude, Some(&mut refs)).unwrap();
+        assert_eq!(vec!["p1", "p3"], data.path_names);
+        assert_eq!(data.path_names.len(), data.paths.len());
+        assert_eq!(
+            vec![BString::from("p1")],
+            refs.into_iter().collect::<Vec<_>>()
+        );
+
+        let mut data = path_data();
+        let mut refs: FnvHashSet<BString> =
+            std::iter::once("p2".into()).collect();
+        assert!(
+            exclude_named_paths(&mut data, &exclude, Some(&mut refs)).is_err()
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use bstr::{io::*, BString, ByteSlice};
+use fnv::FnvHashMap;
+use std::{
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::{Path, PathBuf},
+};
+use structopt::StructOpt;
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+use crate::{
+    error::{GfaUtilsError, Result},
+    gzip,
+    variants::vcf::{self, VCFRecord},
+};
+
+/// Merge the VCFs written by several gfa2vcf runs, such as one per
+/// shard of a large graph, into one, with a single header. The
+/// `##contig` lines of the inputs are combined, and the records are
+/// sorted by contig and position, with duplicate sites merged.
+#[derive(StructOpt, Debug)]
+pub struct MergeVcfArgs {
+    /// The VCFs to merge, plain or gzipped. The header of the first
+    /// is used for the output, and all must have the same samples.
+    #[structopt(name = "VCF files", parse(from_os_str), required = true)]
+    vcfs: Vec<PathBuf>,
+    /// Write the VCF to the given file instead of stdout. It's
+    /// compressed with bgzip if the name ends with ".vcf.gz".
+    #[structopt(
+        name = "VCF output file",
+        long = "output",
+        short = "o",
+        parse(from_os_str)
+    )]
+    output: Option<PathBuf>,
+    /// Keep records for the same site from different inputs as they
+    /// are, instead of merging them into one.
+    #[structopt(name = "keep duplicates", long = "keep-duplicates")]
+    keep_duplicates: bool,
+}
+
+/// The header and records of a VCF.
+struct VcfFile {
+    /// The `##` meta lines, without the `##contig` lines.
+    meta: Vec<BString>,
+    /// The index in `meta` where the `##contig` lines were.
+    contigs_at: usize,
+    contigs: Vec<(BString, usize)>,
+    /// The `#CHROM` line, with the sample names.
+    columns: BString,
+    records: Vec<VCFRecord>,
+}
+
+/// The ID and length of a `##contig=<ID=...,length=...>` line, if it
+/// is one.
+fn parse_contig(line: &[u8]) -> Option<(BString, usize)> {
+    let fields = line.strip_prefix(b"##contig=<")?.strip_suffix(b">")?;
+    let mut id = None;
+    let mut length = None;
+    for field in fields.split_str(",") {
+        if let Some(value) = field.strip_prefix(b"ID=") {
+            id = Some(BString::from(value));
+        } else if let Some(value) = field.strip_prefix(b"length=") {
+            length = value.to_str().ok()?.parse().ok();
+        }
+    }
+    Some((id?, length?))
+}
+
+fn open_vcf(path: &Path) -> Result<Box<dyn Read>> {
+    let file = File::open(path).map_err(|err| {
+        format!("Error opening VCF {}: {}", path.display(), err)
+    })?;
+    if gzip::is_gzip_file(path)? {
+        Ok(Box::new(gzip::GzDecoder::new(file)))
+    } else {
+        Ok(Box::new(file))
+    }
+}
+
+fn load_vcf(path: &Path) -> Result<VcfFile> {
+    let mut vcf = VcfFile {
+        meta: Vec::new(),
+        contigs_at: 0,
+        contigs: Vec::new(),
+        columns: BString::default(),
+        records: Vec::new(),
+    };
+    let mut contigs_at = None;
+
+    let reader = BufReader::new(open_vcf(path)?);
+    for (ix, line) in reader.byte_lines().enumerate() {
+        let mut line = line?;
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        if line.is_empty() {
+            continue;
+        }
+        if let Some(contig) = parse_contig(&line) {
+            contigs_at.get_or_insert(vcf.meta.len());
+            vcf.contigs.push(contig);
+        } else if line.starts_with(b"##") {
+            vcf.meta.push(line.into());
+        } else if line.starts_with(b"#") {
+            vcf.columns = line.into();
+        } else {
+            let record = VCFRecord::parse(&line).ok_or_else(|| {
+                format!(
+                    "Invalid VCF record on line {} of {}",
+                    ix + 1,
+                    path.display()
+                )
+            })?;
+            vcf.records.push(record);
+        }
+    }
+
+    if vcf.columns.is_empty() {
+        return Err(format!("VCF {} has no #CHROM line", path.display()).into());
+    }
+    vcf.contigs_at = contigs_at.unwrap_or(vcf.meta.len());
+    Ok(vcf)
+}
+
+/// Add the `contigs` of another VCF to `merged`, keeping the order in
+/// which they're first seen. Fails if a contig has another length in
+/// `merged` than in `contigs`.
+fn merge_contigs(
+    merged: &mut Vec<(BString, usize)>,
+    contigs: &[(BString, usize)],
+    path: &Path,
+) -> Result<()> {
+    let lengths: FnvHashMap<BString, usize> = merged.iter().cloned().collect();
+    for (name, length) in contigs.iter() {
+        match lengths.get(name) {
+            Some(known) if known != length => {
+                return Err(GfaUtilsError::Other(format!(
+                    "Contig {} has length {} in {}, but {} in the VCFs \
+                     before it",
+                    name,
+                    length,
+                    path.display(),
+                    known
+                )));
+            }
+            Some(_) => (),
+            None => merged.push((name.clone(), *length)),
+        }
+    }
+    Ok(())
+}
+
+pub fn merge_vcf(args: &MergeVcfArgs) -> Result<()> {
+    let mut files = args.vcfs.iter();
+    let first_path = files.next().ok_or("No VCFs to merge")?;
+    let first = load_vcf(first_path)?;
+    info!(
+        "Loaded {} records from {}",
+        first.records.len(),
+        first_path.display()
+    );
+
+    let mut contigs = first.contigs;
+    let mut records = first.records;
+
+    for path in files {
+        let vcf = load_vcf(path)?;
+        if vcf.columns != first.columns {
+            return Err(format!(
+                "The samples of {} don't match those of {}",
+                path.display(),
+                first_path.display()
+            )
+            .into());
+        }
+        merge_contigs(&mut contigs, &vcf.contigs, path)?;
+        info!(
+            "Loaded {} records from {}",
+            vcf.records.len(),
+            path.display()
+        );
+        records.extend(vcf.records);
+    }
+
+    let count = records.len();
+    if args.keep_duplicates {
+        vcf::sort_records(&mut records, &contigs);
+    } else {
+        vcf::merge_duplicate_records(&mut records, &contigs);
+        if records.len() < count {
+            info!("Merged {} duplicate records", count - records.len());
+        }
+    }
+
+    let mut out = super::gfa2vcf::vcf_writer(args.output.as_deref(), false)?;
+
+    let (before, after) = first.meta.split_at(first.contigs_at);
+    for line in before.iter() {
+        writeln!(out, "{}", line)?;
+    }
+    for (name, length) in contigs.iter() {
+        writeln!(out, "##contig=<ID={},length={}>", name, length)?;
+    }
+    for line in after.iter() {
+        writeln!(out, "{}", line)?;
+    }
+    writeln!(out, "{}", first.columns)?;
+
+    for record in records.iter() {
+        writeln!(out, "{}", record)?;
+    }
+    out.finish()?;
+
+    info!(
+        "Wrote {} records from {} VCFs",
+        records.len(),
+        args.vcfs.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contigs_merged_in_order() {
+        assert_eq!(
+            Some(("chr1".into(), 1200)),
+            parse_contig(b"##contig=<ID=chr1,length=1200>")
+        );
+        assert_eq!(None, parse_contig(b"##contig=<ID=chr1>"));
+        assert_eq!(None, parse_contig(b"##reference=graph.gfa"));
+
+        let path = Path::new("b.vcf");
+        let mut contigs = vec![("a".into(), 10), ("b".into(), 20)];
+        merge_contigs(&mut contigs, &[("c".into(), 5), ("a".into(), 10)], path)
+            .unwrap();
+        assert_eq!(
+            vec![("a".into(), 10), ("b".into(), 20), ("c".into(), 5)],
+            contigs
+        );
+
+        assert!(merge_contigs(&mut contigs, &[("b".into(), 21)], path).is_err());
+    }
+}
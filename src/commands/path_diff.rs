@@ -0,0 +1,243 @@
+use bstr::{BStr, BString, ByteSlice};
+use fnv::{FnvHashMap, FnvHashSet};
+use std::{fmt, path::PathBuf};
+use structopt::StructOpt;
+
+use gfa::gfa::{Orientation, Path, GFA};
+
+use super::{load_gfa, Result};
+
+/// Compare the paths of the input GFA to those of another GFA, by
+/// name, and report the paths that are only in one of them, and the
+/// shared paths whose steps differ.
+#[derive(StructOpt, Debug)]
+pub struct PathDiffArgs {
+    /// The GFA to compare the input GFA to.
+    #[structopt(name = "other GFA file", parse(from_os_str))]
+    other_gfa: PathBuf,
+    /// Also give the first step where each changed path differs, and
+    /// the segments the two paths have there.
+    #[structopt(name = "verbose", long = "verbose")]
+    verbose: bool,
+}
+
+/// How a path differs between two GFAs, as found by `diff_paths`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathDiff {
+    /// The path is only in the first GFA.
+    OnlyInFirst(BString),
+    /// The path is only in the second GFA.
+    OnlyInSecond(BString),
+    /// The path is in both GFAs, with the same steps.
+    Identical(BString),
+    /// The path is in both GFAs, but its steps differ, first at the
+    /// 0-based step index `step`. The steps there are given as
+    /// segment names with orientations, like `12+`, or `None` if the
+    /// path ends before it.
+    Changed {
+        path: BString,
+        step: usize,
+        first: Option<BString>,
+        second: Option<BString>,
+    },
+}
+
+impl fmt::Display for PathDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let step = |step: &Option<BString>| match step {
+            Some(step) => step.to_string(),
+            None => "end".to_string(),
+        };
+        match self {
+            PathDiff::OnlyInFirst(path) => write!(f, "only in first\t{}", path),
+            PathDiff::OnlyInSecond(path) => {
+                write!(f, "only in second\t{}", path)
+            }
+            PathDiff::Identical(path) => write!(f, "identical\t{}", path),
+            PathDiff::Changed {
+                path,
+                step: step_ix,
+                first,
+                second,
+            } => write!(
+                f,
+                "changed\t{}\tstep {}: {} != {}",
+                path,
+                step_ix + 1,
+                step(first),
+                step(second)
+            ),
+        }
+    }
+}
+
+fn step_name((segment, orient): (&BStr, Orientation)) -> BString {
+    format!("{}{}", segment, orient).into()
+}
+
+/// The paths of the GFA by name. If a name is used by more than one
+/// path, the first one is used.
+fn named_paths(
+    gfa: &GFA<Vec<u8>, ()>,
+) -> FnvHashMap<&BStr, &Path<Vec<u8>, ()>> {
+    let mut paths = FnvHashMap::default();
+    for path in gfa.paths.iter() {
+        paths.entry(path.path_name.as_bstr()).or_insert(path);
+    }
+    paths
+}
+
+/// Compare the paths of two GFAs by name, and their steps by segment
+/// name and orientation. The paths of the first GFA come first, in
+/// order, followed by those only in the second.
+pub fn diff_paths(
+    first: &GFA<Vec<u8>, ()>,
+    second: &GFA<Vec<u8>, ()>,
+) -> Vec<PathDiff> {
+    let second_paths = named_paths(second);
+
+    let mut diffs: Vec<PathDiff> = Vec::new();
+    let mut seen: FnvHashSet<&BStr> = FnvHashSet::default();
+    for path in first.paths.iter() {
+        let name = path.path_name.as_bstr();
+        if !seen.insert(name) {
+            continue;
+        }
+        let other = match second_paths.get(name) {
+            Some(other) => other,
+            None => {
+                diffs.push(PathDiff::OnlyInFirst(name.to_owned()));
+                continue;
+            }
+        };
+
+        let mut steps = path.iter();
+        let mut other_steps = other.iter();
+        let mut step = 0;
+        let diff = loop {
+            match (steps.next(), other_steps.next()) {
+                (None, None) => break PathDiff::Identical(name.to_owned()),
+                (a, b) if a == b => step += 1,
+                (a, b) => {
+                    break PathDiff::Changed {
+                        path: name.to_owned(),
+                        step,
+                        first: a.map(step_name),
+                        second: b.map(step_name),
+                    }
+                }
+            }
+        };
+        diffs.push(diff);
+    }
+
+    for path in second.paths.iter() {
+        let name = path.path_name.as_bstr();
+        if seen.insert(name) {
+            diffs.push(PathDiff::OnlyInSecond(name.to_owned()));
+        }
+    }
+
+    diffs
+}
+
+/// Print the paths that are only in one of the GFAs, and those that
+/// differ, followed by a summary with the number of each.
+pub fn path_diff(gfa_path: &PathBuf, args: &PathDiffArgs) -> Result<()> {
+    let first: GFA<Vec<u8>, ()> = load_gfa(gfa_path)?;
+    let second: GFA<Vec<u8>, ()> = load_gfa(&args.other_gfa)?;
+
+    let diffs = diff_paths(&first, &second);
+
+    let mut counts = [0usize; 4];
+    for diff in diffs.iter() {
+        match diff {
+            PathDiff::Identical(_) => counts[0] += 1,
+            PathDiff::Changed { path, .. } => {
+                counts[1] += 1;
+                if args.verbose {
+                    println!("{}", diff);
+                } else {
+                    println!("changed\t{}", path);
+                }
+            }
+            PathDiff::OnlyInFirst(_) => {
+                counts[2] += 1;
+                println!("{}", diff);
+            }
+            PathDiff::OnlyInSecond(_) => {
+                counts[3] += 1;
+                println!("{}", diff);
+            }
+        }
+    }
+
+    println!(
+        "{} identical, {} changed, {} only in {}, {} only in {}",
+        counts[0],
+        counts[1],
+        counts[2],
+        gfa_path.display(),
+        counts[3],
+        args.other_gfa.display()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gfa::parser::GFAParser;
+
+    fn gfa(paths: &[&str]) -> GFA<Vec<u8>, ()> {
+        let lines: Vec<String> = ["S\t1\tA", "S\t2\tC", "S\t3\tG"]
+            .iter()
+            .map(|s| s.to_string())
+            .chain(paths.iter().map(|p| format!("P\t{}\t*", p)))
+            .collect();
+        GFAParser::new()
+            .parse_lines(lines.iter().map(|l| l.as_bytes()))
+            .unwrap()
+    }
+
+    #[test]
+    fn paths_compared_by_name_and_steps() {
+        let first = gfa(&[
+            "same\t1+,2+,3+",
+            "flipped\t1+,2+,3+",
+            "shorter\t1+,2+",
+            "old\t1+",
+        ]);
+        let second = gfa(&[
+            "new\t3-",
+            "shorter\t1+,2+,3+",
+            "flipped\t1+,2-,3+",
+            "same\t1+,2+,3+",
+        ]);
+
+        let diffs = diff_paths(&first, &second);
+        assert_eq!(
+            vec![
+                PathDiff::Identical("same".into()),
+                PathDiff::Changed {
+                    path: "flipped".into(),
+                    step: 1,
+                    first: Some("2+".into()),
+                    second: Some("2-".into()),
+                },
+                PathDiff::Changed {
+                    path: "shorter".into(),
+                    step: 2,
+                    first: None,
+                    second: Some("3+".into()),
+                },
+                PathDiff::OnlyInFirst("old".into()),
+                PathDiff::OnlyInSecond("new".into()),
+            ],
+            diffs
+        );
+        assert_eq!("changed\tshorter\tstep 3: end != 3+", diffs[2].to_string());
+    }
+}
@@ -0,0 +1,199 @@
+use bstr::{BString, ByteSlice};
+use fnv::{FnvHashMap, FnvHashSet};
+use std::{
+    io::{BufWriter, Write},
+    path::PathBuf,
+};
+use structopt::StructOpt;
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+use gfa::gfa::{Orientation, GFA};
+
+use crate::util::reverse_complement;
+
+use super::{gfa2vcf::load_paths_file, load_gfa, Result};
+
+/// Output the sequence of each path in the GFA as a FASTA record.
+#[derive(StructOpt, Debug)]
+pub struct Paths2FastaArgs {
+    /// Wrap sequence lines at this many characters. If omitted or 0,
+    /// each sequence is written on a single line.
+    #[structopt(name = "line width", long = "line-width")]
+    line_width: Option<usize>,
+    /// Only output the paths whose names are listed in this file, one
+    /// per line.
+    #[structopt(
+        name = "file containing paths to output",
+        long = "paths-file",
+        parse(from_os_str)
+    )]
+    paths_file: Option<PathBuf>,
+}
+
+/// Wraps a writer to break the sequence written to it into lines of
+/// at most `width` characters. A width of 0 disables wrapping.
+struct SequenceWriter<W: Write> {
+    inner: W,
+    width: usize,
+    column: usize,
+}
+
+impl<W: Write> SequenceWriter<W> {
+    fn new(inner: W, width: usize) -> Self {
+        Self {
+            inner,
+            width,
+            column: 0,
+        }
+    }
+
+    fn write_seq(&mut self, mut seq: &[u8]) -> std::io::Result<()> {
+        if self.width == 0 {
+            return self.inner.write_all(seq);
+        }
+
+        while !seq.is_empty() {
+            if self.column == self.width {
+                self.inner.write_all(b"\n")?;
+                self.column = 0;
+            }
+            let len = seq.len().min(self.width - self.column);
+            self.inner.write_all(&seq[..len])?;
+            self.column += len;
+            seq = &seq[len..];
+        }
+        Ok(())
+    }
+
+    /// End the current record's sequence with a newline.
+    fn finish_record(&mut self) -> std::io::Result<()> {
+        self.column = 0;
+        self.inner.write_all(b"\n")
+    }
+}
+
+/// Write the sequence of one path as a FASTA record, one segment at a
+/// time.
+fn write_path_record<W, I>(
+    out: &mut SequenceWriter<W>,
+    name: &[u8],
+    segment_map: &FnvHashMap<usize, &[u8]>,
+    steps: I,
+) -> Result<()>
+where
+    W: Write,
+    I: Iterator<Item = (usize, Orientation)>,
+{
+    out.inner.write_all(b">")?;
+    out.inner.write_all(name)?;
+    out.inner.write_all(b"\n")?;
+
+    for (segment, orient) in steps {
+        let seq = segment_map.get(&segment).ok_or_else(|| {
+            format!(
+                "Path {} uses segment {}, which is not in the graph",
+                name.as_bstr(),
+                segment
+            )
+        })?;
+        match orient {
+            Orientation::Forward => out.write_seq(seq)?,
+            Orientation::Backward => out.write_seq(&reverse_complement(seq))?,
+        }
+    }
+
+    out.finish_record()?;
+    Ok(())
+}
+
+pub fn paths2fasta(
+    gfa_path: &PathBuf,
+    args: &Paths2FastaArgs,
+    path_options: crate::variants::PathDataOptions,
+) -> Result<()> {
+    let selected: Option<FnvHashSet<BString>> = args
+        .paths_file
+        .clone()
+        .map(load_paths_file)
+        .transpose()?
+        .map(|paths| paths.into_iter().collect());
+
+    let gfa: GFA<usize, ()> = load_gfa(gfa_path)?;
+
+    let segment_map =
+        crate::variants::borrowed_segment_map(&gfa, path_options)?;
+
+    if let Some(selected) = selected.as_ref() {
+        let gfa_paths: FnvHashSet<&[u8]> = gfa
+            .paths
+            .iter()
+            .map(|path| path.path_name.as_slice())
+            .collect();
+        for name in selected.iter() {
+            if !gfa_paths.contains(name.as_slice()) {
+                warn!("Path does not exist in graph: {}", name);
+            }
+        }
+    }
+
+    let stdout = std::io::stdout();
+    let mut out = SequenceWriter::new(
+        BufWriter::new(stdout.lock()),
+        args.line_width.unwrap_or(0),
+    );
+
+    let mut count = 0;
+    for path in gfa.paths.iter() {
+        if let Some(selected) = selected.as_ref() {
+            if !selected.contains(path.path_name.as_bstr()) {
+                continue;
+            }
+        }
+        write_path_record(
+            &mut out,
+            &path.path_name,
+            &segment_map,
+            path.iter(),
+        )?;
+        count += 1;
+    }
+
+    out.inner.flush()?;
+    info!("Wrote {} paths", count);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fasta(width: usize, steps: &[(usize, Orientation)]) -> String {
+        let segments: [(usize, &[u8]); 3] =
+            [(1, b"ACGT"), (2, b"GGA"), (3, b"TTAC")];
+        let segment_map = segments.iter().copied().collect();
+
+        let mut out = SequenceWriter::new(Vec::new(), width);
+        write_path_record(
+            &mut out,
+            b"path",
+            &segment_map,
+            steps.iter().copied(),
+        )
+        .unwrap();
+        String::from_utf8(out.inner).unwrap()
+    }
+
+    #[test]
+    fn path_records() {
+        use Orientation::*;
+        let steps = [(1, Forward), (2, Backward), (3, Forward)];
+
+        assert_eq!(">path\nACGTTCCTTAC\n", fasta(0, &steps));
+        assert_eq!(">path\nACGT\nTCCT\nTAC\n", fasta(4, &steps));
+        assert_eq!(">path\nACGTTCCTTAC\n", fasta(11, &steps));
+        assert_eq!(">path\nGTAA\n", fasta(0, &[(3, Backward)]));
+    }
+}
@@ -1,13 +1,9 @@
-use saboten::{
-    biedgedgraph::*,
-    cactusgraph,
-    cactusgraph::{BridgeForest, CactusGraph, CactusTree},
-};
+use bstr::{io::*, BString, ByteSlice};
+use structopt::StructOpt;
 
-use bstr::{io::*, ByteSlice};
 use std::{
     fs::File,
-    io::BufReader,
+    io::{BufReader, BufWriter, Read, Write},
     path::{Path, PathBuf},
 };
 
@@ -19,115 +15,513 @@ use gfa::{
 #[allow(unused_imports)]
 use log::{debug, info, log_enabled, warn};
 
+use crate::{bubbles::BubbleAlgorithm, error::GfaUtilsError};
+
 use super::Result;
 
-pub fn run_saboten(gfa_path: &PathBuf) -> Result<()> {
-    let ultrabubbles = find_ultrabubbles(gfa_path)?;
-    print_ultrabubbles(ultrabubbles.iter())
+/// Find the ultrabubbles in the GFA, and print them as tab-separated
+/// pairs of segment IDs. The output can be loaded by the
+/// --ultrabubbles option of the gfa2vcf and snps commands.
+#[derive(StructOpt, Debug)]
+pub struct SabotenArgs {
+    /// Load the ultrabubbles from a file, text or binary, instead of
+    /// finding them in the GFA, which isn't needed then. Together with
+    /// --output, this converts between the two formats.
+    #[structopt(name = "ultrabubbles file", long = "from", parse(from_os_str))]
+    from: Option<PathBuf>,
+    /// Save the ultrabubbles to the given file instead of printing
+    /// them. If the name ends with ".ubz", the compact binary format
+    /// is used, which is much faster to load.
+    #[structopt(
+        name = "output file",
+        long = "output",
+        short = "o",
+        parse(from_os_str)
+    )]
+    output: Option<PathBuf>,
+    /// Sort the ultrabubbles by start, then end, segment ID.
+    #[structopt(long = "sort")]
+    sort: bool,
+    /// Only print the number of ultrabubbles.
+    #[structopt(long = "count")]
+    count: bool,
+    /// The algorithm used to find the bubbles: `saboten`, for the
+    /// ultrabubbles of the cactus graph decomposition, or `bcc`, for
+    /// a simpler one based on biconnected components, which doesn't
+    /// find nested bubbles.
+    #[structopt(
+        long = "bubble-algo",
+        default_value = "saboten",
+        possible_values = &["saboten", "bcc"]
+    )]
+    bubble_algo: BubbleAlgorithm,
+}
+
+pub fn run_saboten(
+    gfa_path: Option<&PathBuf>,
+    args: &SabotenArgs,
+) -> Result<()> {
+    let mut ultrabubbles = match (&args.from, gfa_path) {
+        (Some(path), _) => load_ultrabubbles(path)?,
+        (None, Some(gfa_path)) => {
+            find_ultrabubbles(gfa_path, args.bubble_algo)?
+        }
+        (None, None) => {
+            return Err("The input GFA file is required, give it with -i, \
+                        or load the ultrabubbles with --from"
+                .into())
+        }
+    };
+
+    if args.count {
+        println!("{}", ultrabubbles.len());
+        return Ok(());
+    }
+
+    if args.sort {
+        sort_ultrabubbles(&mut ultrabubbles);
+    }
+
+    match &args.output {
+        Some(path) => save_ultrabubbles(path, &ultrabubbles)?,
+        None => print_ultrabubbles(ultrabubbles.iter())?,
+    }
+    Ok(())
+}
+
+/// Sort ultrabubbles by the segment ID of their start, then that of
+/// their end. Everything that processes ultrabubbles in order, such
+/// as assigning them to paths, relies on this ordering to give the
+/// same results no matter what order the ultrabubbles were found or
+/// loaded in.
+pub fn sort_ultrabubbles(ultrabubbles: &mut [(u64, u64)]) {
+    ultrabubbles.sort_unstable_by_key(|&(from, to)| (from, to));
 }
 
-pub fn print_ultrabubbles<'a, I>(ultrabubbles: I) -> Result<()>
+pub fn print_ultrabubbles<'a, I>(ultrabubbles: I) -> crate::error::Result<()>
 where
     I: Iterator<Item = &'a (u64, u64)> + 'a,
 {
+    let stdout = std::io::stdout();
+    write_ultrabubbles(stdout.lock(), ultrabubbles)
+}
+
+pub fn write_ultrabubbles<'a, W, I>(
+    writer: W,
+    ultrabubbles: I,
+) -> crate::error::Result<()>
+where
+    W: Write,
+    I: Iterator<Item = &'a (u64, u64)> + 'a,
+{
+    let mut writer = BufWriter::new(writer);
+    for (x, y) in ultrabubbles {
+        writeln!(writer, "{}\t{}", x, y)?;
+    }
+    writer.flush()?;
+
+    Ok(())
+}
+
+/// The first bytes of a binary ultrabubbles file. The first byte
+/// can't start a UTF-8 character, so no text file is mistaken for one.
+pub const UBZ_MAGIC: [u8; 4] = *b"\xffUBZ";
+
+/// The version of the binary ultrabubbles format that's written.
+pub const UBZ_VERSION: u32 = 1;
+
+/// The extension that makes `save_ultrabubbles` use the binary
+/// format.
+pub const UBZ_EXTENSION: &str = "ubz";
+
+/// Write ultrabubbles in the binary format: `UBZ_MAGIC`, the format
+/// version as a little-endian u32, and the number of ultrabubbles as a
+/// little-endian u64, followed by the segment IDs of each ultrabubble
+/// as two little-endian u64s.
+pub fn write_ultrabubbles_binary<W: Write>(
+    writer: W,
+    ultrabubbles: &[(u64, u64)],
+) -> crate::error::Result<()> {
+    let mut writer = BufWriter::new(writer);
+    writer.write_all(&UBZ_MAGIC)?;
+    writer.write_all(&UBZ_VERSION.to_le_bytes())?;
+    writer.write_all(&(ultrabubbles.len() as u64).to_le_bytes())?;
     for (x, y) in ultrabubbles {
-        println!("{}\t{}", x, y);
+        writer.write_all(&x.to_le_bytes())?;
+        writer.write_all(&y.to_le_bytes())?;
     }
+    writer.flush()?;
 
     Ok(())
 }
 
-pub fn find_ultrabubbles(gfa_path: &PathBuf) -> Result<Vec<(u64, u64)>> {
+/// Save ultrabubbles to a file, in the format read by
+/// `load_ultrabubbles`. Files whose names end with ".ubz" get the
+/// binary format, and other files the text one.
+pub fn save_ultrabubbles<P: AsRef<Path>>(
+    path: P,
+    ultrabubbles: &[(u64, u64)],
+) -> crate::error::Result<()> {
+    info!("Saving ultrabubbles to file {}", path.as_ref().display());
+    let file = File::create(path.as_ref()).map_err(|err| {
+        let msg = format!(
+            "Error creating ultrabubbles file {}: {}",
+            path.as_ref().display(),
+            err
+        );
+        std::io::Error::new(err.kind(), msg)
+    })?;
+    let binary = path
+        .as_ref()
+        .extension()
+        .is_some_and(|ext| ext == UBZ_EXTENSION);
+    if binary {
+        write_ultrabubbles_binary(file, ultrabubbles)
+    } else {
+        write_ultrabubbles(file, ultrabubbles.iter())
+    }
+}
+
+/// Find the bubbles in the GFA file at `gfa_path` with the given
+/// algorithm. Only the segments and links are loaded; to find the
+/// bubbles of a GFA that's already loaded, use
+/// `find_ultrabubbles_in_gfa`.
+pub fn find_ultrabubbles(
+    gfa_path: &PathBuf,
+    algorithm: BubbleAlgorithm,
+) -> crate::error::Result<Vec<(u64, u64)>> {
     let mut parser_builder = GFAParserBuilder::all();
     parser_builder.paths = false;
     parser_builder.containments = false;
     let parser: GFAParser<usize, ()> = parser_builder.build();
 
+    let gfa: GFA<usize, ()> = super::parse_gfa_file(&parser, gfa_path)?;
+    Ok(find_ultrabubbles_in_gfa(&gfa, algorithm))
+}
+
+/// Find the bubbles in an already loaded GFA with the given
+/// algorithm, sorted by start, then end, segment ID.
+pub fn find_ultrabubbles_in_gfa(
+    gfa: &GFA<usize, ()>,
+    algorithm: BubbleAlgorithm,
+) -> Vec<(u64, u64)> {
     info!("Computing ultrabubbles");
-    let be_graph = {
-        let gfa: GFA<usize, ()> = parser.parse_file(gfa_path)?;
-
-        debug!("Building biedged graph");
-        let t = std::time::Instant::now();
-        let be_graph = BiedgedGraph::from_gfa(&gfa);
-        debug!(
-            "  biedged graph took {:.3} ms",
-            t.elapsed().as_secs_f64() * 1000.0
-        );
-        debug!("");
+    let ultrabubbles = algorithm.finder().find(gfa);
+    debug!("Done computing ultrabubbles");
+    ultrabubbles
+}
+
+/// An error for the ultrabubbles or BED file at `path`.
+fn load_error<P: AsRef<Path>, E: ToString>(
+    path: P,
+    reason: E,
+) -> GfaUtilsError {
+    GfaUtilsError::UltrabubbleLoad {
+        path: path.as_ref().to_path_buf(),
+        reason: reason.to_string(),
+    }
+}
+
+/// Whether the file at `path` is in the binary ultrabubbles format,
+/// going by its first bytes.
+pub fn is_binary_ultrabubbles<P: AsRef<Path>>(
+    path: P,
+) -> crate::error::Result<bool> {
+    let file =
+        File::open(path.as_ref()).map_err(|err| load_error(&path, err))?;
+    let mut magic = Vec::with_capacity(UBZ_MAGIC.len());
+    file.take(UBZ_MAGIC.len() as u64)
+        .read_to_end(&mut magic)
+        .map_err(|err| load_error(&path, err))?;
+    Ok(magic == UBZ_MAGIC)
+}
 
-        be_graph
+/// Load ultrabubbles saved in the binary format, as written by
+/// `write_ultrabubbles_binary`.
+fn load_ultrabubbles_binary<P: AsRef<Path>>(
+    path: P,
+) -> crate::error::Result<Vec<(u64, u64)>> {
+    let data =
+        std::fs::read(path.as_ref()).map_err(|err| load_error(&path, err))?;
+    let (header, pairs) = data.split_at(data.len().min(16));
+    if header.len() < 16 {
+        return Err(load_error(&path, "Binary ultrabubbles file is truncated"));
+    }
+
+    let u64_at = |bytes: &[u8]| {
+        let mut buf = [0u8; 8];
+        buf.copy_from_slice(&bytes[..8]);
+        u64::from_le_bytes(buf)
     };
 
-    debug!("Building cactus graph");
-    let t = std::time::Instant::now();
-    let cactus_graph = CactusGraph::from_biedged_graph(&be_graph);
-    debug!(
-        "  cactus graph took {:.3} ms",
-        t.elapsed().as_secs_f64() * 1000.0
-    );
-    debug!("");
-
-    debug!("Building cactus tree");
-    let t = std::time::Instant::now();
-    let cactus_tree = CactusTree::from_cactus_graph(&cactus_graph);
-    debug!(
-        "  cactus tree took {:.3} ms",
-        t.elapsed().as_secs_f64() * 1000.0
-    );
-    debug!("");
-
-    debug!("Building bridge forest");
-    let t = std::time::Instant::now();
-    let bridge_forest = BridgeForest::from_cactus_graph(&cactus_graph);
-    debug!(
-        "  bridge forest took {:.3} ms",
-        t.elapsed().as_secs_f64() * 1000.0
-    );
-    debug!("");
-
-    debug!("Finding ultrabubbles");
-    let t = std::time::Instant::now();
-    let ultrabubbles =
-        cactusgraph::find_ultrabubbles(&cactus_tree, &bridge_forest);
-    debug!(
-        "  ultrabubbles took {:.3} ms",
-        t.elapsed().as_secs_f64() * 1000.0
-    );
-    debug!("");
+    let mut version = [0u8; 4];
+    version.copy_from_slice(&header[4..8]);
+    let version = u32::from_le_bytes(version);
+    if version != UBZ_VERSION {
+        return Err(load_error(
+            &path,
+            format!(
+                "Binary ultrabubbles format version {} is not supported, \
+                 only version {}",
+                version, UBZ_VERSION
+            ),
+        ));
+    }
 
-    let t = std::time::Instant::now();
-    let ultrabubbles = cactusgraph::inverse_map_ultrabubbles(ultrabubbles);
-    debug!(
-        "  inverting ultrabubbles took {:.3} ms",
-        t.elapsed().as_secs_f64() * 1000.0
-    );
+    let count = u64_at(&header[8..]);
+    if pairs.len() as u64 != count.saturating_mul(16) {
+        return Err(load_error(
+            &path,
+            format!(
+                "Binary ultrabubbles file should hold {} ultrabubbles, \
+                 but has {} bytes of them",
+                count,
+                pairs.len()
+            ),
+        ));
+    }
 
-    debug!("Done computing ultrabubbles");
-    Ok(ultrabubbles.into_iter().map(|(x_y, _cont)| x_y).collect())
+    Ok(pairs
+        .chunks_exact(16)
+        .map(|pair| (u64_at(&pair[..8]), u64_at(&pair[8..])))
+        .collect())
 }
 
-static LINE_ERROR: &str = "Ultrabubble record was missing fields";
-
-pub fn load_ultrabubbles<P: AsRef<Path>>(path: P) -> Result<Vec<(u64, u64)>> {
+/// Load ultrabubbles from a file, either as tab-separated pairs of
+/// segment IDs, or in the binary format, which is detected by its
+/// first bytes.
+pub fn load_ultrabubbles<P: AsRef<Path>>(
+    path: P,
+) -> crate::error::Result<Vec<(u64, u64)>> {
     info!("Loading ultrabubbles from file {}", path.as_ref().display());
-    let file = File::open(path.as_ref())?;
+    if is_binary_ultrabubbles(&path)? {
+        return load_ultrabubbles_binary(path);
+    }
+    let file =
+        File::open(path.as_ref()).map_err(|err| load_error(&path, err))?;
     let reader = BufReader::new(file);
     let lines = reader.byte_lines();
 
     let mut ultrabubbles = Vec::new();
 
-    for line in lines {
-        let line = line?;
-        let mut fields = line.split_str("\t");
-        let start = fields.next().ok_or(LINE_ERROR)?.to_str()?;
-        let start = start.parse::<u64>()?;
+    for (line_num, line) in lines.enumerate() {
+        let line = line.map_err(|err| load_error(&path, err))?;
+        let invalid = || {
+            load_error(
+                &path,
+                format!(
+                    "Line {} must contain two segment IDs, separated by a tab",
+                    line_num + 1
+                ),
+            )
+        };
 
-        let end = fields.next().ok_or(LINE_ERROR)?.to_str()?;
-        let end = end.parse::<u64>()?;
+        let mut fields = line.split_str("\t");
+        let mut segment = || -> Option<u64> {
+            fields.next()?.to_str().ok()?.trim().parse().ok()
+        };
+        let start = segment().ok_or_else(invalid)?;
+        let end = segment().ok_or_else(invalid)?;
 
         ultrabubbles.push((start, end));
     }
 
     Ok(ultrabubbles)
 }
+
+/// An interval from a BED file, with 0-based, half-open, coordinates
+/// on the path named `chrom`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BedInterval {
+    pub chrom: BString,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// The contents of a file given as ultrabubbles, which can either be
+/// pairs of segment IDs, or BED intervals on reference paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UltrabubblesFile {
+    Ultrabubbles(Vec<(u64, u64)>),
+    Intervals(Vec<BedInterval>),
+}
+
+/// BED files can start with header lines, and have at least three
+/// fields on each line, while ultrabubble files have two.
+fn is_bed_line(line: &[u8]) -> bool {
+    line.starts_with(b"#")
+        || line.starts_with(b"track")
+        || line.starts_with(b"browser")
+        || line.split_str("\t").count() >= 3
+}
+
+/// Load a file of either ultrabubbles or BED intervals, detecting
+/// which by looking at its first line. Binary ultrabubbles files are
+/// detected too.
+pub fn load_ultrabubbles_or_intervals<P: AsRef<Path>>(
+    path: P,
+) -> crate::error::Result<UltrabubblesFile> {
+    if is_binary_ultrabubbles(&path)? {
+        let ultrabubbles = load_ultrabubbles(path)?;
+        return Ok(UltrabubblesFile::Ultrabubbles(ultrabubbles));
+    }
+
+    let is_bed = {
+        let file =
+            File::open(path.as_ref()).map_err(|err| load_error(&path, err))?;
+        let mut lines = BufReader::new(file).byte_lines();
+        match lines.find(|line| !matches!(line, Ok(l) if l.trim().is_empty())) {
+            Some(line) => {
+                is_bed_line(&line.map_err(|err| load_error(&path, err))?)
+            }
+            None => false,
+        }
+    };
+
+    if is_bed {
+        let intervals = load_bed_intervals(path)?;
+        Ok(UltrabubblesFile::Intervals(intervals))
+    } else {
+        let ultrabubbles = load_ultrabubbles(path)?;
+        Ok(UltrabubblesFile::Ultrabubbles(ultrabubbles))
+    }
+}
+
+/// Load the intervals of a BED file, ignoring any fields after the
+/// first three.
+pub fn load_bed_intervals<P: AsRef<Path>>(
+    path: P,
+) -> crate::error::Result<Vec<BedInterval>> {
+    info!(
+        "Loading BED intervals from file {}",
+        path.as_ref().display()
+    );
+    let file =
+        File::open(path.as_ref()).map_err(|err| load_error(&path, err))?;
+    let lines = BufReader::new(file).byte_lines();
+
+    let mut intervals = Vec::new();
+
+    for (line_num, line) in lines.enumerate() {
+        let line = line.map_err(|err| load_error(&path, err))?;
+        if line.trim().is_empty()
+            || line.starts_with(b"#")
+            || line.starts_with(b"track")
+            || line.starts_with(b"browser")
+        {
+            continue;
+        }
+
+        let invalid = || {
+            load_error(
+                &path,
+                format!(
+                    "Line {} of the BED file must start with a chrom, \
+                     start, and end, with start <= end",
+                    line_num + 1
+                ),
+            )
+        };
+
+        let mut fields = line.split_str("\t");
+        let chrom = fields.next().ok_or_else(invalid)?;
+        let mut coord = || -> Option<usize> {
+            fields.next()?.to_str().ok()?.trim().parse().ok()
+        };
+        let start = coord().ok_or_else(invalid)?;
+        let end = coord().ok_or_else(invalid)?;
+        if start > end {
+            return Err(invalid());
+        }
+
+        intervals.push(BedInterval {
+            chrom: chrom.into(),
+            start,
+            end,
+        });
+    }
+
+    Ok(intervals)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_ultrabubbles_roundtrip() {
+        let ultrabubbles = vec![(1, 4), (4, 6), (12, 7), (u64::MAX, 0)];
+
+        let mut path = std::env::temp_dir();
+        path.push(format!("gfautil_ultrabubbles_{}.tsv", std::process::id()));
+
+        save_ultrabubbles(&path, &ultrabubbles).unwrap();
+        let loaded = load_ultrabubbles(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(ultrabubbles, loaded);
+    }
+
+    #[test]
+    fn binary_ultrabubbles_detected() {
+        let ultrabubbles = vec![(1, 4), (4, 6), (12, 7), (u64::MAX, 0)];
+
+        let mut path = std::env::temp_dir();
+        path.push(format!("gfautil_ultrabubbles_{}.ubz", std::process::id()));
+
+        save_ultrabubbles(&path, &ultrabubbles).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(UBZ_MAGIC, data[..4]);
+        assert_eq!(16 + 16 * ultrabubbles.len(), data.len());
+
+        let loaded = load_ultrabubbles(&path).unwrap();
+        let detected = load_ultrabubbles_or_intervals(&path).unwrap();
+
+        let mut truncated = data.clone();
+        truncated.pop();
+        std::fs::write(&path, &truncated).unwrap();
+        let truncated = load_ultrabubbles(&path);
+
+        let mut newer = data;
+        newer[4] = 2;
+        std::fs::write(&path, &newer).unwrap();
+        let newer = load_ultrabubbles(&path);
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(ultrabubbles, loaded);
+        assert_eq!(UltrabubblesFile::Ultrabubbles(ultrabubbles), detected);
+        assert!(truncated.is_err());
+        assert!(newer.unwrap_err().to_string().contains("version 2"));
+    }
+
+    #[test]
+    fn detect_bed_intervals() {
+        let mut path = std::env::temp_dir();
+        path.push(format!("gfautil_intervals_{}.bed", std::process::id()));
+
+        std::fs::write(&path, "track name=loci\nref\t10\t20\tlocus1\n")
+            .unwrap();
+        let bed = load_ultrabubbles_or_intervals(&path).unwrap();
+
+        std::fs::write(&path, "1\t4\n4\t6\n").unwrap();
+        let ultrabubbles = load_ultrabubbles_or_intervals(&path).unwrap();
+
+        std::fs::write(&path, "ref\t20\t10\n").unwrap();
+        let invalid = load_ultrabubbles_or_intervals(&path);
+
+        std::fs::remove_file(&path).unwrap();
+
+        let interval = BedInterval {
+            chrom: "ref".into(),
+            start: 10,
+            end: 20,
+        };
+        assert_eq!(UltrabubblesFile::Intervals(vec![interval]), bed);
+        assert_eq!(
+            UltrabubblesFile::Ultrabubbles(vec![(1, 4), (4, 6)]),
+            ultrabubbles
+        );
+        assert!(invalid.is_err());
+    }
+}
@@ -114,11 +114,15 @@ fn build_snp_reference_bubbles(
     res
 }
 
-pub fn gfa2snps(gfa_path: &PathBuf, args: SNPArgs) -> Result<()> {
+pub fn gfa2snps(
+    gfa_path: &PathBuf,
+    args: SNPArgs,
+    path_options: variants::PathDataOptions,
+) -> Result<()> {
     let ref_path_name: BString = BString::from(args.ref_path.as_str());
 
     let path_data = {
-        let gfa: GFA<usize, ()> = load_gfa(&gfa_path)?;
+        let gfa: GFA<usize, ()> = load_gfa(gfa_path)?;
 
         if gfa.paths.len() < 2 {
             panic!("GFA must contain at least two paths");
@@ -126,7 +130,7 @@ pub fn gfa2snps(gfa_path: &PathBuf, args: SNPArgs) -> Result<()> {
 
         info!("GFA has {} paths", gfa.paths.len());
 
-        variants::gfa_path_data(gfa)
+        variants::gfa_path_data(gfa, true, path_options)?
     };
 
     info!("Using reference path: {}", ref_path_name);
@@ -140,7 +144,7 @@ pub fn gfa2snps(gfa_path: &PathBuf, args: SNPArgs) -> Result<()> {
     let ref_path = &path_data.paths[ref_path_ix];
 
     let ultrabubbles = if let Ok(mut positions) = snp_positions(&args) {
-        Ok(build_snp_reference_bubbles(&ref_path, &mut positions))
+        Ok(build_snp_reference_bubbles(ref_path, &mut positions))
     } else if let Some(path) = &args.ultrabubbles_file {
         super::saboten::load_ultrabubbles(path)
     } else {
@@ -190,7 +194,13 @@ pub fn gfa2snps(gfa_path: &PathBuf, args: SNPArgs) -> Result<()> {
     }
 
     println!("path\treference base\treference pos\tquery base\tquery pos");
-    for (name, snp_rows) in path_snp_rows.into_iter() {
+    // The paths are written in the order of the GFA, rather than the
+    // arbitrary order of the map
+    for name in path_data.path_names.iter() {
+        let snp_rows = match path_snp_rows.remove(name) {
+            Some(snp_rows) => snp_rows,
+            None => continue,
+        };
         for snp in snp_rows.into_iter() {
             let ref_base = char::from(snp.ref_base);
             let query_base = char::from(snp.query_base);
@@ -1,6 +1,7 @@
 use std::path::PathBuf;
+use structopt::StructOpt;
 
-use gfa::gfa::GFA;
+use gfa::{gfa::GFA, optfields::OptFields};
 
 use handlegraph::hashgraph::HashGraph;
 
@@ -8,6 +9,96 @@ use crate::edges;
 
 use super::{load_gfa, Result};
 
+/// Print summary statistics for the segments, links, and paths in
+/// the GFA.
+#[derive(StructOpt, Debug)]
+pub struct StatsArgs {
+    /// Output a single tab-separated row, with a header line, instead
+    /// of a table.
+    #[structopt(long = "tsv")]
+    tsv: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GFAStats {
+    pub segments: usize,
+    pub links: usize,
+    pub paths: usize,
+    pub total_length: usize,
+    pub n50: usize,
+    pub min_length: usize,
+    pub max_length: usize,
+    pub mean_length: f64,
+}
+
+impl GFAStats {
+    pub fn from_gfa<N, T: OptFields>(gfa: &GFA<N, T>) -> Self {
+        let mut lengths: Vec<usize> =
+            gfa.segments.iter().map(|s| s.sequence.len()).collect();
+        lengths.sort_unstable_by(|a, b| b.cmp(a));
+
+        let total_length: usize = lengths.iter().sum();
+
+        let mut acc = 0;
+        let n50 = lengths
+            .iter()
+            .find(|&&len| {
+                acc += len;
+                acc * 2 >= total_length
+            })
+            .copied()
+            .unwrap_or(0);
+
+        let mean_length = if lengths.is_empty() {
+            0.0
+        } else {
+            total_length as f64 / lengths.len() as f64
+        };
+
+        Self {
+            segments: gfa.segments.len(),
+            links: gfa.links.len(),
+            paths: gfa.paths.len(),
+            total_length,
+            n50,
+            min_length: lengths.last().copied().unwrap_or(0),
+            max_length: lengths.first().copied().unwrap_or(0),
+            mean_length,
+        }
+    }
+
+    fn fields(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("segments", self.segments.to_string()),
+            ("links", self.links.to_string()),
+            ("paths", self.paths.to_string()),
+            ("total_length", self.total_length.to_string()),
+            ("n50", self.n50.to_string()),
+            ("min_length", self.min_length.to_string()),
+            ("max_length", self.max_length.to_string()),
+            ("mean_length", format!("{:.2}", self.mean_length)),
+        ]
+    }
+}
+
+pub fn stats(gfa_path: &PathBuf, args: &StatsArgs) -> Result<()> {
+    let gfa: GFA<Vec<u8>, ()> = load_gfa(gfa_path)?;
+
+    let fields = GFAStats::from_gfa(&gfa).fields();
+
+    if args.tsv {
+        let (names, values): (Vec<_>, Vec<_>) = fields.into_iter().unzip();
+        println!("{}", names.join("\t"));
+        println!("{}", values.join("\t"));
+    } else {
+        for (name, value) in fields {
+            println!("{:<16}{:>16}", name, value);
+        }
+    }
+
+    Ok(())
+}
+
 pub fn edge_count(gfa_path: &PathBuf) -> Result<()> {
     let gfa: GFA<usize, ()> = load_gfa(gfa_path)?;
 
@@ -20,3 +111,37 @@ pub fn edge_count(gfa_path: &PathBuf) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gfa::parser::GFAParser;
+
+    #[test]
+    fn segment_length_stats() {
+        let lines = [
+            "S\t1\tAAAAAAAAAA",
+            "S\t2\tCCCCC",
+            "S\t3\tGGG",
+            "S\t4\tTT",
+            "L\t1\t+\t2\t+\t0M",
+            "P\tp1\t1+,2+\t*",
+        ];
+        let parser = GFAParser::new();
+        let gfa: GFA<Vec<u8>, ()> = parser
+            .parse_lines(lines.iter().map(|l| l.as_bytes()))
+            .unwrap();
+
+        let stats = GFAStats::from_gfa(&gfa);
+
+        assert_eq!(4, stats.segments);
+        assert_eq!(1, stats.links);
+        assert_eq!(1, stats.paths);
+        assert_eq!(20, stats.total_length);
+        assert_eq!(10, stats.n50);
+        assert_eq!(2, stats.min_length);
+        assert_eq!(10, stats.max_length);
+        assert_eq!(5.0, stats.mean_length);
+    }
+}
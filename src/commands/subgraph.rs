@@ -42,9 +42,18 @@ pub struct SubgraphArgs {
     /// Provide a list of names on the command line
     #[structopt(name = "List of names", long = "names", group = "names")]
     list: Option<Vec<String>>,
+    /// Only with `segments`: use the given segments as seeds, and
+    /// include every segment within this many link hops of them. Paths
+    /// are cut to the parts that stay within the subgraph.
+    #[structopt(name = "radius", long = "radius")]
+    radius: Option<usize>,
 }
 
 pub fn subgraph(gfa_path: &PathBuf, args: &SubgraphArgs) -> Result<()> {
+    if args.radius.is_some() && args.subgraph_by != SubgraphBy::Segments {
+        return Err("--radius can only be used with segments".into());
+    }
+
     let gfa: GFA<Vec<u8>, OptionalFields> = load_gfa(gfa_path)?;
 
     let names: Vec<Vec<u8>> = if let Some(list) = &args.list {
@@ -69,9 +78,19 @@ pub fn subgraph(gfa_path: &PathBuf, args: &SubgraphArgs) -> Result<()> {
         }
     };
 
-    let new_gfa = match args.subgraph_by {
-        SubgraphBy::Paths => subgraph::paths_new_subgraph(&gfa, &names),
-        SubgraphBy::Segments => subgraph::segments_subgraph(&gfa, &names),
+    let new_gfa = match (&args.subgraph_by, args.radius) {
+        (SubgraphBy::Paths, _) => subgraph::paths_new_subgraph(&gfa, &names),
+        (SubgraphBy::Segments, None) => {
+            subgraph::segments_subgraph(&gfa, &names)
+        }
+        (SubgraphBy::Segments, Some(radius)) => {
+            for name in names.iter() {
+                if !gfa.segments.iter().any(|s| &s.name == name) {
+                    warn!("Segment {} not found in GFA", name.as_bstr());
+                }
+            }
+            subgraph::neighborhood_subgraph(&gfa, &names, radius)
+        }
     };
     println!("{}", gfa_string(&new_gfa));
 
@@ -0,0 +1,186 @@
+use bstr::{BStr, BString, ByteSlice};
+use fnv::FnvHashSet;
+use std::{fmt, path::PathBuf};
+
+use gfa::{
+    gfa::GFA,
+    optfields::{OptFields, OptionalFields},
+};
+
+use super::{load_gfa, Result};
+
+/// A problem found in a GFA by `validate_gfa`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// More than one segment has this ID.
+    DuplicateSegment(BString),
+    /// A link between the two segments refers to a missing segment.
+    LinkMissingSegment {
+        from: BString,
+        to: BString,
+        missing: BString,
+    },
+    /// A step on the path refers to a missing segment.
+    PathMissingSegment { path: BString, segment: BString },
+    /// The segment's `LN` tag doesn't match its sequence.
+    LengthMismatch {
+        segment: BString,
+        declared: i64,
+        actual: usize,
+    },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::DuplicateSegment(name) => {
+                write!(f, "Segment {} is defined more than once", name)
+            }
+            Problem::LinkMissingSegment { from, to, missing } => write!(
+                f,
+                "Link from {} to {} refers to missing segment {}",
+                from, to, missing
+            ),
+            Problem::PathMissingSegment { path, segment } => {
+                write!(f, "Path {} refers to missing segment {}", path, segment)
+            }
+            Problem::LengthMismatch {
+                segment,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "Segment {} has LN:i:{}, but its sequence has length {}",
+                segment, declared, actual
+            ),
+        }
+    }
+}
+
+/// Find the problems in a GFA, in the order of the segments, links,
+/// and paths they're found in. Segments without a sequence (`*`)
+/// aren't checked against their `LN` tag, and each missing segment
+/// is only reported once for each path.
+pub fn validate_gfa(gfa: &GFA<Vec<u8>, OptionalFields>) -> Vec<Problem> {
+    let mut problems = Vec::new();
+
+    let mut segments: FnvHashSet<&BStr> = FnvHashSet::default();
+    for segment in gfa.segments.iter() {
+        let name = segment.name.as_bstr();
+        if !segments.insert(name) {
+            problems.push(Problem::DuplicateSegment(name.to_owned()));
+        }
+
+        let declared = segment
+            .optional
+            .get_field(b"LN")
+            .and_then(|field| field.get_int());
+        if let Some(declared) = declared {
+            let actual = segment.sequence.len();
+            if segment.sequence != b"*" && declared != actual as i64 {
+                problems.push(Problem::LengthMismatch {
+                    segment: name.to_owned(),
+                    declared,
+                    actual,
+                });
+            }
+        }
+    }
+
+    for link in gfa.links.iter() {
+        let ends = [&link.from_segment, &link.to_segment];
+        for end in ends.iter() {
+            if !segments.contains(end.as_bstr()) {
+                problems.push(Problem::LinkMissingSegment {
+                    from: link.from_segment.as_bstr().to_owned(),
+                    to: link.to_segment.as_bstr().to_owned(),
+                    missing: end.as_bstr().to_owned(),
+                });
+            }
+        }
+    }
+
+    for path in gfa.paths.iter() {
+        let mut reported: FnvHashSet<&BStr> = FnvHashSet::default();
+        for (segment, _) in path.iter() {
+            if !segments.contains(segment) && reported.insert(segment) {
+                problems.push(Problem::PathMissingSegment {
+                    path: path.path_name.as_bstr().to_owned(),
+                    segment: segment.to_owned(),
+                });
+            }
+        }
+    }
+
+    problems
+}
+
+/// Print the problems found in the GFA, or `OK` if there are none,
+/// returning an error if any were found.
+pub fn validate(gfa_path: &PathBuf) -> Result<()> {
+    let gfa: GFA<Vec<u8>, OptionalFields> = load_gfa(gfa_path)?;
+
+    let problems = validate_gfa(&gfa);
+    if problems.is_empty() {
+        println!("OK");
+        return Ok(());
+    }
+
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+
+    Err(format!(
+        "Found {} problems in {}",
+        problems.len(),
+        gfa_path.display()
+    )
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gfa::parser::GFAParser;
+
+    #[test]
+    fn problems_in_gfa() {
+        let lines = [
+            "S\t1\tACGT\tLN:i:4",
+            "S\t2\tA\tLN:i:3",
+            "S\t3\t*\tLN:i:10",
+            "S\t1\tC",
+            "L\t1\t+\t2\t+\t0M",
+            "L\t2\t+\t4\t+\t0M",
+            "P\tp1\t1+,2+,3+\t*",
+            "P\tp2\t1+,5+,3-,5+\t*",
+        ];
+        let parser = GFAParser::new();
+        let gfa: GFA<Vec<u8>, OptionalFields> = parser
+            .parse_lines(lines.iter().map(|l| l.as_bytes()))
+            .unwrap();
+
+        let problems = validate_gfa(&gfa);
+        assert_eq!(
+            vec![
+                Problem::LengthMismatch {
+                    segment: "2".into(),
+                    declared: 3,
+                    actual: 1,
+                },
+                Problem::DuplicateSegment("1".into()),
+                Problem::LinkMissingSegment {
+                    from: "2".into(),
+                    to: "4".into(),
+                    missing: "4".into(),
+                },
+                Problem::PathMissingSegment {
+                    path: "p2".into(),
+                    segment: "5".into(),
+                },
+            ],
+            problems
+        );
+    }
+}
@@ -0,0 +1,109 @@
+//! Configuration files holding command line options, so that long
+//! invocations can be kept in a file and reused, e.g. with
+//! `gfa2vcf --config`.
+//!
+//! The files are TOML, with one `key = value` pair per option, where
+//! the key is the long name of the option, without the leading `--`.
+//! Each command deserializes the file into its own struct of
+//! options, which is then merged with the command line arguments.
+
+use serde::de::DeserializeOwned;
+use std::path::Path;
+
+use crate::error::{GfaUtilsError, Result};
+
+/// Load the configuration file at `path` into `T`, leaving out the
+/// options whose keys are in `unset`. Fails if any of the keys in
+/// `unset` isn't in the file, so that typos are caught.
+pub fn load<T: DeserializeOwned, P: AsRef<Path>>(
+    path: P,
+    unset: &[String],
+) -> Result<T> {
+    let path = path.as_ref();
+    let text = std::fs::read_to_string(path).map_err(|err| {
+        config_error(path, format!("Error reading file: {}", err))
+    })?;
+    parse(&text, unset).map_err(|err| config_error(path, err))
+}
+
+/// Parse the text of a configuration file into `T`, leaving out the
+/// options whose keys are in `unset`.
+pub fn parse<T: DeserializeOwned>(
+    text: &str,
+    unset: &[String],
+) -> std::result::Result<T, String> {
+    let mut table: toml::value::Table =
+        toml::from_str(text).map_err(|err| err.to_string())?;
+    for key in unset {
+        if table.remove(key).is_none() {
+            return Err(format!("--unset {} isn't set in the file", key));
+        }
+    }
+    toml::Value::Table(table)
+        .try_into()
+        .map_err(|err| err.to_string())
+}
+
+pub fn config_error<E: ToString>(path: &Path, err: E) -> GfaUtilsError {
+    GfaUtilsError::Other(format!(
+        "Error in config file {}: {}",
+        path.display(),
+        err.to_string()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Default, PartialEq, Deserialize)]
+    #[serde(rename_all = "kebab-case", deny_unknown_fields)]
+    struct Options {
+        refs: Option<Vec<String>>,
+        no_header: Option<bool>,
+        normalize: Option<bool>,
+        max_alleles: Option<usize>,
+        min_af: Option<f64>,
+    }
+
+    #[test]
+    fn config_values_parsed() {
+        let text = "# Shared options\n\
+                    refs = [\"chm13\", 'grch38#0'] # two references\n\
+                    no-header = true\n\
+                    normalize = false\n\
+                    max-alleles = 1_000\n\
+                    min-af = 0.05\n";
+
+        let options: Options = parse(text, &[]).unwrap();
+        assert_eq!(
+            Options {
+                refs: Some(vec!["chm13".into(), "grch38#0".into()]),
+                no_header: Some(true),
+                normalize: Some(false),
+                max_alleles: Some(1000),
+                min_af: Some(0.05),
+            },
+            options
+        );
+
+        let unset = ["refs".to_string(), "normalize".to_string()];
+        let options: Options = parse(text, &unset).unwrap();
+        assert_eq!(None, options.refs);
+        assert_eq!(None, options.normalize);
+        assert_eq!(Some(true), options.no_header);
+    }
+
+    #[test]
+    fn invalid_configs_rejected() {
+        let err = |text: &str, unset: &[String]| {
+            parse::<Options>(text, unset).unwrap_err()
+        };
+        assert!(err("refs = chm13", &[]).contains("line 1"));
+        assert!(err("max-alleles = \"4\"", &[]).contains("invalid type"));
+        assert!(err("no_header = true", &[]).contains("unknown field"));
+        assert!(err("normalize = true", &["refs".into()])
+            .contains("--unset refs isn't set"));
+    }
+}
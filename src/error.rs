@@ -0,0 +1,109 @@
+use bstr::{BString, ByteSlice};
+use std::{fmt, io, path::PathBuf};
+
+/// The errors that can occur when loading GFAs and ultrabubbles, and
+/// when converting GFAs to VCF, so that callers can handle specific
+/// failures, rather than only print them.
+#[derive(Debug)]
+pub enum GfaUtilsError {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// A line of the GFA couldn't be parsed.
+    GfaParse(String),
+    /// Reference paths that were asked for aren't in the graph.
+    MissingReferencePaths(Vec<BString>),
+    /// None of the reference paths that were asked for are in the
+    /// graph.
+    NoReferencePaths,
+    /// The GFA has fewer than the two paths needed to call variants.
+    TooFewPaths { gfa: PathBuf, count: usize },
+    /// A segment ID is defined more than once in the GFA.
+    DuplicateSegment(usize),
+    /// A step on the path refers to a segment that isn't in the GFA.
+    MissingSegment { path: BString, segment: usize },
+    /// A file of ultrabubbles or BED intervals couldn't be loaded.
+    UltrabubbleLoad { path: PathBuf, reason: String },
+    /// Any other error, such as invalid combinations of options.
+    Other(String),
+}
+
+pub type Result<T> = std::result::Result<T, GfaUtilsError>;
+
+impl fmt::Display for GfaUtilsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GfaUtilsError::Io(err) => write!(f, "{}", err),
+            GfaUtilsError::GfaParse(msg) => {
+                write!(f, "Error parsing GFA: {}", msg)
+            }
+            GfaUtilsError::MissingReferencePaths(paths) => write!(
+                f,
+                "Reference paths do not exist in graph: {}",
+                bstr::join(", ", paths).as_bstr()
+            ),
+            GfaUtilsError::NoReferencePaths => {
+                write!(f, "None of the reference paths exist in the graph")
+            }
+            GfaUtilsError::TooFewPaths { gfa, count } => write!(
+                f,
+                "GFA must contain at least two paths, but {} has {}",
+                gfa.display(),
+                count
+            ),
+            GfaUtilsError::DuplicateSegment(id) => {
+                write!(f, "Segment ID {} is defined more than once", id)
+            }
+            GfaUtilsError::MissingSegment { path, segment } => write!(
+                f,
+                "Path {} refers to segment {}, which is not in the GFA",
+                path, segment
+            ),
+            GfaUtilsError::UltrabubbleLoad { path, reason } => write!(
+                f,
+                "Error loading ultrabubbles from {}: {}",
+                path.display(),
+                reason
+            ),
+            GfaUtilsError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for GfaUtilsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GfaUtilsError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GfaUtilsError {
+    fn from(err: io::Error) -> Self {
+        GfaUtilsError::Io(err)
+    }
+}
+
+impl From<gfa::parser::ParseError> for GfaUtilsError {
+    fn from(err: gfa::parser::ParseError) -> Self {
+        GfaUtilsError::GfaParse(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for GfaUtilsError {
+    fn from(err: serde_json::Error) -> Self {
+        GfaUtilsError::Other(err.to_string())
+    }
+}
+
+impl From<String> for GfaUtilsError {
+    fn from(msg: String) -> Self {
+        GfaUtilsError::Other(msg)
+    }
+}
+
+impl From<&str> for GfaUtilsError {
+    fn from(msg: &str) -> Self {
+        GfaUtilsError::Other(msg.into())
+    }
+}
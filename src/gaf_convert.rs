@@ -9,7 +9,9 @@ use gfa::{
     optfields::{OptFieldVal, OptFields, OptionalFields},
 };
 
+#[allow(clippy::upper_case_acronyms)]
 type GAF = gfa::gafpaf::GAF<OptionalFields>;
+#[allow(clippy::upper_case_acronyms)]
 type PAF = gfa::gafpaf::PAF<OptionalFields>;
 
 fn set_cigar(opts: &mut OptionalFields, cg: CIGAR) {
@@ -20,7 +22,7 @@ fn set_cigar(opts: &mut OptionalFields, cg: CIGAR) {
 fn get_cigar<T: OptFields>(opts: &T) -> Option<CIGAR> {
     let cg = opts.get_field(b"cg")?;
     if let OptFieldVal::Z(cg) = &cg.value {
-        CIGAR::from_bytestring(&cg)
+        CIGAR::from_bytestring(cg)
     } else {
         None
     }
@@ -199,7 +201,7 @@ fn gaf_line_to_pafs<T: OptFields>(
                     query_seq_len: gaf.seq_len,
                     query_seq_range: (query_start, query_end),
                     strand,
-                    target_seq_name: target_seq_name.into(),
+                    target_seq_name,
                     target_seq_len,
                     target_seq_range,
                     residue_matches,
@@ -231,6 +233,7 @@ pub fn gaf_to_paf<T: OptFields>(
     let mut gafs: Vec<GAF> = Vec::new();
 
     for (i, line) in lines.enumerate() {
+        let line = line.trim_end_with(|c| c == '\r');
         let fields = line.split_str(b"\t");
         if let Some(gaf) = parse_gaf(fields) {
             gafs.push(gaf);
@@ -242,7 +245,7 @@ pub fn gaf_to_paf<T: OptFields>(
     let mut pafs: Vec<PAF> = Vec::new();
 
     gafs.iter().for_each(|gaf| {
-        let cur_pafs = gaf_line_to_pafs(&segments, &gaf);
+        let cur_pafs = gaf_line_to_pafs(&segments, gaf);
         pafs.extend(cur_pafs);
     });
 
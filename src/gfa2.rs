@@ -0,0 +1,264 @@
+//! Support for GFA2 input, by translating GFA2 lines into the
+//! equivalent GFA1 lines, which can then be parsed into the GFA1 model
+//! used everywhere else.
+//!
+//! Segments are kept as they are, dovetail edges become links, and
+//! ordered groups become paths. Fragments, gaps, unordered groups, and
+//! edges that aren't dovetail overlaps (i.e. containments) are dropped,
+//! as nothing in gfautil uses them.
+
+use bstr::ByteSlice;
+use fnv::FnvHashSet;
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GFAVersion {
+    GFA1,
+    GFA2,
+}
+
+/// Detect the GFA version of a file from its lines, using the `VN`
+/// tag of the header if there is one, otherwise the first line whose
+/// structure is specific to one of the versions. Defaults to GFA1.
+pub fn detect_version<I, L>(lines: I) -> GFAVersion
+where
+    I: IntoIterator<Item = L>,
+    L: AsRef<[u8]>,
+{
+    for line in lines {
+        let line = line.as_ref();
+        let mut fields = line.split_str("\t");
+        match fields.next() {
+            Some(b"H") => {
+                let version = fields.find_map(|f| f.strip_prefix(b"VN:Z:"));
+                if let Some(version) = version {
+                    if version.starts_with(b"2") {
+                        return GFAVersion::GFA2;
+                    } else {
+                        return GFAVersion::GFA1;
+                    }
+                }
+            }
+            Some(b"E") | Some(b"F") | Some(b"G") | Some(b"O") | Some(b"U") => {
+                return GFAVersion::GFA2
+            }
+            Some(b"L") | Some(b"C") | Some(b"P") | Some(b"W") => {
+                return GFAVersion::GFA1
+            }
+            Some(b"S") => {
+                // GFA2 segments have a length field before the
+                // sequence, which a GFA1 sequence can never look like
+                let _name = fields.next();
+                let second = fields.next().unwrap_or_default();
+                let has_third = fields.next().is_some();
+                if has_third
+                    && !second.is_empty()
+                    && second.iter().all(u8::is_ascii_digit)
+                {
+                    return GFAVersion::GFA2;
+                } else {
+                    return GFAVersion::GFA1;
+                }
+            }
+            _ => (),
+        }
+    }
+    GFAVersion::GFA1
+}
+
+/// Translate the lines of a GFA2 file into GFA1 lines. All lines are
+/// needed up front, since ordered groups can refer to both segments
+/// and edges, which can be defined in any order.
+pub fn gfa2_to_gfa1_lines<L: AsRef<[u8]>>(lines: &[L]) -> Vec<Vec<u8>> {
+    let segment_names: FnvHashSet<&[u8]> = lines
+        .iter()
+        .filter_map(|line| {
+            let mut fields = line.as_ref().split_str("\t");
+            if fields.next()? == b"S" {
+                fields.next()
+            } else {
+                None
+            }
+        })
+        .collect();
+
+    let mut dropped = 0;
+
+    let gfa1_lines: Vec<Vec<u8>> = lines
+        .iter()
+        .filter_map(|line| {
+            let line = line.as_ref();
+            let fields: Vec<&[u8]> = line.split_str("\t").collect();
+            let converted = match fields[0] {
+                b"H" => header_line(&fields),
+                b"S" => segment_line(&fields),
+                b"E" => edge_line(&fields),
+                b"O" => path_line(&fields, &segment_names),
+                b"#" | b"" => return None,
+                _ => None,
+            };
+            if converted.is_none() {
+                dropped += 1;
+            }
+            converted
+        })
+        .collect();
+
+    if dropped > 0 {
+        debug!("Skipped {} GFA2 lines with no GFA1 equivalent", dropped);
+    }
+
+    gfa1_lines
+}
+
+fn header_line(fields: &[&[u8]]) -> Option<Vec<u8>> {
+    let tags: Vec<&[u8]> = fields[1..]
+        .iter()
+        .copied()
+        .filter(|f| !f.starts_with(b"VN:"))
+        .collect();
+    if tags.is_empty() {
+        return None;
+    }
+    Some(bstr::join("\t", std::iter::once(&b"H"[..]).chain(tags)))
+}
+
+fn segment_line(fields: &[&[u8]]) -> Option<Vec<u8>> {
+    if fields.len() < 4 {
+        return None;
+    }
+    let name = fields[1];
+    let sequence = fields[3];
+    let tags = fields[4..].iter().copied();
+    let fields = [&b"S"[..], name, sequence];
+    Some(bstr::join("\t", fields.iter().copied().chain(tags)))
+}
+
+/// Split a GFA2 reference such as `seg1+` into its name and
+/// orientation.
+fn split_reference(reference: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (name, orient) = reference.split_at(reference.len().checked_sub(1)?);
+    match orient {
+        b"+" | b"-" if !name.is_empty() => Some((name, orient)),
+        _ => None,
+    }
+}
+
+fn edge_line(fields: &[&[u8]]) -> Option<Vec<u8>> {
+    if fields.len() < 9 {
+        return None;
+    }
+    let (from, from_orient) = split_reference(fields[2])?;
+    let (to, to_orient) = split_reference(fields[3])?;
+
+    let is_end = |pos: &[u8]| pos.ends_with(b"$");
+    let is_start = |pos: &[u8]| pos == b"0";
+
+    // The overlap must be at the end of the first segment and at the
+    // start of the second, as seen in the orientations used by the
+    // edge; anything else is a containment
+    let from_dovetail = match from_orient {
+        b"+" => is_end(fields[5]),
+        _ => is_start(fields[4]),
+    };
+    let to_dovetail = match to_orient {
+        b"+" => is_start(fields[6]),
+        _ => is_end(fields[7]),
+    };
+    if !from_dovetail || !to_dovetail {
+        return None;
+    }
+
+    let alignment = fields[8];
+    let is_cigar = !alignment.is_empty()
+        && alignment
+            .iter()
+            .all(|&c| c.is_ascii_digit() || b"MIDNSHP=X".contains(&c));
+    let overlap: &[u8] = if is_cigar { alignment } else { b"*" };
+
+    let tags = fields[9..].iter().copied();
+    let fields = [&b"L"[..], from, from_orient, to, to_orient, overlap];
+    Some(bstr::join("\t", fields.iter().copied().chain(tags)))
+}
+
+fn path_line(
+    fields: &[&[u8]],
+    segment_names: &FnvHashSet<&[u8]>,
+) -> Option<Vec<u8>> {
+    if fields.len() < 3 || fields[1] == b"*" {
+        return None;
+    }
+    let name = fields[1];
+
+    // Ordered groups can also include edges and other groups, but the
+    // segments are enough to describe the path
+    let steps: Vec<&[u8]> = fields[2]
+        .split_str(" ")
+        .filter(|reference| {
+            split_reference(reference)
+                .map(|(seg, _)| segment_names.contains(seg))
+                .unwrap_or(false)
+        })
+        .collect();
+
+    if steps.is_empty() {
+        return None;
+    }
+
+    let steps = bstr::join(",", steps);
+    let tags = fields[3..].iter().copied();
+    let fields = [&b"P"[..], name, &steps, b"*"];
+    Some(bstr::join("\t", fields.iter().copied().chain(tags)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GFA2: [&str; 10] = [
+        "H\tVN:Z:2.0\tTS:i:100",
+        "S\t1\t4\tACGT",
+        "S\t2\t1\tA\tRC:i:3",
+        "S\t3\t1\tC",
+        "S\t4\t4\tGGTT",
+        "E\te1\t1+\t2+\t3\t4$\t0\t1\t1M",
+        "E\te2\t1+\t3+\t4$\t4$\t0\t0\t*",
+        "E\te3\t2+\t4-\t1$\t1$\t4$\t4$\t0M",
+        "E\tc1\t1+\t4+\t1\t2\t0\t1\t*",
+        "O\tp1\t1+ e1+ 2+ 4-",
+    ];
+
+    #[test]
+    fn detect_gfa_versions() {
+        assert_eq!(GFAVersion::GFA2, detect_version(GFA2));
+        assert_eq!(GFAVersion::GFA2, detect_version(&GFA2[1..]));
+        assert_eq!(GFAVersion::GFA2, detect_version(["# c", "E\te\t1+"]));
+        assert_eq!(GFAVersion::GFA1, detect_version(["H\tVN:Z:1.0"]));
+        assert_eq!(GFAVersion::GFA1, detect_version(["S\t1\tACGT"]));
+        assert_eq!(GFAVersion::GFA1, detect_version(["S\t1\tA\tLN:i:1"]));
+        assert_eq!(GFAVersion::GFA1, detect_version(Vec::<&str>::new()));
+    }
+
+    #[test]
+    fn translate_gfa2_lines() {
+        let lines = gfa2_to_gfa1_lines(&GFA2);
+        let lines: Vec<_> = lines.iter().map(|l| l.to_str().unwrap()).collect();
+
+        assert_eq!(
+            vec![
+                "H\tTS:i:100",
+                "S\t1\tACGT",
+                "S\t2\tA\tRC:i:3",
+                "S\t3\tC",
+                "S\t4\tGGTT",
+                "L\t1\t+\t2\t+\t1M",
+                "L\t1\t+\t3\t+\t*",
+                "L\t2\t+\t4\t-\t0M",
+                "P\tp1\t1+,2+,4-\t*",
+            ],
+            lines
+        );
+    }
+}
@@ -0,0 +1,552 @@
+//! Decompression of gzip files, so that gzipped GFAs can be read
+//! directly. Multi-member files, including BGZF, are supported.
+
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59,
+    67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5,
+    5, 5, 5, 0,
+];
+
+pub(crate) const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513,
+    769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+pub(crate) const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10,
+    11, 11, 12, 12, 13, 13,
+];
+
+/// The order in which the code length code lengths are stored in a
+/// dynamic block header.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const WINDOW_SIZE: usize = 32768;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// Continue a CRC-32 checksum, starting from `crc`, with `data`.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc = CRC32_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Returns true if the file at `path` starts with the gzip magic
+/// bytes, or has a `.gz` extension.
+pub fn is_gzip_file<P: AsRef<Path>>(path: P) -> io::Result<bool> {
+    let path = path.as_ref();
+    if path.extension().map(|ext| ext == "gz").unwrap_or(false) {
+        return Ok(true);
+    }
+
+    let mut magic = [0u8; 2];
+    let mut file = File::open(path)?;
+    let mut read = 0;
+    while read < magic.len() {
+        let n = file.read(&mut magic[read..])?;
+        if n == 0 {
+            return Ok(false);
+        }
+        read += n;
+    }
+    Ok(magic == GZIP_MAGIC)
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "gzip stream is truncated")
+}
+
+struct BitReader<R: Read> {
+    inner: BufReader<R>,
+    bits: u64,
+    len: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner: BufReader::new(inner),
+            bits: 0,
+            len: 0,
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        let buf = self.inner.fill_buf()?;
+        if let Some(&b) = buf.first() {
+            self.inner.consume(1);
+            Ok(Some(b))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Read `n` bits, least significant bit first.
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        while self.len < n {
+            let b = self.next_byte()?.ok_or_else(truncated)?;
+            self.bits |= (b as u64) << self.len;
+            self.len += 8;
+        }
+        let value = (self.bits & ((1 << n) - 1)) as u32;
+        self.bits >>= n;
+        self.len -= n;
+        Ok(value)
+    }
+
+    /// Discard the remaining bits of the current byte.
+    fn align(&mut self) {
+        let rem = self.len % 8;
+        self.bits >>= rem;
+        self.len -= rem;
+    }
+
+    fn byte(&mut self) -> io::Result<u8> {
+        self.bits(8).map(|b| b as u8)
+    }
+
+    fn u16_le(&mut self) -> io::Result<u16> {
+        let lo = self.byte()? as u16;
+        let hi = self.byte()? as u16;
+        Ok(lo | hi << 8)
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        let lo = self.u16_le()? as u32;
+        let hi = self.u16_le()? as u32;
+        Ok(lo | hi << 16)
+    }
+
+    /// True if there are no more bytes to read. Must be called on a
+    /// byte boundary.
+    fn at_eof(&mut self) -> io::Result<bool> {
+        Ok(self.len == 0 && self.inner.fill_buf()?.is_empty())
+    }
+}
+
+/// A canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    count: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut count = [0u16; 16];
+        for &len in lengths {
+            count[len as usize] += 1;
+        }
+        count[0] = 0;
+
+        let mut left: i32 = 1;
+        for &c in count.iter().skip(1) {
+            left <<= 1;
+            left -= c as i32;
+            if left < 0 {
+                return Err(invalid_data("over-subscribed Huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + count[len];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Self { count, symbols })
+    }
+
+    fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> io::Result<u16> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.count[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(invalid_data("invalid Huffman code"))
+    }
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+    let mut lengths = [0u8; 288];
+    lengths[..144].iter_mut().for_each(|l| *l = 8);
+    lengths[144..256].iter_mut().for_each(|l| *l = 9);
+    lengths[256..280].iter_mut().for_each(|l| *l = 7);
+    lengths[280..].iter_mut().for_each(|l| *l = 8);
+    let lit = Huffman::new(&lengths).unwrap();
+    let dist = Huffman::new(&[5; 30]).unwrap();
+    (lit, dist)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Header,
+    Blocks,
+    Done,
+}
+
+/// Wraps a reader of gzip-compressed data, and decompresses it.
+pub struct GzDecoder<R: Read> {
+    input: BitReader<R>,
+    state: State,
+    members: usize,
+    /// Decompressed output, preceded by up to `WINDOW_SIZE` bytes of
+    /// history for back-references.
+    out: Vec<u8>,
+    pos: usize,
+    crc: u32,
+    member_len: u32,
+    fixed: Option<(Huffman, Huffman)>,
+}
+
+impl<R: Read> GzDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            input: BitReader::new(inner),
+            state: State::Header,
+            members: 0,
+            out: Vec::with_capacity(4 * WINDOW_SIZE),
+            pos: 0,
+            crc: 0,
+            member_len: 0,
+            fixed: None,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let input = &mut self.input;
+        if input.byte()? != GZIP_MAGIC[0] || input.byte()? != GZIP_MAGIC[1] {
+            return Err(invalid_data("not a gzip file"));
+        }
+        if input.byte()? != 8 {
+            return Err(invalid_data("unknown gzip compression method"));
+        }
+        let flags = input.byte()?;
+        // MTIME, XFL, OS
+        for _ in 0..6 {
+            input.byte()?;
+        }
+        if flags & 0x04 != 0 {
+            let xlen = input.u16_le()?;
+            for _ in 0..xlen {
+                input.byte()?;
+            }
+        }
+        // FNAME and FCOMMENT are zero-terminated
+        for &flag in &[0x08, 0x10] {
+            if flags & flag != 0 {
+                while input.byte()? != 0 {}
+            }
+        }
+        if flags & 0x02 != 0 {
+            input.u16_le()?;
+        }
+
+        self.crc = 0;
+        self.member_len = 0;
+        self.members += 1;
+        Ok(())
+    }
+
+    fn read_trailer(&mut self) -> io::Result<()> {
+        self.input.align();
+        let crc = self.input.u32_le()?;
+        let len = self.input.u32_le()?;
+        if crc != self.crc {
+            return Err(invalid_data("gzip CRC mismatch"));
+        }
+        if len != self.member_len {
+            return Err(invalid_data("gzip length mismatch"));
+        }
+        Ok(())
+    }
+
+    /// Decompress the next DEFLATE block, appending to `out`.
+    /// Returns true if it was the last block of the member.
+    fn read_block(&mut self) -> io::Result<bool> {
+        let last = self.input.bits(1)? == 1;
+        match self.input.bits(2)? {
+            0 => self.stored_block()?,
+            1 => {
+                let (lit, dist) = self.fixed.take().unwrap_or_else(fixed_codes);
+                let res = self.codes(&lit, &dist);
+                self.fixed = Some((lit, dist));
+                res?
+            }
+            2 => {
+                let (lit, dist) = self.dynamic_codes()?;
+                self.codes(&lit, &dist)?
+            }
+            _ => return Err(invalid_data("invalid DEFLATE block type")),
+        }
+        Ok(last)
+    }
+
+    fn stored_block(&mut self) -> io::Result<()> {
+        self.input.align();
+        let len = self.input.u16_le()?;
+        let nlen = self.input.u16_le()?;
+        if len != !nlen {
+            return Err(invalid_data("invalid stored block length"));
+        }
+        for _ in 0..len {
+            let b = self.input.byte()?;
+            self.out.push(b);
+        }
+        Ok(())
+    }
+
+    fn dynamic_codes(&mut self) -> io::Result<(Huffman, Huffman)> {
+        let input = &mut self.input;
+        let hlit = input.bits(5)? as usize + 257;
+        let hdist = input.bits(5)? as usize + 1;
+        let hclen = input.bits(4)? as usize + 4;
+
+        let mut cl_lengths = [0u8; 19];
+        for &ix in CODE_LENGTH_ORDER.iter().take(hclen) {
+            cl_lengths[ix] = input.bits(3)? as u8;
+        }
+        let cl_code = Huffman::new(&cl_lengths)?;
+
+        let mut lengths = vec![0u8; hlit + hdist];
+        let mut ix = 0;
+        while ix < lengths.len() {
+            let symbol = cl_code.decode(input)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    if ix == 0 {
+                        return Err(invalid_data("repeat with no length"));
+                    }
+                    (lengths[ix - 1], 3 + input.bits(2)? as usize)
+                }
+                17 => (0, 3 + input.bits(3)? as usize),
+                _ => (0, 11 + input.bits(7)? as usize),
+            };
+            if ix + repeat > lengths.len() {
+                return Err(invalid_data("too many code lengths"));
+            }
+            lengths[ix..ix + repeat].iter_mut().for_each(|l| *l = value);
+            ix += repeat;
+        }
+
+        let lit = Huffman::new(&lengths[..hlit])?;
+        let dist = Huffman::new(&lengths[hlit..])?;
+        Ok((lit, dist))
+    }
+
+    fn codes(&mut self, lit: &Huffman, dist: &Huffman) -> io::Result<()> {
+        loop {
+            let symbol = lit.decode(&mut self.input)? as usize;
+            if symbol < 256 {
+                self.out.push(symbol as u8);
+            } else if symbol == 256 {
+                return Ok(());
+            } else {
+                let len_ix = symbol - 257;
+                if len_ix >= LENGTH_BASE.len() {
+                    return Err(invalid_data("invalid length symbol"));
+                }
+                let length = LENGTH_BASE[len_ix] as usize
+                    + self.input.bits(LENGTH_EXTRA[len_ix] as u32)? as usize;
+
+                let dist_ix = dist.decode(&mut self.input)? as usize;
+                if dist_ix >= DIST_BASE.len() {
+                    return Err(invalid_data("invalid distance symbol"));
+                }
+                let distance = DIST_BASE[dist_ix] as usize
+                    + self.input.bits(DIST_EXTRA[dist_ix] as u32)? as usize;
+
+                if distance > self.out.len() {
+                    return Err(invalid_data("distance too far back"));
+                }
+                let start = self.out.len() - distance;
+                for i in 0..length {
+                    let b = self.out[start + i];
+                    self.out.push(b);
+                }
+            }
+        }
+    }
+
+    /// Decompress more data into `out`. Returns false when the end
+    /// of the input has been reached.
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.out.len() > WINDOW_SIZE {
+            let drop = self.out.len() - WINDOW_SIZE;
+            self.out.drain(..drop);
+            self.pos -= drop;
+        }
+
+        loop {
+            match self.state {
+                State::Header => {
+                    if self.members > 0 && self.input.at_eof()? {
+                        self.state = State::Done;
+                    } else {
+                        self.read_header()?;
+                        self.state = State::Blocks;
+                    }
+                }
+                State::Blocks => {
+                    let start = self.out.len();
+                    let last = self.read_block()?;
+                    let new = &self.out[start..];
+                    self.crc = crc32_update(self.crc, new);
+                    self.member_len =
+                        self.member_len.wrapping_add(new.len() as u32);
+                    if last {
+                        self.read_trailer()?;
+                        self.state = State::Header;
+                    }
+                    if self.out.len() > start {
+                        return Ok(true);
+                    }
+                }
+                State::Done => return Ok(false),
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.out.len() && !self.fill()? {
+            return Ok(0);
+        }
+        let available = &self.out[self.pos..];
+        let len = available.len().min(buf.len());
+        buf[..len].copy_from_slice(&available[..len]);
+        self.pos += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::bgzf::BgzfWriter;
+    use std::io::Write;
+
+    fn decompress(bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut decoder = GzDecoder::new(bytes);
+        let mut out = Vec::new();
+        decoder.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(0xcbf4_3926, crc32(b"123456789"));
+        let partial = crc32_update(crc32(b"1234"), b"56789");
+        assert_eq!(0xcbf4_3926, partial);
+    }
+
+    // Segment lines 1 to 10, as generated by `gfa_text`, compressed
+    // with `gzip -9n`, which uses a dynamic Huffman block
+    const DYNAMIC_GZ: [u8; 81] = [
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x2d, 0x8a,
+        0xc9, 0x11, 0xc0, 0x20, 0x0c, 0xc4, 0xde, 0xa2, 0x9a, 0x90, 0x03, 0x92,
+        0xa7, 0x87, 0x07, 0x05, 0xe0, 0xfe, 0x6b, 0xc9, 0x7a, 0xcc, 0x6b, 0xb5,
+        0x23, 0x2d, 0x2a, 0x63, 0xfa, 0x2c, 0x8b, 0x13, 0xad, 0x09, 0x2e, 0xb4,
+        0x2e, 0xb8, 0xb1, 0x70, 0xe6, 0x71, 0x1e, 0x36, 0x47, 0xd2, 0xd8, 0x3c,
+        0x74, 0x3a, 0xe1, 0xdf, 0x8c, 0x45, 0x5f, 0x96, 0xa2, 0x7a, 0x64, 0x57,
+        0x7e, 0x01, 0x0d, 0xc7, 0x4e, 0x67, 0x00, 0x00, 0x00,
+    ];
+
+    fn gfa_text() -> Vec<u8> {
+        let mut text = Vec::new();
+        for i in 1..=10 {
+            let seq = [&b"ACGT"[i % 4..], &b"GATTACA"[..i % 7]].concat();
+            text.extend_from_slice(format!("S\t{}\t", i).as_bytes());
+            text.extend_from_slice(&seq);
+            text.push(b'\n');
+        }
+        text
+    }
+
+    #[test]
+    fn decompress_gzip() {
+        assert_eq!(gfa_text(), decompress(&DYNAMIC_GZ).unwrap());
+    }
+
+    #[test]
+    fn decompress_bgzf() {
+        let data = b"P\tpath\t1+,2-,3+\t*\n".repeat(10000);
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(&data).unwrap();
+        let compressed = writer.finish().unwrap();
+
+        assert_eq!(data, decompress(&compressed).unwrap());
+    }
+
+    #[test]
+    fn truncated_stream_is_error() {
+        let truncated = &DYNAMIC_GZ[..DYNAMIC_GZ.len() - 10];
+        let err = decompress(truncated).unwrap_err();
+        assert_eq!(io::ErrorKind::UnexpectedEof, err.kind());
+
+        let mut corrupt = DYNAMIC_GZ;
+        corrupt[DYNAMIC_GZ.len() - 6] ^= 0xff;
+        assert!(decompress(&corrupt).is_err());
+    }
+}
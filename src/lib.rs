@@ -1,6 +1,14 @@
+pub mod bgzf;
+pub mod bubbles;
 pub mod commands;
+pub mod config;
 pub mod edges;
+pub mod error;
 pub mod gaf_convert;
+pub mod gfa2;
+pub mod gzip;
 pub mod subgraph;
+pub mod synthetic;
+pub mod tabix;
 pub mod util;
 pub mod variants;
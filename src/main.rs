@@ -1,15 +1,22 @@
 use structopt::StructOpt;
 
-use std::path::PathBuf;
+use std::{ffi::OsString, path::PathBuf};
 
 use gfautil::{
     commands,
     commands::{
         convert_names::GfaIdConvertArgs, gaf2paf::GAF2PAFArgs,
-        gfa2vcf::GFA2VCFArgs, snps::SNPArgs, subgraph::SubgraphArgs, Result,
+        gfa2vcf::GFA2VCFArgs, merge_vcf::MergeVcfArgs, path_diff::PathDiffArgs,
+        paths2fasta::Paths2FastaArgs, saboten::SabotenArgs, snps::SNPArgs,
+        stats::StatsArgs, subgraph::SubgraphArgs, Result,
     },
+    error::GfaUtilsError,
+    variants::PathDataOptions,
 };
 
+// The command is only parsed once, so the size of the largest
+// argument struct doesn't matter
+#[allow(clippy::large_enum_variant)]
 #[derive(StructOpt, Debug)]
 enum Command {
     Subgraph(SubgraphArgs),
@@ -22,14 +29,34 @@ enum Command {
     Gfa2Vcf(GFA2VCFArgs),
     #[structopt(name = "snps")]
     Snps(SNPArgs),
-    #[structopt(name = "ultrabubbles")]
-    Saboten,
+    #[structopt(name = "ultrabubbles", alias = "bubbles")]
+    Saboten(SabotenArgs),
+    #[structopt(name = "stats")]
+    Stats(StatsArgs),
+    #[structopt(name = "paths2fasta")]
+    Paths2Fasta(Paths2FastaArgs),
+    #[structopt(name = "path-diff")]
+    PathDiff(PathDiffArgs),
+    #[structopt(name = "merge-vcf", alias = "merge")]
+    MergeVcf(MergeVcfArgs),
+    /// Check the GFA for links and path steps that refer to missing
+    /// segments, duplicate segment IDs, and segments whose LN tag
+    /// doesn't match their sequence. Prints each problem found, or OK.
+    #[structopt(name = "validate")]
+    Validate,
 }
 
+// The log level is Info by default, or taken from the `RUST_LOG`
+// environment variable if set. These options override both.
 #[derive(StructOpt, Debug)]
 struct LogOpt {
-    /// Show no messages.
-    #[structopt(long)]
+    /// Only show errors, without any other messages or progress, e.g.
+    /// for scripts that capture the output.
+    #[structopt(
+        short,
+        long,
+        conflicts_with_all = &["info", "debug", "verbose"]
+    )]
     quiet: bool,
     /// Show info messages.
     #[structopt(long)]
@@ -37,73 +64,200 @@ struct LogOpt {
     /// Show debug messages.
     #[structopt(long)]
     debug: bool,
+    /// Show more messages: debug messages with -v, and trace messages
+    /// with -vv.
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
 }
 
 #[derive(StructOpt, Debug)]
 struct Opt {
+    /// The input GFA file, which all commands need except merge-vcf,
+    /// and ultrabubbles with --from.
     #[structopt(name = "input GFA file", short, parse(from_os_str))]
-    in_gfa: PathBuf,
+    in_gfa: Option<PathBuf>,
     #[structopt(subcommand)]
     command: Command,
     #[structopt(flatten)]
     log_opts: LogOpt,
-    /// The number of threads to use when applicable. If omitted,
+    /// The number of threads to use when applicable. If omitted or 0,
     /// Rayon's default will be used, based on the RAYON_NUM_THREADS
-    /// environment variable, or the number of logical CPUs.
+    /// environment variable, or the number of logical CPUs. This bounds
+    /// all parallel work, including variant detection in gfa2vcf.
     #[structopt(short, long)]
     threads: Option<usize>,
+    /// Draw progress bars without first checking if stderr is a
+    /// terminal. If the bars still can't be drawn, the progress is
+    /// logged instead, like with --no-progress.
+    #[structopt(long, conflicts_with_all = &["no-progress", "quiet"])]
+    progress: bool,
+    /// Never draw progress bars, and log the progress every few
+    /// percent instead, e.g. when the output goes to a log file.
+    #[structopt(long)]
+    no_progress: bool,
+    /// Allow segment IDs that are defined more than once in the GFA,
+    /// keeping the first definition, instead of exiting with an error.
+    #[structopt(long = "allow-dup-segments")]
+    allow_dup_segments: bool,
+    /// Skip path steps that refer to segments missing from the GFA,
+    /// with a warning, treating the segments as empty, instead of
+    /// exiting with an error.
+    #[structopt(long = "skip-missing-segments")]
+    skip_missing_segments: bool,
+    /// Read the GFA through a memory map instead of a buffered reader,
+    /// unless it's gzipped or can't be mapped.
+    #[cfg(feature = "mmap")]
+    #[structopt(long)]
+    mmap: bool,
+}
+
+/// The log level chosen with the options, if any.
+fn log_level(opt: &LogOpt) -> Option<log::LevelFilter> {
+    use log::LevelFilter;
+    if opt.quiet {
+        Some(LevelFilter::Error)
+    } else if opt.verbose > 1 {
+        Some(LevelFilter::Trace)
+    } else if opt.verbose == 1 || opt.debug {
+        Some(LevelFilter::Debug)
+    } else if opt.info {
+        Some(LevelFilter::Info)
+    } else {
+        None
+    }
 }
 
 fn init_logger(opt: &LogOpt) {
     let mut builder = pretty_env_logger::formatted_builder();
-    if !opt.quiet {
-        let mut log_level = log::LevelFilter::Info;
-        if opt.info {
-            log_level = log::LevelFilter::Info;
+    match log_level(opt) {
+        Some(level) => {
+            builder.filter_level(level);
         }
-        if opt.debug {
-            log_level = log::LevelFilter::Debug;
+        None => {
+            builder.filter_level(log::LevelFilter::Info);
+            if let Ok(filters) = std::env::var("RUST_LOG") {
+                builder.parse_filters(&filters);
+            }
         }
-        builder.filter_level(log_level);
     }
 
     builder.init();
 }
 
-fn main() -> Result<()> {
+/// The command line arguments from the subcommand `name` on, or all
+/// of them, without the program name, if it isn't found.
+fn subcommand_args(name: &str) -> Vec<OsString> {
+    let args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let start = args.iter().position(|arg| arg == name).unwrap_or(0);
+    args[start..].to_vec()
+}
+
+/// Advice on how to fix an error, printed after its message.
+fn error_hint(err: &GfaUtilsError) -> Option<&'static str> {
+    match err {
+        GfaUtilsError::MissingReferencePaths(_) => {
+            Some("Use --lenient to skip the reference paths not in the graph")
+        }
+        GfaUtilsError::DuplicateSegment(_) => Some(
+            "Use --allow-dup-segments to keep the first definition of each \
+             segment",
+        ),
+        GfaUtilsError::MissingSegment { .. } => Some(
+            "Use --skip-missing-segments to skip the steps on missing \
+             segments, or the validate command to list them all",
+        ),
+        GfaUtilsError::UltrabubbleLoad { .. } => Some(
+            "Ultrabubble files can be made with the ultrabubbles command, \
+             or be BED files of intervals on the reference paths",
+        ),
+        _ => None,
+    }
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {}", err);
+        if let Some(hint) =
+            err.downcast_ref::<GfaUtilsError>().and_then(error_hint)
+        {
+            eprintln!("{}", hint);
+        }
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
     let opt = Opt::from_args();
 
     init_logger(&opt.log_opts);
 
-    if let Some(threads) = &opt.threads {
+    #[cfg(feature = "mmap")]
+    commands::set_use_mmap(opt.mmap);
+
+    if opt.progress {
+        gfautil::util::set_progress(Some(true));
+    } else if opt.no_progress || opt.log_opts.quiet {
+        gfautil::util::set_progress(Some(false));
+    }
+
+    if let Some(threads) = opt.threads.as_ref().filter(|&&t| t > 0) {
         log::info!("Initializing threadpool to use {} threads", threads);
         rayon::ThreadPoolBuilder::new()
             .num_threads(*threads)
             .build_global()?;
     }
 
+    if let Command::MergeVcf(args) = &opt.command {
+        return Ok(commands::merge_vcf::merge_vcf(args)?);
+    }
+
+    if let Command::Saboten(args) = &opt.command {
+        return commands::saboten::run_saboten(opt.in_gfa.as_ref(), args);
+    }
+
+    let in_gfa = opt
+        .in_gfa
+        .ok_or("The input GFA file is required, give it with -i")?;
+
+    let path_options = PathDataOptions {
+        allow_duplicate_segments: opt.allow_dup_segments,
+        skip_missing_segments: opt.skip_missing_segments,
+    };
+
     match opt.command {
         Command::Gfa2Vcf(args) => {
-            commands::gfa2vcf::gfa2vcf(&opt.in_gfa, args)?;
+            let cli_args = subcommand_args("gfa2vcf");
+            let args = commands::gfa2vcf::apply_config(args, &cli_args)?;
+            commands::gfa2vcf::gfa2vcf(&in_gfa, args, path_options)?;
         }
         Command::Snps(args) => {
-            commands::snps::gfa2snps(&opt.in_gfa, args)?;
+            commands::snps::gfa2snps(&in_gfa, args, path_options)?;
         }
         Command::Subgraph(args) => {
-            commands::subgraph::subgraph(&opt.in_gfa, &args)?;
+            commands::subgraph::subgraph(&in_gfa, &args)?;
         }
         Command::Gaf2Paf(args) => {
-            commands::gaf2paf::gaf2paf(&opt.in_gfa, &args)?;
+            commands::gaf2paf::gaf2paf(&in_gfa, &args)?;
         }
         Command::EdgeCount => {
-            commands::stats::edge_count(&opt.in_gfa)?;
+            commands::stats::edge_count(&in_gfa)?;
         }
         Command::GfaSegmentIdConversion(args) => {
-            commands::convert_names::convert_segment_ids(&opt.in_gfa, &args)?;
+            commands::convert_names::convert_segment_ids(&in_gfa, &args)?;
+        }
+        Command::Stats(args) => {
+            commands::stats::stats(&in_gfa, &args)?;
+        }
+        Command::Paths2Fasta(args) => {
+            commands::paths2fasta::paths2fasta(&in_gfa, &args, path_options)?;
+        }
+        Command::PathDiff(args) => {
+            commands::path_diff::path_diff(&in_gfa, &args)?;
         }
-        Command::Saboten => {
-            commands::saboten::run_saboten(&opt.in_gfa)?;
+        Command::Validate => {
+            commands::validate::validate(&in_gfa)?;
         }
+        Command::MergeVcf(_) | Command::Saboten(_) => unreachable!(),
     }
     Ok(())
 }
@@ -1,11 +1,12 @@
-use gfa::gfa::GFA;
+use bstr::ByteSlice;
+use gfa::gfa::{Path, GFA};
 use gfa::optfields::OptFields;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 macro_rules! filtered {
     ($coll:expr, $pred:expr) => {
-        $coll.iter().filter($pred).cloned().collect();
+        $coll.iter().filter($pred).cloned().collect()
     };
 }
 
@@ -86,3 +87,203 @@ pub fn segments_subgraph<T: OptFields + Clone>(
         containments,
     }
 }
+
+/// Returns a subgraph GFA containing the segments that can be reached
+/// from the provided seed segments in at most `radius` link hops,
+/// following links in either direction.
+///
+/// Paths are cut into the runs of steps that stay within the
+/// subgraph. A path that is kept whole keeps its name, while each run
+/// of a path that is cut is named after the path and the 1-based,
+/// inclusive range of steps it covers, e.g. `path:3-7`.
+pub fn neighborhood_subgraph<T: OptFields + Clone>(
+    gfa: &GFA<Vec<u8>, T>,
+    seeds: &[Vec<u8>],
+    radius: usize,
+) -> GFA<Vec<u8>, T> {
+    let mut neighbors: HashMap<&[u8], Vec<&[u8]>> = HashMap::new();
+    for link in gfa.links.iter() {
+        let from = link.from_segment.as_slice();
+        let to = link.to_segment.as_slice();
+        neighbors.entry(from).or_default().push(to);
+        neighbors.entry(to).or_default().push(from);
+    }
+
+    let mut segment_names: HashSet<&[u8]> = HashSet::new();
+    let mut queue: VecDeque<(&[u8], usize)> = VecDeque::new();
+    for seed in seeds {
+        if segment_names.insert(seed.as_slice()) {
+            queue.push_back((seed.as_slice(), 0));
+        }
+    }
+
+    while let Some((segment, distance)) = queue.pop_front() {
+        if distance == radius {
+            continue;
+        }
+        for &next in neighbors.get(segment).into_iter().flatten() {
+            if segment_names.insert(next) {
+                queue.push_back((next, distance + 1));
+            }
+        }
+    }
+
+    let segments =
+        filtered!(gfa.segments, |s| segment_names.contains(s.name.as_slice()));
+
+    let links = filtered!(&gfa.links, |l| {
+        segment_names.contains(l.from_segment.as_slice())
+            && segment_names.contains(l.to_segment.as_slice())
+    });
+
+    let containments = filtered!(&gfa.containments, |l| {
+        segment_names.contains(l.container_name.as_slice())
+            && segment_names.contains(l.contained_name.as_slice())
+    });
+
+    let paths = gfa
+        .paths
+        .iter()
+        .flat_map(|path| sub_paths_within(path, &segment_names))
+        .collect();
+
+    GFA {
+        header: gfa.header.clone(),
+        segments,
+        links,
+        paths,
+        containments,
+    }
+}
+
+/// Split a path into the runs of consecutive steps whose segments are
+/// all in `segment_names`.
+fn sub_paths_within<T: OptFields + Clone>(
+    path: &Path<Vec<u8>, T>,
+    segment_names: &HashSet<&[u8]>,
+) -> Vec<Path<Vec<u8>, T>> {
+    let steps: Vec<&[u8]> = path.segment_names.split_str(",").collect();
+    let in_subgraph = |step: &[u8]| {
+        !step.is_empty() && segment_names.contains(&step[..step.len() - 1])
+    };
+
+    // Overlaps can only be carried over if there is one per pair of
+    // consecutive steps, otherwise they're all `*`
+    let has_overlaps = path.overlaps.len() + 1 == steps.len()
+        && path.overlaps.iter().all(|o| o.is_some());
+
+    let mut sub_paths = Vec::new();
+    let mut start = 0;
+    while start < steps.len() {
+        if !in_subgraph(steps[start]) {
+            start += 1;
+            continue;
+        }
+        let mut end = start + 1;
+        while end < steps.len() && in_subgraph(steps[end]) {
+            end += 1;
+        }
+
+        let path_name = if start == 0 && end == steps.len() {
+            path.path_name.clone()
+        } else {
+            let mut name = path.path_name.clone();
+            name.extend(format!(":{}-{}", start + 1, end).bytes());
+            name
+        };
+        let overlaps = if has_overlaps {
+            path.overlaps[start..end - 1].to_vec()
+        } else {
+            vec![None]
+        };
+
+        sub_paths.push(Path::new(
+            path_name,
+            steps[start..end].join(&b","[..]),
+            overlaps,
+            path.optional.clone(),
+        ));
+        start = end;
+    }
+
+    sub_paths
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gfa::{
+        optfields::OptionalFields, parser::GFAParser, writer::gfa_string,
+    };
+
+    fn example_gfa() -> GFA<Vec<u8>, OptionalFields> {
+        let parser = GFAParser::new();
+        parser
+            .parse_lines(
+                [
+                    "H\tVN:Z:1.0",
+                    "S\ts1\tA",
+                    "S\ts2\tC",
+                    "S\ts3\tG",
+                    "S\ts4\tT",
+                    "S\ts5\tA",
+                    "L\ts1\t+\ts2\t+\t0M",
+                    "L\ts2\t+\ts3\t-\t0M",
+                    "L\ts3\t-\ts4\t+\t0M",
+                    "L\ts4\t+\ts5\t+\t0M",
+                    "P\tp1\ts1+,s2+,s3-,s4+,s5+\t*",
+                    "P\tp2\ts2+,s3-\t0M\tXX:Z:x",
+                    "P\tp3\ts5-,s4-,s3+,s2-,s1-\t*",
+                ]
+                .iter()
+                .map(|l| l.as_bytes()),
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn neighborhood_within_radius() {
+        let gfa = example_gfa();
+        let seeds = vec![b"s3".to_vec()];
+
+        let names = |gfa: &GFA<Vec<u8>, OptionalFields>| {
+            gfa.segments
+                .iter()
+                .map(|s| s.name.to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(vec!["s3"], names(&neighborhood_subgraph(&gfa, &seeds, 0)));
+        assert_eq!(
+            vec!["s2", "s3", "s4"],
+            names(&neighborhood_subgraph(&gfa, &seeds, 1))
+        );
+        assert_eq!(
+            vec!["s1", "s2", "s3", "s4", "s5"],
+            names(&neighborhood_subgraph(&gfa, &seeds, 2))
+        );
+    }
+
+    #[test]
+    fn neighborhood_cuts_paths() {
+        let gfa = example_gfa();
+        let seeds = vec![b"s3".to_vec()];
+
+        let subgraph = neighborhood_subgraph(&gfa, &seeds, 1);
+
+        assert_eq!(
+            "H\tVN:Z:1.0
+S\ts2\tC
+S\ts3\tG
+S\ts4\tT
+L\ts2\t+\ts3\t-\t0M
+L\ts3\t-\ts4\t+\t0M
+P\tp1:2-4\ts2+,s3-,s4+\t*
+P\tp2\ts2+,s3-\t0M\tXX:Z:x
+P\tp3:2-4\ts4-,s3+,s2-\t*
+",
+            gfa_string(&subgraph)
+        );
+    }
+}
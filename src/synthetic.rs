@@ -0,0 +1,162 @@
+//! Reproducible synthetic graphs, for benchmarks and tests that need
+//! a graph of a given size without shipping one in `tests/data`.
+
+use gfa::{gfa::GFA, parser::GFAParser};
+
+/// The parameters of a synthetic graph: a chain of `bubbles`
+/// ultrabubbles, each with `alleles` branches of one segment between
+/// two shared anchor segments, and `paths` paths that each take one
+/// branch through every bubble. The first branch of each bubble is
+/// the one taken by the first path, and the branches have random
+/// sequences of 1 to 4 bases, so the paths differ by SNVs, MNPs, and
+/// indels. The same parameters always give the same graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyntheticGraph {
+    pub bubbles: usize,
+    pub alleles: usize,
+    pub paths: usize,
+    /// The length of the anchor segments between the bubbles.
+    pub anchor_length: usize,
+    pub seed: u64,
+}
+
+impl Default for SyntheticGraph {
+    fn default() -> Self {
+        Self {
+            bubbles: 1000,
+            alleles: 3,
+            paths: 16,
+            anchor_length: 32,
+            seed: 1,
+        }
+    }
+}
+
+/// The SplitMix64 generator, which is enough for making up sequences
+/// and doesn't need another dependency.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn sequence(&mut self, len: usize) -> String {
+        (0..len).map(|_| b"ACGT"[self.below(4)] as char).collect()
+    }
+}
+
+impl SyntheticGraph {
+    /// The number of segments in the graph.
+    pub fn segment_count(&self) -> usize {
+        self.bubbles * (self.alleles + 1) + 1
+    }
+
+    /// The segment ID of the anchor before bubble `ix`, or after the
+    /// last bubble if `ix` is `bubbles`.
+    fn anchor(&self, ix: usize) -> usize {
+        ix * (self.alleles + 1) + 1
+    }
+
+    /// The ultrabubbles of the graph, as pairs of anchor segment IDs,
+    /// in the same form and order as the bubble finders return them.
+    pub fn ultrabubbles(&self) -> Vec<(u64, u64)> {
+        (0..self.bubbles)
+            .map(|ix| (self.anchor(ix) as u64, self.anchor(ix + 1) as u64))
+            .collect()
+    }
+
+    /// The lines of the graph as a GFA1 file, without line endings.
+    /// The paths are named `path0`, `path1`, and so on.
+    pub fn lines(&self) -> Vec<String> {
+        assert!(self.alleles >= 2, "A bubble needs at least two alleles");
+        let mut rng = SplitMix64(self.seed);
+        let mut lines = vec!["H\tVN:Z:1.0".to_string()];
+
+        let mut links = Vec::new();
+        for ix in 0..=self.bubbles {
+            let anchor = self.anchor(ix);
+            let seq = rng.sequence(self.anchor_length.max(1));
+            lines.push(format!("S\t{}\t{}", anchor, seq));
+            if ix == self.bubbles {
+                break;
+            }
+            for branch in anchor + 1..=anchor + self.alleles {
+                let len = 1 + rng.below(4);
+                lines.push(format!("S\t{}\t{}", branch, rng.sequence(len)));
+                links.push(format!("L\t{}\t+\t{}\t+\t0M", anchor, branch));
+                links.push(format!(
+                    "L\t{}\t+\t{}\t+\t0M",
+                    branch,
+                    self.anchor(ix + 1)
+                ));
+            }
+        }
+        lines.extend(links);
+
+        for path_ix in 0..self.paths {
+            let mut steps = Vec::with_capacity(2 * self.bubbles + 1);
+            for ix in 0..self.bubbles {
+                let branch = if path_ix == 0 {
+                    0
+                } else {
+                    rng.below(self.alleles)
+                };
+                steps.push(format!("{}+", self.anchor(ix)));
+                steps.push(format!("{}+", self.anchor(ix) + 1 + branch));
+            }
+            steps.push(format!("{}+", self.anchor(self.bubbles)));
+            lines.push(format!("P\tpath{}\t{}\t*", path_ix, steps.join(",")));
+        }
+
+        lines
+    }
+
+    /// Parse the graph from `lines`.
+    pub fn gfa(&self) -> GFA<usize, ()> {
+        let lines = self.lines();
+        GFAParser::new()
+            .parse_lines(lines.iter().map(|line| line.as_bytes()))
+            .expect("Synthetic graph is valid GFA")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        bubbles::{Bcc, BubbleFinder},
+        variants::gfa_path_data,
+    };
+
+    #[test]
+    fn synthetic_graph_is_reproducible() {
+        let params = SyntheticGraph {
+            bubbles: 20,
+            paths: 5,
+            ..SyntheticGraph::default()
+        };
+        assert_eq!(params.lines(), params.lines());
+        assert_ne!(
+            params.lines(),
+            SyntheticGraph { seed: 2, ..params }.lines()
+        );
+
+        let gfa = params.gfa();
+        assert_eq!(params.segment_count(), gfa.segments.len());
+        assert_eq!(params.ultrabubbles(), Bcc.find(&gfa));
+
+        let path_data = gfa_path_data(gfa, false, Default::default()).unwrap();
+        assert_eq!(5, path_data.paths.len());
+        assert!(path_data.paths.iter().all(|path| path.len() == 41));
+    }
+}
@@ -0,0 +1,376 @@
+//! Tabix indexing of bgzipped VCF files, producing the same `.tbi`
+//! files as `tabix -p vcf`.
+//!
+//! The index is built by reading the BGZF blocks of a finished file
+//! back, and records the virtual offset of every record in the
+//! binning scheme shared with BAM indices, along with a linear index
+//! of the first record in each 16kbp window of each contig.
+
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use bstr::{BString, ByteSlice};
+use fnv::FnvHashMap;
+
+use crate::{bgzf::BgzfWriter, gzip::GzDecoder};
+
+const MIN_SHIFT: u32 = 14;
+
+/// The tabix format code for VCF.
+const FORMAT_VCF: i32 = 2;
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// The bin of the smallest level of the binning scheme that contains
+/// the 0-based, half-open, range `beg`-`end`.
+pub fn reg2bin(beg: u64, end: u64) -> u32 {
+    let end = end.max(beg + 1) - 1;
+    let levels = [(14, 4681), (17, 585), (20, 73), (23, 9), (26, 1)];
+    for &(shift, offset) in levels.iter() {
+        if beg >> shift == end >> shift {
+            return offset + (beg >> shift) as u32;
+        }
+    }
+    0
+}
+
+/// The index of a single contig.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct ContigIndex {
+    /// The chunks of virtual offsets of the records in each bin.
+    bins: BTreeMap<u32, Vec<(u64, u64)>>,
+    /// The virtual offset of the first record overlapping each
+    /// window, if any.
+    linear: Vec<Option<u64>>,
+    last_beg: u64,
+}
+
+impl ContigIndex {
+    fn add(&mut self, beg: u64, end: u64, start: u64, stop: u64) {
+        let chunks = self.bins.entry(reg2bin(beg, end)).or_default();
+        match chunks.last_mut() {
+            Some(chunk) if chunk.1 == start => chunk.1 = stop,
+            _ => chunks.push((start, stop)),
+        }
+
+        let first = (beg >> MIN_SHIFT) as usize;
+        let last = ((end.max(beg + 1) - 1) >> MIN_SHIFT) as usize;
+        if self.linear.len() <= last {
+            self.linear.resize(last + 1, None);
+        }
+        for window in self.linear[first..=last].iter_mut() {
+            window.get_or_insert(start);
+        }
+        self.last_beg = beg;
+    }
+
+    /// The linear index, with the windows without records given the
+    /// offset of the closest window before them.
+    fn linear_offsets(&self) -> Vec<u64> {
+        let first = self.linear.iter().find_map(|o| *o).unwrap_or(0);
+        self.linear
+            .iter()
+            .scan(first, |prev, offset| {
+                *prev = offset.unwrap_or(*prev);
+                Some(*prev)
+            })
+            .collect()
+    }
+}
+
+/// A tabix index of the records of a VCF, built by adding the records
+/// in the order they appear in the file.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TabixIndex {
+    names: Vec<BString>,
+    name_ixs: FnvHashMap<BString, usize>,
+    contigs: Vec<ContigIndex>,
+}
+
+impl TabixIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a record on `chrom` covering the 0-based, half-open,
+    /// range `beg`-`end`, that starts at the virtual offset `start`
+    /// and ends before `stop`. Returns an error if the records aren't
+    /// sorted, i.e. grouped by contig and sorted by position.
+    pub fn add_record(
+        &mut self,
+        chrom: &[u8],
+        beg: u64,
+        end: u64,
+        start: u64,
+        stop: u64,
+    ) -> io::Result<()> {
+        let last_ix = self.contigs.len().checked_sub(1);
+        let ix = match self.name_ixs.get(chrom.as_bstr()) {
+            Some(&ix) if Some(ix) == last_ix => ix,
+            Some(_) => {
+                return Err(invalid_data(format!(
+                    "Records on contig {} are not grouped together",
+                    chrom.as_bstr()
+                )))
+            }
+            None => {
+                let ix = self.contigs.len();
+                self.names.push(chrom.into());
+                self.name_ixs.insert(chrom.into(), ix);
+                self.contigs.push(ContigIndex::default());
+                ix
+            }
+        };
+
+        let contig = &mut self.contigs[ix];
+        if beg < contig.last_beg {
+            return Err(invalid_data(format!(
+                "Records on contig {} are not sorted by position",
+                chrom.as_bstr()
+            )));
+        }
+        contig.add(beg, end, start, stop);
+        Ok(())
+    }
+
+    /// Write the uncompressed index in the `.tbi` format, for VCF.
+    pub fn write_to<W: Write>(&self, mut out: W) -> io::Result<()> {
+        let mut names: Vec<u8> = Vec::new();
+        for name in self.names.iter() {
+            names.extend_from_slice(name);
+            names.push(0);
+        }
+
+        out.write_all(b"TBI\x01")?;
+        let header = [
+            self.names.len() as i32,
+            FORMAT_VCF,
+            // The sequence, start, and end columns
+            1,
+            2,
+            0,
+            i32::from(b'#'),
+            // Lines to skip
+            0,
+            names.len() as i32,
+        ];
+        for value in header.iter() {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        out.write_all(&names)?;
+
+        for contig in self.contigs.iter() {
+            out.write_all(&(contig.bins.len() as i32).to_le_bytes())?;
+            for (bin, chunks) in contig.bins.iter() {
+                out.write_all(&bin.to_le_bytes())?;
+                out.write_all(&(chunks.len() as i32).to_le_bytes())?;
+                for &(start, stop) in chunks.iter() {
+                    out.write_all(&start.to_le_bytes())?;
+                    out.write_all(&stop.to_le_bytes())?;
+                }
+            }
+
+            let linear = contig.linear_offsets();
+            out.write_all(&(linear.len() as i32).to_le_bytes())?;
+            for offset in linear {
+                out.write_all(&offset.to_le_bytes())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The 0-based, half-open, range covered by a VCF record line: from
+/// POS to the end of REF, or to the `END` INFO field if there is one.
+fn vcf_line_range(line: &[u8]) -> Option<(&[u8], u64, u64)> {
+    let mut fields = line.split_str("\t");
+    let chrom = fields.next()?;
+    let pos: u64 = fields.next()?.to_str().ok()?.parse().ok()?;
+    let reference = fields.nth(1)?;
+    let info = fields.nth(3).unwrap_or_default();
+
+    let beg = pos.checked_sub(1)?;
+    let end = info
+        .split_str(";")
+        .find_map(|entry| entry.strip_prefix(b"END="))
+        .and_then(|end| end.to_str().ok()?.parse().ok())
+        .unwrap_or(beg + reference.len() as u64);
+
+    Some((chrom, beg, end.max(beg + 1)))
+}
+
+/// Read the next BGZF block from `reader`, still compressed, or
+/// `None` at the end of the input.
+fn read_block<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0u8; 18];
+    match reader.read_exact(&mut header) {
+        Ok(()) => (),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Ok(None)
+        }
+        Err(err) => return Err(err),
+    }
+    if header[..4] != [0x1f, 0x8b, 0x08, 0x04] || &header[12..14] != b"BC" {
+        return Err(invalid_data("File is not compressed with bgzip".into()));
+    }
+    let block_len = u16::from_le_bytes([header[16], header[17]]) as usize + 1;
+
+    let mut block = header.to_vec();
+    block.resize(block_len, 0);
+    reader.read_exact(&mut block[header.len()..])?;
+    Ok(Some(block))
+}
+
+/// Build the tabix index of the bgzipped VCF read from `reader`.
+pub fn index_bgzf_vcf<R: Read>(mut reader: R) -> io::Result<TabixIndex> {
+    let mut index = TabixIndex::new();
+
+    let mut line: Vec<u8> = Vec::new();
+    let mut line_start = 0;
+    let mut block_offset: u64 = 0;
+
+    while let Some(block) = read_block(&mut reader)? {
+        let mut data = Vec::new();
+        GzDecoder::new(block.as_slice()).read_to_end(&mut data)?;
+        let next_block = block_offset + block.len() as u64;
+
+        for (ix, &byte) in data.iter().enumerate() {
+            if line.is_empty() {
+                line_start = (block_offset << 16) | ix as u64;
+            }
+            line.push(byte);
+            if byte != b'\n' {
+                continue;
+            }
+
+            let line_end = if ix + 1 == data.len() {
+                next_block << 16
+            } else {
+                (block_offset << 16) | (ix + 1) as u64
+            };
+
+            let text = line.trim_end_with(|c| c == '\n' || c == '\r');
+            if !text.is_empty() && !text.starts_with(b"#") {
+                let (chrom, beg, end) =
+                    vcf_line_range(text).ok_or_else(|| {
+                        invalid_data(format!(
+                            "Invalid VCF line: {}",
+                            text.as_bstr()
+                        ))
+                    })?;
+                index.add_record(chrom, beg, end, line_start, line_end)?;
+            }
+            line.clear();
+        }
+
+        block_offset = next_block;
+    }
+
+    if !line.is_empty() {
+        return Err(invalid_data("VCF doesn't end with a newline".into()));
+    }
+
+    Ok(index)
+}
+
+/// The path of the tabix index of the file at `path`, i.e. the path
+/// with `.tbi` appended.
+pub fn index_path(path: &Path) -> PathBuf {
+    let mut index_path = path.as_os_str().to_owned();
+    index_path.push(".tbi");
+    index_path.into()
+}
+
+/// Index the bgzipped VCF at `path`, writing the index, itself
+/// compressed with bgzip, next to it.
+pub fn write_vcf_index(path: &Path) -> io::Result<PathBuf> {
+    let file = File::open(path)?;
+    let index = index_bgzf_vcf(BufReader::new(file))?;
+
+    let index_path = index_path(path);
+    let out = File::create(&index_path)?;
+    let mut writer = BgzfWriter::new(BufWriter::new(out));
+    index.write_to(&mut writer)?;
+    writer.finish()?;
+
+    Ok(index_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+        let mut buf = [0u8; 4];
+        buf.copy_from_slice(&bytes[offset..offset + 4]);
+        i32::from_le_bytes(buf)
+    }
+
+    #[test]
+    fn bins() {
+        assert_eq!(4681, reg2bin(0, 1));
+        assert_eq!(4681, reg2bin(0, 1 << 14));
+        assert_eq!(585, reg2bin(0, (1 << 14) + 1));
+        assert_eq!(4682, reg2bin(1 << 14, (1 << 14) + 10));
+        assert_eq!(0, reg2bin(0, 1 << 29));
+    }
+
+    #[test]
+    fn index_bgzipped_vcf() {
+        let mut text = b"##fileformat=VCFv4.2\n#CHROM\tPOS\n".to_vec();
+        for pos in 1..3000 {
+            let line = format!("ref\t{}\t.\tA\tC\t.\tPASS\t.\n", pos * 10);
+            text.extend_from_slice(line.as_bytes());
+        }
+        text.extend_from_slice(b"alt\t5\t.\tA\t.\t.\tPASS\tEND=40000\n");
+
+        let mut writer = BgzfWriter::new(Vec::new());
+        writer.write_all(&text).unwrap();
+        let bgzf = writer.finish().unwrap();
+
+        let index = index_bgzf_vcf(bgzf.as_slice()).unwrap();
+        assert_eq!(vec!["ref", "alt"], index.names);
+
+        let ref_index = &index.contigs[0];
+        assert_eq!(2, ref_index.linear.len());
+        assert_eq!(
+            vec![4681, 4682],
+            ref_index.bins.keys().copied().collect::<Vec<_>>()
+        );
+        // The first record starts after the header lines
+        assert_eq!(Some(32), ref_index.linear[0]);
+
+        let alt_index = &index.contigs[1];
+        assert_eq!(
+            vec![585],
+            alt_index.bins.keys().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(3, alt_index.linear.len());
+
+        let mut tbi = Vec::new();
+        index.write_to(&mut tbi).unwrap();
+        assert_eq!(b"TBI\x01", &tbi[..4]);
+        assert_eq!(2, read_i32(&tbi, 4));
+        assert_eq!(FORMAT_VCF, read_i32(&tbi, 8));
+        assert_eq!(&b"ref\0alt\0"[..], &tbi[36..44]);
+    }
+
+    #[test]
+    fn unsorted_records_are_errors() {
+        let mut index = TabixIndex::new();
+        index.add_record(b"ref", 10, 11, 0, 10).unwrap();
+        assert!(index.add_record(b"ref", 5, 6, 10, 20).is_err());
+
+        let mut index = TabixIndex::new();
+        index.add_record(b"ref", 10, 11, 0, 10).unwrap();
+        index.add_record(b"alt", 10, 11, 10, 20).unwrap();
+        assert!(index.add_record(b"ref", 20, 21, 20, 30).is_err());
+    }
+}
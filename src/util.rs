@@ -1,4 +1,89 @@
-use indicatif::{ProgressBar, ProgressStyle};
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::{
+    io::IsTerminal,
+    sync::atomic::{AtomicBool, AtomicU8, Ordering},
+    time::Duration,
+};
+
+use log::info;
+
+const PROGRESS_AUTO: u8 = 0;
+const PROGRESS_BARS: u8 = 1;
+const PROGRESS_LOG: u8 = 2;
+
+static PROGRESS_MODE: AtomicU8 = AtomicU8::new(PROGRESS_AUTO);
+
+/// The percentage between the log lines that replace progress bars.
+const PROGRESS_LOG_STEP: u64 = 5;
+
+/// Choose whether progress is shown with progress bars, if `show` is
+/// true, or as periodic log messages, if false. By default, progress
+/// bars are only used if stderr is a terminal.
+pub fn set_progress(show: Option<bool>) {
+    let mode = match show {
+        None => PROGRESS_AUTO,
+        Some(true) => PROGRESS_BARS,
+        Some(false) => PROGRESS_LOG,
+    };
+    PROGRESS_MODE.store(mode, Ordering::Relaxed);
+}
+
+fn show_progress_bars() -> bool {
+    match PROGRESS_MODE.load(Ordering::Relaxed) {
+        PROGRESS_BARS => true,
+        PROGRESS_LOG => false,
+        _ => std::io::stderr().is_terminal(),
+    }
+}
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Restores the interrupt handler that was replaced by
+/// `catch_interrupts` when dropped.
+#[must_use = "interrupts are only caught until the guard is dropped"]
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+        }
+    }
+}
+
+/// Catch interrupts (SIGINT, e.g. from Ctrl-C) instead of exiting, so
+/// long-running loops can check `interrupted` and stop cleanly, until
+/// the returned guard is dropped. A second interrupt exits
+/// immediately. Only has an effect on Unix.
+pub fn catch_interrupts() -> InterruptGuard {
+    #[cfg(unix)]
+    unsafe {
+        let handler = handle_interrupt as extern "C" fn(libc::c_int);
+        let previous =
+            libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+        InterruptGuard { previous }
+    }
+    #[cfg(not(unix))]
+    InterruptGuard {}
+}
+
+#[cfg(unix)]
+extern "C" fn handle_interrupt(_signal: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // The exit status a shell gives a process killed by SIGINT
+        unsafe { libc::_exit(130) };
+    }
+}
+
+/// Whether an interrupt has been caught since `catch_interrupts` was
+/// called.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
 
 pub(crate) fn progress_bar(len: usize, steady: bool) -> ProgressBar {
     let p_bar = ProgressBar::new(len as u64);
@@ -7,8 +92,64 @@ pub(crate) fn progress_bar(len: usize, steady: bool) -> ProgressBar {
             .template("[{elapsed_precise}] {bar:80} {pos:>7}/{len:7}")
             .progress_chars("##-"),
     );
-    if steady {
+
+    if !show_progress_bars() || p_bar.is_hidden() {
+        p_bar.set_draw_target(ProgressDrawTarget::hidden());
+        log_progress(&p_bar);
+    } else if steady {
         p_bar.enable_steady_tick(1000);
     }
     p_bar
 }
+
+/// Log the progress of a hidden progress bar every
+/// `PROGRESS_LOG_STEP` percent, from a background thread that stops
+/// once the bar is finished or dropped.
+fn log_progress(p_bar: &ProgressBar) {
+    let weak = p_bar.downgrade();
+    std::thread::spawn(move || {
+        let mut logged = 0;
+        loop {
+            std::thread::sleep(Duration::from_millis(500));
+            let (pos, len, finished) = match weak.upgrade() {
+                Some(p_bar) => {
+                    (p_bar.position(), p_bar.length(), p_bar.is_finished())
+                }
+                None => break,
+            };
+            if let Some(percent) = (pos * 100).checked_div(len) {
+                let step = percent - percent % PROGRESS_LOG_STEP;
+                if step > logged {
+                    info!("Progress: {}% ({}/{})", step, pos, len);
+                    logged = step;
+                }
+            }
+            if finished || pos >= len {
+                break;
+            }
+        }
+    });
+}
+
+/// The complement of a nucleotide, leaving anything other than
+/// `ACGTU`, in either case, unchanged.
+pub fn complement(base: u8) -> u8 {
+    match base {
+        b'A' => b'T',
+        b'C' => b'G',
+        b'G' => b'C',
+        b'T' => b'A',
+        b'U' => b'A',
+        b'a' => b't',
+        b'c' => b'g',
+        b'g' => b'c',
+        b't' => b'a',
+        b'u' => b'a',
+        x => x,
+    }
+}
+
+/// The reverse complement of a sequence.
+pub fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter().rev().copied().map(complement).collect()
+}
@@ -1,29 +1,183 @@
+//! Variant calling on the paths embedded in a GFA graph.
+//!
+//! The sites of variation are the graph's ultrabubbles. For each
+//! ultrabubble, the sub-path covered by the bubble is extracted from
+//! every path that traverses it, and the sub-paths are compared
+//! against the sub-paths of the reference paths.
+//!
+//! The usual flow is to build the [`PathData`] for a GFA using
+//! [`gfa_path_data`], index the positions of the ultrabubble ends in
+//! the paths with [`bubble_path_indices`], and call
+//! [`detect_variants_in_sub_paths`] for each ultrabubble. The result
+//! can be turned into VCF records with [`variant_vcf_record`].
+//!
+//! ```
+//! use fnv::FnvHashSet;
+//! use gfa::{gfa::GFA, parser::GFAParser};
+//! use gfautil::variants::{self, VariantConfig};
+//!
+//! let lines = [
+//!     "S\t1\tACGT",
+//!     "S\t2\tA",
+//!     "S\t3\tC",
+//!     "S\t4\tGGTT",
+//!     "P\tref\t1+,2+,4+\t*",
+//!     "P\talt\t1+,3+,4+\t*",
+//! ];
+//! let gfa: GFA<usize, ()> = GFAParser::new()
+//!     .parse_lines(lines.iter().map(|l| l.as_bytes()))
+//!     .unwrap();
+//!
+//! let path_data =
+//!     variants::gfa_path_data(gfa, false, Default::default()).unwrap();
+//!
+//! let ultrabubbles = [(1, 4)];
+//! let nodes: FnvHashSet<u64> =
+//!     ultrabubbles.iter().flat_map(|&(a, b)| vec![a, b]).collect();
+//! let path_indices =
+//!     variants::bubble_path_indices(&path_data.paths, &nodes);
+//!
+//! let ref_paths: FnvHashSet<_> = vec!["ref".into()].into_iter().collect();
+//! let config = VariantConfig::default();
+//!
+//! let mut records = Vec::new();
+//! for &(from, to) in ultrabubbles.iter() {
+//!     if let Some(vars) = variants::detect_variants_in_sub_paths(
+//!         &config,
+//!         &path_data,
+//!         Some(&ref_paths),
+//!         &path_indices,
+//!         from,
+//!         to,
+//!     ) {
+//!         let path_count = path_data.paths.len();
+//!         let lengths = Default::default();
+//!         records.extend(variants::variant_vcf_record(
+//!             &vars, path_count, 1, &lengths,
+//!         ));
+//!     }
+//! }
+//!
+//! assert_eq!(1, records.len());
+//! assert_eq!(
+//!     "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=1;AN=2;AF=0.5;NS=2\t\
+//!      GT\t0\t1",
+//!     records[0].to_string()
+//! );
+//! ```
+
+pub mod checkpoint;
+pub mod consensus;
+pub mod json;
+pub mod normalize;
 pub mod vcf;
 
 use vcf::VCFRecord;
 
 use bstr::{BStr, BString, ByteSlice};
 use fnv::{FnvHashMap, FnvHashSet};
-use indicatif::ParallelProgressIterator;
+use indicatif::{ParallelProgressIterator, ProgressBar};
 use rayon::prelude::*;
+use std::{borrow::Cow, collections::BTreeMap};
 
-use gfa::gfa::{Orientation, GFA};
+use gfa::{
+    gfa::{Line, Orientation, Path, Segment, GFA},
+    parser::{GFAParser, GFAParserBuilder},
+};
 
-use crate::util::progress_bar;
+use crate::{
+    error::GfaUtilsError,
+    util::{progress_bar, reverse_complement},
+};
 
-use gfa::gfa::Orientation::Forward;
 #[allow(unused_imports)]
 use log::{debug, info, trace, warn};
 
+/// A step on a path: the segment ID, the 1-based offset of the
+/// segment's first base on the path, and the segment's orientation.
+///
+/// Offsets are assigned cumulatively along the path, so a path that
+/// visits a segment more than once (i.e. a cyclic path) has a step
+/// with a different offset for each visit. When such a segment is the
+/// end of an ultrabubble, only its last visit is used to find the
+/// bubble on the path; see `bubble_path_indices`.
 pub type PathStep = (usize, usize, Orientation);
 
+/// Returns true if the path visits any segment more than once.
+pub fn is_cyclic_path(steps: &[PathStep]) -> bool {
+    let mut seen = FnvHashSet::default();
+    steps.iter().any(|&(node, _, _)| !seen.insert(node))
+}
+
+/// The sequence of a segment as it's read on a path step, i.e. its
+/// reverse complement if the step is backward.
+fn oriented_sequence(seq: &[u8], orient: Orientation) -> Cow<'_, [u8]> {
+    if orient.is_reverse() {
+        Cow::Owned(reverse_complement(seq))
+    } else {
+        Cow::Borrowed(seq)
+    }
+}
+
+/// The segment sequences and paths of a GFA, with the paths stored
+/// as steps with offsets, in the same order as in the GFA.
 pub struct PathData {
+    /// The sequence of each segment, which includes every segment on
+    /// the paths, as the variant detection looks them up without
+    /// checking.
     pub segment_map: FnvHashMap<usize, BString>,
     pub path_names: Vec<BString>,
     pub paths: Vec<Vec<PathStep>>,
 }
 
 impl PathData {
+    /// The total sequence length of the path at index `path_ix`.
+    pub fn path_length(&self, path_ix: usize) -> usize {
+        self.paths[path_ix]
+            .last()
+            .map(|&(node, offset, _)| {
+                let len = self.segment_map.get(&node).map_or(0, |s| s.len());
+                offset + len - 1
+            })
+            .unwrap_or(0)
+    }
+
+    /// Remove the paths that visit any segment more than once,
+    /// returning the number of paths removed.
+    pub fn remove_cyclic_paths(&mut self) -> usize {
+        self.retain_paths(|_, steps| !is_cyclic_path(steps))
+    }
+
+    /// Remove the paths with any of the given names, returning the
+    /// number of paths removed.
+    pub fn remove_named_paths(&mut self, names: &FnvHashSet<BString>) -> usize {
+        self.retain_paths(|name, _| !names.contains(name))
+    }
+
+    fn retain_paths<F>(&mut self, keep: F) -> usize
+    where
+        F: Fn(&BString, &[PathStep]) -> bool,
+    {
+        let before = self.paths.len();
+        let (path_names, paths) = std::mem::take(&mut self.path_names)
+            .into_iter()
+            .zip(std::mem::take(&mut self.paths))
+            .filter(|(name, steps)| keep(name, steps))
+            .unzip();
+        self.path_names = path_names;
+        self.paths = paths;
+        before - self.paths.len()
+    }
+
+    /// The sequence of the path at index `path_ix`, made by
+    /// concatenating its segments' sequences, reverse complemented on
+    /// backward steps, which is the sequence the offsets of the path's
+    /// steps refer to.
+    pub fn path_sequence(&self, path_ix: usize) -> BString {
+        sub_path_sequence(&self.segment_map, &self.paths[path_ix])
+    }
+
+    #[allow(dead_code)]
     fn hash_subpath(&self, path: usize, from: usize, to: usize) -> Option<u64> {
         use fnv::FnvHasher;
         use std::hash::{Hash, Hasher};
@@ -47,33 +201,326 @@ impl PathData {
     }
 }
 
-pub fn gfa_path_data(mut gfa: GFA<usize, ()>) -> PathData {
+/// An index from the offsets of a path's steps to the step indices,
+/// for finding the steps that cover a range of path coordinates
+/// without scanning the whole path. Steps on empty segments share
+/// their offset with the step after them, so each offset maps to the
+/// first step at it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PathOffsetIndex {
+    offsets: BTreeMap<usize, usize>,
+    step_count: usize,
+    length: usize,
+}
+
+impl PathOffsetIndex {
+    /// Index the steps of the path at index `path_ix`.
+    pub fn new(path_data: &PathData, path_ix: usize) -> Self {
+        let steps = &path_data.paths[path_ix];
+        let mut offsets = BTreeMap::new();
+        for (step_ix, &(_, offset, _)) in steps.iter().enumerate() {
+            offsets.entry(offset).or_insert(step_ix);
+        }
+        let length = path_data.path_length(path_ix);
+        PathOffsetIndex {
+            offsets,
+            step_count: steps.len(),
+            length,
+        }
+    }
+
+    /// The index of the last step at an offset below `offset`.
+    fn last_step_before(&self, offset: usize) -> usize {
+        self.offsets
+            .range(offset..)
+            .next()
+            .map_or(self.step_count - 1, |(_, &step_ix)| step_ix - 1)
+    }
+
+    /// Returns the first and last indices of the steps that overlap
+    /// the 1-based, inclusive, range `start`-`end` of the path, or
+    /// `None` if the range is outside the path. A step on an empty
+    /// segment overlaps the range if its offset is in it.
+    pub fn steps_in_range(
+        &self,
+        start: usize,
+        end: usize,
+    ) -> Option<(usize, usize)> {
+        if start > end || start > self.length || end == 0 {
+            return None;
+        }
+        // If no step starts at `start`, the step that covers it is the
+        // last one at the closest offset before it, after any empty
+        // steps at that offset
+        let first = match self.offsets.range(..=start).next_back()? {
+            (&offset, &step_ix) if offset == start => step_ix,
+            _ => self.last_step_before(start + 1),
+        };
+        let last = self.last_step_before(end + 1);
+        Some((first, last))
+    }
+}
+
+/// How the segments of a GFA are handled when building a `PathData`
+/// or a segment map.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PathDataOptions {
+    /// Allow segment IDs that are defined more than once. By default
+    /// they're an error, as the segments may have different
+    /// sequences, which would give wrong alleles. If allowed, the
+    /// first definition is kept, with a warning.
+    pub allow_duplicate_segments: bool,
+    /// Skip path steps on segments that aren't defined in the GFA.
+    /// By default they're an error, as the alleles through them
+    /// would be missing sequence. If skipped, the segments are given
+    /// empty sequences, with a warning, so they take up no space on
+    /// the paths.
+    pub skip_missing_segments: bool,
+}
+
+/// Extract the segment sequences and the paths, with the offset of
+/// each step, from a GFA. If `show_progress` is true, a progress bar
+/// over the paths is drawn on stderr.
+///
+/// Segments without a sequence (`*`) are given an empty sequence, so
+/// they take up no space on the paths and are left out of variant
+/// alleles. Segment IDs that are defined more than once, and paths
+/// that use segments that aren't defined, are errors, unless they're
+/// allowed in `options`.
+pub fn gfa_path_data(
+    mut gfa: GFA<usize, ()>,
+    show_progress: bool,
+    options: PathDataOptions,
+) -> crate::error::Result<PathData> {
     let segments = std::mem::take(&mut gfa.segments);
 
     info!("Building map from segment IDs to sequences");
-    let segment_map: FnvHashMap<usize, BString> = segments
-        .into_iter()
-        .map(|seg| (seg.name, seg.sequence.into()))
-        .collect();
+    let segment_map = owned_segment_map(segments, options)?;
 
     let gfa_paths = std::mem::take(&mut gfa.paths);
 
-    let p_bar = progress_bar(gfa_paths.len(), false);
+    build_path_data(segment_map, gfa_paths, show_progress, options)
+}
+
+/// Load the `PathData` of a GFA file directly, streaming the file
+/// once and keeping only the segment sequences and the paths, rather
+/// than loading the entire `GFA` first like `gfa_path_data`.
+///
+/// This lowers the peak memory use, as the links, containments, and
+/// optional fields are never stored, and each segment's sequence is
+/// moved into the segment map as soon as it's parsed, instead of the
+/// segment list and the map both being held at once. On
+/// human-scale pangenomes, the links alone can take up gigabytes.
+pub fn load_path_data<P: AsRef<std::path::Path>>(
+    path: P,
+    show_progress: bool,
+    options: PathDataOptions,
+) -> crate::error::Result<PathData> {
+    stream_path_data(path, show_progress, options, None)
+}
+
+/// Load the `PathData` of a GFA file like `load_path_data`, and, in
+/// the same pass over the file, the graph its bubbles are found in:
+/// the segments, without their sequences, which are only kept in the
+/// segment map, and the links. This is for finding the bubbles of a
+/// graph that's only read once, at the cost of keeping the links in
+/// memory along with the paths until the graph is dropped.
+pub fn load_path_data_with_graph<P: AsRef<std::path::Path>>(
+    path: P,
+    show_progress: bool,
+    options: PathDataOptions,
+) -> crate::error::Result<(PathData, GFA<usize, ()>)> {
+    let mut graph = GFA::new();
+    let path_data =
+        stream_path_data(path, show_progress, options, Some(&mut graph))?;
+    Ok((path_data, graph))
+}
+
+/// Stream the segments and paths of a GFA file into a `PathData`,
+/// also adding the segments, without sequences, and the links to
+/// `graph` if it's given.
+fn stream_path_data<P: AsRef<std::path::Path>>(
+    path: P,
+    show_progress: bool,
+    options: PathDataOptions,
+    mut graph: Option<&mut GFA<usize, ()>>,
+) -> crate::error::Result<PathData> {
+    let mut config = GFAParserBuilder::all();
+    config.links = graph.is_some();
+    config.containments = false;
+    let parser: GFAParser<usize, ()> = config.build();
+
+    info!(
+        "Streaming segments and paths from {}",
+        path.as_ref().display()
+    );
+
+    let mut segment_map: FnvHashMap<usize, BString> = FnvHashMap::default();
+    let mut gfa_paths: Vec<Path<usize, ()>> = Vec::new();
+    let mut duplicate = Ok(());
+
+    crate::commands::for_each_gfa_line(&parser, path, |line| match line {
+        Line::Segment(seg) => {
+            if let Some(graph) = graph.as_mut() {
+                graph.segments.push(Segment {
+                    name: seg.name,
+                    sequence: Default::default(),
+                    optional: (),
+                });
+            }
+            let (name, seq) = segment_entry(seg.name, seg.sequence);
+            let inserted = insert_segment(&mut segment_map, name, seq, options);
+            if duplicate.is_ok() {
+                duplicate = inserted;
+            }
+        }
+        Line::Link(link) => {
+            if let Some(graph) = graph.as_mut() {
+                graph.links.push(link);
+            }
+        }
+        Line::Path(path) => gfa_paths.push(path),
+        _ => (),
+    })?;
+    duplicate?;
+
+    build_path_data(segment_map, gfa_paths, show_progress, options)
+}
+
+/// Map segment IDs to their sequences, taking ownership of them, so
+/// the map can outlive the GFA the segments came from, e.g. to be
+/// returned from a helper while the rest of the GFA is dropped. The
+/// sequences are moved rather than copied, so this costs no more
+/// memory than the segments did, but the segments must be given up;
+/// use `borrowed_segment_map` to keep the GFA intact. Sequences that
+/// are `*` are replaced by empty sequences, and duplicate segment IDs
+/// are handled as described in `PathDataOptions`.
+pub fn owned_segment_map(
+    segments: Vec<Segment<usize, ()>>,
+    options: PathDataOptions,
+) -> crate::error::Result<FnvHashMap<usize, BString>> {
+    // Sized for all the segments up front, as growing the map one
+    // rehash at a time is a noticeable part of loading large graphs
+    let mut segment_map = FnvHashMap::with_capacity_and_hasher(
+        segments.len(),
+        Default::default(),
+    );
+    for seg in segments {
+        let (name, seq) = segment_entry(seg.name, seg.sequence);
+        insert_segment(&mut segment_map, name, seq, options)?;
+    }
+    Ok(segment_map)
+}
+
+/// Map segment IDs to their sequences, borrowing them from `gfa`.
+/// This is the cheapest way to look up sequences while the whole GFA
+/// is loaded anyway, but the map can't outlive it; see
+/// `owned_segment_map`. Sequences that are `*` are mapped to empty
+/// sequences, and duplicate segment IDs are handled like in
+/// `owned_segment_map`.
+pub fn borrowed_segment_map(
+    gfa: &GFA<usize, ()>,
+    options: PathDataOptions,
+) -> crate::error::Result<FnvHashMap<usize, &[u8]>> {
+    // Sized up front, like in `owned_segment_map`
+    let mut segment_map = FnvHashMap::with_capacity_and_hasher(
+        gfa.segments.len(),
+        Default::default(),
+    );
+    for seg in gfa.segments.iter() {
+        let seq: &[u8] = if seg.sequence == b"*" {
+            &[]
+        } else {
+            &seg.sequence
+        };
+        insert_segment(&mut segment_map, seg.name, seq, options)?;
+    }
+    Ok(segment_map)
+}
+
+/// Add a segment to a segment map, keeping the first sequence of a
+/// duplicate segment ID if those are allowed.
+fn insert_segment<S>(
+    segment_map: &mut FnvHashMap<usize, S>,
+    name: usize,
+    seq: S,
+    options: PathDataOptions,
+) -> crate::error::Result<()> {
+    use std::collections::hash_map::Entry;
+    match segment_map.entry(name) {
+        Entry::Vacant(entry) => {
+            entry.insert(seq);
+            Ok(())
+        }
+        Entry::Occupied(_) => {
+            if options.allow_duplicate_segments {
+                warn!(
+                    "Segment {} is defined more than once, keeping the first",
+                    name
+                );
+                Ok(())
+            } else {
+                Err(GfaUtilsError::DuplicateSegment(name))
+            }
+        }
+    }
+}
+
+/// The segment map entry for a segment, with `*` replaced by an
+/// empty sequence.
+fn segment_entry(name: usize, sequence: Vec<u8>) -> (usize, BString) {
+    if sequence == b"*" {
+        (name, BString::from(""))
+    } else {
+        (name, sequence.into())
+    }
+}
+
+/// The steps of `path` with their 1-based offsets on it, which are
+/// one more than the sum of the lengths of the segments before each
+/// step. Segments that aren't in `segment_map` take up no space.
+fn path_steps_with_offsets(
+    segment_map: &FnvHashMap<usize, BString>,
+    path: &Path<usize, ()>,
+) -> Vec<PathStep> {
+    path.iter()
+        .scan(1, |offset, (step, orient)| {
+            let step_offset = *offset;
+            *offset += segment_map.get(&step).map_or(0, |s| s.len());
+            Some((step, step_offset, orient))
+        })
+        .collect()
+}
+
+fn build_path_data(
+    mut segment_map: FnvHashMap<usize, BString>,
+    gfa_paths: Vec<Path<usize, ()>>,
+    show_progress: bool,
+    options: PathDataOptions,
+) -> crate::error::Result<PathData> {
+    let empty_segments = segment_map.values().filter(|s| s.is_empty()).count();
+    if empty_segments > 0 {
+        warn!(
+            "{} segments have no sequence, and will be treated as empty",
+            empty_segments
+        );
+    }
+
+    let p_bar = if show_progress {
+        progress_bar(gfa_paths.len(), false)
+    } else {
+        ProgressBar::hidden()
+    };
 
+    // Each path's offsets only depend on the segment lengths, so the
+    // paths are done in parallel; unzipping the indexed iterator keeps
+    // them in the order of the GFA
     info!("Extracting paths and offsets from GFA");
     let (path_names, paths): (Vec<_>, Vec<_>) = gfa_paths
         .into_par_iter()
         .progress_with(p_bar)
         .map(|mut path| {
-            let steps: Vec<(usize, usize, Orientation)> = path
-                .iter()
-                .scan(1, |offset, (step, orient)| {
-                    let step_offset = *offset;
-                    let step_len = segment_map.get(&step).unwrap().len();
-                    *offset += step_len;
-                    Some((step, step_offset, orient))
-                })
-                .collect();
+            let steps = path_steps_with_offsets(&segment_map, &path);
 
             let path_name = std::mem::take(&mut path.path_name);
 
@@ -81,17 +528,64 @@ pub fn gfa_path_data(mut gfa: GFA<usize, ()>) -> PathData {
         })
         .unzip();
 
-    PathData {
+    let mut missing_segments: FnvHashSet<usize> = FnvHashSet::default();
+    for (name, steps) in path_names.iter().zip(paths.iter()) {
+        let missing = steps
+            .iter()
+            .filter(|(step, _, _)| !segment_map.contains_key(step));
+        for &(segment, _, _) in missing {
+            if !options.skip_missing_segments {
+                return Err(GfaUtilsError::MissingSegment {
+                    path: name.clone(),
+                    segment,
+                });
+            }
+            if missing_segments.insert(segment) {
+                warn!(
+                    "Path {} refers to segment {}, which is not in the GFA, \
+                     skipping it",
+                    name, segment
+                );
+            }
+        }
+    }
+
+    if !missing_segments.is_empty() {
+        warn!(
+            "Skipped {} segments that are used by paths but not in the GFA, \
+             treating them as empty",
+            missing_segments.len()
+        );
+        segment_map.extend(
+            missing_segments.into_iter().map(|s| (s, BString::from(""))),
+        );
+    }
+
+    for (name, steps) in path_names.iter().zip(paths.iter()) {
+        if is_cyclic_path(steps) {
+            warn!(
+                "Path {} visits some segments more than once, so variants \
+                 on it may get wrong coordinates",
+                name
+            );
+        }
+    }
+
+    Ok(PathData {
         segment_map,
         path_names,
         paths,
-    }
+    })
 }
 
+/// Find the step indices of each of the `vertices` on each path,
+/// returning a map from segment ID to a map from path index to step
+/// index. If a path visits a vertex more than once, the index of the
+/// last visit is used.
 pub fn bubble_path_indices(
-    paths: &[Vec<(usize, usize, Orientation)>],
+    paths: &[Vec<PathStep>],
     vertices: &FnvHashSet<u64>,
-) -> FnvHashMap<u64, FnvHashMap<usize, usize>> {
+) -> PathIndices {
     let mut transposed: FnvHashMap<usize, FnvHashMap<u64, usize>> =
         FnvHashMap::default();
 
@@ -141,6 +635,8 @@ pub fn bubble_path_indices(
     path_map
 }
 
+/// The site of a variant: the reference path it's on, the reference
+/// sequence, and its 1-based position on the reference path.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct VariantKey {
     pub ref_name: BString,
@@ -148,6 +644,7 @@ pub struct VariantKey {
     pub pos: usize,
 }
 
+/// An alternate allele at a `VariantKey`.
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Variant {
     Del(BString),
@@ -157,6 +654,30 @@ pub enum Variant {
     Clumped(BString),
 }
 
+impl Variant {
+    /// The sequence of the allele, as written in the ALT column.
+    pub fn alt_sequence(&self) -> BString {
+        match self {
+            Variant::Snv(base) => std::iter::once(*base).collect(),
+            Variant::Del(seq)
+            | Variant::Ins(seq)
+            | Variant::Mnp(seq)
+            | Variant::Clumped(seq) => seq.clone(),
+        }
+    }
+
+    /// The type of the allele, as written in the `TYPE` INFO field.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Variant::Del(_) => "del",
+            Variant::Ins(_) => "ins",
+            Variant::Snv(_) => "snv",
+            Variant::Mnp(_) => "mnp",
+            Variant::Clumped(_) => "clumped",
+        }
+    }
+}
+
 impl std::fmt::Display for Variant {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -227,40 +748,56 @@ fn detect_variants_against_ref_ranges<H: VariantHandler>(
             break;
         }
 
-        let (ref_node, ref_offset, _) = ref_path[ref_ix];
+        let (ref_node, ref_offset, ref_orient) = ref_path[ref_ix];
         let ref_seq = segment_sequences.get(&ref_node).unwrap();
+        let ref_seq = oriented_sequence(ref_seq, ref_orient);
 
         ref_seq_ix = ref_offset;
 
-        let (query_node, query_offset, _) = query_path[query_ix];
+        let (query_node, query_offset, query_orient) = query_path[query_ix];
         let query_seq = segment_sequences.get(&query_node).unwrap();
+        let query_seq = oriented_sequence(query_seq, query_orient);
 
         query_seq_ix = query_offset;
 
-        if ref_node == query_node {
+        if ref_node == query_node && ref_orient == query_orient {
             ref_ix += 1;
             query_ix += 1;
-        } else {
-            if ref_ix + 1 > ref_end || query_ix + 1 > query_end {
-                trace!("At end of ref or query");
-                break;
+        } else if ref_node == query_node {
+            // The same segment traversed in opposite directions
+            if ref_seq != query_seq {
+                handler.mismatch(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
+            } else {
+                handler.match_(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
             }
-            let (next_ref_node, _next_ref_offset, _) = ref_path[ref_ix + 1];
-            let (next_query_node, _next_query_offset, _) =
-                query_path[query_ix + 1];
 
-            if next_ref_node == query_node {
+            ref_ix += 1;
+            query_ix += 1;
+        } else {
+            // The segments after the current ones, if they're still
+            // in range; an indel can be right before the end
+            let next_ref_node = Some(ref_ix + 1)
+                .filter(|_| ref_ix < ref_end)
+                .map(|ix| ref_path[ix].0);
+            let next_query_node = Some(query_ix + 1)
+                .filter(|_| query_ix < query_end)
+                .map(|ix| query_path[ix].0);
+
+            if next_ref_node == Some(query_node) {
                 trace!("Deletion at ref {}\t query {}", ref_ix, query_ix);
                 // Deletion
                 handler.deletion(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
 
                 ref_ix += 1;
-            } else if next_query_node == ref_node {
+            } else if next_query_node == Some(ref_node) {
                 trace!("Insertion at ref {}\t query {}", ref_ix, query_ix);
                 // Insertion
                 handler.insertion(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
 
                 query_ix += 1;
+            } else if next_ref_node.is_none() || next_query_node.is_none() {
+                trace!("At end of ref or query");
+                break;
             } else {
                 if ref_seq != query_seq {
                     handler.mismatch(
@@ -297,41 +834,56 @@ fn detect_variants_against_ref_with<H: VariantHandler>(
             break;
         }
 
-        let (ref_node, ref_offset, _) = ref_path[ref_ix];
+        let (ref_node, ref_offset, ref_orient) = ref_path[ref_ix];
         let ref_seq = segment_sequences.get(&ref_node).unwrap();
+        let ref_seq = oriented_sequence(ref_seq, ref_orient);
 
         ref_seq_ix = ref_offset;
 
-        let (query_node, query_offset, _) = query_path[query_ix];
+        let (query_node, query_offset, query_orient) = query_path[query_ix];
         let query_seq = segment_sequences.get(&query_node).unwrap();
+        let query_seq = oriented_sequence(query_seq, query_orient);
 
         query_seq_ix = query_offset;
 
-        if ref_node == query_node {
+        if ref_node == query_node && ref_orient == query_orient {
             ref_ix += 1;
             query_ix += 1;
-        } else {
-            if ref_ix + 1 >= ref_path.len() || query_ix + 1 >= query_path.len()
-            {
-                trace!("At end of ref or query");
-                break;
+        } else if ref_node == query_node {
+            // The same segment traversed in opposite directions
+            if ref_seq != query_seq {
+                handler.mismatch(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
+            } else {
+                handler.match_(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
             }
-            let (next_ref_node, _next_ref_offset, _) = ref_path[ref_ix + 1];
-            let (next_query_node, _next_query_offset, _) =
-                query_path[query_ix + 1];
 
-            if next_ref_node == query_node {
+            ref_ix += 1;
+            query_ix += 1;
+        } else {
+            // The segments after the current ones, if they're still
+            // in range; an indel can be right before the end
+            let next_ref_node = Some(ref_ix + 1)
+                .filter(|_| ref_ix + 1 < ref_path.len())
+                .map(|ix| ref_path[ix].0);
+            let next_query_node = Some(query_ix + 1)
+                .filter(|_| query_ix + 1 < query_path.len())
+                .map(|ix| query_path[ix].0);
+
+            if next_ref_node == Some(query_node) {
                 trace!("Deletion at ref {}\t query {}", ref_ix, query_ix);
                 // Deletion
                 handler.deletion(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
 
                 ref_ix += 1;
-            } else if next_query_node == ref_node {
+            } else if next_query_node == Some(ref_node) {
                 trace!("Insertion at ref {}\t query {}", ref_ix, query_ix);
                 // Insertion
                 handler.insertion(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
 
                 query_ix += 1;
+            } else if next_ref_node.is_none() || next_query_node.is_none() {
+                trace!("At end of ref or query");
+                break;
             } else {
                 if ref_seq != query_seq {
                     handler.mismatch(
@@ -379,6 +931,33 @@ impl<'a> VCFVariantHandler<'a> {
     }
 }
 
+/// The last base of the closest segment with a sequence before step
+/// `ix` on `path`, as read in the step's orientation, which is used
+/// as the padding base of indels. At the start of the path, the first
+/// segment is used instead.
+fn anchor_base(
+    segment_sequences: &FnvHashMap<usize, BString>,
+    path: &[PathStep],
+    ix: usize,
+) -> Option<u8> {
+    let steps = if ix == 0 { &path[..1] } else { &path[..ix] };
+    steps.iter().rev().find_map(|&(node, _, orient)| {
+        let seq = segment_sequences.get(&node)?;
+        oriented_sequence(seq, orient).last().copied()
+    })
+}
+
+/// The sequence of the segment at step `ix` on `path`, as read in
+/// the step's orientation.
+fn step_sequence<'a>(
+    segment_sequences: &'a FnvHashMap<usize, BString>,
+    path: &[PathStep],
+    ix: usize,
+) -> Cow<'a, [u8]> {
+    let (node, _, orient) = path[ix];
+    oriented_sequence(segment_sequences.get(&node).unwrap(), orient)
+}
+
 impl<'a> VariantHandler for VCFVariantHandler<'a> {
     fn deletion(
         &mut self,
@@ -387,19 +966,19 @@ impl<'a> VariantHandler for VCFVariantHandler<'a> {
         ref_seq_ix: usize,
         _query_seq_ix: usize,
     ) {
-        let (ref_node, _ref_offset, _) = self.ref_path[ref_ix];
-        let ref_seq = self.segment_sequences.get(&ref_node).unwrap();
+        let ref_seq =
+            step_sequence(self.segment_sequences, self.ref_path, ref_ix);
 
-        // Deletion
-        let (prev_ref_node, _prev_ref_offset, _) = if ref_ix == 0 {
-            self.ref_path[ref_ix]
-        } else {
-            self.ref_path[ref_ix - 1]
-        };
-
-        let prev_ref_seq = self.segment_sequences.get(&prev_ref_node).unwrap();
+        if ref_seq.is_empty() {
+            return;
+        }
 
-        let last_prev_seq: u8 = *prev_ref_seq.last().unwrap();
+        // Deletion
+        let last_prev_seq: u8 =
+            match anchor_base(self.segment_sequences, self.ref_path, ref_ix) {
+                Some(base) => base,
+                None => return,
+            };
 
         let key_ref_seq: BString = std::iter::once(last_prev_seq)
             .chain(ref_seq.iter().copied())
@@ -424,17 +1003,18 @@ impl<'a> VariantHandler for VCFVariantHandler<'a> {
         ref_seq_ix: usize,
         _query_seq_ix: usize,
     ) {
-        let (query_node, _query_offset, _) = self.query_path[query_ix];
-        let query_seq = self.segment_sequences.get(&query_node).unwrap();
+        let query_seq =
+            step_sequence(self.segment_sequences, self.query_path, query_ix);
 
-        let (prev_ref_node, _prev_ref_offset, _) = if ref_ix == 0 {
-            self.ref_path[ref_ix]
-        } else {
-            self.ref_path[ref_ix - 1]
-        };
-        let prev_ref_seq = self.segment_sequences.get(&prev_ref_node).unwrap();
+        if query_seq.is_empty() {
+            return;
+        }
 
-        let last_prev_seq: u8 = *prev_ref_seq.last().unwrap();
+        let last_prev_seq: u8 =
+            match anchor_base(self.segment_sequences, self.ref_path, ref_ix) {
+                Some(base) => base,
+                None => return,
+            };
 
         let key_ref_seq: BString = std::iter::once(last_prev_seq).collect();
 
@@ -458,13 +1038,55 @@ impl<'a> VariantHandler for VCFVariantHandler<'a> {
         ref_ix: usize,
         query_ix: usize,
         ref_seq_ix: usize,
-        _query_seq_ix: usize,
+        query_seq_ix: usize,
     ) {
-        let (ref_node, _ref_offset, _) = self.ref_path[ref_ix];
-        let ref_seq = self.segment_sequences.get(&ref_node).unwrap();
+        let ref_seq =
+            step_sequence(self.segment_sequences, self.ref_path, ref_ix);
+        let query_seq =
+            step_sequence(self.segment_sequences, self.query_path, query_ix);
+
+        // A mismatch against an empty segment is really an insertion
+        // or deletion of the other segment's sequence
+        if ref_seq.is_empty() {
+            self.insertion(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
+            return;
+        } else if query_seq.is_empty() {
+            self.deletion(ref_ix, query_ix, ref_seq_ix, query_seq_ix);
+            return;
+        }
 
-        let (query_node, _query_offset, _) = self.query_path[query_ix];
-        let query_seq = self.segment_sequences.get(&query_node).unwrap();
+        // Segments of different lengths give an indel rather than a
+        // substitution, anchored on the base before them like the
+        // other indels, so that the REF and ALT share their first base
+        if ref_seq.len() != query_seq.len() {
+            let anchor = match anchor_base(
+                self.segment_sequences,
+                self.ref_path,
+                ref_ix,
+            ) {
+                Some(base) => base,
+                None => return,
+            };
+            let var_key = VariantKey {
+                ref_name: self.ref_name.into(),
+                pos: ref_seq_ix - 1,
+                sequence: std::iter::once(anchor)
+                    .chain(ref_seq.iter().copied())
+                    .collect(),
+            };
+            let alt: BString = std::iter::once(anchor)
+                .chain(query_seq.iter().copied())
+                .collect();
+            let variant = if query_seq.len() > ref_seq.len() {
+                trace!("Insertion at ref {}\t query {}", ref_ix, query_ix);
+                Variant::Ins(alt)
+            } else {
+                trace!("Deletion at ref {}\t query {}", ref_ix, query_ix);
+                Variant::Del(alt)
+            };
+            self.variants.entry(var_key).or_default().insert(variant);
+            return;
+        }
 
         let var_key = VariantKey {
             ref_name: self.ref_name.into(),
@@ -537,11 +1159,10 @@ impl<'a> VariantHandler for SNPVariantHandler<'a> {
         ref_seq_ix: usize,
         query_seq_ix: usize,
     ) {
-        let (ref_node, _ref_offset, _) = self.ref_path[ref_ix];
-        let ref_seq = self.segment_sequences.get(&ref_node).unwrap();
-
-        let (query_node, _query_offset, _) = self.query_path[query_ix];
-        let query_seq = self.segment_sequences.get(&query_node).unwrap();
+        let ref_seq =
+            step_sequence(self.segment_sequences, self.ref_path, ref_ix);
+        let query_seq =
+            step_sequence(self.segment_sequences, self.query_path, query_ix);
 
         if ref_seq.len() == 1 && query_seq.len() == 1 {
             let ref_base = ref_seq[0];
@@ -575,20 +1196,42 @@ impl<'a> VariantHandler for SNPVariantHandler<'a> {
     fn match_(&mut self, _: usize, _: usize, _: usize, _: usize) {}
 }
 
-fn sub_path_edge_orient(
-    path: &[(usize, usize, Orientation)],
+/// The orientations of the `from` and `to` end segments of a bubble
+/// on a path, given their step indices on the path, as found by
+/// `PathIndex::step_ranges`. They're given per end segment, rather
+/// than in the order the path visits them.
+fn bubble_end_orients(
+    path: &[PathStep],
+    (from_ix, to_ix): (usize, usize),
 ) -> (Orientation, Orientation) {
-    let from = path.first().unwrap().2;
-    let to = path.last().unwrap().2;
-    (from, to)
+    (path[from_ix].2, path[to_ix].2)
 }
 
+/// Settings for variant detection.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct VariantConfig {
+    /// Don't compare a path against the reference if it traverses
+    /// either end segment of the bubble in another orientation than
+    /// the reference does, i.e. if the orientations of `from` on the
+    /// two paths differ, or those of `to`. A path that goes through
+    /// the bubble in the other direction than the reference has both
+    /// ends flipped, and is skipped, while one that only has an
+    /// inversion between the ends is still compared.
     pub ignore_inverted_paths: bool,
+    /// Group the paths through a bubble by the sequence they spell,
+    /// rather than by the segments they visit, so that alleles with
+    /// identical sequences are reported as one.
+    pub merge_identical_alleles: bool,
+    /// Only compare the paths whose sequence through a bubble has the
+    /// same length as the reference's, and only report SNVs and MNPs,
+    /// which skips the indel handling for SNP-only output.
+    pub only_snps: bool,
 }
 
 impl VariantConfig {
+    /// Whether to skip comparing a path against the reference, given
+    /// the orientations of the `from` and `to` ends of the bubble on
+    /// each, as described for `ignore_inverted_paths`.
     pub fn ignore_path(
         &self,
         ref_orient: (Orientation, Orientation),
@@ -607,60 +1250,356 @@ impl Default for VariantConfig {
     fn default() -> Self {
         Self {
             ignore_inverted_paths: true,
+            merge_identical_alleles: false,
+            only_snps: false,
         }
     }
 }
 
+/// The step indices of segments on paths, as built by
+/// `bubble_path_indices`.
 pub type PathIndices = FnvHashMap<u64, FnvHashMap<usize, usize>>;
 
-fn path_data_sub_path_ranges(
+/// Lookups of the sub paths between two segments, such as the ends of
+/// an ultrabubble, on the paths of a `PathData`, using the step
+/// indices in a `PathIndices`. The segments must be in the
+/// `PathIndices`; as there, the last visit to a segment is used on
+/// cyclic paths.
+///
+/// The ranges of steps between `from` and `to` are given in that
+/// order, so the first index is greater than the second if the path
+/// goes from `to` to `from`.
+#[derive(Clone, Copy)]
+pub struct PathIndex<'a> {
+    path_data: &'a PathData,
+    path_indices: &'a PathIndices,
+}
+
+impl<'a> PathIndex<'a> {
+    pub fn new(path_data: &'a PathData, path_indices: &'a PathIndices) -> Self {
+        Self {
+            path_data,
+            path_indices,
+        }
+    }
+
+    /// The indices of the steps on `from` and `to` on the path at
+    /// index `path_ix`, or `None` if the path doesn't visit both.
+    pub fn step_range(
+        &self,
+        path_ix: usize,
+        from: u64,
+        to: u64,
+    ) -> Option<(usize, usize)> {
+        let from_ix = *self.path_indices.get(&from)?.get(&path_ix)?;
+        let to_ix = *self.path_indices.get(&to)?.get(&path_ix)?;
+        Some((from_ix, to_ix))
+    }
+
+    /// The step ranges between `from` and `to` on each path that
+    /// visits both, with more than one step between them, in the
+    /// order of the paths.
+    pub fn step_ranges(
+        &self,
+        from: u64,
+        to: u64,
+    ) -> Option<Vec<(usize, (usize, usize))>> {
+        let from_indices = self.path_indices.get(&from)?;
+        let to_indices = self.path_indices.get(&to)?;
+
+        let ranges = (0..self.path_data.paths.len())
+            .filter_map(|path_ix| {
+                let from_ix = *from_indices.get(&path_ix)?;
+                let to_ix = *to_indices.get(&path_ix)?;
+                Some((path_ix, (from_ix, to_ix))).filter(|_| from_ix != to_ix)
+            })
+            .collect();
+        Some(ranges)
+    }
+
+    /// The steps of the path at index `path_ix` from `from` to `to`,
+    /// including both, in the order of the path.
+    pub fn sub_path(
+        &self,
+        path_ix: usize,
+        from: u64,
+        to: u64,
+    ) -> Option<&'a [PathStep]> {
+        let (from_ix, to_ix) = self.step_range(path_ix, from, to)?;
+        let path = self.path_data.paths.get(path_ix)?;
+        Some(&path[from_ix.min(to_ix)..=from_ix.max(to_ix)])
+    }
+
+    /// The 1-based, inclusive, range of coordinates covered by the
+    /// steps from `from` to `to` on the path at index `path_ix`,
+    /// including both end segments.
+    pub fn span_between(
+        &self,
+        path_ix: usize,
+        from: u64,
+        to: u64,
+    ) -> Option<(usize, usize)> {
+        let steps = self.sub_path(path_ix, from, to)?;
+        let (_, start, _) = *steps.first()?;
+        let (end_node, end_offset, _) = *steps.last()?;
+        let end_len = self.path_data.segment_map.get(&end_node)?.len();
+
+        let end = (end_offset + end_len).saturating_sub(1).max(start);
+        Some((start, end))
+    }
+
+    /// The sequence spelled by the path at index `path_ix` from
+    /// `from` to `to`, including both end segments, and read in that
+    /// direction, i.e. reverse complemented if the path goes from
+    /// `to` to `from`.
+    pub fn allele_sequence(
+        &self,
+        path_ix: usize,
+        from: u64,
+        to: u64,
+    ) -> Option<BString> {
+        let (from_ix, to_ix) = self.step_range(path_ix, from, to)?;
+        let steps = self.sub_path(path_ix, from, to)?;
+        let seq = sub_path_sequence(&self.path_data.segment_map, steps);
+        if from_ix <= to_ix {
+            Some(seq)
+        } else {
+            Some(reverse_complement(&seq).into())
+        }
+    }
+}
+
+/// The variants found in an ultrabubble against a single reference
+/// path, along with the paths that carry each variant.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BubbleVariants {
+    /// The segment IDs of the ultrabubble's ends.
+    pub ultrabubble: (u64, u64),
+    /// Index of the reference path.
+    pub ref_path: usize,
+    /// Indices of the paths that were compared against the
+    /// reference, including the reference itself. These have a
+    /// genotype at every variant in the bubble.
+    pub paths: FnvHashSet<usize>,
+    /// For each variant site, the paths that carry each alternate
+    /// allele.
+    pub variants:
+        FnvHashMap<VariantKey, FnvHashMap<Variant, FnvHashSet<usize>>>,
+}
+
+impl BubbleVariants {
+    /// Leave the path at index `path_ix` out of the genotypes, e.g.
+    /// when it's a synthetic reference rather than a sample. Variants
+    /// that no remaining path carries are removed.
+    pub fn remove_path(&mut self, path_ix: usize) {
+        self.paths.remove(&path_ix);
+        self.variants.retain(|_, var_map| {
+            var_map.retain(|_, paths| {
+                paths.remove(&path_ix);
+                !paths.is_empty()
+            });
+            !var_map.is_empty()
+        });
+    }
+}
+
+/// A step of an allele through a bubble, without its offset.
+pub type AlleleStep = (usize, Orientation);
+
+pub(crate) fn flip(orient: Orientation) -> Orientation {
+    match orient {
+        Orientation::Forward => Orientation::Backward,
+        Orientation::Backward => Orientation::Forward,
+    }
+}
+
+/// The steps of the path at index `path_ix` through the bubble
+/// `from`-`to`, read from `from` to `to`, i.e. reversed, with the
+/// orientations flipped, if the path goes the other way.
+pub fn bubble_allele(
+    path_data: &PathData,
+    path_indices: &PathIndices,
+    path_ix: usize,
+    (from, to): (u64, u64),
+) -> Option<Vec<AlleleStep>> {
+    let index = PathIndex::new(path_data, path_indices);
+    let (from_ix, to_ix) = index.step_range(path_ix, from, to)?;
+    let steps = index.sub_path(path_ix, from, to)?;
+
+    let allele = if from_ix <= to_ix {
+        steps
+            .iter()
+            .map(|&(node, _, orient)| (node, orient))
+            .collect()
+    } else {
+        steps
+            .iter()
+            .rev()
+            .map(|&(node, _, orient)| (node, flip(orient)))
+            .collect()
+    };
+    Some(allele)
+}
+
+/// A distinct traversal of a bubble, in segment space, and the paths
+/// that take it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BubbleAllele {
+    /// The steps through the bubble, read from `from` to `to`.
+    pub steps: Vec<AlleleStep>,
+    /// Indices of the paths with this allele, in increasing order.
+    pub paths: Vec<usize>,
+    /// Whether any of the paths is a reference path.
+    pub is_ref: bool,
+}
+
+/// The distinct alleles through the bubble `from`-`to`, as the
+/// segments and orientations that the paths traverse, read in the
+/// bubble's direction like in `bubble_allele`. Paths that traverse
+/// the bubble in reverse thus share an allele with those that spell
+/// the same steps forward. Reference alleles, i.e. those taken by a
+/// path in `ref_path_names`, or by any path if it's `None`, come
+/// first, and otherwise the alleles are in the order of the first
+/// path that takes them.
+pub fn bubble_alleles(
     path_data: &PathData,
     path_indices: &PathIndices,
+    ref_path_names: Option<&FnvHashSet<BString>>,
     from: u64,
     to: u64,
-) -> Option<Vec<(usize, (usize, usize))>> {
-    let from_indices = path_indices.get(&from)?;
-    let to_indices = path_indices.get(&to)?;
+) -> Vec<BubbleAllele> {
+    let mut alleles: Vec<BubbleAllele> = Vec::new();
+    let mut allele_ixs: FnvHashMap<Vec<AlleleStep>, usize> =
+        FnvHashMap::default();
 
-    let sub_path_ranges = path_data
-        .paths
-        .iter()
-        .enumerate()
-        .filter_map(|(path_ix, path)| {
-            let from_ix = *from_indices.get(&path_ix)?;
-            let to_ix = *to_indices.get(&path_ix)?;
+    for (path_ix, name) in path_data.path_names.iter().enumerate() {
+        let steps =
+            match bubble_allele(path_data, path_indices, path_ix, (from, to)) {
+                Some(steps) if steps.len() > 1 => steps,
+                _ => continue,
+            };
+        let is_ref = ref_path_names.is_none_or(|refs| refs.contains(name));
+
+        let allele_ix = *allele_ixs.entry(steps).or_insert_with_key(|steps| {
+            alleles.push(BubbleAllele {
+                steps: steps.clone(),
+                paths: Vec::new(),
+                is_ref: false,
+            });
+            alleles.len() - 1
+        });
+        let allele = &mut alleles[allele_ix];
+        allele.paths.push(path_ix);
+        allele.is_ref |= is_ref;
+    }
+
+    alleles.sort_by_key(|allele| !allele.is_ref);
+    alleles
+}
+
+/// Returns the 1-based, inclusive, range of coordinates covered by
+/// the ultrabubble `from`-`to` on the path with index `path_ix`, or
+/// `None` if the path doesn't traverse the bubble.
+pub fn bubble_path_range(
+    path_data: &PathData,
+    path_indices: &PathIndices,
+    path_ix: usize,
+    from: u64,
+    to: u64,
+) -> Option<(usize, usize)> {
+    PathIndex::new(path_data, path_indices).span_between(path_ix, from, to)
+}
+
+/// Identifies the allele a sub path through a bubble represents.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum AlleleKey<'a> {
+    Steps(&'a [PathStep]),
+    Sequence(BString),
+}
 
-            let from = from_ix.min(to_ix);
-            let to = from_ix.max(to_ix);
+/// The sequence spelled by the segments of a sub path, with the
+/// segments on backward steps reverse complemented.
+fn sub_path_sequence(
+    segment_map: &FnvHashMap<usize, BString>,
+    steps: &[PathStep],
+) -> BString {
+    let mut seq = BString::from(Vec::new());
+    for &(node, _, orient) in steps {
+        if let Some(segment) = segment_map.get(&node) {
+            seq.extend_from_slice(&oriented_sequence(segment, orient));
+        }
+    }
+    seq
+}
 
-            // let from = from_ix;
-            // let to = to_ix;
+/// The length of the longest run of `N`s, in either case, in the
+/// alleles of the ultrabubble `from`-`to`, i.e. in the sequences of
+/// the paths through it between its end segments. A run that spans
+/// several segments is counted whole. `path_indices` must include
+/// both `from` and `to`.
+pub fn longest_n_run(
+    path_data: &PathData,
+    path_indices: &PathIndices,
+    from: u64,
+    to: u64,
+) -> usize {
+    let index = PathIndex::new(path_data, path_indices);
+    let sub_path_ranges = match index.step_ranges(from, to) {
+        Some(ranges) => ranges,
+        None => return 0,
+    };
 
-            let sub_path = &path[from..=to];
-            if sub_path.len() > 1 {
-                Some((path_ix, (from_ix, to_ix)))
+    let mut longest = 0;
+    for &(path_ix, _) in sub_path_ranges.iter() {
+        let steps = index.sub_path(path_ix, from, to).unwrap();
+        let inner = &steps[1..steps.len() - 1];
+        let mut run = 0;
+        for &(node, _, orient) in inner {
+            let seq = match path_data.segment_map.get(&node) {
+                Some(seq) => seq,
+                None => continue,
+            };
+            // The reverse complement of N is N, so only the order of
+            // the bases depends on the orientation
+            let mut count = |base: &u8| {
+                if base.eq_ignore_ascii_case(&b'N') {
+                    run += 1;
+                    longest = longest.max(run);
+                } else {
+                    run = 0;
+                }
+            };
+            if orient.is_reverse() {
+                seq.iter().rev().for_each(&mut count);
             } else {
-                None
+                seq.iter().for_each(&mut count);
             }
-        })
-        .collect();
-
-    Some(sub_path_ranges)
+        }
+    }
+    longest
 }
 
+/// Detect the variants in the ultrabubble `from`-`to`, comparing the
+/// sub-paths of all paths that traverse the bubble against the
+/// sub-paths of the reference paths. If `ref_path_names` is `None`,
+/// every path is used as a reference. `path_indices` must include
+/// both `from` and `to`.
+///
+/// Returns the variants found against each reference path, or `None`
+/// if no path traverses the bubble.
 pub fn detect_variants_in_sub_paths(
     variant_config: &VariantConfig,
     path_data: &PathData,
     ref_path_names: Option<&FnvHashSet<BString>>,
-    path_indices: &FnvHashMap<u64, FnvHashMap<usize, usize>>,
+    path_indices: &PathIndices,
     from: u64,
     to: u64,
-) -> Option<FnvHashMap<BString, FnvHashMap<VariantKey, FnvHashSet<Variant>>>> {
-    let mut variants: FnvHashMap<BString, FnvHashMap<_, FnvHashSet<_>>> =
+) -> Option<FnvHashMap<BString, BubbleVariants>> {
+    let mut variants: FnvHashMap<BString, BubbleVariants> =
         FnvHashMap::default();
 
-    let sub_path_ranges =
-        path_data_sub_path_ranges(path_data, path_indices, from, to)?;
+    let index = PathIndex::new(path_data, path_indices);
+    let sub_path_ranges = index.step_ranges(from, to)?;
 
     let is_ref_path = |p: &BStr| {
         if let Some(ref_path_names) = ref_path_names {
@@ -670,47 +1609,64 @@ pub fn detect_variants_in_sub_paths(
         }
     };
 
-    let mut query_path_ranges = sub_path_ranges.clone();
-
-    query_path_ranges.sort_by(|&(x_ix, (x0, x1)), &(y_ix, (y0, y1))| {
-        let x = path_data.paths.get(x_ix).unwrap();
-        let y = path_data.paths.get(y_ix).unwrap();
-
-        let xa = x0.min(x1);
-        let xb = x0.max(x1);
-
-        let ya = y0.min(y1);
-        let yb = y0.max(y1);
-
-        let xs = &x[xa..=xb];
-        let ys = &y[ya..=yb];
-
-        // let xs = &x[x0..=x1];
-        // let ys = &y[y0..=y1];
-
-        xs.cmp(ys)
-    });
-
-    query_path_ranges.dedup_by(
-        |&mut (x_ix, (x0, x1)), &mut (y_ix, (y0, y1))| {
-            let x = path_data.paths.get(x_ix).unwrap();
-            let y = path_data.paths.get(y_ix).unwrap();
+    // Sub paths are considered identical if they visit the same
+    // steps, or, when merging identical alleles, if they spell the
+    // same sequence
+    let allele_key = |path_ix: usize| {
+        let steps = index.sub_path(path_ix, from, to).unwrap();
+        if variant_config.merge_identical_alleles {
+            AlleleKey::Sequence(sub_path_sequence(
+                &path_data.segment_map,
+                steps,
+            ))
+        } else {
+            AlleleKey::Steps(steps)
+        }
+    };
 
-            // let xs = &x[x0..=x1];
-            // let ys = &y[y0..=y1];
+    let mut query_path_ranges: Vec<_> = sub_path_ranges
+        .iter()
+        .map(|&(path_ix, range)| (allele_key(path_ix), path_ix, range))
+        .collect();
 
-            let xa = x0.min(x1);
-            let xb = x0.max(x1);
+    query_path_ranges.sort();
 
-            let ya = y0.min(y1);
-            let yb = y0.max(y1);
+    // Only one path out of each set of identical sub paths is
+    // compared against the reference, but the results apply to all
+    // of them
+    let mut identical_paths: FnvHashMap<usize, Vec<usize>> =
+        FnvHashMap::default();
+    let mut rep: Option<(&AlleleKey, usize)> = None;
+    for (key, path_ix, _) in query_path_ranges.iter() {
+        match rep {
+            Some((rep_key, rep_ix)) if rep_key == key => {
+                identical_paths.entry(rep_ix).or_default().push(*path_ix);
+            }
+            _ => {
+                rep = Some((key, *path_ix));
+                identical_paths.entry(*path_ix).or_default().push(*path_ix);
+            }
+        }
+    }
 
-            let xs = &x[xa..=xb];
-            let ys = &y[ya..=yb];
+    // With `only_snps`, the paths whose alleles have a different
+    // length than the reference's can only give indels, so they're
+    // skipped before the comparison
+    let allele_len = |path_ix: usize| -> usize {
+        index.sub_path(path_ix, from, to).map_or(0, |steps| {
+            steps
+                .iter()
+                .filter_map(|(node, _, _)| path_data.segment_map.get(node))
+                .map(|seq| seq.len())
+                .sum()
+        })
+    };
 
-            xs == ys
-        },
-    );
+    query_path_ranges.dedup_by(|(x_key, _, _), (y_key, _, _)| x_key == y_key);
+    let query_path_ranges: Vec<(usize, (usize, usize))> = query_path_ranges
+        .into_iter()
+        .map(|(_, path_ix, range)| (path_ix, range))
+        .collect();
 
     variants.extend(sub_path_ranges.iter().filter_map(
         |&(ref_ix, (ref_from, ref_to))| {
@@ -720,78 +1676,122 @@ pub fn detect_variants_in_sub_paths(
             }
 
             let ref_path = path_data.paths.get(ref_ix).unwrap();
-            let ref_orient = sub_path_edge_orient(ref_path);
+            let ref_orient = bubble_end_orients(ref_path, (ref_from, ref_to));
 
-            let mut ref_map: FnvHashMap<VariantKey, FnvHashSet<_>> =
-                FnvHashMap::default();
+            let mut bubble_variants = BubbleVariants {
+                ultrabubble: (from, to),
+                ref_path: ref_ix,
+                ..BubbleVariants::default()
+            };
+            bubble_variants.paths.insert(ref_ix);
 
             for &(query_ix, (query_from, query_to)) in query_path_ranges.iter()
             {
                 let query_name = path_data.path_names.get(query_ix)?;
                 let query_path = path_data.paths.get(query_ix).unwrap();
+                let query_paths = identical_paths.get(&query_ix).unwrap();
 
-                let query_orient = sub_path_edge_orient(query_path);
+                let query_orient =
+                    bubble_end_orients(query_path, (query_from, query_to));
 
-                if ref_name != query_name
-                    && !variant_config.ignore_path(ref_orient, query_orient)
+                if ref_name == query_name || query_paths.contains(&ref_ix) {
+                    bubble_variants.paths.extend(query_paths.iter().copied());
+                } else if variant_config.only_snps
+                    && allele_len(query_ix) != allele_len(ref_ix)
+                {
+                    continue;
+                } else if !variant_config.ignore_path(ref_orient, query_orient)
                 {
+                    // The sub paths are compared along the reference
+                    // path, which is also how the positions on it are
+                    // counted, so the reference's range is flipped
+                    // when it goes from `to` to `from`. A query that
+                    // goes through the bubble the other way than the
+                    // reference is read backward, as its reversed sub
+                    // path with every step flipped
+                    let ref_range =
+                        (ref_from.min(ref_to), ref_from.max(ref_to));
+                    let query_start = query_from.min(query_to);
+                    let query_end = query_from.max(query_to);
+                    let reversed_query: Vec<PathStep>;
+                    let (query_steps, query_range): (&[PathStep], _) =
+                        if (ref_from <= ref_to) == (query_from <= query_to) {
+                            (query_path, (query_start, query_end))
+                        } else {
+                            reversed_query = query_path
+                                [query_start..=query_end]
+                                .iter()
+                                .rev()
+                                .map(|&(node, offset, orient)| {
+                                    (node, offset, flip(orient))
+                                })
+                                .collect();
+                            let last = reversed_query.len() - 1;
+                            (&reversed_query, (0, last))
+                        };
+
                     let mut handler = VCFVariantHandler::new(
                         &path_data.segment_map,
                         ref_name,
                         ref_path,
-                        query_path,
+                        query_steps,
                     );
-
                     detect_variants_against_ref_ranges(
                         &path_data.segment_map,
                         ref_path,
-                        query_path,
-                        (ref_from, ref_to),
-                        (query_from, query_to),
+                        query_steps,
+                        ref_range,
+                        query_range,
                         &mut handler,
                     );
 
-                    for (var_key, var_set) in handler.variants {
-                        ref_map.entry(var_key).or_default().extend(var_set);
+                    bubble_variants.paths.extend(query_paths.iter().copied());
+
+                    for (var_key, mut var_set) in handler.variants {
+                        if variant_config.only_snps {
+                            var_set.retain(|var| {
+                                matches!(var, Variant::Snv(_) | Variant::Mnp(_))
+                            });
+                            if var_set.is_empty() {
+                                continue;
+                            }
+                        }
+                        let entry = bubble_variants
+                            .variants
+                            .entry(var_key)
+                            .or_default();
+                        for var in var_set {
+                            entry
+                                .entry(var)
+                                .or_default()
+                                .extend(query_paths.iter().copied());
+                        }
                     }
                 }
             }
 
             let ref_name: BString = ref_name.clone();
-            Some((ref_name, ref_map))
+            Some((ref_name, bubble_variants))
         },
     ));
 
     Some(variants)
 }
 
-fn path_data_sub_paths<'a, 'b>(
+fn path_data_sub_paths<'a>(
     path_data: &'a PathData,
-    path_indices: &'b PathIndices,
+    path_indices: &'a PathIndices,
     from: u64,
     to: u64,
 ) -> Option<Vec<(usize, &'a [PathStep])>> {
-    let from_indices = path_indices.get(&from)?;
-    let to_indices = path_indices.get(&to)?;
-
-    let sub_paths = path_data
-        .paths
-        .iter()
-        .enumerate()
-        .filter_map(|(path_ix, path)| {
-            let from_ix = *from_indices.get(&path_ix)?;
-            let to_ix = *to_indices.get(&path_ix)?;
-            let from = from_ix.min(to_ix);
-            let to = from_ix.max(to_ix);
-            let sub_path = &path[from..=to];
-            if sub_path.len() > 1 {
-                Some((path_ix, sub_path))
-            } else {
-                None
-            }
+    let index = PathIndex::new(path_data, path_indices);
+    let sub_paths = index
+        .step_ranges(from, to)?
+        .into_iter()
+        .filter_map(|(path_ix, _)| {
+            Some((path_ix, index.sub_path(path_ix, from, to)?))
         })
         .collect();
-
     Some(sub_paths)
 }
 
@@ -836,45 +1836,233 @@ pub fn find_snps_in_sub_paths(
     Some(query_snp_map)
 }
 
+/// Limits on the lengths of the alleles written to VCF records. The
+/// length of an allele is the longer of its REF and ALT sequences.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AlleleLengthFilter {
+    /// Alleles shorter than this are dropped.
+    pub min_length: Option<usize>,
+    /// Alleles longer than this are dropped, unless they're written
+    /// as symbolic alleles.
+    pub max_length: Option<usize>,
+    /// If set, deletions and insertions longer than `max_length` are
+    /// written as `<DEL>` or `<INS>` symbolic alleles instead of being
+    /// dropped, and, if a threshold is given, so are those that change
+    /// the length by more than it.
+    pub symbolic: Option<Option<usize>>,
+}
+
+/// How an allele is written, after applying an `AlleleLengthFilter`.
+enum FilteredAllele {
+    Keep,
+    Drop,
+    Symbolic(&'static str, i64),
+}
+
+impl AlleleLengthFilter {
+    fn filter(&self, ref_seq: &[u8], variant: &Variant) -> FilteredAllele {
+        let alt_len = match variant {
+            Variant::Snv(_) => 1,
+            Variant::Del(seq)
+            | Variant::Ins(seq)
+            | Variant::Mnp(seq)
+            | Variant::Clumped(seq) => seq.len(),
+        };
+        let length = ref_seq.len().max(alt_len);
+
+        if matches!(self.min_length, Some(min) if length < min) {
+            return FilteredAllele::Drop;
+        }
+
+        let sv_len = alt_len as i64 - ref_seq.len() as i64;
+        let large_sv = matches!(
+            self.symbolic,
+            Some(Some(n)) if sv_len.unsigned_abs() as usize > n
+        );
+        let too_long = matches!(self.max_length, Some(max) if length > max);
+        if !large_sv && !too_long {
+            return FilteredAllele::Keep;
+        }
+
+        let symbolic = self.symbolic.is_some();
+        match variant {
+            Variant::Del(_) if symbolic => {
+                FilteredAllele::Symbolic("<DEL>", sv_len)
+            }
+            Variant::Ins(_) if symbolic => {
+                FilteredAllele::Symbolic("<INS>", sv_len)
+            }
+            _ if too_long => FilteredAllele::Drop,
+            _ => FilteredAllele::Keep,
+        }
+    }
+}
+
+/// The entries of a hash map, sorted by key.
+fn sorted_entries<K: Ord, V>(map: &FnvHashMap<K, V>) -> Vec<(&K, &V)> {
+    let mut entries: Vec<_> = map.iter().collect();
+    entries.sort_unstable_by_key(|&(key, _)| key);
+    entries
+}
+
+/// Build the VCF records for the variants in a bubble. `path_count`
+/// is the total number of paths in the graph, each of which gets a
+/// genotype column: `0` if the path matches the reference, the
+/// index of its alternate allele if it has a variant, or `.` if the
+/// path doesn't traverse the bubble. The ALT alleles are sorted by
+/// length, then by sequence, so the output is the same on every run.
+///
+/// Alleles are filtered by length with `allele_lengths`. The paths
+/// that carry a dropped allele get a `.` genotype, as their allele
+/// isn't in the record, and the remaining alleles are numbered in
+/// order, so genotype indices always refer to the ALT alleles that
+/// were written. ALT alleles that are equal to the REF, ignoring case,
+/// are dropped too, but their paths get a `0` genotype, as they carry
+/// the REF. Sites with no remaining alleles get no record. If
+/// only symbolic alleles remain, the REF is shortened to its first
+/// base. Records with symbolic alleles get the type of those alleles
+/// in the `SVTYPE` INFO field, or `.` if they mix deletions and
+/// insertions, their length differences in `SVLEN`, and the end of
+/// the full REF in `END`.
+///
+/// Records with more than `max_alleles` alternate alleles get the
+/// `COMPLEX` filter, all others `PASS`. The `UB` INFO field holds the
+/// segment IDs of the ends of the ultrabubble, and the `AC`, `AN`, and
+/// `AF` fields the allele counts and frequencies over the paths.
 pub fn variant_vcf_record(
-    variants: &FnvHashMap<BString, FnvHashMap<VariantKey, FnvHashSet<Variant>>>,
+    variants: &FnvHashMap<BString, BubbleVariants>,
+    path_count: usize,
+    max_alleles: usize,
+    allele_lengths: &AlleleLengthFilter,
 ) -> Vec<VCFRecord> {
     let mut vcf_records = Vec::new();
 
-    for (_, variant_map) in variants.iter() {
-        for (key, var_set) in variant_map.iter() {
-            let (alt_list, type_set): (Vec<BString>, Vec<BString>) = var_set
+    // The maps are iterated in sorted order, so the order of the
+    // records doesn't depend on how the keys happen to be hashed
+    for (_, bubble_variants) in sorted_entries(variants) {
+        for (key, var_map) in sorted_entries(&bubble_variants.variants) {
+            let mut genotypes: Vec<BString> = vec![".".into(); path_count];
+            for &path_ix in bubble_variants.paths.iter() {
+                genotypes[path_ix] = "0".into();
+            }
+
+            let mut alt_list: Vec<BString> = Vec::with_capacity(var_map.len());
+            let mut type_set: Vec<BString> = Vec::with_capacity(var_map.len());
+            let mut sv_lens: Vec<Option<i64>> = Vec::new();
+
+            // The alleles are sorted by length and then sequence, so
+            // the ALT order and genotype indices don't depend on the
+            // iteration order of the map
+            let mut alleles: Vec<_> = var_map
                 .iter()
-                .map(|var| match var {
-                    Variant::Del(seq) => (seq.clone(), "del".into()),
-                    Variant::Ins(seq) => (seq.clone(), "ins".into()),
-                    Variant::Snv(base) => {
-                        let base_seq =
-                            std::iter::once(*base).collect::<BString>();
-                        (base_seq, "snv".into())
+                .map(|(var, paths)| (var.alt_sequence(), var, paths))
+                .collect();
+            alleles.sort_by(|(seq0, var0, _), (seq1, var1, _)| {
+                seq0.len()
+                    .cmp(&seq1.len())
+                    .then_with(|| seq0.cmp(seq1))
+                    .then_with(|| var0.cmp(var1))
+            });
+
+            for (alt, var, paths) in alleles {
+                // An ALT that spells the REF would make an invalid
+                // record. Bases are compared ignoring case, as VCF
+                // does, so a soft-masked copy of the reference segment
+                // gives one. The paths that carry it carry the REF, so
+                // they're genotyped as such
+                if alt.eq_ignore_ascii_case(&key.sequence) {
+                    debug!(
+                        "Dropping {} allele equal to the REF at {}:{}",
+                        var.type_name(),
+                        key.ref_name,
+                        key.pos
+                    );
+                    for &path_ix in paths.iter() {
+                        genotypes[path_ix] = "0".into();
                     }
-                    Variant::Mnp(seq) => (seq.clone(), "mnp".into()),
-                    Variant::Clumped(seq) => (seq.clone(), "clumped".into()),
-                })
-                .unzip();
+                    continue;
+                }
+
+                let var_type = var.type_name();
+
+                let (alt, sv_len) =
+                    match allele_lengths.filter(&key.sequence, var) {
+                        FilteredAllele::Keep => (alt, None),
+                        FilteredAllele::Symbolic(symbol, sv_len) => {
+                            (symbol.into(), Some(sv_len))
+                        }
+                        FilteredAllele::Drop => {
+                            for &path_ix in paths.iter() {
+                                genotypes[path_ix] = ".".into();
+                            }
+                            continue;
+                        }
+                    };
+
+                alt_list.push(alt);
+                type_set.push(var_type.into());
+                sv_lens.push(sv_len);
+
+                let genotype: BString = alt_list.len().to_string().into();
+                for &path_ix in paths.iter() {
+                    genotypes[path_ix] = genotype.clone();
+                }
+            }
+
+            if alt_list.is_empty() {
+                continue;
+            }
 
+            let filter = if alt_list.len() > max_alleles {
+                vcf::FILTER_COMPLEX
+            } else {
+                vcf::FILTER_PASS
+            };
+
+            let reference = if sv_lens.iter().all(Option::is_some) {
+                key.sequence[..1].into()
+            } else {
+                key.sequence.clone()
+            };
+
+            let sv_type = vcf::sv_type(alt_list.iter().map(|alt| &alt[..]));
             let alts = bstr::join(",", alt_list);
             let mut types: BString = "TYPE=".into();
             let types_temp = bstr::join(";TYPE=", type_set);
             types.extend(types_temp);
+            let (from, to) = bubble_variants.ultrabubble;
+            types.extend(format!(";UB={},{}", from, to).bytes());
+
+            if sv_lens.iter().any(Option::is_some) {
+                let sv_lens: Vec<String> = sv_lens
+                    .iter()
+                    .map(|len| len.map_or(".".into(), |l| l.to_string()))
+                    .collect();
+                let end = key.pos + key.sequence.len() - 1;
+                types.extend(
+                    format!(
+                        ";SVTYPE={};SVLEN={};END={}",
+                        sv_type.unwrap_or("."),
+                        sv_lens.join(","),
+                        end
+                    )
+                    .bytes(),
+                );
+            }
 
-            let vcf = VCFRecord {
+            let mut vcf = VCFRecord {
                 chromosome: key.ref_name.clone(),
                 position: key.pos as i64,
                 id: None,
-                reference: key.sequence.clone(),
+                reference,
                 alternate: Some(alts.into()),
                 quality: None,
-                filter: None,
+                filter: Some(filter.into()),
                 info: Some(types),
-                format: None,
-                sample_name: None,
+                format: Some("GT".into()),
+                samples: Some(bstr::join("\t", genotypes).into()),
             };
+            vcf.update_allele_counts();
 
             vcf_records.push(vcf);
         }
@@ -882,3 +2070,1457 @@ pub fn variant_vcf_record(
 
     vcf_records
 }
+
+/// Build a reference record for each reference path in `variants`
+/// on which the bubble has no variants, i.e. where every path that
+/// was compared agrees with the reference. These are like the
+/// reference blocks of a gVCF: the ALT is `.`, and every path that
+/// traverses the bubble gets the `0` genotype.
+///
+/// The record spans the bubble's coordinates on the reference path,
+/// including both of its end segments. By default, the REF is the
+/// entire reference sequence of the bubble. If `include_end` is true,
+/// the REF is only the first base, and the last position is given in
+/// the `END` INFO field instead. Like in `variant_vcf_record`, each
+/// of the first `path_count` paths gets a genotype column.
+pub fn ref_block_vcf_records(
+    variants: &FnvHashMap<BString, BubbleVariants>,
+    path_data: &PathData,
+    path_indices: &PathIndices,
+    path_count: usize,
+    include_end: bool,
+) -> Vec<VCFRecord> {
+    let mut vcf_records = Vec::new();
+
+    for (ref_name, bubble_variants) in sorted_entries(variants) {
+        if !bubble_variants.variants.is_empty() {
+            continue;
+        }
+
+        let (from, to) = bubble_variants.ultrabubble;
+        let ref_ix = bubble_variants.ref_path;
+        let step_ix = |node: u64| path_indices.get(&node)?.get(&ref_ix);
+        let (from_ix, to_ix) = match (step_ix(from), step_ix(to)) {
+            (Some(&from_ix), Some(&to_ix)) => (from_ix, to_ix),
+            _ => continue,
+        };
+
+        let steps =
+            &path_data.paths[ref_ix][from_ix.min(to_ix)..=from_ix.max(to_ix)];
+        let sequence = sub_path_sequence(&path_data.segment_map, steps);
+        if sequence.is_empty() {
+            continue;
+        }
+
+        let (_, start, _) = steps[0];
+        let mut info: BString = format!("UB={},{}", from, to).into();
+        let reference = if include_end {
+            let end = start + sequence.len() - 1;
+            info.extend(format!(";END={}", end).bytes());
+            sequence[..1].into()
+        } else {
+            sequence
+        };
+
+        let mut genotypes: Vec<BString> = vec![".".into(); path_count];
+        for &path_ix in bubble_variants.paths.iter() {
+            genotypes[path_ix] = "0".into();
+        }
+
+        let mut vcf = VCFRecord {
+            chromosome: ref_name.clone(),
+            position: start as i64,
+            id: None,
+            reference,
+            alternate: None,
+            quality: None,
+            filter: Some(vcf::FILTER_PASS.into()),
+            info: Some(info),
+            format: Some("GT".into()),
+            samples: Some(bstr::join("\t", genotypes).into()),
+        };
+        vcf.update_allele_counts();
+
+        vcf_records.push(vcf);
+    }
+
+    vcf_records
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use gfa::parser::GFAParser;
+
+    fn path_data_from_lines(lines: &[&str]) -> PathData {
+        let parser = GFAParser::new();
+        let gfa: GFA<usize, ()> = parser
+            .parse_lines(lines.iter().map(|l| l.as_bytes()))
+            .unwrap();
+        gfa_path_data(gfa, false, Default::default()).unwrap()
+    }
+
+    #[test]
+    fn owned_and_borrowed_segment_maps() {
+        fn gfa() -> GFA<usize, ()> {
+            let lines = ["S\t1\tACGT", "S\t2\t*", "S\t3\tGG"];
+            GFAParser::new()
+                .parse_lines(lines.iter().map(|l| l.as_bytes()))
+                .unwrap()
+        }
+
+        // The owned map can be returned without the GFA
+        fn load_segments() -> FnvHashMap<usize, BString> {
+            let mut gfa = gfa();
+            owned_segment_map(
+                std::mem::take(&mut gfa.segments),
+                Default::default(),
+            )
+            .unwrap()
+        }
+
+        let owned = load_segments();
+        let gfa = gfa();
+        let borrowed = borrowed_segment_map(&gfa, Default::default()).unwrap();
+
+        assert_eq!(3, owned.len());
+        assert_eq!(b"", owned[&2].as_slice());
+        for (id, seq) in borrowed.iter() {
+            assert_eq!(*seq, owned[id].as_slice());
+        }
+    }
+
+    #[test]
+    fn duplicate_segment_ids() {
+        let lines = ["S\t1\tACGT", "S\t2\tA", "S\t2\tC", "P\tp\t1+,2+\t*"];
+        let gfa = || -> GFA<usize, ()> {
+            GFAParser::new()
+                .parse_lines(lines.iter().map(|l| l.as_bytes()))
+                .unwrap()
+        };
+
+        let err = gfa_path_data(gfa(), false, Default::default())
+            .err()
+            .unwrap();
+        assert!(matches!(err, GfaUtilsError::DuplicateSegment(2)));
+        assert!(borrowed_segment_map(&gfa(), Default::default()).is_err());
+
+        let options = PathDataOptions {
+            allow_duplicate_segments: true,
+            ..Default::default()
+        };
+        let path_data = gfa_path_data(gfa(), false, options).unwrap();
+        let gfa = gfa();
+        let borrowed = borrowed_segment_map(&gfa, options).unwrap();
+
+        assert_eq!(b"A", path_data.segment_map[&2].as_slice());
+        assert_eq!(b"A", borrowed[&2]);
+    }
+
+    #[test]
+    fn missing_path_segments() {
+        let lines = [
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t4\tGG",
+            "P\tok\t1+,2+,4+\t*",
+            "P\tbroken\t1+,3+,4+\t*",
+        ];
+        let gfa = || -> GFA<usize, ()> {
+            GFAParser::new()
+                .parse_lines(lines.iter().map(|l| l.as_bytes()))
+                .unwrap()
+        };
+
+        let err = gfa_path_data(gfa(), false, Default::default())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            &err,
+            GfaUtilsError::MissingSegment { path, segment: 3 }
+                if path == "broken"
+        ));
+        assert_eq!(
+            "Path broken refers to segment 3, which is not in the GFA",
+            err.to_string()
+        );
+
+        let options = PathDataOptions {
+            skip_missing_segments: true,
+            ..Default::default()
+        };
+        let path_data = gfa_path_data(gfa(), false, options);
+
+        let path_data = path_data.unwrap();
+        assert_eq!(b"", path_data.segment_map[&3].as_slice());
+        assert_eq!(
+            vec![(1, 1, Orientation::Forward), (3, 5, Orientation::Forward)],
+            path_data.paths[1][..2].to_vec()
+        );
+        assert_eq!(6, path_data.path_length(1));
+    }
+
+    #[test]
+    fn n_runs_across_segments() {
+        // The run in `gap` spans two segments, and is read the same
+        // way on `rev`; the longer runs in the end segments are shared
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACNNNNNNNNNN",
+            "S\t2\tANNn",
+            "S\t3\tNNNC",
+            "S\t4\tNA",
+            "S\t5\tNNNNNNNNNNGT",
+            "P\tgap\t1+,2+,3+,5+\t*",
+            "P\tref\t1+,4+,5+\t*",
+            "P\trev\t5-,3-,2-,1-\t*",
+        ]);
+        let nodes = [1, 5].iter().copied().collect();
+        let path_indices = bubble_path_indices(&path_data.paths, &nodes);
+        assert_eq!(6, longest_n_run(&path_data, &path_indices, 1, 5));
+
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACNNNNNNNNNN",
+            "S\t4\tNA",
+            "S\t5\tNNNNNNNNNNGT",
+            "P\tref\t1+,4+,5+\t*",
+        ]);
+        let path_indices = bubble_path_indices(&path_data.paths, &nodes);
+        assert_eq!(1, longest_n_run(&path_data, &path_indices, 1, 5));
+    }
+
+    #[test]
+    fn bubble_alleles_in_segment_space() {
+        use Orientation::*;
+
+        // `rev` takes the same allele as `alt`, backward
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "P\talt\t1+,3+,4+\t*",
+            "P\tref\t1+,2+,4+\t*",
+            "P\trev\t4-,3-,1-\t*",
+        ]);
+        let nodes = [1, 4].iter().copied().collect();
+        let path_indices = bubble_path_indices(&path_data.paths, &nodes);
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once("ref".into()).collect();
+
+        let alleles =
+            bubble_alleles(&path_data, &path_indices, Some(&ref_paths), 1, 4);
+        assert_eq!(
+            vec![
+                BubbleAllele {
+                    steps: vec![(1, Forward), (2, Forward), (4, Forward)],
+                    paths: vec![1],
+                    is_ref: true,
+                },
+                BubbleAllele {
+                    steps: vec![(1, Forward), (3, Forward), (4, Forward)],
+                    paths: vec![0, 2],
+                    is_ref: false,
+                },
+            ],
+            alleles
+        );
+    }
+
+    fn bubble_records(
+        path_data: &PathData,
+        ref_path_names: Option<&FnvHashSet<BString>>,
+        from: u64,
+        to: u64,
+        max_alleles: usize,
+    ) -> Vec<VCFRecord> {
+        let config = VariantConfig::default();
+        bubble_records_with_config(
+            &config,
+            &AlleleLengthFilter::default(),
+            path_data,
+            ref_path_names,
+            from,
+            to,
+            max_alleles,
+        )
+    }
+
+    fn bubble_records_with_config(
+        config: &VariantConfig,
+        allele_lengths: &AlleleLengthFilter,
+        path_data: &PathData,
+        ref_path_names: Option<&FnvHashSet<BString>>,
+        from: u64,
+        to: u64,
+        max_alleles: usize,
+    ) -> Vec<VCFRecord> {
+        let nodes = [from, to].iter().copied().collect();
+        let path_indices = bubble_path_indices(&path_data.paths, &nodes);
+        let vars = detect_variants_in_sub_paths(
+            config,
+            path_data,
+            ref_path_names,
+            &path_indices,
+            from,
+            to,
+        )
+        .unwrap();
+        variant_vcf_record(
+            &vars,
+            path_data.paths.len(),
+            max_alleles,
+            allele_lengths,
+        )
+    }
+
+    #[test]
+    fn longer_query_segment_is_insertion() {
+        // The single base segment 2 against the two bases of segment
+        // 3 is an insertion of C, anchored on the T before it
+        let lines = [
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tCA",
+            "S\t4\tG",
+            "S\t5\tGGTT",
+            "P\tref\t1+,2+,5+\t*",
+            "P\tins\t1+,3+,5+\t*",
+            "P\tsnv\t1+,4+,5+\t*",
+        ];
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let path_data = path_data_from_lines(&lines);
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 5, 1);
+        let mut summary: Vec<_> = records
+            .iter()
+            .map(|r| {
+                (
+                    r.position,
+                    r.reference.clone(),
+                    r.alternate.clone().unwrap(),
+                    r.samples.clone().unwrap(),
+                )
+            })
+            .collect();
+        summary.sort();
+        assert_eq!(
+            vec![
+                (4, "TA".into(), "TCA".into(), "0\t1\t0".into()),
+                (5, "A".into(), "G".into(), "0\t0\t1".into()),
+            ],
+            summary
+        );
+        let ins = records.iter().find(|r| r.position == 4).unwrap();
+        assert!(ins.info.as_ref().unwrap().starts_with(b"TYPE=ins;"));
+
+        // The shorter segment is a deletion the other way around
+        let lines = [
+            "S\t1\tACGT",
+            "S\t2\tCA",
+            "S\t3\tA",
+            "S\t5\tGGTT",
+            "P\tref\t1+,2+,5+\t*",
+            "P\tdel\t1+,3+,5+\t*",
+        ];
+        let path_data = path_data_from_lines(&lines);
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 5, 1);
+        assert_eq!(1, records.len());
+        assert_eq!(
+            ("TCA", "TA"),
+            (
+                records[0].reference.to_str().unwrap(),
+                records[0].alternate.as_ref().unwrap().to_str().unwrap()
+            )
+        );
+        assert!(records[0].info.as_ref().unwrap().starts_with(b"TYPE=del;"));
+    }
+
+    #[test]
+    fn region_queries_on_long_path() {
+        // A synthetic path over segments of varying length, with a
+        // step on the empty segment 8 after every fifth step
+        let step_count = 100_000;
+        let segment_map: FnvHashMap<usize, BString> = (1..=8)
+            .map(|id| (id, BString::from(vec![b'A'; id % 8])))
+            .collect();
+
+        let mut steps = Vec::with_capacity(step_count);
+        let mut offset = 1;
+        for step_ix in 0..step_count {
+            let node = if step_ix % 6 == 5 { 8 } else { step_ix % 7 + 1 };
+            steps.push((node, offset, Orientation::Forward));
+            offset += segment_map[&node].len();
+        }
+
+        let path_data = PathData {
+            segment_map,
+            path_names: vec!["ref".into()],
+            paths: vec![steps],
+        };
+        let length = path_data.path_length(0);
+        let index = PathOffsetIndex::new(&path_data, 0);
+
+        // Steps on the empty segment cover their offset
+        let steps = &path_data.paths[0];
+        let step_end =
+            |(node, offset, _): PathStep| offset + (node % 8).max(1) - 1;
+        let linear_scan = |start: usize, end: usize| {
+            let first = steps.iter().position(|&s| step_end(s) >= start)?;
+            let last =
+                steps.iter().rposition(|&(_, offset, _)| offset <= end)?;
+            Some((first, last))
+        };
+
+        let empty_offset = steps[5].1;
+        for &(start, end) in &[
+            (1, 1),
+            (1, 10),
+            (3, 3),
+            (empty_offset, empty_offset),
+            (empty_offset - 1, empty_offset - 1),
+            (empty_offset + 1, empty_offset + 3),
+            (length / 2, length / 2 + 1000),
+            (length - 5, length),
+            (length - 5, length + 100),
+        ] {
+            assert_eq!(
+                linear_scan(start, end),
+                index.steps_in_range(start, end),
+                "{}-{}",
+                start,
+                end
+            );
+        }
+
+        // Every window agrees with the scan, though the scan is only
+        // run on a sample, as it's linear in the path length
+        for start in (1..length).step_by(37) {
+            let range = index.steps_in_range(start, start + 50);
+            if start % 1001 == 0 {
+                assert_eq!(linear_scan(start, start + 50), range);
+            }
+            assert!(range.is_some());
+        }
+
+        assert_eq!(None, index.steps_in_range(length + 1, length + 10));
+        assert_eq!(None, index.steps_in_range(10, 5));
+    }
+
+    #[test]
+    fn alt_equal_to_ref_genotyped_as_ref() {
+        // Such an allele can't come from the detection, so the
+        // variants are built by hand
+        let key = VariantKey {
+            ref_name: "ref".into(),
+            sequence: "A".into(),
+            pos: 5,
+        };
+        let mut bubble_variants = BubbleVariants {
+            ultrabubble: (1, 5),
+            ref_path: 0,
+            paths: (0..3).collect(),
+            ..BubbleVariants::default()
+        };
+        let var_map = bubble_variants.variants.entry(key).or_default();
+        var_map.insert(Variant::Snv(b'A'), std::iter::once(1).collect());
+        var_map.insert(Variant::Snv(b'G'), std::iter::once(2).collect());
+        let variants: FnvHashMap<BString, BubbleVariants> =
+            std::iter::once(("ref".into(), bubble_variants)).collect();
+
+        let records =
+            variant_vcf_record(&variants, 3, 1, &AlleleLengthFilter::default());
+        assert_eq!(1, records.len());
+        assert_eq!(Some("G".into()), records[0].alternate);
+        assert_eq!(Some("0\t0\t1".into()), records[0].samples);
+    }
+
+    #[test]
+    fn soft_masked_ref_allele_not_an_alt() {
+        // Segment 3 is a soft-masked copy of the reference's segment 2,
+        // which the detection reports as an SNV, but which is the same
+        // base as the REF
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\ta",
+            "S\t4\tGGTT",
+            "S\t5\tC",
+            "P\tref\t1+,2+,4+\t*",
+            "P\tmasked\t1+,3+,4+\t*",
+            "P\talt\t1+,5+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 1);
+        assert_eq!(1, records.len());
+        assert_eq!(Some("C".into()), records[0].alternate);
+        assert_eq!(Some("PASS".into()), records[0].filter);
+        assert_eq!(Some("0\t0\t1".into()), records[0].samples);
+
+        // Without the other ALT, no record is left
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\ta",
+            "S\t4\tGGTT",
+            "P\tref\t1+,2+,4+\t*",
+            "P\tmasked\t1+,3+,4+\t*",
+        ]);
+        assert!(
+            bubble_records(&path_data, Some(&ref_paths), 1, 4, 1).is_empty()
+        );
+    }
+
+    #[test]
+    fn snv_genotype_columns() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "S\t5\tCA",
+            "L\t1\t+\t2\t+\t0M",
+            "L\t1\t+\t3\t+\t0M",
+            "L\t2\t+\t4\t+\t0M",
+            "L\t3\t+\t4\t+\t0M",
+            "L\t4\t+\t5\t+\t0M",
+            "P\tref\t1+,2+,4+\t*",
+            "P\talt\t1+,3+,4+\t*",
+            "P\tsame\t1+,2+,4+\t*",
+            "P\tother\t4+,5+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 1);
+        assert_eq!(1, records.len());
+
+        let record = &records[0];
+        assert_eq!("ref", record.chromosome);
+        assert_eq!(5, record.position);
+        assert_eq!("A", record.reference);
+        assert_eq!(Some("C".into()), record.alternate);
+        assert_eq!(Some("PASS".into()), record.filter);
+        assert_eq!(Some("GT".into()), record.format);
+        assert_eq!(Some("0\t1\t0\t.".into()), record.samples);
+    }
+
+    #[test]
+    fn multiallelic_filter() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "S\t5\tG",
+            "P\tref\t1+,2+,4+\t*",
+            "P\talt1\t1+,3+,4+\t*",
+            "P\talt2\t1+,5+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 1);
+        assert_eq!(1, records.len());
+        assert_eq!(Some("COMPLEX".into()), records[0].filter);
+
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 2);
+        assert_eq!(1, records.len());
+        assert_eq!(Some("PASS".into()), records[0].filter);
+    }
+
+    #[test]
+    fn allele_counts_and_frequencies() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "S\t5\tG",
+            "S\t6\tCA",
+            "P\tref\t1+,2+,4+\t*",
+            "P\talt1\t1+,3+,4+\t*",
+            "P\talt2\t1+,5+,4+\t*",
+            "P\talt3\t1+,5+,4+\t*",
+            "P\tother\t4+,6+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 2);
+        assert_eq!(1, records.len());
+        let record = &records[0];
+
+        let info = record.info.as_ref().unwrap().to_str().unwrap();
+        let field = |key: &str| -> Vec<&str> {
+            let prefix = format!("{}=", key);
+            let entry = info.split(';').find_map(|e| e.strip_prefix(&prefix));
+            entry.unwrap().split(',').collect()
+        };
+
+        let alts: Vec<&str> = record
+            .alternate
+            .as_ref()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .split(',')
+            .collect();
+        let counts: Vec<usize> =
+            field("AC").iter().map(|c| c.parse().unwrap()).collect();
+        let number: usize = field("AN")[0].parse().unwrap();
+        let freqs: Vec<f64> =
+            field("AF").iter().map(|f| f.parse().unwrap()).collect();
+
+        // The path that doesn't traverse the bubble isn't counted
+        assert_eq!(4, number);
+        assert_eq!(alts.len(), counts.len());
+        assert_eq!(alts.len(), freqs.len());
+
+        let alt_counts: Vec<(&str, usize)> =
+            alts.iter().copied().zip(counts.iter().copied()).collect();
+        assert!(alt_counts.contains(&("C", 1)));
+        assert!(alt_counts.contains(&("G", 2)));
+
+        for (&count, &freq) in counts.iter().zip(freqs.iter()) {
+            assert!((freq - count as f64 / number as f64).abs() < 1e-6);
+        }
+        let ref_freq = 1.0 / number as f64;
+        assert!((freqs.iter().sum::<f64>() + ref_freq - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn merge_identical_alleles() {
+        // alt1 and alt2 spell the same sequence through different
+        // segments, and split_ref spells the reference sequence
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tAT",
+            "S\t3\tCG",
+            "S\t4\tGGTT",
+            "S\t5\tC",
+            "S\t6\tG",
+            "S\t7\tA",
+            "S\t8\tT",
+            "P\tref\t1+,2+,4+\t*",
+            "P\talt1\t1+,3+,4+\t*",
+            "P\talt2\t1+,5+,6+,4+\t*",
+            "P\tsplit_ref\t1+,7+,8+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let config = VariantConfig {
+            merge_identical_alleles: true,
+            ..VariantConfig::default()
+        };
+        let records = bubble_records_with_config(
+            &config,
+            &AlleleLengthFilter::default(),
+            &path_data,
+            Some(&ref_paths),
+            1,
+            4,
+            1,
+        );
+        assert_eq!(1, records.len());
+
+        let record = &records[0];
+        assert_eq!(5, record.position);
+        assert_eq!("AT", record.reference);
+        assert_eq!(Some("CG".into()), record.alternate);
+        assert_eq!(Some("PASS".into()), record.filter);
+        assert_eq!(Some("0\t1\t1\t0".into()), record.samples);
+    }
+
+    #[test]
+    fn records_for_each_reference() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "S\t5\tTT",
+            "P\tref1\t1+,2+,4+\t*",
+            "P\tref2\t5+,1+,3+,4+\t*",
+            "P\tquery\t1+,3+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            vec!["ref1".into(), "ref2".into()].into_iter().collect();
+
+        let mut records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 1);
+        records.sort_by(|a, b| a.vcf_cmp(b));
+        assert_eq!(2, records.len());
+
+        assert_eq!("ref1", records[0].chromosome);
+        assert_eq!(5, records[0].position);
+        assert_eq!("A", records[0].reference);
+        assert_eq!(Some("C".into()), records[0].alternate);
+        assert_eq!(Some("0\t1\t1".into()), records[0].samples);
+
+        assert_eq!("ref2", records[1].chromosome);
+        assert_eq!(7, records[1].position);
+        assert_eq!("C", records[1].reference);
+        assert_eq!(Some("A".into()), records[1].alternate);
+        assert_eq!(Some("1\t0\t0".into()), records[1].samples);
+    }
+
+    #[test]
+    fn empty_segments() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\t*",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "P\tref\t1+,3+,4+\t*",
+            "P\tdel\t1+,2+,4+\t*",
+        ]);
+
+        assert_eq!(Some(&BString::from("")), path_data.segment_map.get(&2));
+        assert_eq!((4, 5, Orientation::Forward), path_data.paths[1][2]);
+        assert_eq!(9, path_data.path_length(0));
+        assert_eq!(8, path_data.path_length(1));
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 1);
+        assert_eq!(1, records.len());
+
+        let record = &records[0];
+        assert_eq!(4, record.position);
+        assert_eq!("TC", record.reference);
+        assert_eq!(Some("T".into()), record.alternate);
+        assert_eq!(Some("0\t1".into()), record.samples);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("del")).collect();
+
+        let records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 1);
+        assert_eq!(1, records.len());
+
+        let record = &records[0];
+        assert_eq!(4, record.position);
+        assert_eq!("T", record.reference);
+        assert_eq!(Some("TC".into()), record.alternate);
+        assert_eq!(Some("1\t0".into()), record.samples);
+    }
+
+    #[test]
+    fn overlapping_bubbles_are_merged() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "S\t5\tTTA",
+            "S\t6\tCA",
+            "P\tref\t1+,2+,4+,5+,6+\t*",
+            "P\talt\t1+,3+,4+,5+,6+\t*",
+            "P\tshort\t1+,3+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let mut records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 1);
+        records.extend(bubble_records(&path_data, Some(&ref_paths), 1, 6, 1));
+        assert_eq!(2, records.len());
+
+        vcf::merge_duplicate_records(&mut records, &[]);
+        assert_eq!(1, records.len());
+
+        let record = &records[0];
+        assert_eq!(5, record.position);
+        assert_eq!(Some("C".into()), record.alternate);
+        assert_eq!(
+            Some("TYPE=snv;UB=1,4;AC=2;AN=3;AF=0.666667;NS=3".into()),
+            record.info
+        );
+        assert_eq!(Some("0\t1\t1".into()), record.samples);
+    }
+
+    #[test]
+    fn inverted_paths_skipped_by_bubble_end_orientations() {
+        // In the bubble 1-4, `tail_flip` only differs from the
+        // reference in the orientation of its last step, which is
+        // outside the bubble, while `end_flip` has the end segment 4
+        // flipped, and `reversed` traverses the bubble backwards
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "S\t5\tTTA",
+            "P\tref\t1+,2+,4+,5+\t*",
+            "P\tsnv\t1+,3+,4+,5+\t*",
+            "P\ttail_flip\t1+,3+,4+,5-\t*",
+            "P\tend_flip\t1+,3+,4-\t*",
+            "P\treversed\t5-,4-,3-,1-\t*",
+        ]);
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let records = |ignore_inverted_paths: bool| {
+            let config = VariantConfig {
+                ignore_inverted_paths,
+                ..VariantConfig::default()
+            };
+            bubble_records_with_config(
+                &config,
+                &AlleleLengthFilter::default(),
+                &path_data,
+                Some(&ref_paths),
+                1,
+                4,
+                usize::MAX,
+            )
+            .iter()
+            .map(|record| record.to_string())
+            .collect::<Vec<_>>()
+        };
+
+        // Only the paths with both ends in the reference's orientation
+        // are compared, and the others get no genotype
+        assert_eq!(
+            vec![
+                "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=2;AN=3;\
+                  AF=0.666667;NS=3\tGT\t0\t1\t1\t.\t."
+            ],
+            records(true)
+        );
+
+        // Without the flag, every path is compared: `reversed` is read
+        // backward, carrying the C, and `end_flip` also reads segment
+        // 4 as its reverse complement
+        assert_eq!(
+            vec![
+                "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=4;AN=5;\
+                  AF=0.8;NS=5\tGT\t0\t1\t1\t1\t1",
+                "ref\t6\t.\tGGTT\tAACC\t.\tPASS\tTYPE=mnp;UB=1,4;AC=1;AN=5;\
+                  AF=0.2;NS=5\tGT\t0\t0\t0\t1\t0",
+            ],
+            records(false)
+        );
+    }
+
+    #[test]
+    fn path_index_lookups() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tCC",
+            "S\t4\tGGTT",
+            "P\tref\t1+,2+,4+\t*",
+            "P\talt\t4-,3-,1-\t*",
+            "P\tshort\t1+,4+\t*",
+            "P\tnone\t3+,4+\t*",
+        ]);
+
+        let nodes = [1, 4].iter().copied().collect();
+        let path_indices = bubble_path_indices(&path_data.paths, &nodes);
+        let index = PathIndex::new(&path_data, &path_indices);
+
+        assert_eq!(
+            Some(vec![(0, (0, 2)), (1, (2, 0)), (2, (0, 1))]),
+            index.step_ranges(1, 4)
+        );
+        assert_eq!(None, index.step_ranges(1, 3));
+        assert_eq!(Some((2, 0)), index.step_range(1, 1, 4));
+        assert_eq!(None, index.step_range(3, 1, 4));
+
+        let nodes = |steps: &[PathStep]| {
+            steps.iter().map(|&(node, _, _)| node).collect::<Vec<_>>()
+        };
+        assert_eq!(Some(vec![4, 3, 1]), index.sub_path(1, 1, 4).map(nodes));
+        assert_eq!(Some(vec![1, 4]), index.sub_path(2, 4, 1).map(nodes));
+
+        assert_eq!(Some((1, 9)), index.span_between(0, 1, 4));
+        assert_eq!(Some((1, 10)), index.span_between(1, 4, 1));
+        assert_eq!(None, index.span_between(3, 1, 4));
+
+        // Read from `from` to `to`, whichever way the path goes
+        let seq = |path_ix, from, to| {
+            index
+                .allele_sequence(path_ix, from, to)
+                .map(|s| s.to_string())
+        };
+        assert_eq!(Some("ACGTAGGTT".into()), seq(0, 1, 4));
+        assert_eq!(Some("AACCTACGT".into()), seq(0, 4, 1));
+        assert_eq!(Some("ACGTCCGGTT".into()), seq(1, 1, 4));
+        assert_eq!(Some("ACGTGGTT".into()), seq(2, 1, 4));
+    }
+
+    #[test]
+    fn bubble_ranges_on_path() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "P\tref\t1+,2+,4+\t*",
+            "P\talt\t1+,3+,4+\t*",
+            "P\tother\t3+,4+\t*",
+        ]);
+
+        let nodes = [1, 2, 4].iter().copied().collect();
+        let path_indices = bubble_path_indices(&path_data.paths, &nodes);
+
+        let range = |path_ix, from, to| {
+            bubble_path_range(&path_data, &path_indices, path_ix, from, to)
+        };
+
+        assert_eq!(9, path_data.path_length(0));
+        assert_eq!(5, path_data.path_length(2));
+        assert_eq!("ACGTAGGTT", path_data.path_sequence(0));
+
+        assert_eq!(Some((1, 9)), range(0, 1, 4));
+        assert_eq!(Some((1, 9)), range(1, 1, 4));
+        assert_eq!(Some((5, 9)), range(0, 2, 4));
+        assert_eq!(None, range(1, 2, 4));
+        assert_eq!(None, range(2, 1, 4));
+    }
+
+    #[test]
+    fn cyclic_paths() {
+        let mut path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tGG",
+            "P\tlinear\t1+,2+,3+\t*",
+            "P\tcyclic\t1+,2+,1+,3+\t*",
+        ]);
+
+        assert!(!is_cyclic_path(&path_data.paths[0]));
+        assert!(is_cyclic_path(&path_data.paths[1]));
+
+        // Each visit to a segment gets its own offset
+        use Orientation::Forward;
+        assert_eq!(
+            vec![
+                (1, 1, Forward),
+                (2, 5, Forward),
+                (1, 6, Forward),
+                (3, 10, Forward)
+            ],
+            path_data.paths[1]
+        );
+
+        assert_eq!(1, path_data.remove_cyclic_paths());
+        assert_eq!(vec![BString::from("linear")], path_data.path_names);
+        assert_eq!(1, path_data.paths.len());
+        assert_eq!(0, path_data.remove_cyclic_paths());
+    }
+
+    #[test]
+    fn parallel_path_offsets_match_serial() {
+        let params = crate::synthetic::SyntheticGraph {
+            bubbles: 50,
+            paths: 200,
+            ..Default::default()
+        };
+        let gfa = params.gfa();
+
+        let segment_map =
+            owned_segment_map(gfa.segments.clone(), Default::default())
+                .unwrap();
+        let serial: Vec<Vec<PathStep>> = gfa
+            .paths
+            .iter()
+            .map(|path| path_steps_with_offsets(&segment_map, path))
+            .collect();
+        let names: Vec<BString> = gfa
+            .paths
+            .iter()
+            .map(|path| path.path_name.clone().into())
+            .collect();
+
+        let path_data = gfa_path_data(gfa, false, Default::default()).unwrap();
+        assert_eq!(names, path_data.path_names);
+        assert_eq!(serial, path_data.paths);
+    }
+
+    #[test]
+    fn path_step_offsets() {
+        let gfa: GFA<usize, ()> = GFAParser::new()
+            .parse_lines(
+                [
+                    "S\t1\tACGT",
+                    "S\t2\tA",
+                    "S\t3\tGGC",
+                    "S\t4\t*",
+                    "S\t5\tTTAACC",
+                    "P\tforward\t1+,2+,3+,5+\t*",
+                    "P\tmixed\t1+,3-,4+,5-\t*",
+                    "P\treverse_start\t5-,2-,1+\t*",
+                ]
+                .iter()
+                .map(|l| l.as_bytes()),
+            )
+            .unwrap();
+
+        let path_data = gfa_path_data(gfa, false, Default::default()).unwrap();
+
+        assert_eq!(
+            vec!["forward", "mixed", "reverse_start"],
+            path_data.path_names
+        );
+
+        use Orientation::{Backward as B, Forward as F};
+
+        // Each offset is the 1-based position of the first base of the
+        // step on the path, the sum of the lengths of the segments
+        // before it plus one
+        assert_eq!(
+            vec![(1, 1, F), (2, 5, F), (3, 6, F), (5, 9, F)],
+            path_data.paths[0]
+        );
+
+        // The orientation doesn't affect the lengths, and segments
+        // without a sequence take up no space
+        assert_eq!(
+            vec![(1, 1, F), (3, 5, B), (4, 8, F), (5, 8, B)],
+            path_data.paths[1]
+        );
+
+        // A path can start with a reverse-complemented segment
+        assert_eq!(vec![(5, 1, B), (2, 7, B), (1, 8, F)], path_data.paths[2]);
+
+        assert_eq!(14, path_data.path_length(0));
+        assert_eq!(13, path_data.path_length(1));
+        assert_eq!(11, path_data.path_length(2));
+    }
+
+    #[test]
+    fn count_records_by_type() {
+        let record = |info: &str| VCFRecord {
+            chromosome: "ref".into(),
+            position: 1,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("C".into()),
+            quality: None,
+            filter: None,
+            info: Some(info.into()),
+            format: None,
+            samples: None,
+        };
+
+        let mut counts = vcf::RecordCounts::default();
+        for info in &[
+            "TYPE=snv;UB=1,4",
+            "TYPE=snv;TYPE=snv;UB=1,4",
+            "TYPE=ins;UB=1,4",
+            "TYPE=del;UB=1,4;AC=1",
+            "TYPE=snv;TYPE=del;UB=1,4",
+            "TYPE=mnp;UB=1,4",
+            "TYPE=clumped;UB=1,4",
+        ] {
+            counts.add(&record(info));
+        }
+
+        let expected = vcf::RecordCounts {
+            snv: 2,
+            insertion: 1,
+            deletion: 1,
+            complex: 3,
+            reference: 0,
+        };
+        assert_eq!(expected, counts);
+        assert_eq!(7, counts.total());
+    }
+
+    #[test]
+    fn allele_length_filter() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tCCCCCC",
+            "S\t3\tTTTTTTTT",
+            "S\t4\tGGTT",
+            "S\t5\tA",
+            "S\t6\tG",
+            "P\tref\t1+,2+,6+,4+\t*",
+            "P\tsnv\t1+,2+,5+,4+\t*",
+            "P\tdel\t1+,6+,4+\t*",
+            "P\tins\t1+,2+,3+,6+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let records = |allele_lengths: AlleleLengthFilter| {
+            let mut records = bubble_records_with_config(
+                &VariantConfig::default(),
+                &allele_lengths,
+                &path_data,
+                Some(&ref_paths),
+                1,
+                4,
+                1,
+            );
+            records.sort_by(|a, b| a.vcf_cmp(b));
+            records
+                .iter()
+                .map(|r| {
+                    let alt = r.alternate.as_ref().unwrap();
+                    let samples = r.samples.as_ref().unwrap();
+                    format!(
+                        "{}\t{}\t{}\t{}",
+                        r.position, r.reference, alt, samples
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            vec![
+                "4\tTCCCCCC\tT\t0\t0\t1\t0",
+                "10\tC\tCTTTTTTTT\t0\t0\t0\t1",
+                "11\tG\tA\t0\t1\t0\t0",
+            ],
+            records(AlleleLengthFilter::default())
+        );
+
+        let max_length = AlleleLengthFilter {
+            max_length: Some(5),
+            ..AlleleLengthFilter::default()
+        };
+        assert_eq!(vec!["11\tG\tA\t0\t1\t0\t0"], records(max_length));
+
+        let symbolic = AlleleLengthFilter {
+            max_length: Some(5),
+            symbolic: Some(None),
+            ..AlleleLengthFilter::default()
+        };
+        assert_eq!(
+            vec![
+                "4\tT\t<DEL>\t0\t0\t1\t0",
+                "10\tC\t<INS>\t0\t0\t0\t1",
+                "11\tG\tA\t0\t1\t0\t0",
+            ],
+            records(symbolic)
+        );
+
+        // Only the length differences count toward the threshold, so
+        // the SNV is kept, and the REF keeps its full sequence while
+        // there are other alleles
+        let sv_threshold = AlleleLengthFilter {
+            symbolic: Some(Some(5)),
+            ..AlleleLengthFilter::default()
+        };
+        assert_eq!(
+            vec![
+                "4\tT\t<DEL>\t0\t0\t1\t0",
+                "10\tC\t<INS>\t0\t0\t0\t1",
+                "11\tG\tA\t0\t1\t0\t0",
+            ],
+            records(sv_threshold)
+        );
+        let below_threshold = AlleleLengthFilter {
+            symbolic: Some(Some(8)),
+            ..AlleleLengthFilter::default()
+        };
+        assert_eq!(
+            records(AlleleLengthFilter::default()),
+            records(below_threshold)
+        );
+
+        let min_length = AlleleLengthFilter {
+            min_length: Some(2),
+            ..AlleleLengthFilter::default()
+        };
+        assert_eq!(
+            vec!["4\tTCCCCCC\tT\t0\t0\t1\t0", "10\tC\tCTTTTTTTT\t0\t0\t0\t1",],
+            records(min_length)
+        );
+    }
+
+    #[test]
+    fn dropped_alleles_are_missing_genotypes() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tCCCCCC",
+            "S\t3\tTTTTTTTT",
+            "S\t4\tGGTT",
+            "S\t5\tA",
+            "S\t6\tG",
+            "P\tref\t1+,2+,6+,4+\t*",
+            "P\tlong\t1+,2+,3+,6+,4+\t*",
+            "P\tshort\t1+,2+,5+,6+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let allele_lengths = AlleleLengthFilter {
+            max_length: Some(5),
+            ..AlleleLengthFilter::default()
+        };
+        let records = bubble_records_with_config(
+            &VariantConfig::default(),
+            &allele_lengths,
+            &path_data,
+            Some(&ref_paths),
+            1,
+            4,
+            1,
+        );
+        assert_eq!(1, records.len());
+
+        // The long allele is dropped, so the remaining allele is 1
+        let record = &records[0];
+        assert_eq!(Some("CA".into()), record.alternate);
+        assert_eq!(Some("0\t.\t1".into()), record.samples);
+        assert!(record.info.as_ref().unwrap().contains_str("AN=2"));
+    }
+
+    #[test]
+    fn backward_steps_are_reverse_complemented() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tTT",
+            "S\t3\tAC",
+            "S\t4\tGGTT",
+            "S\t5\tAAC",
+            "S\t6\tG",
+            "P\tref\t1+,2+,6+,4+\t*",
+            "P\tfwd\t1+,3+,6+,4+\t*",
+            "P\trev\t1+,3-,6+,4+\t*",
+            "P\tins\t1+,2+,5-,6+,4+\t*",
+        ]);
+
+        assert_eq!("ACGTGTGGGTT", path_data.path_sequence(2));
+        assert_eq!("ACGTTTGTTGGGTT", path_data.path_sequence(3));
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let mut records = bubble_records(&path_data, Some(&ref_paths), 1, 4, 2);
+        records.sort_by(|a, b| a.vcf_cmp(b));
+        let records: Vec<_> = records
+            .iter()
+            .map(|r| {
+                format!(
+                    "{}\t{}\t{}\t{}",
+                    r.position,
+                    r.reference,
+                    r.alternate.as_ref().unwrap(),
+                    r.samples.as_ref().unwrap()
+                )
+            })
+            .collect();
+
+        // The forward and backward traversals of segment 3 give
+        // different ALTs, and the inserted segment 5 is read backward
+        assert_eq!(
+            vec!["5\tTT\tAC,GT\t0\t1\t2\t0", "6\tT\tTGTT\t0\t0\t0\t1",],
+            records
+        );
+    }
+
+    #[test]
+    fn ref_block_records_for_invariant_bubbles() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tGGTT",
+            "S\t4\tC",
+            "P\tref\t1+,2+,3+\t*",
+            "P\talt\t1+,2+,3+\t*",
+            "P\tother\t3+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let config = VariantConfig::default();
+        let nodes = [1, 3].iter().copied().collect();
+        let path_indices = bubble_path_indices(&path_data.paths, &nodes);
+        let vars = detect_variants_in_sub_paths(
+            &config,
+            &path_data,
+            Some(&ref_paths),
+            &path_indices,
+            1,
+            3,
+        )
+        .unwrap();
+
+        let no_end =
+            ref_block_vcf_records(&vars, &path_data, &path_indices, 3, false);
+        assert_eq!(1, no_end.len());
+        assert_eq!(
+            "ref\t1\t.\tACGTAGGTT\t.\t.\tPASS\tUB=1,3;AN=2;NS=2\tGT\t0\t0\t.",
+            no_end[0].to_string()
+        );
+
+        let with_end =
+            ref_block_vcf_records(&vars, &path_data, &path_indices, 3, true);
+        assert_eq!(
+            "ref\t1\t.\tA\t.\t.\tPASS\tUB=1,3;END=9;AN=2;NS=2\tGT\t0\t0\t.",
+            with_end[0].to_string()
+        );
+
+        let mut counts = vcf::RecordCounts::default();
+        counts.add(&with_end[0]);
+        assert_eq!(1, counts.reference);
+        assert_eq!(1, counts.total());
+    }
+
+    /// The POS, REF and ALTs of the records in the bubble `from`-`to`,
+    /// checking that each REF is the sequence of the reference path
+    /// at the 1-based POS.
+    fn checked_positions(
+        path_data: &PathData,
+        from: u64,
+        to: u64,
+    ) -> Vec<(i64, String, String)> {
+        let ref_ix = path_data
+            .path_names
+            .iter()
+            .position(|name| name == "ref")
+            .unwrap();
+        let ref_seq = path_data.path_sequence(ref_ix);
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let mut records =
+            bubble_records(path_data, Some(&ref_paths), from, to, 3);
+        records.sort_by(|a, b| a.vcf_cmp(b));
+        records
+            .iter()
+            .map(|r| {
+                let start = r.position as usize - 1;
+                let end = start + r.reference.len();
+                assert_eq!(ref_seq[start..end], r.reference[..]);
+                (
+                    r.position,
+                    r.reference.to_string(),
+                    r.alternate.as_ref().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn vcf_positions_are_one_based() {
+        // ref: ACGTA CC G TTGCA T GG AAC
+        //      1     6  8 9     14 15 17
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGTA",
+            "S\t2\tCC",
+            "S\t3\tG",
+            "S\t4\tC",
+            "S\t5\tTTGCA",
+            "S\t6\tT",
+            "S\t7\tGG",
+            "S\t8\tAAC",
+            "S\t9\tAT",
+            "P\tref\t1+,2+,3+,5+,6+,7+,8+\t*",
+            "P\tsnv\t1+,2+,4+,5+,6+,7+,8+\t*",
+            "P\tdel\t1+,2+,3+,5+,7+,8+\t*",
+            "P\tins\t1+,2+,3+,5+,6+,9+,7+,8+\t*",
+        ]);
+        assert_eq!("ACGTACCGTTGCATGGAAC", path_data.path_sequence(0));
+
+        // The SNV is at the 8th base, the G
+        assert_eq!(
+            vec![(8, "G".to_string(), "C".to_string())],
+            checked_positions(&path_data, 2, 5)
+        );
+
+        // The deleted T is at 14, and indels are padded with the base
+        // before them, so the deletion starts at the A at 13, and the
+        // insertion after the T at the T
+        assert_eq!(
+            vec![
+                (13, "AT".to_string(), "A".to_string()),
+                (14, "T".to_string(), "TAT".to_string()),
+            ],
+            checked_positions(&path_data, 5, 7)
+        );
+    }
+
+    #[test]
+    fn vcf_positions_on_backward_reference() {
+        // The reference is read backward, so positions count from the
+        // end of segment 5, and the REF is reverse complemented
+        let path_data = path_data_from_lines(&[
+            "S\t1\tAAC",
+            "S\t2\tG",
+            "S\t3\tT",
+            "S\t4\tGC",
+            "S\t5\tTTAC",
+            "P\tref\t5-,4-,2-,1-\t*",
+            "P\talt\t5-,4-,3-,1-\t*",
+            "P\tfwd\t1+,3+,4+,5+\t*",
+        ]);
+        assert_eq!("GTAAGCCGTT", path_data.path_sequence(0));
+
+        assert_eq!(
+            vec![(7, "C".to_string(), "A".to_string())],
+            checked_positions(&path_data, 1, 4)
+        );
+
+        // `fwd` goes through the bubble the other way, so it's read
+        // backward to be compared, unless inverted paths are ignored
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+        let genotypes = |ignore_inverted_paths: bool| {
+            let config = VariantConfig {
+                ignore_inverted_paths,
+                ..VariantConfig::default()
+            };
+            let records = bubble_records_with_config(
+                &config,
+                &AlleleLengthFilter::default(),
+                &path_data,
+                Some(&ref_paths),
+                1,
+                4,
+                3,
+            );
+            assert_eq!(1, records.len());
+            records[0].samples.clone().unwrap()
+        };
+        assert_eq!("0\t1\t1", genotypes(false));
+        assert_eq!("0\t1\t.", genotypes(true));
+    }
+
+    #[test]
+    fn alt_alleles_are_sorted() {
+        let path_data = path_data_from_lines(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tC",
+            "S\t4\tGGTT",
+            "S\t5\tT",
+            "S\t6\tG",
+            "P\tref\t1+,2+,4+\t*",
+            "P\talt1\t1+,3+,4+\t*",
+            "P\talt2\t1+,5+,4+\t*",
+            "P\talt3\t1+,6+,4+\t*",
+            "P\talt4\t1+,5+,4+\t*",
+        ]);
+
+        let ref_paths: FnvHashSet<BString> =
+            std::iter::once(BString::from("ref")).collect();
+
+        let first = bubble_records(&path_data, Some(&ref_paths), 1, 4, 3);
+        let second = bubble_records(&path_data, Some(&ref_paths), 1, 4, 3);
+        assert_eq!(first, second);
+
+        assert_eq!(1, first.len());
+        assert_eq!(Some("C,G,T".into()), first[0].alternate);
+        assert_eq!(Some("0\t1\t3\t2\t3".into()), first[0].samples);
+    }
+}
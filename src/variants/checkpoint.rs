@@ -0,0 +1,394 @@
+//! Checkpoints for `gfa2vcf`, so that a run that crashes or is
+//! interrupted can be resumed without redoing the ultrabubbles it had
+//! already processed. As the variants in each bubble are found
+//! independently of the others, it's enough to record which bubbles
+//! are done, along with their records. When the references are
+//! processed one at a time, each bubble is recorded once for each
+//! reference, with the reference's name.
+//!
+//! The checkpoint is a JSONL file in the checkpoint directory. The
+//! first line describes the run, and each following line holds one
+//! processed bubble and its records, as VCF text. Lines are only ever
+//! written whole, but a crash can still leave a partial line at the
+//! end, without a newline, which is dropped when the checkpoint is
+//! loaded.
+
+use bstr::{BStr, BString, ByteSlice};
+use fnv::FnvHashMap;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+use super::vcf::VCFRecord;
+use crate::error::{GfaUtilsError, Result};
+
+/// The name of the checkpoint file in the checkpoint directory.
+pub const CHECKPOINT_FILE: &str = "gfa2vcf.checkpoint.jsonl";
+
+/// Buffered bubbles are written to the file once they take up this
+/// many bytes, or once this long has passed since the last write.
+const FLUSH_BYTES: usize = 1 << 20;
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The first line of the checkpoint file, used to make sure a
+/// checkpoint is only resumed by a run on the same input, unchanged
+/// since, and with the same options.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CheckpointRun {
+    gfa: String,
+    gfa_size: u64,
+    gfa_modified: u64,
+    ultrabubbles: usize,
+    options: String,
+}
+
+impl CheckpointRun {
+    fn new(
+        gfa_path: &Path,
+        ultrabubbles: usize,
+        options: &str,
+    ) -> Result<Self> {
+        let gfa = gfa_path.canonicalize()?;
+        let metadata = std::fs::metadata(&gfa)?;
+        let gfa_modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_nanos() as u64);
+        Ok(Self {
+            gfa: gfa.display().to_string(),
+            gfa_size: metadata.len(),
+            gfa_modified,
+            ultrabubbles,
+            options: options.to_string(),
+        })
+    }
+
+    /// Describe how `self`, from the checkpoint, differs from the
+    /// current run, if it does.
+    fn mismatch(&self, current: &Self) -> Option<String> {
+        if self.gfa != current.gfa {
+            Some(format!(
+                "it's from a run on {}, not {}",
+                self.gfa, current.gfa
+            ))
+        } else if self.gfa_size != current.gfa_size
+            || self.gfa_modified != current.gfa_modified
+        {
+            Some(format!("{} has changed since it was made", self.gfa))
+        } else if self.ultrabubbles != current.ultrabubbles {
+            Some(format!(
+                "it's from a run with {} ultrabubbles, not {}",
+                self.ultrabubbles, current.ultrabubbles
+            ))
+        } else if self.options != current.options {
+            Some(format!(
+                "it's from a run with different options: {}",
+                self.options
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+/// A processed ultrabubble and its records, with the reference it
+/// was processed against, if it wasn't processed against them all.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CheckpointBubble {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    reference: Option<String>,
+    from: u64,
+    to: u64,
+    records: Vec<String>,
+}
+
+struct CheckpointWriter {
+    file: File,
+    buffer: Vec<u8>,
+    last_flush: Instant,
+}
+
+impl CheckpointWriter {
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.write_all(&self.buffer)?;
+        self.file.flush()?;
+        self.buffer.clear();
+        self.last_flush = Instant::now();
+        Ok(())
+    }
+}
+
+/// A checkpoint that the ultrabubbles are added to as they're
+/// processed, from any thread.
+pub struct Checkpoint {
+    path: PathBuf,
+    writer: Mutex<CheckpointWriter>,
+}
+
+/// The ultrabubbles already processed in a previous run, by the
+/// reference they were processed against, if any, with their records.
+pub type CompletedBubbles =
+    FnvHashMap<(Option<BString>, (u64, u64)), Vec<VCFRecord>>;
+
+impl Checkpoint {
+    /// Open the checkpoint in the directory `dir`, creating the
+    /// directory and the checkpoint if they don't exist, and return
+    /// it along with the ultrabubbles it already holds. Fails if the
+    /// checkpoint is from a run on another GFA, or on the same GFA
+    /// before it was modified, or with a different number of
+    /// ultrabubbles, or if its `options` don't match the given ones.
+    /// `options` should describe every option that affects the
+    /// records.
+    pub fn open(
+        dir: &Path,
+        gfa_path: &Path,
+        ultrabubbles: usize,
+        options: &str,
+    ) -> Result<(Self, CompletedBubbles)> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(CHECKPOINT_FILE);
+
+        let run = CheckpointRun::new(gfa_path, ultrabubbles, options)?;
+
+        let mut completed = CompletedBubbles::default();
+        let mut valid_len = 0;
+
+        if path.exists() {
+            let data = std::fs::read(&path)?;
+            // Only lines that end with a newline were written whole
+            let mut lines = data
+                .lines_with_terminator()
+                .take_while(|line| line.ends_with(b"\n"));
+
+            if let Some(line) = lines.next() {
+                let previous: CheckpointRun = serde_json::from_slice(line)
+                    .map_err(|err| checkpoint_error(&path, err))?;
+                if let Some(mismatch) = previous.mismatch(&run) {
+                    return Err(checkpoint_error(&path, mismatch));
+                }
+                valid_len = line.len();
+            }
+
+            for line in lines {
+                let bubble: CheckpointBubble = serde_json::from_slice(line)
+                    .map_err(|err| checkpoint_error(&path, err))?;
+                let records = bubble
+                    .records
+                    .iter()
+                    .map(|record| VCFRecord::parse(record.as_bytes()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| {
+                        checkpoint_error(&path, "invalid VCF record")
+                    })?;
+                let reference = bubble.reference.map(BString::from);
+                completed
+                    .insert((reference, (bubble.from, bubble.to)), records);
+                valid_len += line.len();
+            }
+        }
+
+        let mut file =
+            OpenOptions::new().create(true).append(true).open(&path)?;
+
+        if valid_len == 0 {
+            file.set_len(0)?;
+            serde_json::to_writer(&mut file, &run)?;
+            writeln!(file)?;
+        } else {
+            // Drop the partial line a crash may have left, so that the
+            // new lines aren't appended to it
+            file.set_len(valid_len as u64)?;
+            info!(
+                "Resuming from checkpoint {}, with {} processed \
+                 ultrabubbles",
+                path.display(),
+                completed.len()
+            );
+        }
+
+        let writer = CheckpointWriter {
+            file,
+            buffer: Vec::new(),
+            last_flush: Instant::now(),
+        };
+
+        Ok((
+            Self {
+                path,
+                writer: Mutex::new(writer),
+            },
+            completed,
+        ))
+    }
+
+    /// The path of the checkpoint file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Add the ultrabubble `from`-`to`, processed against `reference`
+    /// or against all references if it's `None`, with its records.
+    /// The bubble is buffered, and the buffer is written to the file
+    /// when it's large enough, or some time after the last write.
+    pub fn add(
+        &self,
+        reference: Option<&BStr>,
+        (from, to): (u64, u64),
+        records: &[VCFRecord],
+    ) -> Result<()> {
+        let bubble = CheckpointBubble {
+            reference: reference.map(|name| name.to_string()),
+            from,
+            to,
+            records: records.iter().map(|record| record.to_string()).collect(),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        serde_json::to_writer(&mut writer.buffer, &bubble)?;
+        writer.buffer.push(b'\n');
+        if writer.buffer.len() >= FLUSH_BYTES
+            || writer.last_flush.elapsed() >= FLUSH_INTERVAL
+        {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Write the buffered ultrabubbles to the file.
+    pub fn flush(&self) -> Result<()> {
+        self.writer.lock().unwrap().flush()?;
+        Ok(())
+    }
+}
+
+fn checkpoint_error<E: std::fmt::Display>(
+    path: &Path,
+    err: E,
+) -> GfaUtilsError {
+    GfaUtilsError::Other(format!(
+        "Can't resume from checkpoint {}: {}",
+        path.display(),
+        err
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(position: i64) -> VCFRecord {
+        VCFRecord {
+            chromosome: "ref".into(),
+            position,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("C".into()),
+            quality: None,
+            filter: Some("PASS".into()),
+            info: Some("TYPE=snv;UB=1,4".into()),
+            format: Some("GT".into()),
+            samples: Some("0\t1".into()),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "gfautil_checkpoint_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn checkpoint_resumed_after_partial_line() {
+        let dir = temp_dir("resumed");
+        let gfa = dir.join("graph.gfa");
+        std::fs::write(&gfa, "H\tVN:Z:1.0\n").unwrap();
+        let ckpt = dir.join("ckpt");
+
+        let (checkpoint, completed) =
+            Checkpoint::open(&ckpt, &gfa, 3, "opts").unwrap();
+        assert!(completed.is_empty());
+        checkpoint
+            .add(None, (1, 4), &[record(5), record(7)])
+            .unwrap();
+        checkpoint.add(None, (4, 6), &[]).unwrap();
+        checkpoint.flush().unwrap();
+        let path = checkpoint.path().to_owned();
+        drop(checkpoint);
+
+        // A run that was killed while writing the next bubble
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"{\"from\":6,\"to\":7,\"rec").unwrap();
+        drop(file);
+
+        let (checkpoint, completed) =
+            Checkpoint::open(&ckpt, &gfa, 3, "opts").unwrap();
+        assert_eq!(2, completed.len());
+        assert_eq!(
+            Some(&vec![record(5), record(7)]),
+            completed.get(&(None, (1, 4)))
+        );
+        assert_eq!(Some(&Vec::new()), completed.get(&(None, (4, 6))));
+
+        checkpoint.add(None, (6, 7), &[record(10)]).unwrap();
+        checkpoint
+            .add(Some("ref".into()), (6, 7), &[record(11)])
+            .unwrap();
+        checkpoint.flush().unwrap();
+        drop(checkpoint);
+
+        // The same GFA, by a different path
+        let relative = dir.join(".").join("graph.gfa");
+        let (_, completed) =
+            Checkpoint::open(&ckpt, &relative, 3, "opts").unwrap();
+        assert_eq!(4, completed.len());
+        assert_eq!(Some(&vec![record(10)]), completed.get(&(None, (6, 7))));
+        assert_eq!(
+            Some(&vec![record(11)]),
+            completed.get(&(Some("ref".into()), (6, 7)))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checkpoint_not_resumed_on_mismatch() {
+        let dir = temp_dir("mismatch");
+        let gfa = dir.join("graph.gfa");
+        std::fs::write(&gfa, "H\tVN:Z:1.0\n").unwrap();
+        let other = dir.join("other.gfa");
+        std::fs::write(&other, "H\tVN:Z:1.0\n").unwrap();
+        let ckpt = dir.join("ckpt");
+
+        let (checkpoint, _) = Checkpoint::open(&ckpt, &gfa, 3, "opts").unwrap();
+        checkpoint.add(None, (1, 4), &[record(5)]).unwrap();
+        checkpoint.flush().unwrap();
+        drop(checkpoint);
+
+        let error = |gfa: &Path, ultrabubbles: usize, options: &str| {
+            match Checkpoint::open(&ckpt, gfa, ultrabubbles, options) {
+                Ok(_) => panic!("resumed a mismatched checkpoint"),
+                Err(err) => err.to_string(),
+            }
+        };
+
+        assert!(error(&gfa, 4, "opts").contains("with 3 ultrabubbles"));
+        assert!(error(&gfa, 3, "other opts").contains("different options"));
+        assert!(error(&other, 3, "opts").contains("it's from a run on"));
+
+        std::fs::write(&gfa, "H\tVN:Z:1.0\nS\t1\tA\n").unwrap();
+        assert!(error(&gfa, 3, "opts").contains("has changed"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
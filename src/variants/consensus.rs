@@ -0,0 +1,264 @@
+//! A synthetic consensus reference, for calling variants when no
+//! single path is a good reference. The consensus follows a backbone
+//! path, except in ultrabubbles, where it takes the most common
+//! allele among the paths that traverse the bubble.
+
+use fnv::FnvHashMap;
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+use super::{bubble_allele, flip, AlleleStep, PathData, PathIndices, PathStep};
+
+/// The most common allele through the bubble `from`-`to`, among the
+/// paths whose alleles start and end with the same steps as the
+/// backbone's. Ties are broken in favor of the backbone's allele,
+/// then by the segment IDs of the steps, so the result doesn't depend
+/// on the order of the paths.
+fn consensus_allele(
+    path_data: &PathData,
+    path_indices: &PathIndices,
+    backbone: usize,
+    bubble: (u64, u64),
+) -> Option<Vec<AlleleStep>> {
+    let backbone_allele =
+        bubble_allele(path_data, path_indices, backbone, bubble)?;
+    let ends = (backbone_allele.first()?, backbone_allele.last()?);
+
+    let mut counts: FnvHashMap<Vec<AlleleStep>, usize> = FnvHashMap::default();
+    for path_ix in 0..path_data.paths.len() {
+        if let Some(allele) =
+            bubble_allele(path_data, path_indices, path_ix, bubble)
+        {
+            if (allele.first()?, allele.last()?) == ends {
+                *counts.entry(allele).or_default() += 1;
+            }
+        }
+    }
+
+    let (allele, _) = counts.into_iter().max_by(|(a0, n0), (a1, n1)| {
+        n0.cmp(n1)
+            .then_with(|| {
+                (a0 == &backbone_allele).cmp(&(a1 == &backbone_allele))
+            })
+            .then_with(|| a1.cmp(a0))
+    })?;
+    Some(allele)
+}
+
+/// Build the steps of a consensus path along the path at index
+/// `backbone`. Each of the outermost ultrabubbles on the backbone,
+/// i.e. those that aren't contained in another bubble on it, is
+/// replaced by the most common allele through the bubble; see
+/// `consensus_allele`. Alleles are read in the backbone's direction,
+/// so paths that traverse a bubble in reverse count toward the same
+/// allele as those that don't. The rest of the backbone is kept as
+/// is, and the offsets of the steps are assigned by concatenating the
+/// segment sequences, as for the paths in the GFA.
+///
+/// Returns the steps and the number of bubbles where the consensus
+/// differs from the backbone.
+pub fn consensus_path(
+    path_data: &PathData,
+    path_indices: &PathIndices,
+    ultrabubbles: &[(u64, u64)],
+    backbone: usize,
+) -> (Vec<PathStep>, usize) {
+    let step_ix = |node: u64| path_indices.get(&node)?.get(&backbone);
+
+    // The step ranges of the bubbles on the backbone, outermost first
+    let mut ranges: Vec<(usize, usize, (u64, u64))> = ultrabubbles
+        .iter()
+        .filter_map(|&(from, to)| {
+            let (&from_ix, &to_ix) = (step_ix(from)?, step_ix(to)?);
+            Some((from_ix.min(to_ix), from_ix.max(to_ix), (from, to)))
+        })
+        .collect();
+    ranges.sort_by(|(s0, e0, b0), (s1, e1, b1)| {
+        s0.cmp(s1).then(e1.cmp(e0)).then(b0.cmp(b1))
+    });
+
+    let backbone_steps = &path_data.paths[backbone];
+    let mut steps: Vec<AlleleStep> = Vec::with_capacity(backbone_steps.len());
+    let mut next_ix = 0;
+    let mut changed = 0;
+
+    for (start, end, bubble) in ranges {
+        // Nested bubbles are left to the enclosing allele
+        if start + 1 < next_ix {
+            continue;
+        }
+        let allele =
+            match consensus_allele(path_data, path_indices, backbone, bubble) {
+                Some(allele) => allele,
+                None => continue,
+            };
+
+        // The allele is read from `from` to `to`, so flip it around if
+        // the backbone goes the other way
+        let allele: Vec<AlleleStep> = if step_ix(bubble.0) <= step_ix(bubble.1)
+        {
+            allele
+        } else {
+            allele
+                .into_iter()
+                .rev()
+                .map(|(node, orient)| (node, flip(orient)))
+                .collect()
+        };
+
+        let backbone_allele = backbone_steps[start..=end]
+            .iter()
+            .map(|&(node, _, orient)| (node, orient));
+        if !allele.iter().copied().eq(backbone_allele) {
+            changed += 1;
+        }
+
+        // Adjacent bubbles share an end segment, which has already
+        // been added with the previous allele
+        steps.extend(
+            backbone_steps[next_ix.min(start)..start]
+                .iter()
+                .map(|&(node, _, orient)| (node, orient)),
+        );
+        let skip = if next_ix > start { 1 } else { 0 };
+        steps.extend(allele.into_iter().skip(skip));
+        next_ix = end + 1;
+    }
+
+    steps.extend(
+        backbone_steps[next_ix.min(backbone_steps.len())..]
+            .iter()
+            .map(|&(node, _, orient)| (node, orient)),
+    );
+
+    let steps = steps
+        .into_iter()
+        .scan(1, |offset, (node, orient)| {
+            let step_offset = *offset;
+            *offset += path_data.segment_map.get(&node).map_or(0, |s| s.len());
+            Some((node, step_offset, orient))
+        })
+        .collect();
+
+    (steps, changed)
+}
+
+/// Add the step indices of the bubble ends on the path at index
+/// `path_ix` to `path_indices`, like `bubble_path_indices` does for
+/// the paths it's given, keeping the last visit to each segment.
+pub fn add_path_indices(
+    path_indices: &mut PathIndices,
+    path_ix: usize,
+    steps: &[PathStep],
+) {
+    for (step_ix, &(node, _, _)) in steps.iter().enumerate() {
+        if let Some(indices) = path_indices.get_mut(&(node as u64)) {
+            indices.insert(path_ix, step_ix);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::variants::{bubble_path_indices, gfa_path_data};
+    use fnv::FnvHashSet;
+    use gfa::{
+        gfa::{Orientation, GFA},
+        parser::GFAParser,
+    };
+
+    fn path_data(lines: &[&str]) -> PathData {
+        let parser = GFAParser::new();
+        let gfa: GFA<usize, ()> = parser
+            .parse_lines(lines.iter().map(|l| l.as_bytes()))
+            .unwrap();
+        gfa_path_data(gfa, false, Default::default()).unwrap()
+    }
+
+    fn consensus_nodes(
+        data: &PathData,
+        ultrabubbles: &[(u64, u64)],
+        backbone: usize,
+    ) -> (Vec<(usize, usize, Orientation)>, usize) {
+        let nodes = ultrabubbles
+            .iter()
+            .flat_map(|&(a, b)| vec![a, b])
+            .collect::<FnvHashSet<_>>();
+        let path_indices = bubble_path_indices(&data.paths, &nodes);
+        consensus_path(data, &path_indices, ultrabubbles, backbone)
+    }
+
+    #[test]
+    fn consensus_takes_most_common_alleles() {
+        use Orientation::*;
+
+        // Bubbles 1-4 and 4-7, where the backbone `a` has the minority
+        // allele in the first, and shares the end segment 4
+        let data = path_data(&[
+            "S\t1\tACGT",
+            "S\t2\tA",
+            "S\t3\tCC",
+            "S\t4\tGGT",
+            "S\t5\tT",
+            "S\t6\tG",
+            "S\t7\tTTA",
+            "P\ta\t1+,2+,4+,5+,7+\t*",
+            "P\tb\t1+,3+,4+,5+,7+\t*",
+            "P\tc\t1+,3+,4+,6+,7+\t*",
+            "P\td\t7-,5-,4-,3-,1-\t*",
+        ]);
+
+        let (steps, changed) = consensus_nodes(&data, &[(1, 4), (4, 7)], 0);
+        assert_eq!(
+            vec![
+                (1, 1, Forward),
+                (3, 5, Forward),
+                (4, 7, Forward),
+                (5, 10, Forward),
+                (7, 11, Forward),
+            ],
+            steps
+        );
+        assert_eq!(1, changed);
+    }
+
+    #[test]
+    fn nested_bubbles_follow_outer_allele() {
+        let data = path_data(&[
+            "S\t1\tA",
+            "S\t2\tC",
+            "S\t3\tG",
+            "S\t4\tT",
+            "S\t5\tA",
+            "P\ta\t1+,2+,3+,5+\t*",
+            "P\tb\t1+,4+,5+\t*",
+            "P\tc\t1+,4+,5+\t*",
+        ]);
+
+        let (steps, changed) = consensus_nodes(&data, &[(1, 5), (2, 3)], 0);
+        let nodes: Vec<usize> = steps.iter().map(|&(n, _, _)| n).collect();
+        assert_eq!(vec![1, 4, 5], nodes);
+        assert_eq!(1, changed);
+    }
+
+    #[test]
+    fn ties_go_to_backbone_allele() {
+        let data = path_data(&[
+            "S\t1\tA",
+            "S\t2\tC",
+            "S\t3\tG",
+            "S\t4\tT",
+            "P\ta\t1+,2+,4+\t*",
+            "P\tb\t1+,3+,4+\t*",
+        ]);
+
+        for backbone in 0..2 {
+            let (steps, changed) = consensus_nodes(&data, &[(1, 4)], backbone);
+            assert_eq!(data.paths[backbone], steps);
+            assert_eq!(0, changed);
+        }
+    }
+}
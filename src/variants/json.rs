@@ -0,0 +1,185 @@
+//! Line-delimited JSON output for variant records, as an alternative
+//! to VCF for tools that would rather not parse VCF text.
+
+use bstr::ByteSlice;
+use serde::{ser::SerializeMap, Serialize, Serializer};
+
+use super::vcf::VCFRecord;
+
+/// A variant record as a JSON object. Built from the final
+/// `VCFRecord`s, so the records are normalized, merged, and sorted
+/// exactly like the VCF output.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct JsonRecord {
+    pub chrom: String,
+    pub pos: i64,
+    /// The ID column, left out if it's missing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "ref")]
+    pub reference: String,
+    pub alts: Vec<String>,
+    pub filter: Option<String>,
+    /// The type of each alternate allele, from the `TYPE` INFO field.
+    pub types: Vec<String>,
+    /// The segment IDs of the ends of the ultrabubble.
+    pub ultrabubble: Option<(u64, u64)>,
+    /// The genotype of each sample, in the order of the VCF sample
+    /// columns.
+    #[serde(serialize_with = "serialize_genotypes")]
+    pub genotypes: Vec<(String, JsonGenotype)>,
+}
+
+/// The genotype of a sample. Samples made up of a single path, which
+/// is the default, have a single allele index, written as a number,
+/// while the haplotypes of samples grouped with `--sample-map` are
+/// written as an array, whether or not they're phased. Missing
+/// alleles, i.e. paths that don't traverse the bubble, are `null`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(untagged)]
+pub enum JsonGenotype {
+    Haploid(Option<usize>),
+    Phased(Vec<Option<usize>>),
+    Unphased(Vec<Option<usize>>),
+}
+
+impl JsonGenotype {
+    fn parse(genotype: &[u8]) -> Self {
+        let allele = |a: &[u8]| a.to_str().ok()?.parse().ok();
+        if genotype.contains(&b'|') {
+            JsonGenotype::Phased(genotype.split_str("|").map(allele).collect())
+        } else if genotype.contains(&b'/') {
+            JsonGenotype::Unphased(
+                genotype.split_str("/").map(allele).collect(),
+            )
+        } else {
+            JsonGenotype::Haploid(allele(genotype))
+        }
+    }
+}
+
+/// Serialize the genotypes as a JSON object, keeping the order of the
+/// samples.
+fn serialize_genotypes<S: Serializer>(
+    genotypes: &[(String, JsonGenotype)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let mut map = serializer.serialize_map(Some(genotypes.len()))?;
+    for (sample, genotype) in genotypes {
+        map.serialize_entry(sample, genotype)?;
+    }
+    map.end()
+}
+
+impl JsonRecord {
+    /// Convert a VCF record, using `sample_names` for the genotype
+    /// columns.
+    pub fn from_vcf_record<S: AsRef<[u8]>>(
+        record: &VCFRecord,
+        sample_names: &[S],
+    ) -> Self {
+        let to_string = |bytes: &[u8]| bytes.to_str_lossy().into_owned();
+
+        let alts = record
+            .alternate
+            .iter()
+            .flat_map(|alts| alts.split_str(","))
+            .map(to_string)
+            .collect();
+
+        let info_values = |key: &str| -> Vec<&[u8]> {
+            let prefix = format!("{}=", key);
+            record
+                .info
+                .iter()
+                .flat_map(|info| info.split_str(";"))
+                .filter_map(|entry| entry.strip_prefix(prefix.as_bytes()))
+                .collect()
+        };
+
+        let types = info_values("TYPE").into_iter().map(to_string).collect();
+
+        let ultrabubble = record.ultrabubble();
+
+        let genotypes = sample_names
+            .iter()
+            .zip(record.samples.iter().flat_map(|s| s.split_str("\t")))
+            .map(|(name, sample)| {
+                // The genotype is the first of the FORMAT fields
+                let genotype = sample.split_str(":").next().unwrap_or_default();
+                (to_string(name.as_ref()), JsonGenotype::parse(genotype))
+            })
+            .collect();
+
+        JsonRecord {
+            chrom: to_string(&record.chromosome),
+            pos: record.position,
+            id: record.id.as_ref().map(|id| to_string(id)),
+            reference: to_string(&record.reference),
+            alts,
+            filter: record.filter.as_ref().map(|f| to_string(f)),
+            types,
+            ultrabubble,
+            genotypes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_from_vcf_record() {
+        let record = VCFRecord {
+            chromosome: "ref".into(),
+            position: 5,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("C,AT".into()),
+            quality: None,
+            filter: Some("PASS".into()),
+            info: Some(
+                "TYPE=snv;TYPE=ins;UB=1,4;AC=1,1;AN=3;AF=0.333333".into(),
+            ),
+            format: Some("GT".into()),
+            samples: Some("0\t1\t2\t.".into()),
+        };
+
+        let json =
+            JsonRecord::from_vcf_record(&record, &["ref", "a", "b", "c"]);
+
+        assert_eq!(vec!["snv", "ins"], json.types);
+        assert_eq!(Some((1, 4)), json.ultrabubble);
+        assert_eq!(
+            r#"{"chrom":"ref","pos":5,"ref":"A","alts":["C","AT"],"filter":"PASS","types":["snv","ins"],"ultrabubble":[1,4],"genotypes":{"ref":0,"a":1,"b":2,"c":null}}"#,
+            serde_json::to_string(&json).unwrap()
+        );
+    }
+
+    #[test]
+    fn json_phased_genotypes() {
+        let record = VCFRecord {
+            chromosome: "ref".into(),
+            position: 5,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("C".into()),
+            quality: None,
+            filter: Some("PASS".into()),
+            info: Some("TYPE=snv;UB=1,4;AC=2;AN=4;AF=0.5".into()),
+            format: Some("GT".into()),
+            samples: Some("0|1\t1|.\t0".into()),
+        };
+
+        let json = JsonRecord::from_vcf_record(&record, &["a", "b", "ref"]);
+        assert_eq!(
+            r#"{"chrom":"ref","pos":5,"ref":"A","alts":["C"],"filter":"PASS","types":["snv"],"ultrabubble":[1,4],"genotypes":{"a":[0,1],"b":[1,null],"ref":0}}"#,
+            serde_json::to_string(&json).unwrap()
+        );
+        assert_eq!(
+            JsonGenotype::Unphased(vec![Some(1), None]),
+            JsonGenotype::parse(b"1/.")
+        );
+    }
+}
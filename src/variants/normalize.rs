@@ -0,0 +1,217 @@
+//! Normalization of VCF records, so that each variant has a single
+//! representation: alleles are trimmed of any bases they all share,
+//! and indels are shifted as far left on the reference as possible,
+//! as done by `bcftools norm` and `vt normalize`.
+
+use bstr::{BString, ByteSlice};
+use fnv::FnvHashMap;
+
+#[allow(unused_imports)]
+use log::{debug, info, warn};
+
+use super::{vcf::VCFRecord, PathData};
+
+/// Normalize the alleles `ref_allele` and `alt_alleles` at the 1-based
+/// position `pos` on `ref_seq`, returning the new position and
+/// alleles. Returns `None` if the reference allele doesn't match
+/// the reference sequence at that position.
+pub fn normalize_alleles(
+    ref_seq: &[u8],
+    pos: usize,
+    ref_allele: &[u8],
+    alt_alleles: &[&[u8]],
+) -> Option<(usize, Vec<Vec<u8>>)> {
+    if pos == 0
+        || ref_seq.get(pos - 1..pos - 1 + ref_allele.len())? != ref_allele
+    {
+        return None;
+    }
+
+    let mut pos = pos;
+    let mut alleles: Vec<Vec<u8>> = std::iter::once(ref_allele)
+        .chain(alt_alleles.iter().copied())
+        .map(|a| a.to_vec())
+        .collect();
+
+    // Trim the shared last base while extending the alleles to the
+    // left when one of them runs out, which shifts indels left. At
+    // the start of the reference, the alleles can't be extended, so
+    // they can't be trimmed to nothing either
+    loop {
+        let mut changed = false;
+
+        let last = alleles[0].last().copied();
+        let can_trim = pos > 1 || alleles.iter().all(|a| a.len() >= 2);
+        if can_trim
+            && last.is_some()
+            && alleles.iter().all(|a| a.last().copied() == last)
+        {
+            alleles.iter_mut().for_each(|a| {
+                a.pop();
+            });
+            changed = true;
+        }
+
+        if alleles.iter().any(|a| a.is_empty()) {
+            if pos == 1 {
+                break;
+            }
+            pos -= 1;
+            let base = ref_seq[pos - 1];
+            alleles.iter_mut().for_each(|a| a.insert(0, base));
+            changed = true;
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    // Trim the shared first bases, keeping at least one base in each
+    // allele
+    while alleles.iter().all(|a| a.len() >= 2)
+        && alleles.iter().all(|a| a[0] == alleles[0][0])
+    {
+        alleles.iter_mut().for_each(|a| {
+            a.remove(0);
+        });
+        pos += 1;
+    }
+
+    Some((pos, alleles))
+}
+
+/// Normalize a VCF record against the sequence of its reference
+/// path. Records whose REF doesn't match the reference sequence, or
+/// that have symbolic alleles, are left unchanged.
+pub fn normalize_record(record: &mut VCFRecord, ref_seq: &[u8]) {
+    let alternate = match record.alternate.as_ref() {
+        Some(alt) => alt,
+        None => return,
+    };
+    let alt_alleles: Vec<&[u8]> = alternate.split_str(",").collect();
+
+    // Symbolic alleles have no sequence to align
+    if alt_alleles.iter().any(|alt| alt.starts_with(b"<")) {
+        return;
+    }
+
+    let normalized = normalize_alleles(
+        ref_seq,
+        record.position as usize,
+        &record.reference,
+        &alt_alleles,
+    );
+
+    match normalized {
+        Some((pos, mut alleles)) => {
+            let alts = alleles.split_off(1);
+            record.position = pos as i64;
+            record.reference = alleles.pop().unwrap().into();
+            record.alternate = Some(bstr::join(",", alts).into());
+        }
+        None => {
+            debug!(
+                "Not normalizing record at {}:{}, REF doesn't match the path",
+                record.chromosome, record.position
+            );
+        }
+    }
+}
+
+/// Normalize all the records against their reference paths. The
+/// sequence of a path is the concatenation of its segments'
+/// sequences, reverse complemented on backward steps, matching the
+/// coordinates and alleles of the variant records.
+pub fn normalize_records(path_data: &PathData, records: &mut [VCFRecord]) {
+    let path_indices: FnvHashMap<&BString, usize> = path_data
+        .path_names
+        .iter()
+        .enumerate()
+        .map(|(ix, name)| (name, ix))
+        .collect();
+
+    let mut ref_seqs: FnvHashMap<BString, BString> = FnvHashMap::default();
+
+    for record in records.iter_mut() {
+        if !ref_seqs.contains_key(&record.chromosome) {
+            let path_ix = match path_indices.get(&record.chromosome) {
+                Some(&ix) => ix,
+                None => continue,
+            };
+            let seq = path_data.path_sequence(path_ix);
+            ref_seqs.insert(record.chromosome.clone(), seq);
+        }
+        let ref_seq = ref_seqs.get(&record.chromosome).unwrap();
+        normalize_record(record, ref_seq);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(
+        ref_seq: &str,
+        pos: usize,
+        ref_allele: &str,
+        alts: &[&str],
+    ) -> Option<(usize, Vec<String>)> {
+        let alts: Vec<&[u8]> = alts.iter().map(|a| a.as_bytes()).collect();
+        let (pos, alleles) = normalize_alleles(
+            ref_seq.as_bytes(),
+            pos,
+            ref_allele.as_bytes(),
+            &alts,
+        )?;
+        let alleles = alleles
+            .into_iter()
+            .map(|a| String::from_utf8(a).unwrap())
+            .collect();
+        Some((pos, alleles))
+    }
+
+    #[test]
+    fn left_align_repeat_insertion() {
+        // CAGAGAGT, with an extra AG inserted after position 5
+        let result = normalize("CAGAGAGT", 5, "G", &["GAG"]);
+        assert_eq!(Some((1, vec!["C".into(), "CAG".into()])), result);
+    }
+
+    #[test]
+    fn left_align_repeat_deletion() {
+        // GCACACAT, with the last CA deleted
+        let result = normalize("GCACACAT", 5, "ACA", &["A"]);
+        assert_eq!(Some((1, vec!["GCA".into(), "G".into()])), result);
+    }
+
+    #[test]
+    fn trim_shared_bases() {
+        assert_eq!(
+            Some((3, vec!["G".into(), "C".into()])),
+            normalize("ACGTT", 1, "ACGT", &["ACCT"])
+        );
+        assert_eq!(
+            Some((2, vec!["C".into(), "T".into()])),
+            normalize("ACGTT", 2, "C", &["T"])
+        );
+        assert_eq!(
+            Some((2, vec!["CG".into(), "C".into(), "CGG".into()])),
+            normalize("ACGTT", 1, "ACG", &["AC", "ACGG"])
+        );
+    }
+
+    #[test]
+    fn indel_at_start_of_reference() {
+        assert_eq!(
+            Some((1, vec!["AA".into(), "A".into()])),
+            normalize("AAC", 2, "AC", &["C"])
+        );
+    }
+
+    #[test]
+    fn mismatched_reference() {
+        assert_eq!(None, normalize("ACGTT", 2, "G", &["T"]));
+        assert_eq!(None, normalize("ACGTT", 5, "TT", &["T"]));
+    }
+}
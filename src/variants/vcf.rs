@@ -1,4 +1,5 @@
-use bstr::BString;
+use bstr::{BString, ByteSlice};
+use fnv::FnvHashMap;
 use std::{
     fmt,
     fmt::{Display, Formatter},
@@ -7,6 +8,12 @@ use std::{
 
 use chrono::prelude::*;
 
+/// FILTER value for records that passed all filters.
+pub const FILTER_PASS: &str = "PASS";
+
+/// FILTER value for records with more alternate alleles than allowed.
+pub const FILTER_COMPLEX: &str = "COMPLEX";
+
 /// A struct that holds Variants, as defined in the VCF format
 #[derive(Debug, PartialEq)]
 pub struct VCFRecord {
@@ -19,7 +26,8 @@ pub struct VCFRecord {
     pub filter: Option<BString>,
     pub info: Option<BString>,
     pub format: Option<BString>,
-    pub sample_name: Option<BString>,
+    /// The tab-separated sample columns, matching `format`.
+    pub samples: Option<BString>,
 }
 
 impl VCFRecord {
@@ -34,6 +42,886 @@ impl VCFRecord {
     }
 }
 
+/// Merge two `;`-separated INFO fields, keeping the entries of `a`
+/// and adding those of `b` whose keys aren't already in it.
+fn merge_info(a: &BString, b: &BString) -> BString {
+    fn key(entry: &[u8]) -> &[u8] {
+        entry.split_str("=").next().unwrap_or_default()
+    }
+    let mut entries: Vec<&[u8]> = a.split_str(";").collect();
+    for entry in b.split_str(";") {
+        if !entries.iter().any(|e| key(e) == key(entry)) {
+            entries.push(entry);
+        }
+    }
+    bstr::join(";", entries).into()
+}
+
+/// Merge two tab-separated sample columns, taking the genotypes of
+/// `b` for the samples that are missing (`.`) in `a`. Genotypes with
+/// the same number of haplotypes are merged one haplotype at a time.
+fn merge_samples(a: &BString, b: &BString) -> BString {
+    fn pick<'a>(x: &'a [u8], y: &'a [u8]) -> &'a [u8] {
+        if x == b"." {
+            y
+        } else {
+            x
+        }
+    }
+    let is_sep = |c: &u8| *c == b'|' || *c == b'/';
+    let samples = a.split_str("\t").zip(b.split_str("\t")).map(|(x, y)| {
+        let xs: Vec<&[u8]> = x.split(is_sep).collect();
+        let ys: Vec<&[u8]> = y.split(is_sep).collect();
+        if xs.len() > 1 && xs.len() == ys.len() {
+            let sep = if x.contains(&b'/') { "/" } else { "|" };
+            let alleles = xs.into_iter().zip(ys).map(|(x, y)| pick(x, y));
+            bstr::join(sep, alleles)
+        } else {
+            pick(x, y).to_vec()
+        }
+    });
+    bstr::join("\t", samples).into()
+}
+
+/// Recode a genotype for the record of a single ALT allele, split
+/// from a multiallelic record: `allele` becomes `1`, the REF stays
+/// `0`, and any other allele becomes `.`.
+fn recode_genotype(genotype: &[u8], allele: &[u8]) -> Vec<u8> {
+    fn recode(a: &[u8], allele: &[u8]) -> &'static [u8] {
+        if a == allele {
+            b"1"
+        } else if a == b"0" {
+            b"0"
+        } else {
+            b"."
+        }
+    }
+
+    let mut recoded = Vec::with_capacity(genotype.len());
+    let mut start = 0;
+    for (ix, &c) in genotype.iter().enumerate() {
+        if c == b'|' || c == b'/' {
+            recoded.extend_from_slice(recode(&genotype[start..ix], allele));
+            recoded.push(c);
+            start = ix + 1;
+        }
+    }
+    recoded.extend_from_slice(recode(&genotype[start..], allele));
+    recoded
+}
+
+/// Recode a genotype after some of the ALT alleles of its record were
+/// dropped: the allele at index `i` becomes `alleles[i]`, or `.` if
+/// that's `None`, as do alleles that are out of range. Missing
+/// alleles stay missing.
+fn remap_genotype(genotype: &[u8], alleles: &[Option<usize>]) -> Vec<u8> {
+    let remap = |a: &[u8]| -> Vec<u8> {
+        let allele = a
+            .to_str()
+            .ok()
+            .and_then(|a| a.parse::<usize>().ok())
+            .and_then(|a| *alleles.get(a)?);
+        match allele {
+            Some(allele) => allele.to_string().into_bytes(),
+            None => b".".to_vec(),
+        }
+    };
+
+    let mut recoded = Vec::with_capacity(genotype.len());
+    let mut start = 0;
+    for (ix, &c) in genotype.iter().enumerate() {
+        if c == b'|' || c == b'/' {
+            recoded.extend(remap(&genotype[start..ix]));
+            recoded.push(c);
+            start = ix + 1;
+        }
+    }
+    recoded.extend(remap(&genotype[start..]));
+    recoded
+}
+
+/// Groups the genotype columns of haplotype paths into one column per
+/// sample, e.g. to write the two haplotypes of a diploid sample as a
+/// phased genotype like `0|1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SampleGroups {
+    names: Vec<BString>,
+    haplotypes: Vec<Vec<usize>>,
+    phased: bool,
+}
+
+impl SampleGroups {
+    /// Create the groups from the sample names and, for each sample,
+    /// the indices of its haplotype paths, in phase order.
+    pub fn new(names: Vec<BString>, haplotypes: Vec<Vec<usize>>) -> Self {
+        assert_eq!(names.len(), haplotypes.len());
+        Self {
+            names,
+            haplotypes,
+            phased: true,
+        }
+    }
+
+    /// Join the genotypes of the haplotypes with `/` instead of `|`,
+    /// marking them as unphased.
+    pub fn unphased(mut self) -> Self {
+        self.phased = false;
+        self
+    }
+
+    pub fn sample_names(&self) -> &[BString] {
+        &self.names
+    }
+
+    pub fn haplotypes(&self) -> &[Vec<usize>] {
+        &self.haplotypes
+    }
+
+    /// Replace the per-path genotype columns of `record` with one
+    /// column per sample, joining the genotypes of its haplotypes
+    /// with `|`, or `/` if unphased. Paths without a column get the
+    /// `.` genotype.
+    pub fn group_genotypes(&self, record: &mut VCFRecord) {
+        let samples = match record.samples.as_ref() {
+            Some(samples) => samples,
+            None => return,
+        };
+        let sep = if self.phased { "|" } else { "/" };
+        let genotypes: Vec<&[u8]> = samples.split_str("\t").collect();
+        let grouped = self.haplotypes.iter().map(|paths| {
+            let alleles = paths
+                .iter()
+                .map(|&ix| genotypes.get(ix).copied().unwrap_or(b"."));
+            bstr::join(sep, alleles)
+        });
+        record.samples = Some(bstr::join("\t", grouped).into());
+        // The allele counts stay the same, but NS now counts samples
+        // rather than paths
+        record.update_allele_counts();
+    }
+}
+
+/// The `SVTYPE` of a record with the given ALT alleles: the type of
+/// its symbolic alleles, if they all have the same one. `SVTYPE` has
+/// a single value per record, so it's missing, `.`, for records that
+/// mix `<DEL>` and `<INS>` alleles.
+pub fn sv_type<'a>(
+    alts: impl IntoIterator<Item = &'a [u8]>,
+) -> Option<&'static str> {
+    let mut sv_type = None;
+    for alt in alts {
+        let alt_type = match alt {
+            b"<DEL>" => "DEL",
+            b"<INS>" => "INS",
+            _ => continue,
+        };
+        if sv_type.is_some_and(|t| t != alt_type) {
+            return None;
+        }
+        sv_type = Some(alt_type);
+    }
+    sv_type
+}
+
+/// Format an allele frequency with at most six decimals, without
+/// trailing zeros.
+fn format_frequency(count: usize, total: usize) -> String {
+    if total == 0 {
+        return ".".into();
+    }
+    let freq = format!("{:.6}", count as f64 / total as f64);
+    freq.trim_end_matches('0').trim_end_matches('.').into()
+}
+
+impl VCFRecord {
+    /// Set the AC, AN, AF, and NS INFO fields from the genotypes in the
+    /// sample columns, replacing any previous values. AC and AF have
+    /// one value for each ALT allele, in the same order, and are left
+    /// out of records without ALT alleles.
+    pub fn update_allele_counts(&mut self) {
+        let alt_count = self.alt_count();
+        let (allele_counts, allele_number) = self.allele_counts();
+        let sample_number = self.called_samples();
+
+        let counts = allele_counts.iter().map(|c| c.to_string());
+        let freqs = allele_counts
+            .iter()
+            .map(|&c| format_frequency(c, allele_number));
+        let allele_info = if alt_count == 0 {
+            format!("AN={};NS={}", allele_number, sample_number)
+        } else {
+            format!(
+                "AC={};AN={};AF={};NS={}",
+                counts.collect::<Vec<_>>().join(","),
+                allele_number,
+                freqs.collect::<Vec<_>>().join(","),
+                sample_number
+            )
+        };
+
+        let mut entries: Vec<&[u8]> = self
+            .info
+            .iter()
+            .flat_map(|info| info.split_str(";"))
+            .filter(|entry| {
+                let key = entry.split_str("=").next().unwrap_or_default();
+                !entry.is_empty()
+                    && !matches!(key, b"AC" | b"AN" | b"AF" | b"NS")
+            })
+            .collect();
+        entries.push(allele_info.as_bytes());
+        self.info = Some(bstr::join(";", entries).into());
+    }
+
+    /// The number of called alleles of each ALT in the genotypes, i.e.
+    /// the AC values, and of all called alleles, the AN value.
+    fn allele_counts(&self) -> (Vec<usize>, usize) {
+        let mut allele_counts = vec![0usize; self.alt_count()];
+        let mut allele_number = 0;
+
+        let genotypes = self.samples.iter().flat_map(|s| s.split_str("\t"));
+        for genotype in genotypes {
+            let alleles = genotype
+                .split(|&c| c == b'/' || c == b'|')
+                .filter_map(|a| a.to_str().ok()?.parse::<usize>().ok());
+            for allele in alleles {
+                allele_number += 1;
+                if let Some(count) =
+                    allele_counts.get_mut(allele.wrapping_sub(1))
+                {
+                    *count += 1;
+                }
+            }
+        }
+
+        (allele_counts, allele_number)
+    }
+
+    /// The number of sample columns with at least one called allele,
+    /// i.e. the NS value. Paths that don't traverse the bubble have a
+    /// `.` genotype, so they aren't counted.
+    fn called_samples(&self) -> usize {
+        self.samples
+            .iter()
+            .flat_map(|s| s.split_str("\t"))
+            .filter(|genotype| {
+                genotype.split(|&c| c == b'/' || c == b'|').any(|a| {
+                    a.to_str()
+                        .ok()
+                        .and_then(|a| a.parse::<usize>().ok())
+                        .is_some()
+                })
+            })
+            .count()
+    }
+
+    /// Drop the ALT alleles whose frequency in the genotypes, i.e. AC
+    /// divided by AN, is below `min_af`. The genotypes of the dropped
+    /// alleles become `.`, the remaining alleles are renumbered, and
+    /// the per-allele INFO fields and allele counts are updated to
+    /// match, with the `SVTYPE`, `SVLEN`, and `END` fields dropped if
+    /// no symbolic ALT remains. The `COMPLEX` filter no longer applies
+    /// if at most `max_alleles` ALTs remain. Records without ALT
+    /// alleles are left alone. Returns false if every ALT was dropped,
+    /// in which case the record should be dropped too.
+    pub fn drop_rare_alleles(
+        &mut self,
+        min_af: f64,
+        max_alleles: usize,
+    ) -> bool {
+        let alt_count = self.alt_count();
+        if alt_count == 0 {
+            return true;
+        }
+
+        let (allele_counts, allele_number) = self.allele_counts();
+        let keep: Vec<bool> = allele_counts
+            .iter()
+            .map(|&count| {
+                allele_number > 0
+                    && count as f64 / allele_number as f64 >= min_af
+            })
+            .collect();
+        if keep.iter().all(|&keep| keep) {
+            return true;
+        }
+        if !keep.iter().any(|&keep| keep) {
+            return false;
+        }
+
+        // The new index of each allele, with the REF first
+        let mut alleles: Vec<Option<usize>> = vec![Some(0)];
+        let mut next = 1;
+        for &keep in keep.iter() {
+            if keep {
+                alleles.push(Some(next));
+                next += 1;
+            } else {
+                alleles.push(None);
+            }
+        }
+
+        let kept = |values: &[u8]| -> Vec<u8> {
+            let values = values
+                .split_str(",")
+                .zip(keep.iter())
+                .filter(|(_, &keep)| keep)
+                .map(|(value, _)| value);
+            bstr::join(",", values)
+        };
+
+        self.alternate = self.alternate.as_ref().map(|alts| kept(alts).into());
+        if next - 1 <= max_alleles
+            && self.filter.as_ref().is_some_and(|f| f == FILTER_COMPLEX)
+        {
+            self.filter = Some(FILTER_PASS.into());
+        }
+
+        let had_inversion = self.info.as_ref().is_some_and(|info| {
+            info.split_str(";").any(|entry| entry == b"INV")
+        });
+        // The SV fields only apply while a symbolic ALT remains
+        let alts: Vec<&[u8]> = self
+            .alternate
+            .iter()
+            .flat_map(|alts| alts.split_str(","))
+            .collect();
+        let symbolic = alts.iter().any(|alt| alt.starts_with(b"<"));
+        let mut type_ix = 0;
+        let info: Vec<Vec<u8>> = self
+            .info
+            .iter()
+            .flat_map(|info| info.split_str(";"))
+            .filter_map(|entry| {
+                let key = entry.split_str("=").next()?;
+                match key {
+                    b"TYPE" => {
+                        type_ix += 1;
+                        Some(entry.to_vec())
+                            .filter(|_| keep.get(type_ix - 1) == Some(&true))
+                    }
+                    b"SVLEN" if symbolic => Some(
+                        [key, b"=", &kept(&entry[key.len() + 1..])].concat(),
+                    ),
+                    b"SVTYPE" if symbolic => {
+                        let sv_type = sv_type(alts.iter().copied());
+                        Some(
+                            format!("SVTYPE={}", sv_type.unwrap_or("."))
+                                .into_bytes(),
+                        )
+                    }
+                    b"SVLEN" | b"SVTYPE" | b"END" if !symbolic => None,
+                    b"INV" => None,
+                    _ => Some(entry.to_vec()),
+                }
+            })
+            .collect();
+        self.info = Some(bstr::join(";", info).into());
+
+        self.samples = self.samples.as_ref().map(|samples| {
+            let genotypes = samples
+                .split_str("\t")
+                .map(|genotype| remap_genotype(genotype, &alleles));
+            bstr::join("\t", genotypes).into()
+        });
+
+        self.update_allele_counts();
+        if had_inversion {
+            self.annotate_inversion();
+        }
+        true
+    }
+
+    /// Add the `INV` INFO flag if any ALT allele is the reverse
+    /// complement of the REF, as for an inverted segment in a bubble.
+    /// Single-base alleles are left alone, as every A/T and C/G SNV
+    /// would otherwise be flagged. Returns whether the flag was added.
+    pub fn annotate_inversion(&mut self) -> bool {
+        if self.reference.len() < 2 {
+            return false;
+        }
+        let rev_comp = crate::util::reverse_complement(&self.reference);
+        let inverted = self.alternate.as_ref().is_some_and(|alts| {
+            alts.split_str(",").any(|alt| alt == rev_comp.as_slice())
+        });
+        if !inverted {
+            return false;
+        }
+
+        let has_flag = self
+            .info
+            .iter()
+            .flat_map(|info| info.split_str(";"))
+            .any(|entry| entry == b"INV");
+        if !has_flag {
+            let info = self.info.get_or_insert_with(BString::default);
+            if !info.is_empty() {
+                info.push(b';');
+            }
+            info.extend_from_slice(b"INV");
+        }
+        true
+    }
+
+    /// Add the number of paths that take each allele, the REF first,
+    /// as the `AD` INFO field, and each sample's count of each allele
+    /// as the `AD` FORMAT field, after the genotype. Samples are single
+    /// paths unless they were grouped, so their counts are mostly 0
+    /// and 1. Samples with no called alleles get a missing `.` value.
+    pub fn add_allele_depths(&mut self) {
+        let allele_count = self.alt_count() + 1;
+        let mut total = vec![0usize; allele_count];
+
+        let samples = self.samples.as_ref().map(|samples| {
+            let columns = samples.split_str("\t").map(|sample| {
+                let mut depths = vec![0usize; allele_count];
+                let genotype = sample.split_str(":").next().unwrap_or_default();
+                let alleles = genotype
+                    .split(|&c| c == b'/' || c == b'|')
+                    .filter_map(|a| a.to_str().ok()?.parse::<usize>().ok());
+                let mut called = false;
+                for allele in alleles {
+                    if let Some(depth) = depths.get_mut(allele) {
+                        *depth += 1;
+                        total[allele] += 1;
+                        called = true;
+                    }
+                }
+
+                let depths = if called {
+                    let depths: Vec<String> =
+                        depths.iter().map(|d| d.to_string()).collect();
+                    depths.join(",")
+                } else {
+                    ".".to_string()
+                };
+                [sample, b":", depths.as_bytes()].concat()
+            });
+            bstr::join("\t", columns).into()
+        });
+        if samples.is_none() {
+            return;
+        }
+        self.samples = samples;
+        if let Some(format) = self.format.as_mut() {
+            format.extend_from_slice(b":AD");
+        }
+
+        let total: Vec<String> = total.iter().map(|d| d.to_string()).collect();
+        let info = self.info.get_or_insert_with(BString::default);
+        if !info.is_empty() {
+            info.push(b';');
+        }
+        info.extend(format!("AD={}", total.join(",")).bytes());
+    }
+
+    /// The segment IDs of the ends of the ultrabubble the record is
+    /// from, as given in the `UB` INFO field.
+    pub fn ultrabubble(&self) -> Option<(u64, u64)> {
+        let ub = self
+            .info
+            .iter()
+            .flat_map(|info| info.split_str(";"))
+            .find_map(|entry| entry.strip_prefix(b"UB="))?;
+        let mut ends = ub.to_str().ok()?.split(',');
+        let from = ends.next()?.parse().ok()?;
+        let to = ends.next()?.parse().ok()?;
+        Some((from, to))
+    }
+
+    /// The number of ALT alleles.
+    pub fn alt_count(&self) -> usize {
+        self.alternate
+            .as_ref()
+            .map_or(0, |alts| alts.split_str(",").count())
+    }
+
+    /// Split a record with several ALT alleles into one record per
+    /// ALT, in the same order. In the record for each ALT, genotypes
+    /// of that allele become `1`, and those of the other ALTs become
+    /// `.`, as the path carries neither the REF nor the ALT. The
+    /// per-allele INFO fields are split too, the allele counts are
+    /// recomputed, and the `COMPLEX` filter no longer applies. The
+    /// records of ALTs that aren't symbolic lose the `SVTYPE`, `SVLEN`,
+    /// and `END` fields. Records with at most one ALT are returned as
+    /// they are.
+    pub fn split_multiallelic(self) -> Vec<VCFRecord> {
+        let alt_count = self.alt_count();
+        if alt_count < 2 {
+            return vec![self];
+        }
+
+        let alts: Vec<&[u8]> = self
+            .alternate
+            .iter()
+            .flat_map(|alts| alts.split_str(","))
+            .collect();
+        let info_entries: Vec<&[u8]> = self
+            .info
+            .iter()
+            .flat_map(|info| info.split_str(";"))
+            .collect();
+
+        (0..alt_count)
+            .map(|alt_ix| {
+                let allele = (alt_ix + 1).to_string().into_bytes();
+
+                let symbolic = alts[alt_ix].starts_with(b"<");
+                let mut type_ix = 0;
+                let info: Vec<Vec<u8>> = info_entries
+                    .iter()
+                    .filter_map(|&entry| {
+                        let key = entry.split_str("=").next()?;
+                        match key {
+                            b"TYPE" => {
+                                type_ix += 1;
+                                Some(entry.to_vec())
+                                    .filter(|_| type_ix == alt_ix + 1)
+                            }
+                            b"SVTYPE" if symbolic => {
+                                let sv_type = sv_type(Some(alts[alt_ix]))?;
+                                Some(format!("SVTYPE={}", sv_type).into_bytes())
+                            }
+                            b"SVLEN" if symbolic => {
+                                let value = entry[key.len() + 1..]
+                                    .split_str(",")
+                                    .nth(alt_ix)?;
+                                Some([key, b"=", value].concat())
+                            }
+                            // The SV fields don't apply to the records of
+                            // the ALTs that aren't symbolic
+                            b"SVTYPE" | b"SVLEN" | b"END" if !symbolic => None,
+                            b"AC" | b"AN" | b"AF" | b"NS" | b"INV" => None,
+                            _ => Some(entry.to_vec()),
+                        }
+                    })
+                    .collect();
+
+                let samples = self.samples.as_ref().map(|samples| {
+                    let genotypes = samples
+                        .split_str("\t")
+                        .map(|genotype| recode_genotype(genotype, &allele));
+                    bstr::join("\t", genotypes).into()
+                });
+
+                let filter = match self.filter.as_ref() {
+                    Some(filter) if filter == FILTER_COMPLEX => {
+                        Some(FILTER_PASS.into())
+                    }
+                    filter => filter.cloned(),
+                };
+
+                let mut record = VCFRecord {
+                    chromosome: self.chromosome.clone(),
+                    position: self.position,
+                    id: self.id.clone(),
+                    reference: self.reference.clone(),
+                    alternate: Some(alts[alt_ix].into()),
+                    quality: self.quality,
+                    filter,
+                    info: Some(bstr::join(";", info).into()),
+                    format: self.format.clone(),
+                    samples,
+                };
+                record.update_allele_counts();
+                if info_entries.contains(&&b"INV"[..]) {
+                    record.annotate_inversion();
+                }
+                record
+            })
+            .collect()
+    }
+
+    /// Decompose a record whose REF and ALT alleles all have the same
+    /// length, such as an MNP, into one SNV record for each base where
+    /// any ALT differs from the REF, like `bcftools norm --atomize`.
+    /// The ALTs of each new record are the distinct bases at that
+    /// position, and each genotype becomes the index of the base its
+    /// allele has there, or `0` where it matches the REF. Records with
+    /// indels or symbolic alleles, and single-base records, are
+    /// returned as they are.
+    pub fn atomize(self) -> Vec<VCFRecord> {
+        let alts: Vec<&[u8]> = self
+            .alternate
+            .iter()
+            .flat_map(|alts| alts.split_str(","))
+            .collect();
+        let ref_len = self.reference.len();
+        if ref_len < 2
+            || alts.is_empty()
+            || alts
+                .iter()
+                .any(|alt| alt.len() != ref_len || alt[0] == b'<')
+        {
+            return vec![self];
+        }
+
+        let info_entries: Vec<&[u8]> = self
+            .info
+            .iter()
+            .flat_map(|info| info.split_str(";"))
+            .filter(|entry| {
+                let key = entry.split_str("=").next().unwrap_or_default();
+                !entry.is_empty()
+                    && !matches!(
+                        key,
+                        b"TYPE"
+                            | b"SVTYPE"
+                            | b"SVLEN"
+                            | b"AC"
+                            | b"AN"
+                            | b"AF"
+                            | b"NS"
+                            | b"INV"
+                    )
+            })
+            .collect();
+
+        (0..ref_len)
+            .filter_map(|ix| {
+                let ref_base = self.reference[ix];
+
+                // The new allele index of each of the old ALTs
+                let mut bases: Vec<u8> = Vec::new();
+                let alleles: Vec<usize> = alts
+                    .iter()
+                    .map(|alt| {
+                        if alt[ix] == ref_base {
+                            return 0;
+                        }
+                        match bases.iter().position(|&b| b == alt[ix]) {
+                            Some(base_ix) => base_ix + 1,
+                            None => {
+                                bases.push(alt[ix]);
+                                bases.len()
+                            }
+                        }
+                    })
+                    .collect();
+                if bases.is_empty() {
+                    return None;
+                }
+
+                let recode = |allele: &[u8]| -> Vec<u8> {
+                    let old = allele
+                        .to_str()
+                        .ok()
+                        .and_then(|a| a.parse::<usize>().ok()?.checked_sub(1));
+                    match old.and_then(|old| alleles.get(old)) {
+                        Some(new) => new.to_string().into_bytes(),
+                        None => allele.to_vec(),
+                    }
+                };
+                let samples = self.samples.as_ref().map(|samples| {
+                    let genotypes = samples.split_str("\t").map(|genotype| {
+                        let mut recoded = Vec::with_capacity(genotype.len());
+                        let mut start = 0;
+                        for (ix, &c) in genotype.iter().enumerate() {
+                            if c == b'|' || c == b'/' {
+                                recoded.extend(recode(&genotype[start..ix]));
+                                recoded.push(c);
+                                start = ix + 1;
+                            }
+                        }
+                        recoded.extend(recode(&genotype[start..]));
+                        recoded
+                    });
+                    bstr::join("\t", genotypes).into()
+                });
+
+                let types = vec![&b"TYPE=snv"[..]; bases.len()];
+                let info =
+                    types.into_iter().chain(info_entries.iter().copied());
+
+                let mut record = VCFRecord {
+                    chromosome: self.chromosome.clone(),
+                    position: self.position + ix as i64,
+                    id: self.id.clone(),
+                    reference: vec![ref_base].into(),
+                    alternate: Some(bstr::join(",", bases.chunks(1)).into()),
+                    quality: self.quality,
+                    filter: self.filter.clone(),
+                    info: Some(bstr::join(";", info).into()),
+                    format: self.format.clone(),
+                    samples,
+                };
+                record.update_allele_counts();
+                Some(record)
+            })
+            .collect()
+    }
+
+    fn is_same_site(&self, other: &VCFRecord) -> bool {
+        self.chromosome == other.chromosome
+            && self.position == other.position
+            && self.reference == other.reference
+            && self.alternate == other.alternate
+    }
+
+    /// Merge the INFO and sample columns of `other`, which must be a
+    /// record for the same site, into this one.
+    fn merge(&mut self, other: &VCFRecord) {
+        self.info = match (self.info.take(), other.info.as_ref()) {
+            (Some(a), Some(b)) => Some(merge_info(&a, b)),
+            (a, b) => a.or_else(|| b.cloned()),
+        };
+
+        if self.format == other.format {
+            if let (Some(a), Some(b)) = (&self.samples, &other.samples) {
+                self.samples = Some(merge_samples(a, b));
+                self.update_allele_counts();
+            }
+        }
+    }
+}
+
+/// Counts of VCF records by the type of variant, e.g. for a summary
+/// at the end of a run.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RecordCounts {
+    pub snv: usize,
+    pub insertion: usize,
+    pub deletion: usize,
+    pub complex: usize,
+    /// Records without ALT alleles, for bubbles with no variants.
+    pub reference: usize,
+}
+
+impl RecordCounts {
+    /// Count a record by the `TYPE` INFO values of its alleles.
+    /// Records whose alleles are all SNVs, all insertions, or all
+    /// deletions are counted as such, and all others, including MNPs
+    /// and clumped variants, as complex. Records without ALT alleles
+    /// are counted as reference records.
+    pub fn add(&mut self, record: &VCFRecord) {
+        if record.alternate.is_none() {
+            self.reference += 1;
+            return;
+        }
+
+        let mut types = record
+            .info
+            .iter()
+            .flat_map(|info| info.split_str(";"))
+            .filter_map(|entry| entry.strip_prefix(b"TYPE="));
+
+        let first = types.next();
+        let same_type = types.all(|t| Some(t) == first);
+
+        match first {
+            Some(b"snv") if same_type => self.snv += 1,
+            Some(b"ins") if same_type => self.insertion += 1,
+            Some(b"del") if same_type => self.deletion += 1,
+            _ => self.complex += 1,
+        }
+    }
+
+    pub fn total(&self) -> usize {
+        self.snv
+            + self.insertion
+            + self.deletion
+            + self.complex
+            + self.reference
+    }
+}
+
+/// Set the ID of each record to one made from the ends of its
+/// ultrabubble, like `ub_12_15`. If several records are from the same
+/// bubble, they get a suffix with their CHROM and POS, like
+/// `ub_12_15_chr1_105`, and if several of those also share them, with
+/// their ALT too, like `ub_12_15_chr1_105_C`, so the IDs are unique,
+/// and only depend on the records themselves rather than their order.
+/// Records without a `UB` INFO field are left alone.
+pub fn assign_bubble_ids(records: &mut [VCFRecord]) {
+    let mut bubble_counts: FnvHashMap<(u64, u64), usize> =
+        FnvHashMap::default();
+    // The bubble, CHROM, and POS of a record
+    type Site<'a> = ((u64, u64), &'a [u8], i64);
+    let mut site_counts: FnvHashMap<Site, usize> = FnvHashMap::default();
+    for record in records.iter() {
+        if let Some(bubble) = record.ultrabubble() {
+            *bubble_counts.entry(bubble).or_default() += 1;
+            let site = (bubble, record.chromosome.as_slice(), record.position);
+            *site_counts.entry(site).or_default() += 1;
+        }
+    }
+
+    let ids: Vec<Option<BString>> = records
+        .iter()
+        .map(|record| {
+            let (from, to) = record.ultrabubble()?;
+            let mut id = format!("ub_{}_{}", from, to);
+            if bubble_counts[&(from, to)] > 1 {
+                id.push_str(&format!(
+                    "_{}_{}",
+                    record.chromosome, record.position
+                ));
+                let site =
+                    ((from, to), record.chromosome.as_slice(), record.position);
+                if let (true, Some(alt)) =
+                    (site_counts[&site] > 1, &record.alternate)
+                {
+                    id.push_str(&format!("_{}", alt));
+                }
+            }
+            Some(id.into())
+        })
+        .collect();
+
+    for (record, id) in records.iter_mut().zip(ids) {
+        if id.is_some() {
+            record.id = id;
+        }
+    }
+}
+
+/// Sort the records into VCF order: grouped by CHROM in the order
+/// the contigs appear in `contigs`, i.e. the `##contig` lines of the
+/// header, then by POS. Records on contigs that aren't in the list
+/// come last, sorted by name.
+pub fn sort_records(records: &mut [VCFRecord], contigs: &[(BString, usize)]) {
+    use std::cmp::Ordering;
+
+    let contig_ranks: FnvHashMap<&BString, usize> = contigs
+        .iter()
+        .enumerate()
+        .map(|(ix, (name, _))| (name, ix))
+        .collect();
+
+    records.sort_by(|v0, v1| {
+        let rank0 = contig_ranks.get(&v0.chromosome);
+        let rank1 = contig_ranks.get(&v1.chromosome);
+        let contig_cmp = match (rank0, rank1) {
+            (Some(r0), Some(r1)) => r0.cmp(r1),
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (None, None) => v0.chromosome.cmp(&v1.chromosome),
+        };
+        contig_cmp
+            .then_with(|| v0.position.cmp(&v1.position))
+            .then_with(|| v0.reference.cmp(&v1.reference))
+            .then_with(|| v0.alternate.cmp(&v1.alternate))
+    });
+}
+
+/// Sort the records with `sort_records`, and collapse the records
+/// with identical CHROM, POS, REF, and ALT into one, merging their
+/// INFO fields and genotypes.
+pub fn merge_duplicate_records(
+    records: &mut Vec<VCFRecord>,
+    contigs: &[(BString, usize)],
+) {
+    sort_records(records, contigs);
+    records.dedup_by(|next, prev| {
+        if prev.is_same_site(next) {
+            prev.merge(next);
+            true
+        } else {
+            false
+        }
+    });
+}
+
 impl Display for VCFRecord {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         fn display_field<T: Display>(field: Option<T>) -> String {
@@ -53,23 +941,165 @@ impl Display for VCFRecord {
         write!(f, "{}\t", display_field(self.filter.as_ref()))?;
         write!(f, "{}", display_field(self.info.as_ref()))?;
         if let Some(format) = self.format.as_ref() {
-            if let Some(sample) = self.sample_name.as_ref() {
+            if let Some(samples) = self.samples.as_ref() {
                 write!(f, "\t{}", format)?;
-                write!(f, "\t{}", sample)?;
+                write!(f, "\t{}", samples)?;
             }
         }
         Ok(())
     }
 }
 
+impl VCFRecord {
+    /// Parse a record from a line of VCF text, such as one written by
+    /// its `Display` implementation, which this is the inverse of.
+    /// Missing (`.`) fields become `None`. Returns `None` if the line
+    /// has fewer than the eight required fields, or if POS or QUAL
+    /// isn't a number.
+    pub fn parse(line: &[u8]) -> Option<VCFRecord> {
+        let optional = |field: &[u8]| -> Option<BString> {
+            if field == b"." {
+                None
+            } else {
+                Some(field.into())
+            }
+        };
+
+        let mut fields = line.splitn_str(10, "\t");
+        let chromosome = fields.next()?.into();
+        let position = fields.next()?.to_str().ok()?.parse().ok()?;
+        let id = optional(fields.next()?);
+        let reference = fields.next()?.into();
+        let alternate = optional(fields.next()?);
+        let quality = match optional(fields.next()?) {
+            Some(quality) => Some(quality.to_str().ok()?.parse().ok()?),
+            None => None,
+        };
+        let filter = optional(fields.next()?);
+        let info = optional(fields.next()?);
+        let format = fields.next().map(BString::from);
+        let samples = fields.next().map(BString::from);
+
+        Some(VCFRecord {
+            chromosome,
+            position,
+            id,
+            reference,
+            alternate,
+            quality,
+            filter,
+            info,
+            format,
+            samples,
+        })
+    }
+}
+
+/// Quote a command line argument for the `##gfa2vcf_command` header
+/// line, so that it can be pasted into a shell, and so that the line
+/// stays a single line. Arguments with characters other than those
+/// that are safe in a shell are single-quoted, and control characters
+/// are written as escapes such as `\n`.
+fn quote_arg(arg: &str) -> String {
+    let is_safe =
+        |c: char| c.is_ascii_alphanumeric() || "_-./=:,+@%^".contains(c);
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return arg.into();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('\'');
+    for c in arg.chars() {
+        match c {
+            '\'' => quoted.push_str("'\\''"),
+            c if c.is_control() => quoted.extend(c.escape_default()),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    quoted
+}
+
+#[derive(Debug, Clone)]
 pub struct VCFHeader {
     reference: PathBuf,
+    command: Option<String>,
+    sample_names: Vec<BString>,
+    contigs: Vec<(BString, usize)>,
+    max_alleles: usize,
+    phased: bool,
+    allele_depth: bool,
 }
 
 impl VCFHeader {
-    pub fn new<T: AsRef<Path>>(path: T) -> Self {
+    /// Create a header for a VCF derived from the GFA at `path`, with
+    /// one genotype column for each of the `sample_names`, and a
+    /// `##contig` line for each of the reference paths in `contigs`,
+    /// given as name and length pairs. `max_alleles` is the threshold
+    /// used for the `COMPLEX` filter.
+    pub fn new<T: AsRef<Path>>(
+        path: T,
+        sample_names: Vec<BString>,
+        contigs: Vec<(BString, usize)>,
+        max_alleles: usize,
+    ) -> Self {
         let reference = path.as_ref().to_owned();
-        Self { reference }
+        Self {
+            reference,
+            command: None,
+            sample_names,
+            contigs,
+            max_alleles,
+            phased: true,
+            allele_depth: false,
+        }
+    }
+
+    /// Record the command line that produced the VCF, e.g. from
+    /// `std::env::args`, in a `##gfa2vcf_command` header line.
+    pub fn with_command<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let args: Vec<String> = args
+            .into_iter()
+            .map(|arg| quote_arg(arg.as_ref()))
+            .collect();
+        self.command = Some(args.join(" "));
+        self
+    }
+
+    /// Note in a `##phasing` header line that the genotypes are
+    /// unphased, rather than phased.
+    pub fn unphased(mut self) -> Self {
+        self.phased = false;
+        self
+    }
+
+    /// Declare the `AD` INFO and FORMAT fields, for records with
+    /// allele depths; see `VCFRecord::add_allele_depths`.
+    pub fn with_allele_depth(mut self) -> Self {
+        self.allele_depth = true;
+        self
+    }
+
+    /// Replace the `##contig` lines, such as for a VCF with the records
+    /// of only one of the reference paths.
+    pub fn with_contigs(mut self, contigs: Vec<(BString, usize)>) -> Self {
+        self.contigs = contigs;
+        self
+    }
+
+    /// The names of the genotype columns, in order.
+    pub fn sample_names(&self) -> &[BString] {
+        &self.sample_names
+    }
+
+    /// The reference paths and their lengths, as in the `##contig`
+    /// lines.
+    pub fn contigs(&self) -> &[(BString, usize)] {
+        &self.contigs
     }
 }
 
@@ -79,30 +1109,428 @@ impl Display for VCFHeader {
 
         writeln!(f, "##fileformat=VCFv4.2")?;
         writeln!(f, "##fileDate={}", date.format("%Y%m%d"))?;
+        writeln!(f, "##source=rs-gfa-utils v{}", env!("CARGO_PKG_VERSION"))?;
+        if let Some(command) = &self.command {
+            writeln!(f, "##gfa2vcf_command={}", command)?;
+        }
         writeln!(f, "##reference={}", self.reference.display())?;
+        // Each path is a haplotype, so the genotypes are phased
+        // unless asked otherwise
+        let phasing = if self.phased { "full" } else { "none" };
+        writeln!(f, "##phasing={}", phasing)?;
+
+        for (name, length) in self.contigs.iter() {
+            writeln!(f, "##contig=<ID={},length={}>", name, length)?;
+        }
+
+        writeln!(
+            f,
+            r#"##FILTER=<ID={},Description="All filters passed">"#,
+            FILTER_PASS
+        )?;
+        writeln!(
+            f,
+            r#"##FILTER=<ID={},Description="More than {} alternate alleles">"#,
+            FILTER_COMPLEX, self.max_alleles
+        )?;
 
         writeln!(
             f,
             r#"##INFO=<ID=TYPE,Number=A,Type=String,Description="Type of each allele (snv, ins, del, mnp, clumped)">"#
         )?;
 
-        // writeln!(
-        //     f,
-        //     r#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#
-        // )?;
+        writeln!(
+            f,
+            r#"##INFO=<ID=UB,Number=2,Type=Integer,Description="Segment IDs of the ends of the ultrabubble containing the variant">"#
+        )?;
+
+        writeln!(
+            f,
+            r#"##INFO=<ID=AC,Number=A,Type=Integer,Description="Allele count in genotypes, for each ALT allele">"#
+        )?;
+
+        writeln!(
+            f,
+            r#"##INFO=<ID=AN,Number=1,Type=Integer,Description="Total number of alleles in called genotypes">"#
+        )?;
+
+        writeln!(
+            f,
+            r#"##INFO=<ID=AF,Number=A,Type=Float,Description="Allele frequency, for each ALT allele">"#
+        )?;
+
+        writeln!(
+            f,
+            r#"##INFO=<ID=NS,Number=1,Type=Integer,Description="Number of samples with called genotypes, i.e. with paths that traverse the bubble">"#
+        )?;
+
+        writeln!(
+            f,
+            r#"##INFO=<ID=END,Number=1,Type=Integer,Description="End position of the REF of a record with symbolic alleles, or of a reference record for a bubble without variants">"#
+        )?;
+
+        writeln!(
+            f,
+            r#"##INFO=<ID=SVTYPE,Number=1,Type=String,Description="Type of the symbolic alleles (DEL, INS), if they all have the same type">"#
+        )?;
+
+        writeln!(
+            f,
+            r#"##INFO=<ID=SVLEN,Number=A,Type=Integer,Description="Difference in length between the REF and ALT alleles, for symbolic alleles">"#
+        )?;
+
+        writeln!(
+            f,
+            r#"##INFO=<ID=INV,Number=0,Type=Flag,Description="An ALT allele is the reverse complement of the REF">"#
+        )?;
+
+        if self.allele_depth {
+            writeln!(
+                f,
+                r#"##INFO=<ID=AD,Number=R,Type=Integer,Description="Number of paths that take each allele, the REF first">"#
+            )?;
+        }
+
+        writeln!(f, r#"##ALT=<ID=DEL,Description="Deletion">"#)?;
+
+        writeln!(f, r#"##ALT=<ID=INS,Description="Insertion">"#)?;
+
+        writeln!(
+            f,
+            r#"##FORMAT=<ID=GT,Number=1,Type=String,Description="Genotype">"#
+        )?;
+
+        if self.allele_depth {
+            writeln!(
+                f,
+                r#"##FORMAT=<ID=AD,Number=R,Type=Integer,Description="Number of the sample's paths that take each allele, the REF first">"#
+            )?;
+        }
 
         let header_line: BString = bstr::join(
             "\t",
             [
-                "#CHROM", "POS", "ID", "REF", "ALT", "QUAL", "FILTER",
-                "INFO",
-                // "FORMAT",
-                // "SampleName",
+                "#CHROM", "POS", "ID", "REF", "ALT", "QUAL", "FILTER", "INFO",
+                "FORMAT",
             ]
-            .iter(),
+            .iter()
+            .map(|s| s.as_bytes())
+            .chain(self.sample_names.iter().map(|s| s.as_slice())),
         )
         .into();
 
         write!(f, "{}", header_line)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_line_header() {
+        let header = VCFHeader::new("graph.gfa", Vec::new(), Vec::new(), 1)
+            .with_command([
+                "gfautil",
+                "-i",
+                "my graph.gfa",
+                "gfa2vcf",
+                "--refs",
+                "chr1,chr2",
+                "--sample-names",
+                "it's\nhere",
+                "",
+            ])
+            .to_string();
+
+        let lines: Vec<&str> = header
+            .lines()
+            .filter(|line| {
+                line.starts_with("##source=")
+                    || line.starts_with("##gfa2vcf_command=")
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                format!("##source=rs-gfa-utils v{}", env!("CARGO_PKG_VERSION")),
+                "##gfa2vcf_command=gfautil -i 'my graph.gfa' gfa2vcf \
+                 --refs chr1,chr2 --sample-names 'it'\\''s\\nhere' ''"
+                    .to_string(),
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn phased_and_unphased_genotypes() {
+        let record = || VCFRecord {
+            chromosome: "ref".into(),
+            position: 5,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("C".into()),
+            quality: None,
+            filter: Some(FILTER_PASS.into()),
+            info: None,
+            format: Some("GT".into()),
+            samples: Some("0\t1\t.\t1".into()),
+        };
+        let groups = SampleGroups::new(
+            vec!["a".into(), "b".into()],
+            vec![vec![0, 1], vec![2, 3]],
+        );
+
+        let mut phased = record();
+        groups.clone().group_genotypes(&mut phased);
+        assert_eq!(Some("0|1\t.|1".into()), phased.samples);
+
+        let mut unphased = record();
+        groups.unphased().group_genotypes(&mut unphased);
+        assert_eq!(Some("0/1\t./1".into()), unphased.samples);
+        assert_eq!(
+            "0/1\t1/1",
+            merge_samples(&"0/1\t./1".into(), &"0/.\t1/.".into())
+        );
+
+        let header = |header: VCFHeader| {
+            header
+                .to_string()
+                .lines()
+                .find(|line| line.starts_with("##phasing="))
+                .map(String::from)
+        };
+        let new = || VCFHeader::new("graph.gfa", Vec::new(), Vec::new(), 1);
+        assert_eq!(Some("##phasing=full".into()), header(new()));
+        assert_eq!(Some("##phasing=none".into()), header(new().unphased()));
+    }
+
+    #[test]
+    fn allele_depths_from_genotypes() {
+        let mut record = VCFRecord {
+            chromosome: "ref".into(),
+            position: 5,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("C".into()),
+            quality: None,
+            filter: Some(FILTER_PASS.into()),
+            info: Some("TYPE=snv;UB=1,4;AC=1;AN=4;AF=0.25".into()),
+            format: Some("GT".into()),
+            samples: Some("0\t1\t0\t0\t.".into()),
+        };
+        record.add_allele_depths();
+        assert_eq!(
+            "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=1;AN=4;AF=0.25;\
+             AD=3,1\tGT:AD\t0:1,0\t1:0,1\t0:1,0\t0:1,0\t.:.",
+            record.to_string()
+        );
+
+        let mut grouped = VCFRecord {
+            alternate: Some("C,G".into()),
+            format: Some("GT".into()),
+            samples: Some("0|2\t2|2\t.|1".into()),
+            info: None,
+            ..record
+        };
+        grouped.add_allele_depths();
+        assert_eq!(Some("AD=1,1,3".into()), grouped.info);
+        assert_eq!(
+            Some("0|2:1,0,1\t2|2:0,0,2\t.|1:0,1,0".into()),
+            grouped.samples
+        );
+    }
+
+    #[test]
+    fn atomize_mnp_records() {
+        let record = |reference: &str, alternate: &str| VCFRecord {
+            chromosome: "ref".into(),
+            position: 10,
+            id: None,
+            reference: reference.into(),
+            alternate: Some(alternate.into()),
+            quality: None,
+            filter: Some(FILTER_PASS.into()),
+            info: Some(
+                "TYPE=mnp;TYPE=mnp;UB=3,7;AC=1,2;AN=4;AF=0.25,0.5".into(),
+            ),
+            format: Some("GT".into()),
+            samples: Some("1|2\t0|2".into()),
+        };
+
+        // The C at 11 is shared by all alleles, and the second ALT has
+        // the REF's G at 12
+        let atomized: Vec<String> = record("ACG", "TCC,GCG")
+            .atomize()
+            .iter()
+            .map(|record| record.to_string())
+            .collect();
+        assert_eq!(
+            vec![
+                "ref\t10\t.\tA\tT,G\t.\tPASS\tTYPE=snv;TYPE=snv;UB=3,7;\
+                 AC=1,2;AN=4;AF=0.25,0.5;NS=2\tGT\t1|2\t0|2",
+                "ref\t12\t.\tG\tC\t.\tPASS\tTYPE=snv;UB=3,7;\
+                 AC=1;AN=4;AF=0.25;NS=2\tGT\t1|0\t0|0",
+            ],
+            atomized
+        );
+
+        assert_eq!(vec![record("AC", "A,TC")], record("AC", "A,TC").atomize());
+    }
+
+    #[test]
+    fn split_phased_multiallelic_record() {
+        let record = VCFRecord {
+            chromosome: "ref".into(),
+            position: 5,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("<INS>,C".into()),
+            quality: None,
+            filter: Some(FILTER_COMPLEX.into()),
+            info: Some(
+                "TYPE=ins;TYPE=snv;UB=1,4;SVTYPE=INS;SVLEN=120,.;END=5;AC=1,2;\
+                 AN=4;AF=0.25,0.5"
+                    .into(),
+            ),
+            format: Some("GT".into()),
+            samples: Some("1|2\t2|0".into()),
+        };
+
+        let split = record.split_multiallelic();
+        assert_eq!(2, split.len());
+        assert_eq!(
+            "ref\t5\t.\tA\t<INS>\t.\tPASS\t\
+             TYPE=ins;UB=1,4;SVTYPE=INS;SVLEN=120;END=5;AC=1;AN=2;AF=0.5;\
+             NS=2\t\
+             GT\t1|.\t.|0",
+            split[0].to_string()
+        );
+        assert_eq!(
+            "ref\t5\t.\tA\tC\t.\tPASS\t\
+             TYPE=snv;UB=1,4;AC=2;AN=3;AF=0.666667;NS=2\t\
+             GT\t.|1\t1|0",
+            split[1].to_string()
+        );
+    }
+
+    #[test]
+    fn records_parsed_from_vcf_lines() {
+        let record = VCFRecord {
+            chromosome: "ref".into(),
+            position: 5,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("C,T".into()),
+            quality: Some(30),
+            filter: Some(FILTER_COMPLEX.into()),
+            info: Some("TYPE=snv;TYPE=snv;UB=1,4".into()),
+            format: Some("GT".into()),
+            samples: Some("0\t1\t.\t2".into()),
+        };
+        let line = record.to_string();
+        assert_eq!(Some(record), VCFRecord::parse(line.as_bytes()));
+
+        let ref_block =
+            VCFRecord::parse(b"ref\t1\t.\tACGT\t.\t.\tPASS\t.").unwrap();
+        assert_eq!(None, ref_block.alternate);
+        assert_eq!(None, ref_block.samples);
+        assert_eq!("ref\t1\t.\tACGT\t.\t.\tPASS\t.", ref_block.to_string());
+
+        assert_eq!(None, VCFRecord::parse(b"ref\tfive\t.\tA\tC\t.\tPASS\t."));
+        assert_eq!(None, VCFRecord::parse(b"ref\t5\t.\tA"));
+    }
+
+    #[test]
+    fn rare_alleles_dropped_and_renumbered() {
+        let record = || VCFRecord {
+            chromosome: "ref".into(),
+            position: 5,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("<INS>,C,<DEL>".into()),
+            quality: None,
+            filter: Some(FILTER_COMPLEX.into()),
+            info: Some(
+                "TYPE=ins;TYPE=snv;TYPE=del;UB=1,4;SVTYPE=.;\
+                 SVLEN=120,.,-80;END=5;AC=1,2,3;AN=7;\
+                 AF=0.142857,0.285714,0.428571"
+                    .into(),
+            ),
+            format: Some("GT".into()),
+            samples: Some("1|2\t3|3\t2|0\t3|.".into()),
+        };
+
+        let mut unchanged = record();
+        assert!(unchanged.drop_rare_alleles(0.1, 1));
+        assert_eq!(record(), unchanged);
+
+        let mut common = record();
+        assert!(common.drop_rare_alleles(0.2, 2));
+        assert_eq!(
+            "ref\t5\t.\tA\tC,<DEL>\t.\tPASS\tTYPE=snv;TYPE=del;UB=1,4;\
+             SVTYPE=DEL;SVLEN=.,-80;END=5;AC=2,3;AN=6;AF=0.333333,0.5;NS=4\t\
+             GT\t.|1\t2|2\t1|0\t2|.",
+            common.to_string()
+        );
+
+        assert!(!record().drop_rare_alleles(0.5, 1));
+
+        // Without a symbolic ALT left, the SV fields are dropped too
+        let mut snv = VCFRecord {
+            alternate: Some("<DEL>,C".into()),
+            info: Some(
+                "TYPE=del;TYPE=snv;UB=1,4;SVTYPE=DEL;SVLEN=-80,.;END=5".into(),
+            ),
+            samples: Some("1|2\t2|2".into()),
+            ..record()
+        };
+        assert!(snv.drop_rare_alleles(0.3, 1));
+        assert_eq!(
+            "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=3;AN=3;AF=1;\
+             NS=2\tGT\t.|1\t1|1",
+            snv.to_string()
+        );
+    }
+
+    #[test]
+    fn ids_from_bubbles() {
+        let record = |position: i64, ub: Option<&str>| VCFRecord {
+            chromosome: "ref".into(),
+            position,
+            id: None,
+            reference: "A".into(),
+            alternate: Some("C".into()),
+            quality: None,
+            filter: Some(FILTER_PASS.into()),
+            info: ub.map(|ub| format!("TYPE=snv;UB={}", ub).into()),
+            format: None,
+            samples: None,
+        };
+        let mut records = vec![
+            record(5, Some("1,4")),
+            record(9, Some("4,6")),
+            record(7, Some("1,4")),
+            record(12, None),
+            record(8, Some("1,4")),
+            record(8, Some("1,4")),
+        ];
+        records[5].alternate = Some("G".into());
+        assert_eq!(Some((4, 6)), records[1].ultrabubble());
+        assert_eq!(None, records[3].ultrabubble());
+
+        assign_bubble_ids(&mut records);
+        let ids: Vec<Option<BString>> =
+            records.into_iter().map(|record| record.id).collect();
+        assert_eq!(
+            vec![
+                Some("ub_1_4_ref_5".into()),
+                Some("ub_4_6".into()),
+                Some("ub_1_4_ref_7".into()),
+                None,
+                Some("ub_1_4_ref_8_C".into()),
+                Some("ub_1_4_ref_8_G".into()),
+            ],
+            ids
+        );
+    }
+}
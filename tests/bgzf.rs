@@ -0,0 +1,93 @@
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+use gfautil::bgzf::BgzfWriter;
+
+/// Decompress using `bgzip`, or `gzip` if it's not available, since
+/// BGZF files are valid multi-member gzip files. Returns `None` if
+/// neither can be run.
+fn decompress_external(bytes: &[u8]) -> Option<Vec<u8>> {
+    for cmd in &["bgzip", "gzip"] {
+        let child = Command::new(cmd)
+            .arg("-dc")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn();
+
+        if let Ok(mut child) = child {
+            let mut stdin = child.stdin.take().unwrap();
+            let input = bytes.to_vec();
+            let writer = std::thread::spawn(move || {
+                stdin.write_all(&input).unwrap();
+            });
+            let output = child.wait_with_output().unwrap();
+            writer.join().unwrap();
+            assert!(output.status.success(), "{} -d failed", cmd);
+            return Some(output.stdout);
+        }
+    }
+    None
+}
+
+fn vcf_lines(count: usize) -> Vec<u8> {
+    let mut vcf = Vec::new();
+    writeln!(vcf, "##fileformat=VCFv4.2").unwrap();
+    writeln!(vcf, "#CHROM\tPOS\tID\tREF\tALT\tQUAL\tFILTER\tINFO").unwrap();
+    let bases = [b'A', b'C', b'G', b'T'];
+    for i in 0..count {
+        let ref_base = bases[i % 4] as char;
+        let alt_base = bases[(i * 7 + 1) % 4] as char;
+        writeln!(
+            vcf,
+            "path{}\t{}\t.\t{}\t{}\t.\t.\tTYPE=snv",
+            i % 3,
+            i * 13 + 1,
+            ref_base,
+            alt_base
+        )
+        .unwrap();
+    }
+    vcf
+}
+
+#[test]
+fn bgzf_roundtrip() {
+    let vcf = vcf_lines(20000);
+
+    let mut writer = BgzfWriter::new(Vec::new());
+    writer.write_all(&vcf).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    assert!(compressed.len() < vcf.len());
+
+    if let Some(decompressed) = decompress_external(&compressed) {
+        assert_eq!(vcf, decompressed);
+    } else {
+        eprintln!("neither bgzip nor gzip found, skipping roundtrip");
+    }
+}
+
+#[test]
+fn bgzf_roundtrip_incompressible() {
+    // A simple xorshift generator gives data that won't compress,
+    // exercising the stored block fallback
+    let mut state: u32 = 0x1234_5678;
+    let data: Vec<u8> = (0..200_000)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect();
+
+    let mut writer = BgzfWriter::new(Vec::new());
+    writer.write_all(&data).unwrap();
+    let compressed = writer.finish().unwrap();
+
+    if let Some(decompressed) = decompress_external(&compressed) {
+        assert_eq!(data, decompressed);
+    }
+}
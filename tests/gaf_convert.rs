@@ -9,6 +9,7 @@ use gfa::{
 
 use gfautil::gaf_convert::gaf_to_paf;
 
+#[allow(clippy::upper_case_acronyms)]
 type PAF = gfa::gafpaf::PAF<OptionalFields>;
 
 fn load_pafs(gfa_path: &str, gaf_path: &str) -> Vec<PAF> {
@@ -18,15 +19,13 @@ fn load_pafs(gfa_path: &str, gaf_path: &str) -> Vec<PAF> {
         parser.parse_file(gfa_path).unwrap();
 
     let gaf_path = PathBuf::from(gaf_path);
-    let pafs = gaf_to_paf(gfa, &gaf_path);
-
-    pafs
+    gaf_to_paf(gfa, &gaf_path)
 }
 
 fn get_cigar(opts: &OptionalFields) -> Option<CIGAR> {
     let cg = opts.get_field(b"cg")?;
     if let OptFieldVal::Z(cg) = &cg.value {
-        CIGAR::from_bytestring(&cg)
+        CIGAR::from_bytestring(cg)
     } else {
         None
     }
@@ -73,35 +72,35 @@ fn gafpaf_no_overlaps() {
 
     // read1
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 6, (0, 1));
-    compare_paf_target(&paf, "2", 3, (2, 3));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read1", 6, (0, 1));
+    compare_paf_target(paf, "2", 3, (2, 3));
+    compare_paf_rest(paf, 1, 1, "1M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 6, (1, 5));
-    compare_paf_target(&paf, "3", 4, (0, 4));
-    compare_paf_rest(&paf, 4, 4, "4M");
+    compare_paf_query(paf, "read1", 6, (1, 5));
+    compare_paf_target(paf, "3", 4, (0, 4));
+    compare_paf_rest(paf, 4, 4, "4M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 6, (5, 6));
-    compare_paf_target(&paf, "4", 5, (0, 1));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read1", 6, (5, 6));
+    compare_paf_target(paf, "4", 5, (0, 1));
+    compare_paf_rest(paf, 1, 1, "1M");
 
     // read2
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 7, (0, 2));
-    compare_paf_target(&paf, "2", 3, (1, 3));
-    compare_paf_rest(&paf, 2, 2, "2M");
+    compare_paf_query(paf, "read2", 7, (0, 2));
+    compare_paf_target(paf, "2", 3, (1, 3));
+    compare_paf_rest(paf, 2, 2, "2M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 7, (2, 6));
-    compare_paf_target(&paf, "5", 4, (0, 4));
-    compare_paf_rest(&paf, 4, 4, "4M");
+    compare_paf_query(paf, "read2", 7, (2, 6));
+    compare_paf_target(paf, "5", 4, (0, 4));
+    compare_paf_rest(paf, 4, 4, "4M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 7, (6, 7));
-    compare_paf_target(&paf, "6", 4, (0, 1));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read2", 7, (6, 7));
+    compare_paf_target(paf, "6", 4, (0, 1));
+    compare_paf_rest(paf, 1, 1, "1M");
 
     assert!(iter.next().is_none());
 }
@@ -113,35 +112,35 @@ fn gafpaf_overlaps() {
 
     // read1
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 6, (0, 1));
-    compare_paf_target(&paf, "2", 3, (2, 3));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read1", 6, (0, 1));
+    compare_paf_target(paf, "2", 3, (2, 3));
+    compare_paf_rest(paf, 1, 1, "1M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 6, (1, 5));
-    compare_paf_target(&paf, "3", 4, (0, 4));
-    compare_paf_rest(&paf, 3, 4, "1I3M");
+    compare_paf_query(paf, "read1", 6, (1, 5));
+    compare_paf_target(paf, "3", 4, (0, 4));
+    compare_paf_rest(paf, 3, 4, "1I3M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 6, (5, 6));
-    compare_paf_target(&paf, "4", 5, (0, 1));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read1", 6, (5, 6));
+    compare_paf_target(paf, "4", 5, (0, 1));
+    compare_paf_rest(paf, 1, 1, "1M");
 
     // read2
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 7, (0, 2));
-    compare_paf_target(&paf, "2", 3, (1, 3));
-    compare_paf_rest(&paf, 2, 2, "2M");
+    compare_paf_query(paf, "read2", 7, (0, 2));
+    compare_paf_target(paf, "2", 3, (1, 3));
+    compare_paf_rest(paf, 2, 2, "2M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 7, (2, 6));
-    compare_paf_target(&paf, "5", 4, (0, 4));
-    compare_paf_rest(&paf, 3, 4, "2M1I1M");
+    compare_paf_query(paf, "read2", 7, (2, 6));
+    compare_paf_target(paf, "5", 4, (0, 4));
+    compare_paf_rest(paf, 3, 4, "2M1I1M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 7, (6, 7));
-    compare_paf_target(&paf, "6", 4, (0, 1));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read2", 7, (6, 7));
+    compare_paf_target(paf, "6", 4, (0, 1));
+    compare_paf_rest(paf, 1, 1, "1M");
 
     assert!(iter.next().is_none());
 }
@@ -153,33 +152,33 @@ fn gafpaf_dels() {
 
     // read1
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 5, (0, 1));
-    compare_paf_target(&paf, "2", 3, (2, 3));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read1", 5, (0, 1));
+    compare_paf_target(paf, "2", 3, (2, 3));
+    compare_paf_rest(paf, 1, 1, "1M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 5, (1, 5));
-    compare_paf_target(&paf, "3", 4, (0, 4));
-    compare_paf_rest(&paf, 3, 4, "1M1D2M");
+    compare_paf_query(paf, "read1", 5, (1, 5));
+    compare_paf_target(paf, "3", 4, (0, 4));
+    compare_paf_rest(paf, 3, 4, "1M1D2M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read1", 5, (5, 6));
-    compare_paf_target(&paf, "4", 5, (0, 1));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read1", 5, (5, 6));
+    compare_paf_target(paf, "4", 5, (0, 1));
+    compare_paf_rest(paf, 1, 1, "1M");
 
     // read2
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 6, (0, 2));
-    compare_paf_target(&paf, "2", 3, (1, 3));
-    compare_paf_rest(&paf, 2, 2, "2M");
+    compare_paf_query(paf, "read2", 6, (0, 2));
+    compare_paf_target(paf, "2", 3, (1, 3));
+    compare_paf_rest(paf, 2, 2, "2M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 6, (2, 6));
-    compare_paf_target(&paf, "5", 4, (0, 4));
-    compare_paf_rest(&paf, 3, 4, "1D3M");
+    compare_paf_query(paf, "read2", 6, (2, 6));
+    compare_paf_target(paf, "5", 4, (0, 4));
+    compare_paf_rest(paf, 3, 4, "1D3M");
 
     let paf = iter.next().unwrap();
-    compare_paf_query(&paf, "read2", 6, (6, 7));
-    compare_paf_target(&paf, "6", 4, (0, 1));
-    compare_paf_rest(&paf, 1, 1, "1M");
+    compare_paf_query(paf, "read2", 6, (6, 7));
+    compare_paf_target(paf, "6", 4, (0, 1));
+    compare_paf_rest(paf, 1, 1, "1M");
 }
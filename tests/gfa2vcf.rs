@@ -0,0 +1,1545 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use structopt::StructOpt;
+
+use gfautil::{
+    commands::{
+        gfa2vcf::{apply_config, gfa2vcf, GFA2VCFArgs},
+        saboten::{run_saboten, SabotenArgs},
+    },
+    error::GfaUtilsError,
+    synthetic::SyntheticGraph,
+};
+
+/// A unique temporary file path, as the tests run in parallel.
+fn temp_path(name: &str) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let count = COUNTER.fetch_add(1, Ordering::SeqCst);
+    let mut path = std::env::temp_dir();
+    path.push(format!("gfautil_{}_{}_{}", std::process::id(), count, name));
+    path
+}
+
+fn run_gfa2vcf(gfa: &str, args: &[&str]) -> Vec<u8> {
+    let output = temp_path("out.vcf");
+    let output_str = output.to_str().unwrap();
+
+    // The config file, if any, is applied like on the command line
+    let cli_args: Vec<std::ffi::OsString> = std::iter::once("gfa2vcf")
+        .chain(args.iter().copied())
+        .chain(vec!["-o", output_str])
+        .map(Into::into)
+        .collect();
+    let args = GFA2VCFArgs::from_iter_safe(&cli_args).unwrap();
+    let args = apply_config(args, &cli_args).unwrap();
+
+    gfa2vcf(&PathBuf::from(gfa), args, Default::default()).unwrap();
+
+    let vcf = std::fs::read(&output).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    vcf
+}
+
+/// Run gfa2vcf on a GFA made of `lines`, with the ultrabubbles in
+/// `ultrabubbles` as the text of an ultrabubbles file, like "1\t4\n",
+/// and the arguments `args`, and return the lines of the output.
+fn run_on_gfa_lines(
+    lines: &[&str],
+    ultrabubbles: &str,
+    args: &[&str],
+) -> Vec<String> {
+    let gfa = temp_path("lines.gfa");
+    std::fs::write(&gfa, lines.join("\n")).unwrap();
+    let ultrabubbles_path = temp_path("lines.ub");
+    std::fs::write(&ultrabubbles_path, ultrabubbles).unwrap();
+
+    let mut all_args =
+        vec!["--ultrabubbles", ultrabubbles_path.to_str().unwrap()];
+    all_args.extend_from_slice(args);
+    let vcf = run_gfa2vcf(gfa.to_str().unwrap(), &all_args);
+
+    std::fs::remove_file(&gfa).unwrap();
+    std::fs::remove_file(&ultrabubbles_path).unwrap();
+    String::from_utf8(vcf)
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn output_is_reproducible() {
+    let first = run_gfa2vcf("tests/data/paths.gfa", &[]);
+    let second = run_gfa2vcf("tests/data/paths.gfa", &[]);
+    assert_eq!(first, second);
+}
+
+#[test]
+fn output_does_not_depend_on_ultrabubble_order() {
+    let sorted = temp_path("sorted.ub");
+    let reversed = temp_path("reversed.ub");
+    std::fs::write(&sorted, "1\t4\n4\t6\n6\t7\n").unwrap();
+    std::fs::write(&reversed, "6\t7\n4\t6\n1\t4\n").unwrap();
+
+    let sorted_vcf = run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &["--ultrabubbles", sorted.to_str().unwrap()],
+    );
+    let reversed_vcf = run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &["--ultrabubbles", reversed.to_str().unwrap()],
+    );
+
+    std::fs::remove_file(&sorted).unwrap();
+    std::fs::remove_file(&reversed).unwrap();
+
+    assert!(!sorted_vcf.is_empty());
+    assert_eq!(sorted_vcf, reversed_vcf);
+}
+
+#[test]
+fn limit_keeps_first_sorted_ultrabubbles() {
+    let reversed = temp_path("reversed.ub");
+    let first = temp_path("first.ub");
+    std::fs::write(&reversed, "6\t7\n4\t6\n1\t4\n").unwrap();
+    std::fs::write(&first, "1\t4\n4\t6\n").unwrap();
+
+    // Reference blocks give every bubble records, so the bubbles
+    // that were left out can be told apart
+    let vcf = |ultrabubbles: &PathBuf, extra_args: &[&str]| {
+        let mut args = vec![
+            "--ultrabubbles",
+            ultrabubbles.to_str().unwrap(),
+            "--include-ref-allele-record",
+        ];
+        args.extend_from_slice(extra_args);
+        run_gfa2vcf("tests/data/paths.gfa", &args)
+    };
+
+    let limited_vcf = vcf(&reversed, &["--limit", "2"]);
+    let first_vcf = vcf(&first, &[]);
+    let all_vcf = vcf(&reversed, &[]);
+
+    std::fs::remove_file(&reversed).unwrap();
+    std::fs::remove_file(&first).unwrap();
+
+    assert_eq!(first_vcf, limited_vcf);
+    assert_ne!(all_vcf, limited_vcf);
+}
+
+#[test]
+fn representative_path_assignments() {
+    // 3-4 is only on alt1, and 9-10 isn't on any path
+    let ultrabubbles = temp_path("assign.ub");
+    let assignments = temp_path("assignments.tsv");
+    std::fs::write(&ultrabubbles, "1\t4\n3\t4\n9\t10\n").unwrap();
+
+    run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &[
+            "--ultrabubbles",
+            ultrabubbles.to_str().unwrap(),
+            "--write-assignments",
+            assignments.to_str().unwrap(),
+        ],
+    );
+    let written = std::fs::read_to_string(&assignments).unwrap();
+
+    std::fs::remove_file(&ultrabubbles).unwrap();
+    std::fs::remove_file(&assignments).unwrap();
+
+    assert_eq!("ref\t1\t4\nalt1\t3\t4\n", written);
+}
+
+#[test]
+fn alleles_in_graph_coordinates() {
+    let coords = temp_path("coords.tsv");
+    run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &[
+            "--ultrabubbles",
+            "tests/data/paths.ultrabubbles",
+            "--refs",
+            "ref",
+            "--graph-coords",
+            coords.to_str().unwrap(),
+        ],
+    );
+    let written = std::fs::read_to_string(&coords).unwrap();
+    std::fs::remove_file(&coords).unwrap();
+
+    let bubble_1_4: Vec<&str> = written
+        .lines()
+        .filter(|l| l.starts_with("1\t4\t"))
+        .collect();
+    assert_eq!(
+        vec!["1\t4\tREF\t1+,2+,4+\tref,alt2", "1\t4\tALT\t1+,3+,4+\talt1"],
+        bubble_1_4
+    );
+}
+
+#[test]
+fn records_without_header() {
+    let ultrabubbles = ["--ultrabubbles", "tests/data/paths.ultrabubbles"];
+    let full = run_gfa2vcf("tests/data/paths.gfa", &ultrabubbles);
+    let records = run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &[ultrabubbles[0], ultrabubbles[1], "--no-header"],
+    );
+
+    let full_records: Vec<&[u8]> = full
+        .split(|&b| b == b'\n')
+        .filter(|line| !line.starts_with(b"#"))
+        .collect();
+
+    assert!(full_records.len() > 1);
+    assert!(!records.starts_with(b"#"));
+    assert_eq!(
+        full_records,
+        records.split(|&b| b == b'\n').collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn records_follow_contig_order() {
+    // chr10 sorts before chr2 lexicographically, but chr2 comes first
+    // in the graph, and thus in the header
+    let lines = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tA",
+        "S\t3\tC",
+        "S\t4\tGGTT",
+        "S\t5\tT",
+        "S\t6\tCA",
+        "S\t7\tG",
+        "P\tchr2\t1+,2+,4+,5+,6+\t*",
+        "P\tchr10\t1+,3+,4+,7+,6+\t*",
+    ];
+    let vcf =
+        run_on_gfa_lines(&lines, "1\t4\n4\t6\n", &["--refs", "chr2", "chr10"]);
+
+    let header_contigs: Vec<&str> = vcf
+        .iter()
+        .filter_map(|line| line.strip_prefix("##contig=<ID="))
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+    assert_eq!(vec!["chr2", "chr10"], header_contigs);
+
+    let sites: Vec<(&str, usize)> = vcf
+        .iter()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| {
+            let mut fields = line.split('\t');
+            let chrom = fields.next().unwrap();
+            let pos = fields.next().unwrap().parse().unwrap();
+            (chrom, pos)
+        })
+        .collect();
+    assert_eq!(
+        vec![("chr2", 5), ("chr2", 10), ("chr10", 5), ("chr10", 10)],
+        sites
+    );
+}
+
+#[test]
+fn json_output_matches_vcf_records() {
+    let ultrabubbles = ["--ultrabubbles", "tests/data/paths.ultrabubbles"];
+    let vcf = run_gfa2vcf("tests/data/paths.gfa", &ultrabubbles);
+    let json = run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &[ultrabubbles[0], ultrabubbles[1], "--format", "json"],
+    );
+
+    let vcf = String::from_utf8(vcf).unwrap();
+    let json = String::from_utf8(json).unwrap();
+
+    let vcf_records: Vec<Vec<&str>> = vcf
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(|line| line.split('\t').collect())
+        .collect();
+    let json_records: Vec<serde_json::Value> = json
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+
+    assert!(!json_records.is_empty());
+    assert_eq!(vcf_records.len(), json_records.len());
+
+    for (fields, record) in vcf_records.iter().zip(json_records.iter()) {
+        assert_eq!(fields[0], record["chrom"]);
+        assert_eq!(fields[1], record["pos"].to_string());
+        assert_eq!(fields[3], record["ref"]);
+        assert_eq!(fields[4], record["alts"][0]);
+        let ub = fields[7]
+            .split(';')
+            .find_map(|field| field.strip_prefix("UB="))
+            .unwrap();
+        let ub = format!("[{}]", ub);
+        assert_eq!(ub, record["ultrabubble"].to_string());
+        assert_eq!(fields[9], record["genotypes"]["ref"].to_string());
+    }
+}
+
+#[test]
+fn tabix_index_for_bgzipped_output() {
+    use std::io::Read;
+
+    let output = temp_path("indexed.vcf.gz");
+    let output_str = output.to_str().unwrap();
+    let args = GFA2VCFArgs::from_iter_safe(&[
+        "gfa2vcf",
+        "--ultrabubbles",
+        "tests/data/paths.ultrabubbles",
+        "-o",
+        output_str,
+        "--tabix",
+    ])
+    .unwrap();
+    gfa2vcf(
+        &PathBuf::from("tests/data/paths.gfa"),
+        args,
+        Default::default(),
+    )
+    .unwrap();
+
+    let index_path = gfautil::tabix::index_path(&output);
+    let index = std::fs::read(&index_path).unwrap();
+    let mut tbi = Vec::new();
+    gfautil::gzip::GzDecoder::new(index.as_slice())
+        .read_to_end(&mut tbi)
+        .unwrap();
+    assert_eq!(b"TBI\x01", &tbi[..4]);
+
+    std::fs::remove_file(&output).unwrap();
+    std::fs::remove_file(&index_path).unwrap();
+
+    let plain = temp_path("plain.vcf");
+    let args = GFA2VCFArgs::from_iter_safe(&[
+        "gfa2vcf",
+        "-o",
+        plain.to_str().unwrap(),
+        "--tabix",
+    ])
+    .unwrap();
+    assert!(gfa2vcf(
+        &PathBuf::from("tests/data/paths.gfa"),
+        args,
+        Default::default()
+    )
+    .is_err());
+    assert!(!plain.exists());
+}
+
+#[test]
+fn reference_path_lengths_file() {
+    let fai = temp_path("refs.fai");
+    run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &[
+            "--refs",
+            "ref",
+            "alt1",
+            "--write-fai",
+            fai.to_str().unwrap(),
+        ],
+    );
+
+    let lengths = std::fs::read_to_string(&fai).unwrap();
+    std::fs::remove_file(&fai).unwrap();
+    assert_eq!("ref\t21\nalt1\t18\n", lengths);
+}
+
+#[test]
+fn consensus_reference_takes_most_common_alleles() {
+    let records = |args: &[&str]| {
+        let args = [
+            &[
+                "--ultrabubbles",
+                "tests/data/paths.ultrabubbles",
+                "--no-header",
+            ],
+            args,
+        ]
+        .concat();
+        let vcf = run_gfa2vcf("tests/data/paths.gfa", &args);
+        String::from_utf8(vcf)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    let consensus = records(&["--consensus-ref"]);
+    assert_eq!(
+        vec![
+            "consensus\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=1;AN=3;\
+             AF=0.333333;NS=3\tGT\t0\t1\t0",
+            "consensus\t9\t.\tT\tTTTA\t.\tPASS\tTYPE=ins;UB=4,6;AC=1;AN=3;\
+             AF=0.333333;NS=3\tGT\t1\t0\t0",
+        ],
+        consensus
+    );
+
+    // The backbone's own allele in the 1-4 bubble is in the minority
+    let from_alt1 =
+        records(&["--consensus-ref", "--consensus-backbone", "alt1"]);
+    assert_eq!(consensus, from_alt1);
+}
+
+#[test]
+fn errors_can_be_matched() {
+    let run = |gfa: &str, args: &[&str]| {
+        let args = std::iter::once("gfa2vcf").chain(args.iter().copied());
+        let args = GFA2VCFArgs::from_iter_safe(args).unwrap();
+        gfa2vcf(&PathBuf::from(gfa), args, Default::default()).unwrap_err()
+    };
+
+    match run(
+        "tests/data/paths.gfa",
+        &["--refs", "ref", "chm13", "grch38"],
+    ) {
+        GfaUtilsError::MissingReferencePaths(paths) => {
+            assert_eq!(vec!["chm13", "grch38"], paths)
+        }
+        err => panic!("Unexpected error: {}", err),
+    }
+
+    let err = run("tests/data/paths.gfa", &["--refs", "chm13", "--lenient"]);
+    assert!(matches!(err, GfaUtilsError::NoReferencePaths));
+
+    let err = run("tests/data/paths.gfa", &["-u", "tests/data/paths.gfa"]);
+    assert!(matches!(err, GfaUtilsError::UltrabubbleLoad { .. }));
+
+    let single_path = temp_path("single.gfa");
+    std::fs::write(&single_path, "S\t1\tA\nP\tp\t1+\t*\n").unwrap();
+    let err = run(single_path.to_str().unwrap(), &[]);
+    std::fs::remove_file(&single_path).unwrap();
+    assert!(matches!(err, GfaUtilsError::TooFewPaths { count: 1, .. }));
+}
+
+#[test]
+fn inverted_alleles_are_flagged() {
+    // The alt path traverses segment 2 in reverse, so its allele is
+    // the reverse complement of the reference's
+    let lines = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tAAC",
+        "S\t3\tGGTT",
+        "P\tref\t1+,2+,3+\t*",
+        "P\talt\t1+,2-,3+\t*",
+    ];
+    let records = |extra: &[&str]| {
+        let args = [&["--refs", "ref", "--no-header"], extra].concat();
+        run_on_gfa_lines(&lines, "1\t3\n", &args)
+    };
+
+    let flagged = records(&[]);
+    let unflagged = records(&["--no-inv"]);
+
+    assert_eq!(1, flagged.len());
+    let fields: Vec<&str> = flagged[0].split('\t').collect();
+    assert_eq!(&["ref", "5", ".", "AAC", "GTT"], &fields[..5]);
+    assert!(fields[7].split(';').any(|entry| entry == "INV"));
+
+    assert!(!unflagged.concat().contains("INV"));
+}
+
+#[test]
+fn variant_records_end_to_end() {
+    // `rev` spells the same sequence as `alt`, but traverses the
+    // bubble backward, so it's only compared without --no-inv
+    let lines = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tA",
+        "S\t3\tC",
+        "S\t4\tGGTT",
+        "P\tref\t1+,2+,4+\t*",
+        "P\talt\t1+,3+,4+\t*",
+        "P\trev\t4-,3-,1-\t*",
+    ];
+    let records = |extra: &[&str]| {
+        let args = [&["--refs", "ref", "--no-header"], extra].concat();
+        run_on_gfa_lines(&lines, "1\t4\n", &args)
+    };
+
+    let all_paths = records(&[]);
+    let no_inv = records(&["--no-inv"]);
+
+    assert_eq!(1, all_paths.len());
+    let fields: Vec<&str> = all_paths[0].split('\t').collect();
+    assert_eq!(&["ref", "5", ".", "A", "C", ".", "PASS"], &fields[..7]);
+    assert_eq!(&["GT", "0", "1"], &fields[8..11]);
+    assert_ne!(".", fields[11]);
+
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=1;AN=2;AF=0.5;NS=2\t\
+             GT\t0\t1\t."
+        ],
+        no_inv
+    );
+}
+
+#[test]
+fn output_is_identical_across_runs_and_thread_counts() {
+    // Each run is a separate process, so nothing carries over between
+    // them; the header is left out, as it holds the date
+    let run = |threads: &str, command: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_gfautil"))
+            .args(["-i", "tests/data/paths.gfa", "--threads", threads])
+            .args(command)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    let gfa2vcf = ["gfa2vcf", "--no-header", "--include-ref-allele-record"];
+    let snps = ["snps", "--ref", "ref", "--snps", "5"];
+
+    for command in [&gfa2vcf[..], &snps[..]].iter() {
+        let first = run("1", command);
+        assert!(!first.is_empty());
+        for &threads in &["1", "2", "4"] {
+            assert_eq!(first, run(threads, command));
+        }
+    }
+}
+
+#[test]
+fn alt_order_independent_of_thread_count() {
+    // Enough bubbles to be split between the threads, with up to 4
+    // ALTs at each site, so that the ALTs would be in different orders
+    // if they followed the order the paths are processed in
+    let params = SyntheticGraph {
+        bubbles: 2000,
+        alleles: 5,
+        paths: 16,
+        ..Default::default()
+    };
+    let gfa = temp_path("synthetic.gfa");
+    std::fs::write(&gfa, params.lines().join("\n")).unwrap();
+    let ultrabubbles = temp_path("synthetic.ub");
+    let ultrabubbles_text: String = params
+        .ultrabubbles()
+        .iter()
+        .map(|(from, to)| format!("{}\t{}\n", from, to))
+        .collect();
+    std::fs::write(&ultrabubbles, ultrabubbles_text).unwrap();
+
+    let run = |threads: &str| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_gfautil"))
+            .args(["-i", gfa.to_str().unwrap(), "--threads", threads])
+            .args(["gfa2vcf", "--no-header", "--refs", "path0"])
+            .args(["--ultrabubbles", ultrabubbles.to_str().unwrap()])
+            .args(["--max-alleles", "5"])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        output.stdout
+    };
+
+    let single = run("1");
+    let parallel = run("8");
+
+    std::fs::remove_file(&gfa).unwrap();
+    std::fs::remove_file(&ultrabubbles).unwrap();
+
+    let multiallelic = String::from_utf8_lossy(&single)
+        .lines()
+        .filter(|line| line.split('\t').nth(4).unwrap().contains(','))
+        .count();
+    assert!(multiallelic > 100);
+    assert!(single == parallel);
+}
+
+#[test]
+fn haplotypes_grouped_into_phased_genotypes() {
+    let sample_map = temp_path("samples.tsv");
+    std::fs::write(&sample_map, "alt1\tS1\nalt2\tS1\n").unwrap();
+
+    let run = |extra: &[&str]| {
+        let mut args = vec![
+            "--ultrabubbles",
+            "tests/data/paths.ultrabubbles",
+            "--refs",
+            "ref",
+            "--sample-map",
+            sample_map.to_str().unwrap(),
+        ];
+        args.extend_from_slice(extra);
+        String::from_utf8(run_gfa2vcf("tests/data/paths.gfa", &args)).unwrap()
+    };
+    let vcf = run(&[]);
+    let unphased = run(&["--unphased"]);
+    std::fs::remove_file(&sample_map).unwrap();
+
+    let header = vcf.lines().find(|l| l.starts_with("#CHROM")).unwrap();
+    assert!(header.ends_with("\tFORMAT\tref\tS1"));
+    assert!(vcf.lines().any(|l| l == "##phasing=full"));
+
+    let record = vcf.lines().find(|l| !l.starts_with('#')).unwrap();
+    assert!(record.starts_with("ref\t5\t.\tA\tC\t"));
+    assert!(record.ends_with("AC=1;AN=3;AF=0.333333;NS=2\tGT\t0\t1|0"));
+
+    assert!(unphased.lines().any(|l| l == "##phasing=none"));
+    let record = unphased.lines().find(|l| !l.starts_with('#')).unwrap();
+    assert!(record.ends_with("AC=1;AN=3;AF=0.333333;NS=2\tGT\t0\t1/0"));
+}
+
+#[test]
+fn multiallelic_records_dropped_or_split() {
+    let lines = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tA",
+        "S\t3\tC",
+        "S\t4\tG",
+        "S\t5\tGGTT",
+        "P\tref\t1+,2+,5+\t*",
+        "P\ta\t1+,3+,5+\t*",
+        "P\tb\t1+,4+,5+\t*",
+    ];
+    let records = |extra: &[&str]| {
+        let args = [&["--refs", "ref", "--no-header"], extra].concat();
+        run_on_gfa_lines(&lines, "1\t5\n", &args)
+    };
+
+    let multiallelic = records(&[]);
+    let dropped = records(&["--biallelic-only"]);
+    let split = records(&["--biallelic-only", "--split-multiallelic"]);
+
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tA\tC,G\t.\tCOMPLEX\t\
+             TYPE=snv;TYPE=snv;UB=1,5;AC=1,1;AN=3;AF=0.333333,0.333333;NS=3\t\
+             GT\t0\t1\t2"
+        ],
+        multiallelic
+    );
+    assert!(dropped.is_empty());
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,5;AC=1;AN=2;AF=0.5;NS=2\t\
+             GT\t0\t1\t.",
+            "ref\t5\t.\tA\tG\t.\tPASS\tTYPE=snv;UB=1,5;AC=1;AN=2;AF=0.5;NS=2\t\
+             GT\t0\t.\t1",
+        ],
+        split
+    );
+}
+
+#[test]
+fn mnp_atomized_into_snvs() {
+    let lines = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tTAC",
+        "S\t3\tGAT",
+        "S\t4\tGGTT",
+        "S\t5\tCC",
+        "P\tref\t1+,2+,4+\t*",
+        "P\tmnp\t1+,3+,4+\t*",
+        "P\tins\t1+,2+,5+,4+\t*",
+    ];
+    let records = |extra: &[&str]| {
+        let args = [&["--refs", "ref", "--no-header"], extra].concat();
+        run_on_gfa_lines(&lines, "1\t4\n", &args)
+    };
+
+    let mnp = records(&[]);
+    let atomized = records(&["--atomize"]);
+
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tTAC\tGAT\t.\tPASS\tTYPE=mnp;UB=1,4;AC=1;AN=3;\
+             AF=0.333333;NS=3\tGT\t0\t1\t0",
+            "ref\t7\t.\tC\tCCC\t.\tPASS\tTYPE=ins;UB=1,4;AC=1;AN=3;\
+             AF=0.333333;NS=3\tGT\t0\t0\t1",
+        ],
+        mnp
+    );
+
+    // The shared A at 6 gives no record, and the insertion is kept
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tT\tG\t.\tPASS\tTYPE=snv;UB=1,4;AC=1;AN=3;\
+             AF=0.333333;NS=3\tGT\t0\t1\t0",
+            "ref\t7\t.\tC\tCCC\t.\tPASS\tTYPE=ins;UB=1,4;AC=1;AN=3;\
+             AF=0.333333;NS=3\tGT\t0\t0\t1",
+            "ref\t7\t.\tC\tT\t.\tPASS\tTYPE=snv;UB=1,4;AC=1;AN=3;\
+             AF=0.333333;NS=3\tGT\t0\t1\t0",
+        ],
+        atomized
+    );
+}
+
+#[test]
+fn large_indels_as_symbolic_alleles() {
+    let lines = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tCCCCCCCCCC",
+        "S\t3\tTTTTTTTTTTTT",
+        "S\t4\tGGTT",
+        "P\tref\t1+,2+,4+\t*",
+        "P\tdel\t1+,4+\t*",
+        "P\tins\t1+,2+,3+,4+\t*",
+    ];
+    let records = |args: &[&str]| {
+        let args = [&["--refs", "ref", "--no-header"], args].concat();
+        run_on_gfa_lines(&lines, "1\t4\n", &args)
+    };
+
+    let symbolic = records(&["--symbolic-alleles", "9"]);
+    let explicit = records(&["--symbolic-alleles", "12"]);
+
+    // The threshold can also be given with its own name, and without
+    // one, only the alleles over --max-allele-length are symbolic
+    assert_eq!(symbolic, records(&["--symbolic-sv-threshold", "9"]));
+    assert_eq!(
+        symbolic,
+        records(&["--symbolic-alleles", "--max-allele-length", "10"])
+    );
+    assert_eq!(explicit, records(&["--symbolic-alleles"]));
+
+    // In a config file, the option is either a threshold or a switch
+    let config = temp_path("symbolic.toml");
+    let config_path = config.to_str().unwrap();
+    std::fs::write(&config, "symbolic-alleles = 9\n").unwrap();
+    assert_eq!(symbolic, records(&["--config", config_path]));
+    std::fs::write(
+        &config,
+        "symbolic-alleles = true\nmax-allele-length = 10\n",
+    )
+    .unwrap();
+    assert_eq!(symbolic, records(&["--config", config_path]));
+    std::fs::remove_file(&config).unwrap();
+
+    // The deletion of the 10 Cs at 5-14, and the insertion of 12 Ts
+    assert_eq!(
+        vec![
+            "ref\t4\t.\tT\t<DEL>\t.\tPASS\tTYPE=del;UB=1,4;SVTYPE=DEL;\
+             SVLEN=-10;END=14;AC=1;AN=3;AF=0.333333;NS=3\tGT\t0\t1\t0",
+            "ref\t14\t.\tC\t<INS>\t.\tPASS\tTYPE=ins;UB=1,4;SVTYPE=INS;\
+             SVLEN=12;END=14;AC=1;AN=3;AF=0.333333;NS=3\tGT\t0\t0\t1",
+        ],
+        symbolic
+    );
+
+    assert_eq!(
+        vec![
+            "ref\t4\t.\tTCCCCCCCCCC\tT\t.\tPASS\tTYPE=del;UB=1,4;AC=1;AN=3;\
+             AF=0.333333;NS=3\tGT\t0\t1\t0",
+            "ref\t14\t.\tC\tCTTTTTTTTTTTT\t.\tPASS\tTYPE=ins;UB=1,4;AC=1;\
+             AN=3;AF=0.333333;NS=3\tGT\t0\t0\t1",
+        ],
+        explicit
+    );
+}
+
+#[test]
+fn only_snps_skips_indels_and_mnps() {
+    // A SNV and a deletion in 1-4, an MNP in 4-7, and a deletion in
+    // 7-9
+    let lines = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tA",
+        "S\t3\tC",
+        "S\t4\tGGTT",
+        "S\t5\tCA",
+        "S\t6\tTG",
+        "S\t7\tG",
+        "S\t8\tTTA",
+        "S\t9\tGATTACA",
+        "P\tref\t1+,2+,4+,5+,7+,8+,9+\t*",
+        "P\talt1\t1+,3+,4+,6+,7+,9+\t*",
+        "P\talt2\t1+,3+,4+,5+,7+,8+,9+\t*",
+        "P\talt3\t1+,4+,5+,7+,8+,9+\t*",
+    ];
+    let records = |extra: &[&str]| {
+        let args =
+            [&["--refs", "ref", "--no-header", "--only-snps"], extra].concat();
+        run_on_gfa_lines(&lines, "1\t4\n4\t7\n7\t9\n", &args)
+    };
+
+    let snps = records(&[]);
+    let atomized = records(&["--atomize"]);
+
+    // The path with the deletion in 1-4 is left out of that record
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=2;AN=3;\
+             AF=0.666667;NS=3\tGT\t0\t1\t1\t."
+        ],
+        snps
+    );
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=2;AN=3;\
+             AF=0.666667;NS=3\tGT\t0\t1\t1\t.",
+            "ref\t10\t.\tC\tT\t.\tPASS\tTYPE=snv;UB=4,7;AC=1;AN=4;\
+             AF=0.25;NS=4\tGT\t0\t1\t0\t0",
+            "ref\t11\t.\tA\tG\t.\tPASS\tTYPE=snv;UB=4,7;AC=1;AN=4;\
+             AF=0.25;NS=4\tGT\t0\t1\t0\t0",
+        ],
+        atomized
+    );
+}
+
+#[test]
+fn records_in_target_intervals() {
+    let targets = temp_path("targets.bed");
+    let positions = |bed: &str| {
+        std::fs::write(&targets, bed).unwrap();
+        let vcf = run_gfa2vcf(
+            "tests/data/paths.gfa",
+            &[
+                "--ultrabubbles",
+                "tests/data/paths.ultrabubbles",
+                "--refs",
+                "ref",
+                "--no-header",
+                "--targets",
+                targets.to_str().unwrap(),
+            ],
+        );
+        String::from_utf8(vcf)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('\t').nth(1).unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+
+    let all = positions("ref\t0\t21\n");
+    let first = positions("ref\t4\t5\n");
+    let second = positions("ref\t5\t8\nref\t8\t9\n");
+    let neither = positions("ref\t5\t8\nalt1\t0\t18\n");
+    std::fs::remove_file(&targets).unwrap();
+
+    assert_eq!(vec!["5", "9"], all);
+    assert_eq!(vec!["5"], first);
+    assert_eq!(vec!["9"], second);
+    assert!(neither.is_empty());
+}
+
+#[test]
+fn bubbles_longer_than_max_span_skipped() {
+    let record_count = |max_span: &str| {
+        let vcf = run_gfa2vcf(
+            "tests/data/paths.gfa",
+            &[
+                "--ultrabubbles",
+                "tests/data/paths.ultrabubbles",
+                "--refs",
+                "ref",
+                "--no-header",
+                "--max-bubble-span",
+                max_span,
+            ],
+        );
+        String::from_utf8(vcf).unwrap().lines().count()
+    };
+
+    // Each of the bubbles spans 9 bases on the reference
+    assert_eq!(2, record_count("9"));
+    assert_eq!(0, record_count("8"));
+}
+
+#[test]
+fn paths_per_allele_as_allele_depth() {
+    let lines = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tA",
+        "S\t3\tC",
+        "S\t4\tGGTT",
+        "P\tref\t1+,2+,4+\t*",
+        "P\ta\t1+,2+,4+\t*",
+        "P\tb\t1+,3+,4+\t*",
+        "P\tc\t1+,2+,4+\t*",
+    ];
+    let args = ["--refs", "ref", "--allele-depth"];
+    let vcf = run_on_gfa_lines(&lines, "1\t4\n", &args);
+    let json = run_on_gfa_lines(
+        &lines,
+        "1\t4\n",
+        &[&args[..], &["--format", "json"]].concat(),
+    );
+
+    assert!(vcf.iter().any(|l| l.starts_with("##INFO=<ID=AD,Number=R,")));
+    assert!(vcf
+        .iter()
+        .any(|l| l.starts_with("##FORMAT=<ID=AD,Number=R,")));
+    let plain = run_on_gfa_lines(&lines, "1\t4\n", &["--refs", "ref"]);
+    assert!(!plain.iter().any(|line| line.contains("<ID=AD,")));
+    let records: Vec<&String> =
+        vcf.iter().filter(|line| !line.starts_with('#')).collect();
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=1;AN=4;AF=0.25;NS=4;\
+             AD=3,1\tGT:AD\t0:1,0\t0:1,0\t1:0,1\t0:1,0"
+        ],
+        records
+    );
+
+    let json: serde_json::Value = serde_json::from_str(&json.concat()).unwrap();
+    assert_eq!(serde_json::json!(1), json["genotypes"]["b"]);
+}
+
+#[test]
+fn automatic_reference_path() {
+    let chroms = |extra_args: &[&str]| {
+        let mut args = vec![
+            "--ultrabubbles",
+            "tests/data/paths.ultrabubbles",
+            "--no-header",
+        ];
+        args.extend_from_slice(extra_args);
+        let vcf = run_gfa2vcf("tests/data/paths.gfa", &args);
+        let mut chroms: Vec<String> = String::from_utf8(vcf)
+            .unwrap()
+            .lines()
+            .map(|line| line.split('\t').next().unwrap().to_string())
+            .collect();
+        chroms.dedup();
+        chroms
+    };
+
+    assert_eq!(vec!["ref", "alt1", "alt2"], chroms(&[]));
+    assert_eq!(vec!["ref"], chroms(&["--auto-ref", "longest"]));
+    assert_eq!(vec!["ref"], chroms(&["--auto-ref", "most-bubbles"]));
+    // It can't be combined with explicit references
+    assert!(GFA2VCFArgs::from_iter_safe(&[
+        "gfa2vcf",
+        "--auto-ref",
+        "first",
+        "--refs",
+        "alt1",
+    ])
+    .is_err());
+}
+
+#[test]
+fn input_files_with_crlf_line_endings() {
+    let run = |dir: &str| {
+        let file = |name: &str| format!("tests/data/{}/{}", dir, name);
+        let (ultrabubbles, refs, targets, samples) = (
+            file("paths.ultrabubbles"),
+            file("refs.txt"),
+            file("targets.bed"),
+            file("samples.tsv"),
+        );
+        let vcf = run_gfa2vcf(
+            &file("paths.gfa"),
+            &[
+                "--ultrabubbles",
+                &ultrabubbles,
+                "--paths-file",
+                &refs,
+                "--targets",
+                &targets,
+                "--sample-names",
+                &samples,
+            ],
+        );
+        String::from_utf8(vcf)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with("##"))
+            .map(String::from)
+            .collect::<Vec<_>>()
+    };
+
+    let crlf = run("crlf");
+    assert!(crlf.iter().all(|line| !line.contains('\r')));
+    assert!(crlf[0].ends_with("\tsample_ref\tsample_alt1\talt2"));
+    assert_eq!(3, crlf.len());
+    assert!(crlf[1..].iter().all(|line| line.starts_with("ref\t")));
+}
+
+#[test]
+fn quiet_and_verbose_logging() {
+    let run = |log_args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_gfautil"))
+            .env_remove("RUST_LOG")
+            .args(log_args)
+            .args(["-i", "tests/data/paths.gfa", "gfa2vcf", "--no-header"])
+            .args(["--ultrabubbles", "tests/data/paths.ultrabubbles"])
+            .output()
+            .unwrap()
+    };
+
+    let default = run(&[]);
+    let quiet = run(&["--quiet"]);
+    let verbose = run(&["-v"]);
+
+    assert!(quiet.status.success());
+    assert!(!quiet.stdout.is_empty());
+    assert!(quiet.stderr.is_empty());
+    assert_eq!(default.stdout, quiet.stdout);
+
+    let stderr = String::from_utf8(verbose.stderr).unwrap();
+    assert!(stderr.contains("DEBUG"));
+    assert!(!stderr.contains("TRACE"));
+    assert!(!String::from_utf8(default.stderr).unwrap().contains("DEBUG"));
+}
+
+#[test]
+fn resumed_from_checkpoint() {
+    let checkpoint = temp_path("checkpoint");
+    let run = |checkpoint: Option<&str>| {
+        let mut args = vec![
+            "--ultrabubbles",
+            "tests/data/paths.ultrabubbles",
+            "--refs",
+            "ref",
+            "--no-header",
+        ];
+        if let Some(dir) = checkpoint {
+            args.extend_from_slice(&["--checkpoint", dir]);
+        }
+        run_gfa2vcf("tests/data/paths.gfa", &args)
+    };
+
+    let expected = run(None);
+    assert_eq!(expected, run(checkpoint.to_str()));
+
+    // Kill the run partway through writing the second bubble, by
+    // cutting the checkpoint short
+    let checkpoint_file = checkpoint.join("gfa2vcf.checkpoint.jsonl");
+    let lines = std::fs::read_to_string(&checkpoint_file).unwrap();
+    let lines: Vec<&str> = lines.lines().collect();
+    assert_eq!(4, lines.len());
+    let killed = format!("{}\n{}\n{}", lines[0], lines[1], &lines[2][..10]);
+    std::fs::write(&checkpoint_file, killed).unwrap();
+
+    let resumed = run(checkpoint.to_str());
+    let lines_after = std::fs::read_to_string(&checkpoint_file).unwrap();
+
+    std::fs::remove_dir_all(&checkpoint).unwrap();
+
+    assert_eq!(expected, resumed);
+    assert_eq!(4, lines_after.lines().count());
+    assert!(lines_after.starts_with(&format!("{}\n{}\n", lines[0], lines[1])));
+}
+
+#[test]
+fn rare_alleles_dropped_below_min_af() {
+    // Of the 12 paths, 2 have a C SNV and 1 a G SNV in 1-4, and only
+    // 1 has the G SNV in 4-7
+    let mut lines: Vec<String> = [
+        "H\tVN:Z:1.0",
+        "S\t1\tACGT",
+        "S\t2\tA",
+        "S\t3\tC",
+        "S\t4\tGGTT",
+        "S\t5\tG",
+        "S\t6\tT",
+        "S\t7\tTTA",
+    ]
+    .iter()
+    .map(|line| line.to_string())
+    .collect();
+    for path_ix in 0..12 {
+        let (first, second) = match path_ix {
+            1 | 2 => (3, 6),
+            3 => (5, 5),
+            _ => (2, 6),
+        };
+        lines.push(format!(
+            "P\tp{}\t1+,{}+,4+,{}+,7+\t*",
+            path_ix, first, second
+        ));
+    }
+    let lines: Vec<&str> = lines.iter().map(String::as_str).collect();
+    let records = |extra: &[&str]| {
+        let args = [&["--refs", "p0", "--no-header"], extra].concat();
+        run_on_gfa_lines(&lines, "1\t4\n4\t7\n", &args)
+    };
+
+    let all = records(&[]);
+    let common = records(&["--min-af", "0.1"]);
+
+    assert_eq!(
+        vec![
+            "p0\t5\t.\tA\tC,G\t.\tCOMPLEX\tTYPE=snv;TYPE=snv;UB=1,4;AC=2,1;\
+             AN=12;AF=0.166667,0.083333;NS=12\t\
+             GT\t0\t1\t1\t2\t0\t0\t0\t0\t0\t0\t0\t0",
+            "p0\t10\t.\tT\tG\t.\tPASS\tTYPE=snv;UB=4,7;AC=1;AN=12;\
+             AF=0.083333;NS=12\tGT\t0\t0\t0\t1\t0\t0\t0\t0\t0\t0\t0\t0",
+        ],
+        all
+    );
+
+    // The singleton G is dropped, which leaves no ALT in 4-7, and its
+    // path is no longer counted in AN, and with only one ALT left,
+    // the record in 1-4 isn't complex anymore
+    assert_eq!(
+        vec![
+            "p0\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=2;AN=11;\
+             AF=0.181818;NS=11\tGT\t0\t1\t1\t.\t0\t0\t0\t0\t0\t0\t0\t0"
+        ],
+        common
+    );
+}
+
+#[test]
+fn manifest_describes_run() {
+    let manifest = temp_path("manifest.json");
+    let args = [
+        "--refs",
+        "ref",
+        "--no-header",
+        "--manifest",
+        manifest.to_str().unwrap(),
+    ];
+    run_gfa2vcf("tests/data/paths.gfa", &args);
+
+    let json = std::fs::read(&manifest).unwrap();
+    std::fs::remove_file(&manifest).unwrap();
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+
+    let gfa_size = std::fs::metadata("tests/data/paths.gfa").unwrap().len();
+    assert_eq!("tests/data/paths.gfa", json["gfa"]);
+    assert_eq!(gfa_size, json["gfa_size"]);
+    assert_eq!(7, json["segments"]);
+    assert_eq!(8, json["links"]);
+    assert_eq!(3, json["paths"]);
+    assert_eq!(2, json["ultrabubbles"]);
+    assert_eq!(serde_json::json!(["ref"]), json["reference_paths"]);
+    assert_eq!(1, json["records"]);
+    assert!(json["wall_clock_seconds"].as_f64().unwrap() >= 0.0);
+    let outputs = json["outputs"].as_array().unwrap();
+    assert_eq!(1, outputs.len());
+    assert!(outputs[0].as_str().unwrap().ends_with("out.vcf"));
+    assert!(json["command"].is_array());
+}
+
+#[test]
+fn overlapping_shards_merged() {
+    use gfautil::commands::merge_vcf::{merge_vcf, MergeVcfArgs};
+
+    // Both shards have the bubble 4-6, with the deletion at 9
+    let shards = [("1\t4\n4\t6\n", "a"), ("4\t6\n6\t7\n", "b")];
+    let mut shard_vcfs = Vec::new();
+    for (bubbles, name) in shards.iter() {
+        let ultrabubbles = temp_path(&format!("shard_{}.ub", name));
+        std::fs::write(&ultrabubbles, bubbles).unwrap();
+        let args = ["--ultrabubbles", ultrabubbles.to_str().unwrap()];
+        let vcf = run_gfa2vcf("tests/data/paths.gfa", &args);
+        std::fs::remove_file(&ultrabubbles).unwrap();
+
+        let path = temp_path(&format!("shard_{}.vcf", name));
+        std::fs::write(&path, vcf).unwrap();
+        shard_vcfs.push(path);
+    }
+    let whole = run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &["--ultrabubbles", "tests/data/paths.ultrabubbles"],
+    );
+
+    let output = temp_path("merged.vcf");
+    let args = std::iter::once("merge-vcf")
+        .chain(shard_vcfs.iter().map(|p| p.to_str().unwrap()))
+        .chain(vec!["-o", output.to_str().unwrap()]);
+    merge_vcf(&MergeVcfArgs::from_iter_safe(args).unwrap()).unwrap();
+    let merged = std::fs::read_to_string(&output).unwrap();
+
+    std::fs::remove_file(&output).unwrap();
+    for path in shard_vcfs.iter() {
+        std::fs::remove_file(path).unwrap();
+    }
+
+    let records = |vcf: &str| -> Vec<String> {
+        vcf.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(String::from)
+            .collect()
+    };
+    let header = |vcf: &str| -> Vec<String> {
+        vcf.lines()
+            .filter(|line| {
+                line.starts_with("##contig") || line.starts_with("#CHROM")
+            })
+            .map(String::from)
+            .collect()
+    };
+
+    let whole = String::from_utf8(whole).unwrap();
+    assert_eq!(records(&whole), records(&merged));
+    assert_eq!(header(&whole), header(&merged));
+    assert_eq!(
+        1,
+        merged.lines().filter(|l| l.starts_with("#CHROM")).count()
+    );
+}
+
+#[test]
+fn ids_from_bubble_ends() {
+    let args = [
+        "--ultrabubbles",
+        "tests/data/paths.ultrabubbles",
+        "--no-header",
+        "--id-from-bubble",
+    ];
+    let vcf = run_gfa2vcf("tests/data/paths.gfa", &args);
+    let vcf = String::from_utf8(vcf).unwrap();
+
+    // Every path is a reference, so each bubble has a record on each
+    let ids: Vec<&str> = vcf
+        .lines()
+        .map(|line| line.split('\t').nth(2).unwrap())
+        .collect();
+    assert_eq!(
+        vec![
+            "ub_1_4_ref_5",
+            "ub_4_6_ref_9",
+            "ub_1_4_alt1_5",
+            "ub_4_6_alt1_9",
+            "ub_1_4_alt2_5",
+            "ub_4_6_alt2_9"
+        ],
+        ids
+    );
+
+    let args = [
+        "--ultrabubbles",
+        "tests/data/paths.ultrabubbles",
+        "--refs",
+        "ref",
+        "--no-header",
+        "--id-from-bubble",
+    ];
+    let vcf = run_gfa2vcf("tests/data/paths.gfa", &args);
+    let vcf = String::from_utf8(vcf).unwrap();
+    let ids: Vec<&str> = vcf
+        .lines()
+        .map(|line| line.split('\t').nth(2).unwrap())
+        .collect();
+    assert_eq!(vec!["ub_1_4", "ub_4_6"], ids);
+}
+
+#[test]
+fn binary_ultrabubbles_match_text() {
+    let binary = temp_path("paths.ubz");
+    let text = temp_path("paths.ultrabubbles");
+
+    // Convert the text ultrabubbles to binary and back, without a GFA
+    let args = SabotenArgs::from_iter_safe(vec![
+        "ultrabubbles",
+        "--from",
+        "tests/data/paths.ultrabubbles",
+        "-o",
+        binary.to_str().unwrap(),
+    ])
+    .unwrap();
+    run_saboten(None, &args).unwrap();
+    let args = SabotenArgs::from_iter_safe(vec![
+        "ultrabubbles",
+        "--from",
+        binary.to_str().unwrap(),
+        "-o",
+        text.to_str().unwrap(),
+    ])
+    .unwrap();
+    run_saboten(None, &args).unwrap();
+    assert_eq!(
+        std::fs::read(&text).unwrap(),
+        std::fs::read("tests/data/paths.ultrabubbles").unwrap()
+    );
+
+    let from_text = run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &["--ultrabubbles", text.to_str().unwrap(), "--refs", "ref"],
+    );
+    let from_binary = run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &["--ultrabubbles", binary.to_str().unwrap(), "--refs", "ref"],
+    );
+
+    std::fs::remove_file(&binary).unwrap();
+    std::fs::remove_file(&text).unwrap();
+
+    assert_eq!(from_text, from_binary);
+}
+
+#[test]
+fn samples_with_data_counted_as_ns() {
+    // The path `partial` starts after the bubble 1-4, so it has no
+    // genotype there, while all paths traverse 4-7
+    let lines = [
+        "S\t1\tACGT",
+        "S\t2\tA",
+        "S\t3\tC",
+        "S\t4\tGGTT",
+        "S\t5\tA",
+        "S\t6\tG",
+        "S\t7\tCA",
+        "L\t1\t+\t2\t+\t0M",
+        "L\t1\t+\t3\t+\t0M",
+        "L\t2\t+\t4\t+\t0M",
+        "L\t3\t+\t4\t+\t0M",
+        "L\t4\t+\t5\t+\t0M",
+        "L\t4\t+\t6\t+\t0M",
+        "L\t5\t+\t7\t+\t0M",
+        "L\t6\t+\t7\t+\t0M",
+        "P\tref\t1+,2+,4+,5+,7+\t*",
+        "P\talt\t1+,3+,4+,5+,7+\t*",
+        "P\tother\t1+,3+,4+,6+,7+\t*",
+        "P\tpartial\t4+,6+,7+\t*",
+    ];
+    let sample_map = temp_path("partial_samples.tsv");
+    std::fs::write(&sample_map, "other\tS1\npartial\tS1\n").unwrap();
+
+    let run = |extra: &[&str]| {
+        let args = [&["--refs", "ref"], extra].concat();
+        run_on_gfa_lines(&lines, "1\t4\n4\t7\n", &args)
+    };
+    let vcf = run(&[]);
+    let grouped = run(&["--sample-map", sample_map.to_str().unwrap()]);
+
+    std::fs::remove_file(&sample_map).unwrap();
+
+    assert!(vcf
+        .iter()
+        .any(|line| line.starts_with("##INFO=<ID=NS,Number=1,Type=Integer,")));
+    let records: Vec<&String> =
+        vcf.iter().filter(|line| !line.starts_with('#')).collect();
+    assert_eq!(
+        vec![
+            "ref\t5\t.\tA\tC\t.\tPASS\tTYPE=snv;UB=1,4;AC=2;AN=3;\
+             AF=0.666667;NS=3\tGT\t0\t1\t1\t.",
+            "ref\t10\t.\tA\tG\t.\tPASS\tTYPE=snv;UB=4,7;AC=2;AN=4;\
+             AF=0.5;NS=4\tGT\t0\t0\t1\t1",
+        ],
+        records
+    );
+
+    // A sample counts if any of its paths traverse the bubble
+    let records: Vec<&String> = grouped
+        .iter()
+        .filter(|line| !line.starts_with('#'))
+        .collect();
+    assert!(records[0].ends_with("AN=3;AF=0.666667;NS=3\tGT\t0\t1\t1|."));
+    assert!(records[1].ends_with("AN=4;AF=0.5;NS=3\tGT\t0\t0\t1|1"));
+}
+
+#[test]
+fn config_file_options_below_command_line() {
+    let config = temp_path("gfa2vcf.toml");
+    std::fs::write(
+        &config,
+        "# Overridden on the command line\n\
+         ultrabubbles = \"tests/data/missing.ultrabubbles\"\n\
+         format = \"json\"\n\
+         \n\
+         refs = [\"ref\"]\n\
+         no-header = true\n\
+         normalize = false\n",
+    )
+    .unwrap();
+    let output = temp_path("config.vcf");
+
+    let cli_args: Vec<std::ffi::OsString> = vec![
+        "gfa2vcf",
+        "--config",
+        config.to_str().unwrap(),
+        "-u",
+        "tests/data/paths.ultrabubbles",
+        "--format=vcf",
+        "-o",
+        output.to_str().unwrap(),
+    ]
+    .into_iter()
+    .map(Into::into)
+    .collect();
+    let args = GFA2VCFArgs::from_iter_safe(&cli_args).unwrap();
+    let args = apply_config(args, &cli_args).unwrap();
+    gfa2vcf(
+        &PathBuf::from("tests/data/paths.gfa"),
+        args,
+        Default::default(),
+    )
+    .unwrap();
+
+    let vcf = String::from_utf8(std::fs::read(&output).unwrap()).unwrap();
+    std::fs::remove_file(&output).unwrap();
+
+    // The command line gives the ultrabubbles and format, the config
+    // the references and --no-header, and --max-alleles is left at
+    // its default of 1, so the records pass
+    let records: Vec<&str> = vcf.lines().collect();
+    assert_eq!(2, records.len());
+    assert!(records[0].starts_with("ref\t5\t.\tA\tC\t.\tPASS\t"));
+    assert!(records[1].starts_with("ref\t9\t.\tTTTA\tT\t.\tPASS\t"));
+
+    // Options in the file can be unset on the command line
+    let cli_args: Vec<std::ffi::OsString> = vec![
+        "gfa2vcf",
+        "--config",
+        config.to_str().unwrap(),
+        "-u",
+        "tests/data/paths.ultrabubbles",
+        "--format",
+        "vcf",
+        "--unset",
+        "no-header",
+        "-o",
+        output.to_str().unwrap(),
+    ]
+    .into_iter()
+    .map(Into::into)
+    .collect();
+    let args = GFA2VCFArgs::from_iter_safe(&cli_args).unwrap();
+    let args = apply_config(args, &cli_args).unwrap();
+    gfa2vcf(
+        &PathBuf::from("tests/data/paths.gfa"),
+        args,
+        Default::default(),
+    )
+    .unwrap();
+
+    let vcf = String::from_utf8(std::fs::read(&output).unwrap()).unwrap();
+    std::fs::remove_file(&output).unwrap();
+    assert!(vcf.starts_with("##fileformat=VCF"));
+    assert_eq!(2, vcf.lines().filter(|l| !l.starts_with('#')).count());
+
+    // Options in the file are checked like those on the command line
+    std::fs::write(&config, "refs = [\"ref\"]\nauto-ref = \"first\"\n")
+        .unwrap();
+    let cli_args: Vec<std::ffi::OsString> =
+        vec!["gfa2vcf".into(), "--config".into(), config.clone().into()];
+    let args = GFA2VCFArgs::from_iter_safe(&cli_args).unwrap();
+    let err = apply_config(args, &cli_args).err().unwrap();
+    std::fs::remove_file(&config).unwrap();
+
+    let err = err.to_string();
+    assert!(err.starts_with("Error in config file"));
+    assert!(err.contains("cannot be used with"));
+}
+
+#[test]
+fn split_by_ref_writes_file_per_reference() {
+    let dir = temp_path("split_by_ref");
+    std::fs::create_dir_all(&dir).unwrap();
+    let output = dir.join("out.vcf");
+    let manifest = dir.join("manifest.json");
+
+    let args = GFA2VCFArgs::from_iter_safe(vec![
+        "gfa2vcf",
+        "--ultrabubbles",
+        "tests/data/paths.ultrabubbles",
+        "--refs",
+        "ref",
+        "alt1",
+        "--split-by-ref",
+        "-o",
+        output.to_str().unwrap(),
+        "--manifest",
+        manifest.to_str().unwrap(),
+    ])
+    .unwrap();
+    gfa2vcf(
+        &PathBuf::from("tests/data/paths.gfa"),
+        args,
+        Default::default(),
+    )
+    .unwrap();
+
+    assert!(!output.exists());
+    let ref_vcf = std::fs::read_to_string(dir.join("out.ref.vcf")).unwrap();
+    let alt1_vcf = std::fs::read_to_string(dir.join("out.alt1.vcf")).unwrap();
+    let json = std::fs::read(&manifest).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // The manifest lists the files that were written
+    let json: serde_json::Value = serde_json::from_slice(&json).unwrap();
+    let outputs: Vec<&str> = json["outputs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|path| path.as_str().unwrap())
+        .collect();
+    assert_eq!(2, outputs.len());
+    assert!(outputs[0].ends_with("out.ref.vcf"));
+    assert!(outputs[1].ends_with("out.alt1.vcf"));
+
+    let records = |vcf: &str| -> Vec<String> {
+        vcf.lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| line.split('\t').take(5).collect::<Vec<_>>().join(" "))
+            .collect()
+    };
+    assert_eq!(vec!["ref 5 . A C", "ref 9 . TTTA T"], records(&ref_vcf));
+    assert_eq!(vec!["alt1 5 . C A", "alt1 9 . T TTTA"], records(&alt1_vcf));
+
+    // Each file only has the contig of its own reference
+    assert!(ref_vcf.contains("##contig=<ID=ref,length=21>"));
+    assert!(!ref_vcf.contains("##contig=<ID=alt1"));
+    assert!(alt1_vcf.contains("##contig=<ID=alt1,length=18>"));
+    assert!(!alt1_vcf.contains("##contig=<ID=ref"));
+
+    // The records are the same as when they're written to one file
+    let combined = run_gfa2vcf(
+        "tests/data/paths.gfa",
+        &[
+            "--ultrabubbles",
+            "tests/data/paths.ultrabubbles",
+            "--refs",
+            "ref",
+            "alt1",
+        ],
+    );
+    let combined = String::from_utf8(combined).unwrap();
+    let mut split = records(&ref_vcf);
+    split.extend(records(&alt1_vcf));
+    assert_eq!(split, records(&combined));
+}
+
+#[test]
+fn bubbles_with_n_runs_skipped() {
+    let positions = |args: &[&str]| -> Vec<String> {
+        let mut all_args =
+            vec!["--ultrabubbles", "tests/data/n_runs.ultrabubbles"];
+        all_args.extend(args);
+        let vcf = run_gfa2vcf("tests/data/n_runs.gfa", &all_args);
+        String::from_utf8(vcf)
+            .unwrap()
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                format!("{}:{}", fields[1], fields[4])
+            })
+            .collect()
+    };
+
+    // Without the flag, the gap in the first bubble is an insertion
+    assert_eq!(vec!["4:TNNNNNNNN", "10:T"], positions(&["--refs", "ref"]));
+    assert_eq!(
+        vec!["10:T"],
+        positions(&["--refs", "ref", "--trim-n-runs", "8"])
+    );
+    assert_eq!(
+        vec!["4:TNNNNNNNN", "10:T"],
+        positions(&["--refs", "ref", "--trim-n-runs", "9"])
+    );
+    assert_eq!(
+        vec!["10:T"],
+        positions(&["--refs", "ref", "--skip-n-runs", "8"])
+    );
+
+    assert!(
+        GFA2VCFArgs::from_iter_safe(vec!["gfa2vcf", "--trim-n-runs", "0"])
+            .is_err()
+    );
+}
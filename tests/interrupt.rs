@@ -0,0 +1,57 @@
+// Interrupts are caught process-wide, so this test gets its own test
+// binary, to keep them from reaching the other gfa2vcf tests.
+#![cfg(unix)]
+
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use gfautil::{
+    commands::gfa2vcf::{gfa2vcf, GFA2VCFArgs},
+    util::{catch_interrupts, interrupted},
+};
+
+/// The current SIGINT handler.
+fn sigint_handler() -> libc::sighandler_t {
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        libc::sigaction(libc::SIGINT, std::ptr::null(), &mut action);
+        action.sa_sigaction
+    }
+}
+
+#[test]
+fn interrupted_run_removes_output_and_restores_handler() {
+    // Ignored, rather than the default, so that a missed restore shows
+    // up without killing the test
+    unsafe { libc::signal(libc::SIGINT, libc::SIG_IGN) };
+
+    // An interrupt caught before the run, so it stops before the first
+    // ultrabubble
+    let guard = catch_interrupts();
+    unsafe { libc::raise(libc::SIGINT) };
+    drop(guard);
+    assert!(interrupted());
+    assert_eq!(libc::SIG_IGN, sigint_handler());
+
+    let output = std::env::temp_dir()
+        .join(format!("gfautil_interrupted_{}.vcf", std::process::id()));
+    let args = GFA2VCFArgs::from_iter_safe(&[
+        "gfa2vcf",
+        "-u",
+        "tests/data/paths.ultrabubbles",
+        "-o",
+        output.to_str().unwrap(),
+    ])
+    .unwrap();
+
+    let err = gfa2vcf(
+        &PathBuf::from("tests/data/paths.gfa"),
+        args,
+        Default::default(),
+    )
+    .unwrap_err();
+
+    assert!(err.to_string().starts_with("Interrupted"));
+    assert!(!output.exists());
+    assert_eq!(libc::SIG_IGN, sigint_handler());
+}
@@ -0,0 +1,192 @@
+use gfa::gfa::GFA;
+
+use gfautil::{
+    commands::load_gfa,
+    error::GfaUtilsError,
+    variants::{gfa_path_data, load_path_data, load_path_data_with_graph},
+};
+
+#[test]
+fn load_gzipped_gfa() {
+    let plain: GFA<usize, ()> = load_gfa("tests/data/paths.gfa").unwrap();
+    let gzipped: GFA<usize, ()> = load_gfa("tests/data/paths.gfa.gz").unwrap();
+
+    assert_eq!(7, gzipped.segments.len());
+    assert_eq!(3, gzipped.paths.len());
+    assert_eq!(plain, gzipped);
+}
+
+#[test]
+fn load_truncated_gzipped_gfa() {
+    let bytes = std::fs::read("tests/data/paths.gfa.gz").unwrap();
+    let truncated = &bytes[..bytes.len() / 2];
+
+    let mut path = std::env::temp_dir();
+    path.push(format!("gfautil_truncated_{}.gfa.gz", std::process::id()));
+    std::fs::write(&path, truncated).unwrap();
+
+    let result: Result<GFA<usize, ()>, _> = load_gfa(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let err = result.unwrap_err();
+    assert!(err.to_string().contains("truncated"));
+}
+
+#[test]
+fn load_gfa2() {
+    let gfa1: GFA<usize, ()> = load_gfa("tests/data/paths.gfa").unwrap();
+    let gfa2: GFA<usize, ()> = load_gfa("tests/data/paths.gfa2").unwrap();
+
+    assert_eq!(gfa1.segments, gfa2.segments);
+    assert_eq!(gfa1.links, gfa2.links);
+    assert_eq!(gfa1.paths, gfa2.paths);
+}
+
+#[test]
+fn load_gfa_with_string_ids() {
+    let mut path = std::env::temp_dir();
+    path.push(format!("gfautil_string_ids_{}.gfa", std::process::id()));
+    std::fs::write(&path, "S\t1\tACGT\nS\tchr1_42\tA\nP\tp\t1+,chr1_42+\t*\n")
+        .unwrap();
+
+    let result: Result<GFA<usize, ()>, _> = load_gfa(&path);
+    std::fs::remove_file(&path).unwrap();
+
+    let err = result.unwrap_err();
+    assert!(matches!(err, GfaUtilsError::GfaParse(_)));
+    let err = err.to_string();
+    assert!(err.contains("integer segment IDs"));
+    assert!(err.contains("`chr1_42`"));
+}
+
+#[test]
+fn stream_path_data() {
+    let gfa: GFA<usize, ()> = load_gfa("tests/data/paths.gfa").unwrap();
+    let expected = gfa_path_data(gfa, false, Default::default()).unwrap();
+
+    for path in &[
+        "tests/data/paths.gfa",
+        "tests/data/paths.gfa.gz",
+        "tests/data/paths.gfa2",
+    ] {
+        let streamed = load_path_data(path, false, Default::default()).unwrap();
+        assert_eq!(expected.segment_map, streamed.segment_map);
+        assert_eq!(expected.path_names, streamed.path_names);
+        assert_eq!(expected.paths, streamed.paths);
+    }
+}
+
+#[test]
+fn stream_path_data_with_duplicate_segment() {
+    let mut path = std::env::temp_dir();
+    path.push(format!("gfautil_dup_segments_{}.gfa", std::process::id()));
+    std::fs::write(&path, "S\t1\tACGT\nS\t2\tA\nS\t1\tC\nP\tp\t1+,2+\t*\n")
+        .unwrap();
+
+    let result = load_path_data(&path, false, Default::default());
+    std::fs::remove_file(&path).unwrap();
+
+    let err = result.err().unwrap();
+    assert!(matches!(err, GfaUtilsError::DuplicateSegment(1)));
+    assert_eq!("Segment ID 1 is defined more than once", err.to_string());
+}
+
+#[test]
+fn load_walks_as_paths() {
+    let gfa: GFA<usize, ()> = load_gfa("tests/data/walks.gfa").unwrap();
+    let names: Vec<&[u8]> =
+        gfa.paths.iter().map(|p| p.path_name.as_slice()).collect();
+    assert_eq!(
+        vec![&b"ref#0#chr1"[..], b"HG002#1#chr1", b"HG002#2#chr1"],
+        names
+    );
+
+    let path_data =
+        load_path_data("tests/data/walks.gfa", false, Default::default())
+            .unwrap();
+    assert_eq!(3, path_data.paths.len());
+    let steps: Vec<usize> =
+        path_data.paths[2].iter().map(|&(seg, _, _)| seg).collect();
+    assert_eq!(vec![4, 2, 1], steps);
+    assert!(path_data.paths[2].iter().all(|&(_, _, o)| o.is_reverse()));
+}
+
+#[cfg(feature = "mmap")]
+#[test]
+fn load_memory_mapped_gfa() {
+    let buffered: GFA<usize, ()> = load_gfa("tests/data/paths.gfa").unwrap();
+    gfautil::commands::set_use_mmap(true);
+    let mapped: GFA<usize, ()> = load_gfa("tests/data/paths.gfa").unwrap();
+    let gzipped: GFA<usize, ()> = load_gfa("tests/data/paths.gfa.gz").unwrap();
+    gfautil::commands::set_use_mmap(false);
+
+    assert_eq!(buffered, mapped);
+    assert_eq!(buffered, gzipped);
+}
+
+#[test]
+fn ultrabubbles_in_loaded_gfa() {
+    use gfautil::{
+        bubbles::BubbleAlgorithm,
+        commands::saboten::{find_ultrabubbles, find_ultrabubbles_in_gfa},
+    };
+
+    let path = std::path::PathBuf::from("tests/data/paths.gfa");
+    let gfa: GFA<usize, ()> = load_gfa(&path).unwrap();
+    for &algorithm in [BubbleAlgorithm::Saboten, BubbleAlgorithm::Bcc].iter() {
+        assert_eq!(
+            find_ultrabubbles(&path, algorithm).unwrap(),
+            find_ultrabubbles_in_gfa(&gfa, algorithm)
+        );
+    }
+    assert_eq!(
+        vec![(1, 4), (4, 6)],
+        find_ultrabubbles_in_gfa(&gfa, BubbleAlgorithm::Bcc)
+    );
+}
+
+#[test]
+fn ultrabubbles_in_streamed_graph() {
+    use gfautil::{bubbles::BubbleAlgorithm, commands::saboten};
+
+    let path = std::path::PathBuf::from("tests/data/paths.gfa");
+    let expected = load_path_data(&path, false, Default::default()).unwrap();
+    let gfa: GFA<usize, ()> = load_gfa(&path).unwrap();
+
+    let (path_data, graph) =
+        load_path_data_with_graph(&path, false, Default::default()).unwrap();
+    assert_eq!(expected.segment_map, path_data.segment_map);
+    assert_eq!(expected.path_names, path_data.path_names);
+    assert_eq!(expected.paths, path_data.paths);
+    assert_eq!(gfa.links, graph.links);
+    assert!(graph.segments.iter().all(|seg| seg.sequence.is_empty()));
+    assert!(graph.paths.is_empty());
+
+    for &algorithm in [BubbleAlgorithm::Saboten, BubbleAlgorithm::Bcc].iter() {
+        assert_eq!(
+            saboten::find_ultrabubbles(&path, algorithm).unwrap(),
+            saboten::find_ultrabubbles_in_gfa(&graph, algorithm)
+        );
+    }
+}
+
+#[test]
+fn load_gfa_with_crlf_line_endings() {
+    // The last line ends with a `\r` but no newline
+    let lf: GFA<usize, ()> = load_gfa("tests/data/paths.gfa").unwrap();
+    let crlf: GFA<usize, ()> = load_gfa("tests/data/crlf/paths.gfa").unwrap();
+    assert_eq!(lf, crlf);
+
+    let gfa2: GFA<usize, ()> = load_gfa("tests/data/crlf/paths.gfa2").unwrap();
+    assert_eq!(lf.segments, gfa2.segments);
+    assert_eq!(lf.paths, gfa2.paths);
+
+    let lf = load_path_data("tests/data/paths.gfa", false, Default::default())
+        .unwrap();
+    let crlf =
+        load_path_data("tests/data/crlf/paths.gfa", false, Default::default())
+            .unwrap();
+    assert_eq!(lf.path_names, crlf.path_names);
+    assert_eq!(lf.paths, crlf.paths);
+    assert_eq!(lf.segment_map, crlf.segment_map);
+}